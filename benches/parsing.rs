@@ -1,10 +1,12 @@
 use std::collections::HashSet;
+use std::hint::black_box;
+use std::sync::Arc;
 
-use criterion::{Criterion, Throughput, black_box, criterion_group, criterion_main};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
 use portage_atom::{Cpn, Cpv, Dep};
 use portage_atom_resolvo::{
-    DepEntry, InMemoryRepository, PackageDeps, PackageMetadata, PortageDependencyProvider,
-    UseConfig, interner,
+    CacheKey, DepClassPolicy, DepEntry, InMemoryRepository, InstalledSet, PackageDeps,
+    PackageMetadata, PortageDependencyProvider, UseConfig, interner, repository_fingerprint,
 };
 use resolvo::{Problem, Solver};
 
@@ -103,6 +105,7 @@ fn bench_repository_add(c: &mut Criterion) {
     group.finish();
 }
 
+#[allow(clippy::unnecessary_to_owned)]
 fn bench_string_alloc(c: &mut Criterion) {
     c.bench_function("create_same_string_1000x", |b| {
         b.iter(|| {
@@ -246,7 +249,7 @@ fn bench_provider_construction(c: &mut Criterion) {
     let use_config = UseConfig::from(
         ["ssl", "xml"]
             .iter()
-            .map(|s| interner::Interned::intern(*s))
+            .map(|s| interner::Interned::intern(s))
             .collect::<HashSet<_>>(),
     );
 
@@ -262,7 +265,7 @@ fn bench_solve_resolution(c: &mut Criterion) {
     let use_config = UseConfig::from(
         ["ssl", "xml"]
             .iter()
-            .map(|s| interner::Interned::intern(*s))
+            .map(|s| interner::Interned::intern(s))
             .collect::<HashSet<_>>(),
     );
 
@@ -280,7 +283,7 @@ fn bench_solve_resolution(c: &mut Criterion) {
             let problem = Problem::new().requirements(reqs);
             let mut solver = Solver::new(provider);
             let result = solver.solve(problem);
-            black_box(result);
+            let _ = black_box(result);
         })
     });
 }
@@ -290,7 +293,7 @@ fn bench_solve_resolution_isolated(c: &mut Criterion) {
     let use_config = UseConfig::from(
         ["ssl", "xml"]
             .iter()
-            .map(|s| interner::Interned::intern(*s))
+            .map(|s| interner::Interned::intern(s))
             .collect::<HashSet<_>>(),
     );
 
@@ -320,6 +323,141 @@ fn bench_solve_resolution_isolated(c: &mut Criterion) {
     group.finish();
 }
 
+/// Build a synthetic @world-sized repository: every package build-depends
+/// on a few of its predecessors and runtime-depends on one more, with a
+/// `|| ( )` group mixed in.
+fn build_world_repo(size: usize) -> InMemoryRepository {
+    let mut repo = InMemoryRepository::new();
+    for i in 0..size {
+        let depend: Vec<DepEntry> = (1..=3)
+            .filter(|k| i >= *k)
+            .map(|k| DepEntry::Atom(Dep::parse(&format!("cat/pkg{}", i - k)).unwrap()))
+            .collect();
+        let mut rdepend = Vec::new();
+        if i >= 2 {
+            rdepend.push(DepEntry::AnyOf(vec![
+                DepEntry::Atom(Dep::parse(&format!(">=cat/pkg{}-2", i / 2)).unwrap()),
+                DepEntry::Atom(Dep::parse(&format!("cat/pkg{}", i / 2)).unwrap()),
+            ]));
+        }
+        repo.add(PackageMetadata {
+            cpv: Cpv::parse(&format!("cat/pkg{i}-1.0")).unwrap(),
+            slot: Some(interner::Interned::intern("0")),
+            subslot: None,
+            iuse: vec![],
+            use_flags: HashSet::new(),
+            repo: None,
+            dependencies: PackageDeps {
+                depend,
+                rdepend,
+                ..PackageDeps::default()
+            },
+        });
+    }
+    repo
+}
+
+fn bench_dependency_graph(c: &mut Criterion) {
+    const SIZE: usize = 1500;
+    let repo = build_world_repo(SIZE);
+    let mut provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+    let reqs: Vec<_> = (0..SIZE)
+        .map(|i| provider.intern_requirement(&Dep::parse(&format!("cat/pkg{i}")).unwrap()))
+        .collect();
+    let mut solver = Solver::new(provider);
+    let solution = solver.solve(Problem::new().requirements(reqs)).unwrap();
+    let provider = solver.provider();
+
+    let mut group = c.benchmark_group("graph");
+    group.throughput(Throughput::Elements(SIZE as u64));
+
+    group.bench_function("dependency_graph_1500", |b| {
+        b.iter(|| black_box(provider.dependency_graph(&solution)))
+    });
+    group.bench_function("install_order_1500", |b| {
+        b.iter(|| black_box(provider.install_order(&solution)))
+    });
+
+    group.finish();
+}
+
+fn bench_provider_cache(c: &mut Criterion) {
+    const SIZE: usize = 1500;
+    let repo = build_world_repo(SIZE);
+    let use_config = UseConfig::default();
+    let installed = InstalledSet::default();
+    let repository = repository_fingerprint(&repo, &installed).unwrap();
+    let key = CacheKey::new(repository, &use_config, None, &DepClassPolicy::default());
+    let mut bytes = Vec::new();
+    PortageDependencyProvider::new(&repo, &use_config)
+        .write_cache(&mut bytes, repository)
+        .unwrap();
+
+    let mut group = c.benchmark_group("cache");
+    group.throughput(Throughput::Elements(SIZE as u64));
+    group.bench_function("build_1500", |b| {
+        b.iter(|| black_box(PortageDependencyProvider::new(&repo, &use_config)))
+    });
+    group.bench_function("load_1500", |b| {
+        b.iter(|| black_box(PortageDependencyProvider::read_cache(bytes.as_slice(), &key).unwrap()))
+    });
+    group.bench_function("fingerprint_1500", |b| {
+        b.iter(|| black_box(repository_fingerprint(&repo, &installed).unwrap()))
+    });
+    group.finish();
+}
+
+fn bench_lazy_provider(c: &mut Criterion) {
+    let repo = Arc::new(build_realistic_repo());
+    let use_config = UseConfig::from(
+        ["ssl", "xml"]
+            .iter()
+            .map(|s| interner::Interned::intern(s))
+            .collect::<HashSet<_>>(),
+    );
+    let installed = InstalledSet::default();
+    let roots = [
+        "net-misc/curl",
+        "app-portage/gentoolkit",
+        "www-client/firefox",
+    ];
+    let solve = |mut provider: PortageDependencyProvider| {
+        let reqs: Vec<_> = roots
+            .iter()
+            .map(|s| provider.intern_requirement(&Dep::parse(s).unwrap()))
+            .collect();
+        let mut solver = Solver::new(provider);
+        black_box(solver.solve(Problem::new().requirements(reqs)).unwrap());
+    };
+
+    let mut group = c.benchmark_group("provider_mode");
+    group.bench_function("eager_construction", |b| {
+        b.iter(|| black_box(PortageDependencyProvider::new(&*repo, &use_config)))
+    });
+    group.bench_function("lazy_construction", |b| {
+        b.iter(|| {
+            black_box(PortageDependencyProvider::lazy(
+                repo.clone(),
+                &use_config,
+                &installed,
+            ))
+        })
+    });
+    group.bench_function("eager_solve", |b| {
+        b.iter(|| solve(PortageDependencyProvider::new(&*repo, &use_config)))
+    });
+    group.bench_function("lazy_solve", |b| {
+        b.iter(|| {
+            solve(PortageDependencyProvider::lazy(
+                repo.clone(),
+                &use_config,
+                &installed,
+            ))
+        })
+    });
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_cpn_parsing,
@@ -331,6 +469,9 @@ criterion_group!(
     bench_provider_construction,
     bench_solve_resolution,
     bench_solve_resolution_isolated,
+    bench_dependency_graph,
+    bench_provider_cache,
+    bench_lazy_provider,
 );
 
 criterion_main!(benches);
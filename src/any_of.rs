@@ -0,0 +1,345 @@
+//! Alternative selection in `|| ( )` groups.
+//!
+//! resolvo tries the version sets of a [`Requirement::Union`] in order, so
+//! that order picks the alternative a solve pulls in when several would
+//! do. Like Portage, the provider hands resolvo the alternatives of every
+//! any-of group reordered: first those already pulled into the graph, then
+//! those with an installed version, then the rest, each tier left-most
+//! first. The encoded dependencies keep the ebuild's order.
+//!
+//! An alternative is pulled when a root atom, or a plain (non-alternative)
+//! dependency shared by every candidate of a pulled package, names it. An
+//! all-of alternative `( a b )` ranks as its least preferred member, an
+//! unslotted atom as its most preferred slot.
+//! Visibility needs no ranking: resolvo skips alternatives whose
+//! candidates are all excluded.
+
+use std::collections::HashSet;
+
+use portage_atom::Dep;
+use resolvo::{KnownDependencies, NameId, Requirement, VersionSetId, VersionSetUnionId};
+
+use crate::pool::{Domain, PackageName};
+use crate::provider::{PortageDependencyProvider, extract_slot};
+
+/// Preference tier of an any-of alternative, most preferred first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Preference {
+    Pulled,
+    Installed,
+    Other,
+}
+
+impl PortageDependencyProvider {
+    /// `deps` with the alternatives of every any-of group in preferred
+    /// order.
+    pub(crate) fn prefer_alternatives(&self, mut deps: KnownDependencies) -> KnownDependencies {
+        for req in &mut deps.requirements {
+            if let Requirement::Union(union) = req.requirement {
+                req.requirement = Requirement::Union(self.preferred_union(union));
+            }
+        }
+        deps
+    }
+
+    /// `union` reordered by [`Preference`], interned once per union.
+    fn preferred_union(&self, union: VersionSetUnionId) -> VersionSetUnionId {
+        if let Some(&preferred) = self.preferred_unions.borrow().get(&union) {
+            return preferred;
+        }
+        let sets = self.pool.resolve_version_set_union(union).to_vec();
+        // An unslotted atom contributes one version set per slot; they stay
+        // together, in slot order, and rank as the atom's best slot.
+        let mut alternatives: Vec<Vec<VersionSetId>> = Vec::new();
+        for &vs in &sets {
+            let constraint = self.pool.resolve_version_set(vs);
+            match alternatives.last_mut() {
+                Some(last)
+                    if constraint.slot.is_none()
+                        && self.pool.resolve_version_set(last[0]).slot.is_none()
+                        && self.pool.resolve_version_set(last[0]).cpn == constraint.cpn =>
+                {
+                    last.push(vs)
+                }
+                _ => alternatives.push(vec![vs]),
+            }
+        }
+        alternatives.sort_by_cached_key(|alternative| {
+            alternative
+                .iter()
+                .map(|&vs| self.preference(self.pool.version_set_name(vs)))
+                .min()
+        });
+        let ranked: Vec<VersionSetId> = alternatives.into_iter().flatten().collect();
+        let preferred = if ranked == sets {
+            union
+        } else {
+            let preferred = self.pool.intern_version_set_union(ranked);
+            // Keep the group's provenance for explanations and conflicts.
+            let origins = self
+                .origins
+                .borrow()
+                .get(&Requirement::Union(union))
+                .cloned();
+            if let Some(origins) = origins {
+                self.origins
+                    .borrow_mut()
+                    .insert(Requirement::Union(preferred), origins);
+            }
+            preferred
+        };
+        self.preferred_unions.borrow_mut().insert(union, preferred);
+        preferred
+    }
+
+    fn preference(&self, name: NameId) -> Preference {
+        let cpn = self.pool.resolve_name(name).cpn;
+        if cpn.category.as_str() == "virtual" && cpn.package.as_str().starts_with("allof_") {
+            return self
+                .plain_requirements(name)
+                .into_iter()
+                .map(|member| self.preference(member))
+                .max()
+                .unwrap_or(Preference::Other);
+        }
+        if self.is_pulled(name) {
+            return Preference::Pulled;
+        }
+        self.load_cpn(cpn);
+        let installed = self.installed.borrow();
+        let has_installed = self
+            .candidates
+            .borrow()
+            .get(&name)
+            .is_some_and(|sids| sids.iter().any(|sid| installed.contains(sid)));
+        if has_installed {
+            Preference::Installed
+        } else {
+            Preference::Other
+        }
+    }
+
+    /// Whether the root atoms pull `name` into the graph. The pulled names
+    /// are computed once per set of roots.
+    fn is_pulled(&self, name: NameId) -> bool {
+        if let Some(pulled) = self.pulled.borrow().as_ref() {
+            return pulled.contains(&name);
+        }
+        let mut pulled = HashSet::new();
+        let mut queue: Vec<NameId> = self
+            .roots
+            .iter()
+            .filter_map(|(dep, domain)| self.plain_name(dep, *domain))
+            .collect();
+        while let Some(name) = queue.pop() {
+            if pulled.insert(name) {
+                queue.extend(self.plain_requirements(name));
+            }
+        }
+        let is_pulled = pulled.contains(&name);
+        *self.pulled.borrow_mut() = Some(pulled);
+        is_pulled
+    }
+
+    /// The single name a root atom requires, if it does not range over
+    /// several slots.
+    fn plain_name(&self, dep: &Dep, domain: Domain) -> Option<NameId> {
+        if dep.blocker.is_some() {
+            return None;
+        }
+        if let (Some(slot), _) = extract_slot(dep) {
+            return Some(self.pool.intern_name(PackageName {
+                cpn: dep.cpn,
+                slot: Some(slot),
+                domain,
+            }));
+        }
+        match self.cpn_slots.borrow().get(&(domain, dep.cpn))?.as_slice() {
+            &[name] => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Names every candidate of `name` requires unconditionally through a
+    /// single version set.
+    fn plain_requirements(&self, name: NameId) -> HashSet<NameId> {
+        self.load_cpn(self.pool.resolve_name(name).cpn);
+        let candidates = self
+            .candidates
+            .borrow()
+            .get(&name)
+            .cloned()
+            .unwrap_or_default();
+        let mut common: Option<HashSet<NameId>> = None;
+        for sid in candidates {
+            if self.lazy.is_some() && !self.dependencies.borrow().contains_key(&sid) {
+                self.convert_solvable(sid);
+            }
+            let required: HashSet<NameId> = self
+                .dependencies
+                .borrow()
+                .get(&sid)
+                .map(|deps| {
+                    deps.requirements
+                        .iter()
+                        .filter(|req| req.condition.is_none())
+                        .filter_map(|req| match req.requirement {
+                            Requirement::Single(vs) => Some(self.pool.version_set_name(vs)),
+                            Requirement::Union(_) => None,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            common = Some(match common {
+                Some(common) => &common & &required,
+                None => required,
+            });
+        }
+        common.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use portage_atom::Cpv;
+    use resolvo::{Problem, SolvableId, Solver};
+
+    use super::*;
+    use crate::tests::{Fixture, pkg, pkg_build_run, solve_cpvs};
+
+    /// app-1.0 and suite-1.0 take `|| ( libressl openssl )`; every tool
+    /// version suite pulls in needs openssl.
+    fn any_of_fixture() -> Fixture {
+        Fixture::new([
+            pkg_build_run(
+                "app-misc/app-1.0",
+                "",
+                "|| ( dev-lib/libressl dev-lib/openssl )",
+            ),
+            pkg_build_run(
+                "app-misc/suite-1.0",
+                "",
+                "|| ( dev-lib/libressl dev-lib/openssl ) app-misc/tool",
+            ),
+            pkg_build_run("app-misc/tool-1.0", "", "dev-lib/openssl"),
+            pkg_build_run("app-misc/tool-2.0", "", "dev-lib/openssl"),
+            pkg("dev-lib/libressl-3.8", "0", vec![]),
+            pkg("dev-lib/openssl-3.0", "0", vec![]),
+        ])
+    }
+
+    #[test]
+    fn any_of_prefers_leftmost_alternative() {
+        // Nothing installed or pulled.
+        let cpvs = solve_cpvs(any_of_fixture().provider(), &["app-misc/app"]);
+        assert!(cpvs.contains("dev-lib/libressl-3.8"));
+        assert!(!cpvs.contains("dev-lib/openssl-3.0"));
+    }
+
+    #[test]
+    fn any_of_prefers_installed_alternative() {
+        let fixture = any_of_fixture().favored([pkg("dev-lib/openssl-3.0", "0", vec![])]);
+        let cpvs = solve_cpvs(fixture.provider(), &["app-misc/app"]);
+        assert!(cpvs.contains("dev-lib/openssl-3.0"));
+        assert!(!cpvs.contains("dev-lib/libressl-3.8"));
+    }
+
+    #[test]
+    fn any_of_prefers_alternative_of_root_atom() {
+        let cpvs = solve_cpvs(
+            any_of_fixture().provider(),
+            &["app-misc/app", "dev-lib/openssl"],
+        );
+        assert!(!cpvs.contains("dev-lib/libressl-3.8"));
+    }
+
+    #[test]
+    fn any_of_prefers_alternative_every_pulled_version_needs() {
+        let cpvs = solve_cpvs(any_of_fixture().provider(), &["app-misc/suite"]);
+        assert!(cpvs.contains("dev-lib/openssl-3.0"));
+        assert!(!cpvs.contains("dev-lib/libressl-3.8"));
+    }
+
+    #[test]
+    fn any_of_skips_masked_alternative() {
+        let mut provider = any_of_fixture().provider();
+        let libressl = Cpv::parse("dev-lib/libressl-3.8").unwrap();
+        for sid in provider.find_solvables(Domain::Target, &libressl) {
+            provider.mask(sid, "masked");
+        }
+        let cpvs = solve_cpvs(provider, &["app-misc/app"]);
+        assert!(cpvs.contains("dev-lib/openssl-3.0"));
+        assert!(!cpvs.contains("dev-lib/libressl-3.8"));
+    }
+
+    #[test]
+    fn any_of_all_of_alternative_ranks_as_its_members() {
+        // The all-of alternative is an internal virtual, not a package of
+        // the solution.
+        let fixture = Fixture::new([
+            pkg_build_run(
+                "app-misc/bundle-1.0",
+                "",
+                "|| ( dev-lib/libressl ( dev-lib/openssl dev-lib/zlib ) )",
+            ),
+            pkg("dev-lib/libressl-3.8", "0", vec![]),
+            pkg("dev-lib/openssl-3.0", "0", vec![]),
+            pkg("dev-lib/zlib-1.3", "0", vec![]),
+        ])
+        .favored([
+            pkg("dev-lib/openssl-3.0", "0", vec![]),
+            pkg("dev-lib/zlib-1.3", "0", vec![]),
+        ]);
+        let mut provider = fixture.provider();
+        let req = provider.intern_requirement(&Dep::parse("app-misc/bundle").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let provider = solver.provider();
+        let allof: Vec<SolvableId> = solution
+            .iter()
+            .copied()
+            .filter(|&sid| {
+                let cpn = &provider.package_metadata(sid).cpv.cpn;
+                cpn.package.as_str().starts_with("allof_")
+            })
+            .collect();
+        assert_eq!(allof.len(), 1);
+        assert!(provider.is_synthetic(allof[0]));
+        assert!(solution.iter().all(|&sid| {
+            provider.package_metadata(sid).cpv.to_string() != "dev-lib/libressl-3.8"
+        }));
+        // openssl and zlib are both installed, so the group is reordered;
+        // the reordered union keeps the group's origin.
+        let (&encoded, &preferred) = provider.preferred_unions.borrow().iter().next().unwrap();
+        assert_ne!(encoded, preferred);
+        let origins = provider.requirement_origins(Requirement::Union(preferred));
+        assert_eq!(origins.len(), 1);
+        assert_eq!(
+            origins[0].atom,
+            "|| ( dev-lib/libressl ( dev-lib/openssl dev-lib/zlib ) )"
+        );
+    }
+
+    #[test]
+    fn any_of_encoding_keeps_ebuild_order() {
+        // The installed alternative is preferred without reordering the
+        // encoded dependencies.
+        let fixture = any_of_fixture().favored([
+            pkg("dev-lib/openssl-3.0", "0", vec![]),
+            pkg("dev-lib/zlib-1.3", "0", vec![]),
+        ]);
+        let provider = fixture.provider();
+        let app = provider.find_solvables(Domain::Target, &Cpv::parse("app-misc/app-1.0").unwrap());
+        let deps = provider.dependencies.borrow()[&app[0]].clone();
+        let Requirement::Union(union) = deps.requirements[0].requirement else {
+            panic!("expected an any-of union");
+        };
+        let first = provider.pool().resolve_version_set_union(union)[0];
+        assert_eq!(
+            provider.pool().resolve_version_set(first).cpn.to_string(),
+            "dev-lib/libressl"
+        );
+    }
+}
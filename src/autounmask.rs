@@ -0,0 +1,838 @@
+//! Autounmask: configuration changes that make an unsolvable request
+//! solvable.
+//!
+//! The provider only sees the packages a [`PackageRepository`] returns, so
+//! masked packages are normally left out of it. [`Autounmask`] takes the
+//! whole repository together with a [`Visibility`] that explains why a
+//! package is masked, and re-solves with progressively more relaxed
+//! configuration, like emerge's `--autounmask`:
+//!
+//! 1. USE flags that guard dependencies become solver-decided, so the
+//!    solver may flip them;
+//! 2. packages masked only by keywords become visible;
+//! 3. then packages masked by unaccepted licenses;
+//! 4. then packages masked by `package.mask`.
+//!
+//! The first stage that solves yields an [`AutounmaskReport`]: the
+//! `package.use`, `package.accept_keywords`, `package.license` and
+//! `package.unmask` lines that make the request solvable. Visible
+//! candidates are preferred over relaxed ones, and flipped flags are
+//! reduced until none can be dropped.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use portage_atom::gentoo_interner::{DefaultInterner, Interned};
+use portage_atom::{Cpv, Dep, DepEntry, UseDepKind};
+use resolvo::{
+    ArenaId, ConditionalRequirement, Problem, SolvableId, Solver, UnsolvableOrCancelled,
+};
+
+use crate::builder::ProviderBuilder;
+use crate::error::ResolveError;
+use crate::ordering::{Candidate, CandidateOrdering, NewestFirst, OrderingContext};
+use crate::pool::{Domain, InstalledSet, PackageMetadata, UseConfig};
+use crate::provider::PortageDependencyProvider;
+use crate::repository::PackageRepository;
+
+/// Why a package is not visible.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MaskReason {
+    /// None of its `KEYWORDS` is accepted; accepting this keyword (e.g.
+    /// `~amd64`) would unmask it.
+    Keyword(Interned<DefaultInterner>),
+    /// This license of its `LICENSE` is not accepted.
+    License(Interned<DefaultInterner>),
+    /// A `package.mask` entry matches it.
+    PackageMask,
+}
+
+impl fmt::Display for MaskReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaskReason::Keyword(keyword) => write!(f, "missing keyword {keyword}"),
+            MaskReason::License(license) => write!(f, "license {license}"),
+            MaskReason::PackageMask => write!(f, "package.mask"),
+        }
+    }
+}
+
+impl MaskReason {
+    fn stage(&self) -> Stage {
+        match self {
+            MaskReason::Keyword(_) => Stage::Keywords,
+            MaskReason::License(_) => Stage::Licenses,
+            MaskReason::PackageMask => Stage::Unmask,
+        }
+    }
+}
+
+/// Visibility of repository packages.
+///
+/// [`PackageMetadata`] carries neither `KEYWORDS` nor `LICENSE`; the
+/// implementation answers from whatever the caller has, e.g. the ebuild
+/// metadata cache and the profile's accepted keywords and licenses.
+/// Closures returning the reasons are visibilities too.
+pub trait Visibility {
+    /// Every reason `meta` is masked; empty when it is visible.
+    fn mask_reasons(&self, meta: &PackageMetadata) -> Vec<MaskReason>;
+}
+
+impl<F: Fn(&PackageMetadata) -> Vec<MaskReason>> Visibility for F {
+    fn mask_reasons(&self, meta: &PackageMetadata) -> Vec<MaskReason> {
+        self(meta)
+    }
+}
+
+/// Portage configuration file an autounmask change belongs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConfigFile {
+    PackageUse,
+    PackageAcceptKeywords,
+    PackageLicense,
+    PackageUnmask,
+}
+
+impl ConfigFile {
+    /// Name of the file under `/etc/portage`.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ConfigFile::PackageUse => "package.use",
+            ConfigFile::PackageAcceptKeywords => "package.accept_keywords",
+            ConfigFile::PackageLicense => "package.license",
+            ConfigFile::PackageUnmask => "package.unmask",
+        }
+    }
+}
+
+impl fmt::Display for ConfigFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.file_name())
+    }
+}
+
+/// One suggested configuration line. Displays as the line, e.g.
+/// `=dev-libs/foo-1.0 ~amd64`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ConfigChange {
+    /// Enable or disable `flag` for `cpv`.
+    Use {
+        cpv: Cpv,
+        flag: Interned<DefaultInterner>,
+        enabled: bool,
+    },
+    /// Accept `keyword` for `cpv`.
+    AcceptKeywords {
+        cpv: Cpv,
+        keyword: Interned<DefaultInterner>,
+    },
+    /// Accept `license` for `cpv`.
+    License {
+        cpv: Cpv,
+        license: Interned<DefaultInterner>,
+    },
+    /// Unmask `cpv`.
+    Unmask { cpv: Cpv },
+}
+
+impl ConfigChange {
+    pub fn file(&self) -> ConfigFile {
+        match self {
+            ConfigChange::Use { .. } => ConfigFile::PackageUse,
+            ConfigChange::AcceptKeywords { .. } => ConfigFile::PackageAcceptKeywords,
+            ConfigChange::License { .. } => ConfigFile::PackageLicense,
+            ConfigChange::Unmask { .. } => ConfigFile::PackageUnmask,
+        }
+    }
+
+    pub fn cpv(&self) -> &Cpv {
+        match self {
+            ConfigChange::Use { cpv, .. }
+            | ConfigChange::AcceptKeywords { cpv, .. }
+            | ConfigChange::License { cpv, .. }
+            | ConfigChange::Unmask { cpv } => cpv,
+        }
+    }
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "={}", self.cpv())?;
+        match self {
+            ConfigChange::Use { flag, enabled, .. } => {
+                write!(f, " {}{flag}", if *enabled { "" } else { "-" })
+            }
+            ConfigChange::AcceptKeywords { keyword, .. } => write!(f, " {keyword}"),
+            ConfigChange::License { license, .. } => write!(f, " {license}"),
+            ConfigChange::Unmask { .. } => Ok(()),
+        }
+    }
+}
+
+/// Result of [`Autounmask::suggest`].
+///
+/// Displays as the contents of every file with changes, each preceded by
+/// a `# <file>` comment line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AutounmaskReport {
+    /// Suggested lines, ordered by file, then package, then line.
+    pub changes: Vec<ConfigChange>,
+    /// Packages of the solution the changes allow, in solution order.
+    pub solution: Vec<Cpv>,
+}
+
+impl AutounmaskReport {
+    /// Whether the request solves without changes.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Lines for `file`, one per change, each ending in a newline.
+    pub fn file_contents(&self, file: ConfigFile) -> String {
+        self.changes
+            .iter()
+            .filter(|change| change.file() == file)
+            .map(|change| format!("{change}\n"))
+            .collect()
+    }
+}
+
+impl fmt::Display for AutounmaskReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let files: BTreeSet<ConfigFile> = self.changes.iter().map(ConfigChange::file).collect();
+        for (index, file) in files.into_iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "# {file}")?;
+            f.write_str(&self.file_contents(file))?;
+        }
+        Ok(())
+    }
+}
+
+/// A relaxation stage; see the [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Use,
+    Keywords,
+    Licenses,
+    Unmask,
+}
+
+/// Repository package identity: CPV and repository.
+type PackageKey = (Cpv, Option<Interned<DefaultInterner>>);
+
+fn package_key(meta: &PackageMetadata) -> PackageKey {
+    (meta.cpv.clone(), meta.repo)
+}
+
+/// Autounmask search over a repository.
+///
+/// [`suggest`](Self::suggest) re-solves in stages, each keeping the
+/// relaxations of the earlier ones: flags of `flag? ( )` groups become
+/// solver-decided, then keyword-masked, license-masked and finally
+/// `package.mask`ed packages become visible. Visible candidates are still
+/// tried first, and flipped flags are reduced until none can be dropped.
+///
+/// ```
+/// use portage_atom_resolvo::{Autounmask, InMemoryRepository, MaskReason, PackageMetadata};
+///
+/// let repo = InMemoryRepository::new();
+/// let visibility = |_: &PackageMetadata| Vec::<MaskReason>::new();
+/// let report = Autounmask::new(&repo, &visibility)
+///     .suggest(&[])
+///     .unwrap()
+///     .expect("an empty request solves");
+/// assert!(report.is_empty());
+/// ```
+pub struct Autounmask<'a> {
+    repo: &'a dyn PackageRepository,
+    visibility: &'a dyn Visibility,
+    use_config: Option<&'a UseConfig>,
+    installed: Option<&'a InstalledSet>,
+    stages: Vec<Stage>,
+}
+
+impl<'a> Autounmask<'a> {
+    /// Search over every package of `repo`, masked ones included, with
+    /// every relaxation enabled.
+    pub fn new(repo: &'a dyn PackageRepository, visibility: &'a dyn Visibility) -> Self {
+        Self {
+            repo,
+            visibility,
+            use_config: None,
+            installed: None,
+            stages: vec![Stage::Use, Stage::Keywords, Stage::Licenses, Stage::Unmask],
+        }
+    }
+
+    /// USE configuration of the request.
+    pub fn use_config(mut self, use_config: &'a UseConfig) -> Self {
+        self.use_config = Some(use_config);
+        self
+    }
+
+    /// Installed packages. They are visible whatever `visibility` says.
+    pub fn installed(mut self, installed: &'a InstalledSet) -> Self {
+        self.installed = Some(installed);
+        self
+    }
+
+    /// Whether USE flags may be flipped (`--autounmask-use`).
+    pub fn use_changes(self, allowed: bool) -> Self {
+        self.stage(Stage::Use, allowed)
+    }
+
+    /// Whether keywords may be accepted (`--autounmask-keep-keywords=n`).
+    pub fn keywords(self, allowed: bool) -> Self {
+        self.stage(Stage::Keywords, allowed)
+    }
+
+    /// Whether licenses may be accepted (`--autounmask-license`).
+    pub fn licenses(self, allowed: bool) -> Self {
+        self.stage(Stage::Licenses, allowed)
+    }
+
+    /// Whether `package.mask` entries may be overridden
+    /// (`--autounmask-unmask`).
+    pub fn unmask(self, allowed: bool) -> Self {
+        self.stage(Stage::Unmask, allowed)
+    }
+
+    fn stage(mut self, stage: Stage, allowed: bool) -> Self {
+        self.stages.retain(|&s| s != stage);
+        if allowed {
+            self.stages.push(stage);
+            self.stages.sort_by_key(|&s| s as u8);
+        }
+        self
+    }
+
+    /// Suggest the configuration changes that make `atoms` solvable.
+    ///
+    /// Returns an empty report if the request already solves, and `None`
+    /// if it does not solve even with every enabled relaxation.
+    pub fn suggest(&self, atoms: &[Dep]) -> Result<Option<AutounmaskReport>, ResolveError> {
+        let default_use_config = UseConfig::default();
+        let use_config = self.use_config.unwrap_or(&default_use_config);
+        let masks = self.masks()?;
+        let flippable = if self.stages.contains(&Stage::Use) {
+            self.flippable_flags(use_config)?
+        } else {
+            HashSet::new()
+        };
+
+        let mut allowed: Vec<Stage> = Vec::new();
+        let stages = std::iter::once(None).chain(self.stages.iter().copied().map(Some));
+        for stage in stages {
+            allowed.extend(stage);
+            let mut search = Search {
+                autounmask: self,
+                atoms,
+                masks: &masks,
+                hidden: HashSet::new(),
+                relaxed: HashSet::new(),
+            };
+            for (key, reasons) in &masks {
+                if reasons
+                    .iter()
+                    .all(|reason| allowed.contains(&reason.stage()))
+                {
+                    search.relaxed.insert(key.clone());
+                } else {
+                    search.hidden.insert(key.clone());
+                }
+            }
+
+            if !allowed.contains(&Stage::Use) {
+                let (mut solver, requirements) = search.solver(use_config)?;
+                if let Some(solution) = solve(&mut solver, requirements)? {
+                    return Ok(Some(report(&solver, &solution, &masks, &[])));
+                }
+                continue;
+            }
+            let relaxed_config = UseConfig {
+                enabled: &use_config.enabled - &flippable,
+                disabled: &use_config.disabled - &flippable,
+                solver_decided: &use_config.solver_decided | &flippable,
+            };
+            let (mut solver, requirements) = search.solver(&relaxed_config)?;
+            let Some(solution) = solve(&mut solver, requirements.clone())? else {
+                continue;
+            };
+            let flips = decided_flips(solver.provider(), &solution, use_config, &flippable);
+            let minimizer = Minimizer {
+                requirements,
+                use_config,
+                flippable: &flippable,
+            };
+            // Should the flags the solver chose not solve once fixed, its
+            // solver-decided solution stands.
+            let (flips, solution) = minimizer
+                .minimize(&mut solver, flips.clone())?
+                .unwrap_or((flips, solution));
+            return Ok(Some(report(&solver, &solution, &masks, &flips)));
+        }
+        Ok(None)
+    }
+
+    /// Mask reasons of every masked repository package that is not
+    /// installed.
+    fn masks(&self) -> Result<HashMap<PackageKey, Vec<MaskReason>>, ResolveError> {
+        let installed: HashSet<&Cpv> = self
+            .installed
+            .map(|installed| {
+                installed
+                    .packages
+                    .iter()
+                    .map(|(meta, _)| &meta.cpv)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut masks = HashMap::new();
+        for cpn in self.repo.all_packages()? {
+            for meta in self.repo.versions_for(&cpn)?.iter() {
+                if installed.contains(&meta.cpv) {
+                    continue;
+                }
+                let reasons = self.visibility.mask_reasons(meta);
+                if !reasons.is_empty() {
+                    masks.insert(package_key(meta), reasons);
+                }
+            }
+        }
+        Ok(masks)
+    }
+
+    /// Flags of `flag? ( )` groups that are not already solver-decided.
+    /// Flags used by conditional USE dependencies (`[flag?]`, `[flag=]`)
+    /// are left alone, since those cannot refer to a solver-decided flag.
+    fn flippable_flags(
+        &self,
+        use_config: &UseConfig,
+    ) -> Result<HashSet<Interned<DefaultInterner>>, ResolveError> {
+        let mut conditionals = HashSet::new();
+        let mut use_dep_flags = HashSet::new();
+        for cpn in self.repo.all_packages()? {
+            for meta in self.repo.versions_for(&cpn)?.iter() {
+                for (_, entries) in meta.dependencies.iter_classes() {
+                    collect_flags(entries, &mut conditionals, &mut use_dep_flags);
+                }
+            }
+        }
+        Ok(conditionals
+            .into_iter()
+            .filter(|flag| {
+                !use_dep_flags.contains(flag) && !use_config.solver_decided.contains(flag)
+            })
+            .collect())
+    }
+}
+
+/// Flags of `flag? ( )` groups into `conditionals`, and flags of
+/// conditional USE dependencies into `use_dep_flags`.
+fn collect_flags(
+    entries: &[DepEntry],
+    conditionals: &mut HashSet<Interned<DefaultInterner>>,
+    use_dep_flags: &mut HashSet<Interned<DefaultInterner>>,
+) {
+    for entry in entries {
+        match entry {
+            DepEntry::Atom(dep) => {
+                let conditional = dep.use_deps.iter().flatten().filter(|use_dep| {
+                    !matches!(use_dep.kind, UseDepKind::Enabled | UseDepKind::Disabled)
+                });
+                use_dep_flags.extend(conditional.map(|use_dep| use_dep.flag));
+            }
+            DepEntry::UseConditional { flag, children, .. } => {
+                conditionals.insert(*flag);
+                collect_flags(children, conditionals, use_dep_flags);
+            }
+            DepEntry::AllOf(children)
+            | DepEntry::AnyOf(children)
+            | DepEntry::ExactlyOneOf(children)
+            | DepEntry::AtMostOneOf(children) => {
+                collect_flags(children, conditionals, use_dep_flags)
+            }
+        }
+    }
+}
+
+/// Whether a dependency tree has a `flag? ( )` / `!flag? ( )` group.
+fn references_flag(entries: &[DepEntry], flag: Interned<DefaultInterner>) -> bool {
+    entries.iter().any(|entry| match entry {
+        DepEntry::Atom(_) => false,
+        DepEntry::UseConditional {
+            flag: group_flag,
+            children,
+            ..
+        } => *group_flag == flag || references_flag(children, flag),
+        DepEntry::AllOf(children)
+        | DepEntry::AnyOf(children)
+        | DepEntry::ExactlyOneOf(children)
+        | DepEntry::AtMostOneOf(children) => references_flag(children, flag),
+    })
+}
+
+/// A flag and the state it is flipped to.
+type Flip = (Interned<DefaultInterner>, bool);
+
+/// Flippable flags whose virtual in `solution` disagrees with
+/// `use_config`, sorted by name.
+fn decided_flips(
+    provider: &PortageDependencyProvider,
+    solution: &[SolvableId],
+    use_config: &UseConfig,
+    flippable: &HashSet<Interned<DefaultInterner>>,
+) -> Vec<Flip> {
+    let mut flips: Vec<Flip> = provider
+        .flag_solvables(Domain::Target)
+        .filter(|(flag, _, _)| flippable.contains(flag))
+        .filter_map(|(flag, on, off)| {
+            let enabled = use_config.enabled.contains(&flag);
+            if solution.contains(&on) && !enabled {
+                Some((flag, true))
+            } else if solution.contains(&off) && enabled {
+                Some((flag, false))
+            } else {
+                None
+            }
+        })
+        .collect();
+    flips.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+    flips
+}
+
+/// Build the report for a solved request.
+fn report(
+    solver: &Solver<PortageDependencyProvider>,
+    solution: &[SolvableId],
+    masks: &HashMap<PackageKey, Vec<MaskReason>>,
+    flips: &[Flip],
+) -> AutounmaskReport {
+    let provider = solver.provider();
+    let packages: Vec<&PackageMetadata> = solution
+        .iter()
+        .filter(|&&sid| !provider.is_synthetic(sid))
+        .map(|&sid| provider.package_metadata(sid))
+        .collect();
+
+    let mut changes = Vec::new();
+    for meta in &packages {
+        for reason in masks.get(&package_key(meta)).into_iter().flatten() {
+            let cpv = meta.cpv.clone();
+            changes.push(match reason {
+                MaskReason::Keyword(keyword) => ConfigChange::AcceptKeywords {
+                    cpv,
+                    keyword: *keyword,
+                },
+                MaskReason::License(license) => ConfigChange::License {
+                    cpv,
+                    license: *license,
+                },
+                MaskReason::PackageMask => ConfigChange::Unmask { cpv },
+            });
+        }
+        for &(flag, enabled) in flips {
+            let references = meta
+                .dependencies
+                .iter_classes()
+                .any(|(_, entries)| references_flag(entries, flag));
+            if references {
+                changes.push(ConfigChange::Use {
+                    cpv: meta.cpv.clone(),
+                    flag,
+                    enabled,
+                });
+            }
+        }
+    }
+    let mut seen = HashSet::new();
+    changes.retain(|change| seen.insert(change.clone()));
+    changes.sort_by(|a, b| {
+        (a.file(), a.cpv())
+            .cmp(&(b.file(), b.cpv()))
+            .then_with(|| a.to_string().cmp(&b.to_string()))
+    });
+
+    AutounmaskReport {
+        changes,
+        solution: packages.iter().map(|meta| meta.cpv.clone()).collect(),
+    }
+}
+
+/// One relaxation stage: which packages stay masked and which are
+/// visible only thanks to the stage.
+struct Search<'s, 'a> {
+    autounmask: &'s Autounmask<'a>,
+    atoms: &'s [Dep],
+    masks: &'s HashMap<PackageKey, Vec<MaskReason>>,
+    hidden: HashSet<PackageKey>,
+    relaxed: HashSet<PackageKey>,
+}
+
+impl Search<'_, '_> {
+    /// A solver for the stage under `use_config`, and the requirements of
+    /// the request. resolvo caches the candidates of every name in its
+    /// solver, so each stage, which changes what is visible, needs its own.
+    fn solver(
+        &self,
+        use_config: &UseConfig,
+    ) -> Result<
+        (
+            Solver<PortageDependencyProvider>,
+            Vec<ConditionalRequirement>,
+        ),
+        ResolveError,
+    > {
+        let default_installed = InstalledSet::default();
+        let mut provider = ProviderBuilder::new(self.autounmask.repo)
+            .use_config(use_config)
+            .installed(self.autounmask.installed.unwrap_or(&default_installed))
+            .ordering(VisibleFirst {
+                relaxed: self.relaxed.clone(),
+            })
+            .build()?;
+        // Masked packages stay in the pool, so dependencies on them are
+        // not dropped as dependencies on unknown packages are.
+        for index in 0..provider.pool().solvable_count() {
+            let sid = SolvableId::from_usize(index);
+            if provider.is_synthetic(sid) {
+                continue;
+            }
+            let key = package_key(provider.package_metadata(sid));
+            if self.hidden.contains(&key) {
+                let reasons: Vec<String> =
+                    self.masks[&key].iter().map(ToString::to_string).collect();
+                provider.mask(sid, format!("masked by {}", reasons.join(", ")));
+            }
+        }
+        let requirements = self
+            .atoms
+            .iter()
+            .map(|dep| provider.intern_requirement(dep))
+            .collect();
+        Ok((Solver::new(provider), requirements))
+    }
+}
+
+/// Solve `requirements`; `None` if unsolvable.
+fn solve(
+    solver: &mut Solver<PortageDependencyProvider>,
+    requirements: Vec<ConditionalRequirement>,
+) -> Result<Option<Vec<SolvableId>>, ResolveError> {
+    match solver.solve(Problem::new().requirements(requirements)) {
+        Ok(solution) => Ok(Some(solution)),
+        Err(UnsolvableOrCancelled::Unsolvable(_)) => Ok(None),
+        Err(UnsolvableOrCancelled::Cancelled(value)) => Err(ResolveError::from_cancel_value(value)),
+    }
+}
+
+/// The flips left after [`Minimizer::minimize`] and the solution they
+/// give.
+type Minimized = (Vec<Flip>, Vec<SolvableId>);
+
+/// Flip reduction on the solver of the USE stage. Every flippable flag
+/// stays solver-decided there; an attempt fixes each one to its flipped
+/// or configured state through a root requirement on its flag virtual,
+/// so all attempts share one provider and solver.
+struct Minimizer<'m> {
+    /// Requirements of the request.
+    requirements: Vec<ConditionalRequirement>,
+    use_config: &'m UseConfig,
+    flippable: &'m HashSet<Interned<DefaultInterner>>,
+}
+
+impl Minimizer<'_> {
+    /// Drop every flip the request solves without, one at a time; `None`
+    /// if it does not solve with `flips` fixed.
+    fn minimize(
+        &self,
+        solver: &mut Solver<PortageDependencyProvider>,
+        mut flips: Vec<Flip>,
+    ) -> Result<Option<Minimized>, ResolveError> {
+        let Some(mut solution) = self.solve_with(solver, &flips)? else {
+            return Ok(None);
+        };
+        let mut index = 0;
+        while index < flips.len() {
+            let mut fewer = flips.clone();
+            fewer.remove(index);
+            match self.solve_with(solver, &fewer)? {
+                Some(found) => {
+                    flips = fewer;
+                    solution = found;
+                }
+                None => index += 1,
+            }
+        }
+        Ok(Some((flips, solution)))
+    }
+
+    /// Solve with `flips` applied and every other flippable flag as
+    /// configured.
+    fn solve_with(
+        &self,
+        solver: &mut Solver<PortageDependencyProvider>,
+        flips: &[Flip],
+    ) -> Result<Option<Vec<SolvableId>>, ResolveError> {
+        let mut flags: Vec<_> = self.flippable.iter().copied().collect();
+        flags.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        let mut requirements = self.requirements.clone();
+        for flag in flags {
+            let enabled = flips
+                .iter()
+                .find(|(flipped, _)| *flipped == flag)
+                .map_or(self.use_config.enabled.contains(&flag), |&(_, on)| on);
+            requirements.extend(solver.provider().flag_requirement(flag, enabled));
+        }
+        solve(solver, requirements)
+    }
+}
+
+/// Visible candidates before relaxed ones, then [`NewestFirst`].
+struct VisibleFirst {
+    relaxed: HashSet<PackageKey>,
+}
+
+impl CandidateOrdering for VisibleFirst {
+    fn compare(
+        &self,
+        a: Candidate<'_>,
+        b: Candidate<'_>,
+        context: OrderingContext<'_>,
+    ) -> Ordering {
+        let relaxed = |candidate: Candidate<'_>| {
+            !candidate.installed && self.relaxed.contains(&package_key(candidate.metadata))
+        };
+        relaxed(a)
+            .cmp(&relaxed(b))
+            .then_with(|| NewestFirst.compare(a, b, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{Fixture, pkg, pkg_build_run};
+
+    /// `app-misc/app` needs a keyworded lib whose dependency has a
+    /// license; `app-misc/tool` pulls in a package-masked gdb with USE=debug.
+    fn autounmask_fixture() -> Fixture {
+        Fixture::new([
+            pkg_build_run("app-misc/app-1.0", "", ">=dev-lib/lib-2"),
+            pkg_build_run(
+                "app-misc/tool-1.0",
+                "",
+                "debug? ( dev-util/gdb ) doc? ( app-doc/docs )",
+            ),
+            pkg("app-doc/docs-1.0", "0", vec![]),
+            pkg("dev-util/gdb-1.0", "0", vec![]),
+            pkg("dev-lib/lib-1.0", "0", vec![]),
+            pkg_build_run("dev-lib/lib-2.0", "", "dev-lib/dep"),
+            pkg("dev-lib/lib-2.5", "0", vec![]),
+            pkg("dev-lib/lib-3.0", "0", vec![]),
+            pkg("dev-lib/dep-1.0", "0", vec![]),
+        ])
+    }
+
+    fn visibility(meta: &PackageMetadata) -> Vec<MaskReason> {
+        match meta.cpv.to_string().as_str() {
+            "dev-lib/lib-2.0" => vec![MaskReason::Keyword(Interned::intern("~amd64"))],
+            "dev-lib/lib-2.5" => vec![
+                MaskReason::Keyword(Interned::intern("~amd64")),
+                MaskReason::PackageMask,
+            ],
+            "dev-lib/lib-3.0" | "dev-util/gdb-1.0" => vec![MaskReason::PackageMask],
+            "dev-lib/dep-1.0" => vec![MaskReason::License(Interned::intern("EULA"))],
+            _ => vec![],
+        }
+    }
+
+    fn suggest(autounmask: Autounmask<'_>, atom: &str) -> Option<AutounmaskReport> {
+        autounmask.suggest(&[Dep::parse(atom).unwrap()]).unwrap()
+    }
+
+    #[test]
+    fn autounmask_visible_solution_needs_no_changes() {
+        let fixture = autounmask_fixture();
+        let report = suggest(Autounmask::new(&*fixture.repo, &visibility), "dev-lib/lib").unwrap();
+        assert!(report.is_empty());
+        assert_eq!(report.solution, [Cpv::parse("dev-lib/lib-1.0").unwrap()]);
+    }
+
+    #[test]
+    fn autounmask_accepts_keywords_and_licenses_first() {
+        // Keywords and licenses are accepted before anything is unmasked,
+        // so lib-2.0 wins over the newer package-masked versions.
+        let fixture = autounmask_fixture();
+        let report = suggest(Autounmask::new(&*fixture.repo, &visibility), "app-misc/app").unwrap();
+        assert_eq!(
+            report.to_string(),
+            "# package.accept_keywords\n=dev-lib/lib-2.0 ~amd64\n\n\
+             # package.license\n=dev-lib/dep-1.0 EULA\n"
+        );
+        assert_eq!(
+            report.file_contents(ConfigFile::PackageLicense),
+            "=dev-lib/dep-1.0 EULA\n"
+        );
+        assert!(report.file_contents(ConfigFile::PackageUse).is_empty());
+    }
+
+    #[test]
+    fn autounmask_unmasks_without_license_changes() {
+        // Without license changes lib-3.0 must be unmasked instead, and
+        // without that either nothing solves.
+        let fixture = autounmask_fixture();
+        let autounmask = Autounmask::new(&*fixture.repo, &visibility).licenses(false);
+        let report = suggest(autounmask, "app-misc/app").unwrap();
+        assert_eq!(report.to_string(), "# package.unmask\n=dev-lib/lib-3.0\n");
+        let autounmask = Autounmask::new(&*fixture.repo, &visibility)
+            .licenses(false)
+            .unmask(false);
+        assert!(suggest(autounmask, "app-misc/app").is_none());
+    }
+
+    #[test]
+    fn autounmask_unmasks_when_nothing_else_does() {
+        let fixture = autounmask_fixture();
+        let report = suggest(
+            Autounmask::new(&*fixture.repo, &visibility),
+            ">=dev-lib/lib-3",
+        )
+        .unwrap();
+        assert_eq!(report.to_string(), "# package.unmask\n=dev-lib/lib-3.0\n");
+        let autounmask = Autounmask::new(&*fixture.repo, &visibility).unmask(false);
+        assert!(suggest(autounmask, ">=dev-lib/lib-3").is_none());
+    }
+
+    #[test]
+    fn autounmask_flips_only_the_blocking_flag() {
+        // USE changes come before unmasking; doc stays on.
+        let fixture = autounmask_fixture().use_flags(&["debug", "doc"]);
+        let autounmask =
+            Autounmask::new(&*fixture.repo, &visibility).use_config(&fixture.use_config);
+        let report = suggest(autounmask, "app-misc/tool").unwrap();
+        assert_eq!(
+            report.to_string(),
+            "# package.use\n=app-misc/tool-1.0 -debug\n"
+        );
+        assert!(
+            report
+                .solution
+                .contains(&Cpv::parse("app-doc/docs-1.0").unwrap())
+        );
+    }
+
+    #[test]
+    fn autounmask_unmasks_without_use_changes() {
+        let fixture = autounmask_fixture().use_flags(&["debug", "doc"]);
+        let autounmask = Autounmask::new(&*fixture.repo, &visibility)
+            .use_config(&fixture.use_config)
+            .use_changes(false);
+        let report = suggest(autounmask, "app-misc/tool").unwrap();
+        assert_eq!(report.to_string(), "# package.unmask\n=dev-util/gdb-1.0\n");
+    }
+}
@@ -0,0 +1,895 @@
+//! `portage-resolve`: resolve root atoms against a repository and print the
+//! merge plan, the conflict or explanations.
+//!
+//! Built with the `cli` feature; run `portage-resolve --help` for usage.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use portage_atom::{Dep, Operator, SlotDep};
+use portage_atom_resolvo::{
+    CacheError, CacheKey, DepClassPolicy, DependencyCycle, InMemoryRepository, InclusionChain,
+    InstalledSet, PackageMetadata, PortageDependencyProvider, RequirementOrigin, ResolveError,
+    UseConfig, VersionObstacle, repository_fingerprint, version_matches,
+};
+use resolvo::{ArenaId, Problem, SolvableId, Solver, UnsolvableOrCancelled};
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+
+const USAGE: &str = "\
+Usage: portage-resolve [OPTIONS] <ATOM|@SET>...
+
+Resolve root atoms and package sets and print the merge plan.
+
+Inputs:
+  --repo PATH        repository fixture: JSON array of package metadata
+  --cache PATH       provider cache; with --repo it is reused while current
+                     and rewritten otherwise, without --repo it is trusted
+  --installed PATH   installed set (JSON); requires --repo
+  --use PATH         USE configuration (JSON)
+  --sets PATH        package sets: JSON object of set name -> atom list
+
+Output:
+  --format FORMAT    human (default), json or dot
+  --explain ATOM     explain why packages matching ATOM are or are not in
+                     the plan (repeatable; not available with dot)
+  -h, --help         print this help
+
+The @installed set lists every installed package in its slot.
+
+Exit status: 0 resolved, 1 unsolvable (conflict or unbreakable dependency
+cycle), 2 usage error, 3 unreadable or invalid input.";
+
+/// Exit status for a conflict or an unbreakable dependency cycle.
+const EXIT_UNSOLVABLE: u8 = 1;
+/// Exit status for bad command-line arguments.
+const EXIT_USAGE: u8 = 2;
+/// Exit status for input files that cannot be read or are invalid.
+const EXIT_INPUT: u8 = 3;
+/// Most inclusion chains printed per explained package.
+const MAX_CHAINS: usize = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Format {
+    #[default]
+    Human,
+    Json,
+    Dot,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    repo: Option<PathBuf>,
+    cache: Option<PathBuf>,
+    installed: Option<PathBuf>,
+    use_config: Option<PathBuf>,
+    sets: Option<PathBuf>,
+    format: Format,
+    explain: Vec<String>,
+    targets: Vec<String>,
+    help: bool,
+}
+
+/// Why a run failed before producing a result.
+#[derive(Debug)]
+enum Error {
+    Usage(String),
+    Input(String),
+}
+
+impl Error {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Error::Usage(_) => EXIT_USAGE,
+            Error::Input(_) => EXIT_INPUT,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(message) => write!(f, "{message}\n\n{USAGE}"),
+            Error::Input(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Input(format!("writing output: {err}"))
+    }
+}
+
+/// Result of a run that got as far as solving.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Resolved,
+    Unsolvable,
+}
+
+fn main() -> ExitCode {
+    let result = parse_args(std::env::args().skip(1)).and_then(|options| {
+        if options.help {
+            println!("{USAGE}");
+            return Ok(Outcome::Resolved);
+        }
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        let outcome = run(&options, &mut out)?;
+        out.flush()?;
+        Ok(outcome)
+    });
+    match result {
+        Ok(Outcome::Resolved) => ExitCode::SUCCESS,
+        Ok(Outcome::Unsolvable) => ExitCode::from(EXIT_UNSOLVABLE),
+        Err(err) => {
+            eprintln!("portage-resolve: {err}");
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, Error> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            options.help = true;
+            continue;
+        }
+        let Some(flag) = arg.strip_prefix("--") else {
+            options.targets.push(arg);
+            continue;
+        };
+        let (name, inline) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (flag, None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| Error::Usage(format!("--{name} needs a value")))
+        };
+        match name {
+            "repo" => options.repo = Some(value()?.into()),
+            "cache" => options.cache = Some(value()?.into()),
+            "installed" => options.installed = Some(value()?.into()),
+            "use" => options.use_config = Some(value()?.into()),
+            "sets" => options.sets = Some(value()?.into()),
+            "explain" => options.explain.push(value()?),
+            "format" => {
+                options.format = match value()?.as_str() {
+                    "human" => Format::Human,
+                    "json" => Format::Json,
+                    "dot" => Format::Dot,
+                    other => return Err(Error::Usage(format!("unknown format `{other}`"))),
+                }
+            }
+            _ => return Err(Error::Usage(format!("unknown option `{arg}`"))),
+        }
+    }
+    if options.help {
+        return Ok(options);
+    }
+    if options.repo.is_none() && options.cache.is_none() {
+        return Err(Error::Usage("either --repo or --cache is required".into()));
+    }
+    if options.repo.is_none() && options.installed.is_some() {
+        return Err(Error::Usage(
+            "--installed requires --repo; a cache already holds its installed set".into(),
+        ));
+    }
+    if options.targets.is_empty() {
+        return Err(Error::Usage("no atoms or sets to resolve".into()));
+    }
+    if options.format == Format::Dot && !options.explain.is_empty() {
+        return Err(Error::Usage(
+            "--explain is not available with --format dot".into(),
+        ));
+    }
+    Ok(options)
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| Error::Input(format!("reading {}: {err}", path.display())))?;
+    serde_json::from_str(&text)
+        .map_err(|err| Error::Input(format!("parsing {}: {err}", path.display())))
+}
+
+fn parse_atom(text: &str) -> Result<Dep, Error> {
+    Dep::parse(text).map_err(|err| Error::Usage(format!("invalid atom `{text}`: {err}")))
+}
+
+/// Build the provider from `--repo` (reusing `--cache` when current) or
+/// load it from `--cache` alone.
+fn load_provider(options: &Options) -> Result<PortageDependencyProvider, Error> {
+    let use_config: Option<UseConfig> = options.use_config.as_deref().map(read_json).transpose()?;
+    let policy = DepClassPolicy::default();
+
+    let Some(repo_path) = &options.repo else {
+        let cache_path = options.cache.as_deref().expect("checked by parse_args");
+        let bytes = fs::read(cache_path)
+            .map_err(|err| Error::Input(format!("reading {}: {err}", cache_path.display())))?;
+        let cache_error =
+            |err: CacheError| Error::Input(format!("{}: {err}", cache_path.display()));
+        let stored = CacheKey::read(bytes.as_slice()).map_err(cache_error)?;
+        let key = match &use_config {
+            Some(use_config) => CacheKey::new(stored.repository, use_config, None, &policy),
+            None => stored,
+        };
+        return PortageDependencyProvider::read_cache(bytes.as_slice(), &key).map_err(cache_error);
+    };
+
+    let packages: Vec<PackageMetadata> = read_json(repo_path)?;
+    let installed: InstalledSet = options
+        .installed
+        .as_deref()
+        .map(read_json)
+        .transpose()?
+        .unwrap_or_default();
+    let use_config = use_config.unwrap_or_default();
+    let mut repo = InMemoryRepository::new();
+    for meta in packages {
+        repo.add(meta);
+    }
+    let invalid = |err: ResolveError| Error::Input(format!("{}: {err}", repo_path.display()));
+
+    let Some(cache_path) = &options.cache else {
+        return PortageDependencyProvider::try_with_installed(&repo, &use_config, &installed)
+            .map_err(invalid);
+    };
+    let fingerprint =
+        repository_fingerprint(&repo, &installed).map_err(|err| invalid(err.into()))?;
+    let key = CacheKey::new(fingerprint, &use_config, None, &policy);
+    if let Ok(bytes) = fs::read(cache_path) {
+        // A stale, corrupt or outdated cache is simply rebuilt.
+        if let Ok(provider) = PortageDependencyProvider::read_cache(bytes.as_slice(), &key) {
+            return Ok(provider);
+        }
+    }
+    let provider = PortageDependencyProvider::try_with_installed(&repo, &use_config, &installed)
+        .map_err(invalid)?;
+    let write_error =
+        |err: &dyn fmt::Display| Error::Input(format!("writing {}: {err}", cache_path.display()));
+    let file = File::create(cache_path).map_err(|err| write_error(&err))?;
+    provider
+        .write_cache(BufWriter::new(file), fingerprint)
+        .map_err(|err| write_error(&err))?;
+    Ok(provider)
+}
+
+/// Expand `@set` targets and parse every root atom.
+fn root_atoms(options: &Options, provider: &PortageDependencyProvider) -> Result<Vec<Dep>, Error> {
+    let mut sets: HashMap<String, Vec<String>> = match &options.sets {
+        Some(path) => read_json(path)?,
+        None => HashMap::new(),
+    };
+    if !sets.contains_key("installed") {
+        let installed = all_solvables(provider)
+            .filter(|&sid| provider.is_installed(sid))
+            .map(|sid| {
+                let meta = provider.package_metadata(sid);
+                match meta.slot {
+                    Some(slot) => format!("{}:{slot}", meta.cpv.cpn),
+                    None => meta.cpv.cpn.to_string(),
+                }
+            })
+            .collect();
+        sets.insert("installed".to_string(), installed);
+    }
+
+    let mut atoms = Vec::new();
+    let mut seen = HashSet::new();
+    for target in &options.targets {
+        let texts = match target.strip_prefix('@') {
+            Some(name) => sets
+                .get(name)
+                .ok_or_else(|| Error::Usage(format!("unknown package set `{target}`")))?
+                .clone(),
+            None => vec![target.clone()],
+        };
+        for text in texts {
+            if seen.insert(text.clone()) {
+                atoms.push(parse_atom(&text)?);
+            }
+        }
+    }
+    Ok(atoms)
+}
+
+/// Every real (non-synthetic) solvable of the provider.
+fn all_solvables(provider: &PortageDependencyProvider) -> impl Iterator<Item = SolvableId> + '_ {
+    (0..provider.pool().solvable_count())
+        .map(SolvableId::from_usize)
+        .filter(|&sid| !provider.is_synthetic(sid))
+}
+
+/// Whether `dep` names `sid`: same package, matching version and slot.
+fn atom_matches(dep: &Dep, provider: &PortageDependencyProvider, sid: SolvableId) -> bool {
+    let meta = provider.package_metadata(sid);
+    if dep.cpn != meta.cpv.cpn {
+        return false;
+    }
+    if let Some(version) = &dep.version {
+        let op = dep.op.unwrap_or(Operator::Equal);
+        if !version_matches(&meta.cpv.version, &op, dep.glob, version) {
+            return false;
+        }
+    }
+    match &dep.slot_dep {
+        Some(SlotDep::Slot {
+            slot: Some(slot), ..
+        }) => meta.slot == Some(slot.slot),
+        _ => true,
+    }
+}
+
+fn run(options: &Options, out: &mut impl Write) -> Result<Outcome, Error> {
+    let mut provider = load_provider(options)?;
+    let atoms = root_atoms(options, &provider)?;
+    let explain = options
+        .explain
+        .iter()
+        .map(|text| parse_atom(text))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let requirements = atoms
+        .iter()
+        .map(|dep| provider.intern_requirement(dep))
+        .collect();
+    let mut solver = Solver::new(provider);
+    let solution = match solver.solve(Problem::new().requirements(requirements)) {
+        Ok(solution) => solution,
+        Err(UnsolvableOrCancelled::Unsolvable(conflict)) => {
+            match options.format {
+                Format::Human => {
+                    writeln!(out, "Unable to resolve:")?;
+                    writeln!(out, "{}", conflict.display_user_friendly(&solver))?;
+                }
+                Format::Json => {
+                    let report = json!({
+                        "status": "unsolvable",
+                        "conflict": conflict.display_user_friendly(&solver).to_string(),
+                    });
+                    writeln!(out, "{report:#}")?;
+                }
+                Format::Dot => {
+                    let graph = conflict.graph(&solver);
+                    write!(out, "{}", solver.provider().conflict_dot(&graph))?;
+                }
+            }
+            return Ok(Outcome::Unsolvable);
+        }
+        Err(UnsolvableOrCancelled::Cancelled(value)) => {
+            return Err(match value.downcast::<ResolveError>() {
+                Ok(err) => Error::Input(err.to_string()),
+                Err(_) => Error::Input("resolution cancelled".into()),
+            });
+        }
+    };
+    let provider = solver.provider();
+    let order = provider.install_order(&solution);
+    let plan: Vec<SolvableId> = match &order {
+        Ok(order) => order
+            .iter()
+            .copied()
+            .filter(|&sid| !provider.is_synthetic(sid))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    match options.format {
+        Format::Human => write_human(out, provider, &solution, &order, &plan, &explain)?,
+        Format::Json => write_json(out, provider, &solution, &order, &plan, &explain)?,
+        Format::Dot => write!(out, "{}", provider.solution_dot(&solution))?,
+    }
+    Ok(match order {
+        Ok(_) => Outcome::Resolved,
+        Err(_) => Outcome::Unsolvable,
+    })
+}
+
+/// `cat/pkg-ver:slot::repo`.
+fn package_label(provider: &PortageDependencyProvider, sid: SolvableId) -> String {
+    let meta = provider.package_metadata(sid);
+    let mut label = meta.cpv.to_string();
+    if let Some(slot) = meta.slot {
+        label.push_str(&format!(":{slot}"));
+    }
+    if let Some(repo) = meta.repo {
+        label.push_str(&format!("::{repo}"));
+    }
+    label
+}
+
+/// The package's IUSE in sorted order, disabled flags prefixed with `-`.
+fn use_flags(provider: &PortageDependencyProvider, sid: SolvableId) -> Vec<String> {
+    let meta = provider.package_metadata(sid);
+    let mut flags: Vec<String> = meta
+        .iuse
+        .iter()
+        .map(|flag| {
+            let name = flag.strip_prefix(['+', '-']).unwrap_or(flag);
+            if meta
+                .use_flags
+                .iter()
+                .any(|enabled| enabled.as_str() == name)
+            {
+                name.to_string()
+            } else {
+                format!("-{name}")
+            }
+        })
+        .collect();
+    flags.sort_by(|a, b| a.trim_start_matches('-').cmp(b.trim_start_matches('-')));
+    flags
+}
+
+fn cycle_labels(
+    provider: &PortageDependencyProvider,
+    order: &Result<Vec<SolvableId>, Vec<DependencyCycle>>,
+) -> Vec<Vec<String>> {
+    match order {
+        Ok(_) => Vec::new(),
+        Err(cycles) => cycles
+            .iter()
+            .map(|cycle| {
+                cycle
+                    .members
+                    .iter()
+                    .map(|&sid| package_label(provider, sid))
+                    .collect()
+            })
+            .collect(),
+    }
+}
+
+/// Solvables named by an `--explain` atom.
+fn explained(provider: &PortageDependencyProvider, dep: &Dep) -> impl Iterator<Item = SolvableId> {
+    all_solvables(provider).filter(move |&sid| atom_matches(dep, provider, sid))
+}
+
+fn chain_lines(provider: &PortageDependencyProvider, chain: &InclusionChain) -> Vec<String> {
+    let mut lines = vec![format!(
+        "root `{}` selects {}",
+        chain.root,
+        package_label(provider, chain.root_solvable)
+    )];
+    for step in &chain.steps {
+        let mut atom = step.use_conditions.join(" ");
+        if !atom.is_empty() {
+            atom.push(' ');
+        }
+        atom.push_str(&step.atom);
+        lines.push(format!(
+            "{} {} `{atom}` pulls in {}",
+            package_label(provider, step.from),
+            step.class,
+            package_label(provider, step.to)
+        ));
+    }
+    lines
+}
+
+fn labels(provider: &PortageDependencyProvider, solvables: &[SolvableId]) -> String {
+    solvables
+        .iter()
+        .map(|&sid| package_label(provider, sid))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn obstacle_text(provider: &PortageDependencyProvider, obstacle: &VersionObstacle) -> String {
+    let origins = |origins: &[RequirementOrigin]| {
+        if origins.is_empty() {
+            return "an internal choice".to_string();
+        }
+        origins
+            .iter()
+            .map(|origin| {
+                format!(
+                    "{} {} `{}`",
+                    package_label(provider, origin.solvable),
+                    origin.class,
+                    origin.atom
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match obstacle {
+        VersionObstacle::Root { atom } => format!("root atom `{atom}` does not accept it"),
+        VersionObstacle::Requirement { origins: from } => {
+            format!("required in another version by {}", origins(from))
+        }
+        VersionObstacle::UseDep {
+            origins: from,
+            flags,
+        } => {
+            let flags: Vec<String> = flags
+                .iter()
+                .map(|(flag, on)| {
+                    if *on {
+                        flag.to_string()
+                    } else {
+                        format!("-{flag}")
+                    }
+                })
+                .collect();
+            format!("needs USE=\"{}\" for {}", flags.join(" "), origins(from))
+        }
+        VersionObstacle::Blocker {
+            origins: from,
+            blocker,
+        } => format!("blocked ({blocker:?}) by {}", origins(from)),
+        VersionObstacle::Constraint { origins: from } => {
+            format!("constrained by {}", origins(from))
+        }
+        VersionObstacle::Locked { installed } => {
+            format!(
+                "installed {} is locked",
+                package_label(provider, *installed)
+            )
+        }
+        VersionObstacle::Masked { reason } => reason.clone(),
+        VersionObstacle::Downgrade { installed } => {
+            format!(
+                "downgrade from installed {} is forbidden",
+                package_label(provider, *installed)
+            )
+        }
+        VersionObstacle::Dependency {
+            origins: from,
+            selected,
+        } => format!(
+            "{} would displace {}",
+            origins(from),
+            labels(provider, selected)
+        ),
+        VersionObstacle::Blocks {
+            origins: from,
+            selected,
+        } => format!("{} rejects {}", origins(from), labels(provider, selected)),
+    }
+}
+
+fn write_human(
+    out: &mut impl Write,
+    provider: &PortageDependencyProvider,
+    solution: &[SolvableId],
+    order: &Result<Vec<SolvableId>, Vec<DependencyCycle>>,
+    plan: &[SolvableId],
+    explain: &[Dep],
+) -> io::Result<()> {
+    if order.is_ok() {
+        writeln!(out, "Merge plan ({} packages):", plan.len())?;
+        for &sid in plan {
+            let marker = if provider.is_installed(sid) { "I" } else { "N" };
+            write!(out, "[{marker}] {}", package_label(provider, sid))?;
+            let flags = use_flags(provider, sid);
+            if !flags.is_empty() {
+                write!(out, " USE=\"{}\"", flags.join(" "))?;
+            }
+            writeln!(out)?;
+        }
+    } else {
+        writeln!(out, "Unbreakable dependency cycles:")?;
+        for cycle in cycle_labels(provider, order) {
+            writeln!(out, "  {}", cycle.join(" <-> "))?;
+        }
+    }
+
+    for dep in explain {
+        writeln!(out)?;
+        let mut any = false;
+        for sid in explained(provider, dep) {
+            any = true;
+            let label = package_label(provider, sid);
+            if solution.contains(&sid) {
+                writeln!(out, "{label} is selected:")?;
+                for chain in provider.explain_inclusion(solution, sid, MAX_CHAINS) {
+                    for (depth, line) in chain_lines(provider, &chain).iter().enumerate() {
+                        writeln!(out, "  {:indent$}{line}", "", indent = depth * 2)?;
+                    }
+                }
+            } else {
+                writeln!(out, "{label} is not selected:")?;
+                let obstacles = provider.explain_exclusion(solution, sid);
+                if obstacles.is_empty() {
+                    writeln!(out, "  nothing requires it")?;
+                }
+                for obstacle in &obstacles {
+                    writeln!(out, "  {}", obstacle_text(provider, obstacle))?;
+                }
+            }
+        }
+        if !any {
+            writeln!(out, "`{dep}` matches no known package")?;
+        }
+    }
+    Ok(())
+}
+
+fn write_json(
+    out: &mut impl Write,
+    provider: &PortageDependencyProvider,
+    solution: &[SolvableId],
+    order: &Result<Vec<SolvableId>, Vec<DependencyCycle>>,
+    plan: &[SolvableId],
+    explain: &[Dep],
+) -> io::Result<()> {
+    let plan: Vec<Value> = plan
+        .iter()
+        .map(|&sid| {
+            let meta = provider.package_metadata(sid);
+            json!({
+                "cpv": meta.cpv.to_string(),
+                "slot": meta.slot.map(|slot| slot.to_string()),
+                "repo": meta.repo.map(|repo| repo.to_string()),
+                "installed": provider.is_installed(sid),
+                "use": use_flags(provider, sid),
+            })
+        })
+        .collect();
+    let explanations: Vec<Value> = explain
+        .iter()
+        .flat_map(|dep| {
+            explained(provider, dep).map(move |sid| {
+                let selected = solution.contains(&sid);
+                let chains: Vec<Value> = if selected {
+                    provider
+                        .explain_inclusion(solution, sid, MAX_CHAINS)
+                        .iter()
+                        .map(|chain| {
+                            let steps: Vec<Value> = chain
+                                .steps
+                                .iter()
+                                .map(|step| {
+                                    json!({
+                                        "from": package_label(provider, step.from),
+                                        "to": package_label(provider, step.to),
+                                        "class": step.class.to_string(),
+                                        "atom": step.atom,
+                                        "use_conditions": step.use_conditions,
+                                    })
+                                })
+                                .collect();
+                            json!({
+                                "root": chain.root,
+                                "selects": package_label(provider, chain.root_solvable),
+                                "steps": steps,
+                            })
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                let obstacles: Vec<String> = provider
+                    .explain_exclusion(solution, sid)
+                    .iter()
+                    .map(|obstacle| obstacle_text(provider, obstacle))
+                    .collect();
+                json!({
+                    "atom": dep.to_string(),
+                    "package": package_label(provider, sid),
+                    "selected": selected,
+                    "chains": chains,
+                    "obstacles": obstacles,
+                })
+            })
+        })
+        .collect();
+    let report = match order {
+        Ok(_) => json!({
+            "status": "resolved",
+            "plan": plan,
+            "explanations": explanations,
+        }),
+        Err(_) => json!({
+            "status": "cycle",
+            "cycles": cycle_labels(provider, order),
+            "explanations": explanations,
+        }),
+    };
+    writeln!(out, "{report:#}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Result<Options, Error> {
+        parse_args(list.iter().map(ToString::to_string))
+    }
+
+    /// Scratch directory unique to one test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("portage-resolve-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn run_to_string(options: &Options) -> (Result<Outcome, Error>, String) {
+        let mut out = Vec::new();
+        let result = run(options, &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    const REPO: &str = r#"[
+        {"cpv": "app-misc/app-1.0", "slot": "0",
+         "dependencies": {"rdepend": ">=dev-libs/lib-2"}},
+        {"cpv": "app-misc/old-1.0", "slot": "0",
+         "dependencies": {"rdepend": "<dev-libs/lib-2"}},
+        {"cpv": "dev-libs/lib-1.0", "slot": "0"},
+        {"cpv": "dev-libs/lib-2.0", "slot": "0",
+         "iuse": ["ssl"], "use_flags": ["ssl"]}
+    ]"#;
+
+    #[test]
+    fn parse_arguments() {
+        let options = args(&[
+            "--repo",
+            "repo.json",
+            "--format=json",
+            "--explain",
+            "dev-libs/lib",
+            "app-misc/app",
+            "@world",
+        ])
+        .unwrap();
+        assert_eq!(options.repo, Some(PathBuf::from("repo.json")));
+        assert_eq!(options.format, Format::Json);
+        assert_eq!(options.explain, ["dev-libs/lib"]);
+        assert_eq!(options.targets, ["app-misc/app", "@world"]);
+
+        assert!(args(&["--help"]).unwrap().help);
+        for bad in [
+            &["app-misc/app"][..],
+            &["--repo", "r.json"],
+            &["--repo", "r.json", "--format", "xml", "a/b"],
+            &["--repo", "r.json", "--bogus", "a/b"],
+            &["--cache", "c.bin", "--installed", "i.json", "a/b"],
+            &[
+                "--repo",
+                "r.json",
+                "--format",
+                "dot",
+                "--explain",
+                "a/b",
+                "a/b",
+            ],
+            &["--repo"],
+        ] {
+            let err = args(bad).unwrap_err();
+            assert_eq!(err.exit_code(), EXIT_USAGE, "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn resolve_explain_and_fail() {
+        let dir = scratch("resolve");
+        let repo = dir.join("repo.json");
+        fs::write(&repo, REPO).unwrap();
+        let sets = dir.join("sets.json");
+        fs::write(&sets, r#"{"world": ["app-misc/app"]}"#).unwrap();
+
+        let options = args(&[
+            "--repo",
+            repo.to_str().unwrap(),
+            "--sets",
+            sets.to_str().unwrap(),
+            "--explain",
+            "dev-libs/lib",
+            "@world",
+        ])
+        .unwrap();
+        let (result, out) = run_to_string(&options);
+        assert_eq!(result.unwrap(), Outcome::Resolved);
+        let lib = out.find("[N] dev-libs/lib-2.0:0 USE=\"ssl\"").unwrap();
+        let app = out.find("[N] app-misc/app-1.0:0").unwrap();
+        assert!(lib < app, "{out}");
+        assert!(out.contains("dev-libs/lib-2.0:0 is selected:"), "{out}");
+        assert!(out.contains("root `app-misc/app` selects app-misc/app-1.0:0"));
+        assert!(out.contains("dev-libs/lib-1.0:0 is not selected:"), "{out}");
+
+        let json_options = Options {
+            format: Format::Json,
+            ..options
+        };
+        let (result, out) = run_to_string(&json_options);
+        assert_eq!(result.unwrap(), Outcome::Resolved);
+        let report: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(report["status"], "resolved");
+        assert_eq!(report["plan"][1]["cpv"], "app-misc/app-1.0");
+        assert_eq!(report["explanations"].as_array().unwrap().len(), 2);
+
+        // app needs lib >= 2, old needs lib < 2: only one lib slot.
+        let options = args(&[
+            "--repo",
+            repo.to_str().unwrap(),
+            "app-misc/app",
+            "app-misc/old",
+        ])
+        .unwrap();
+        let (result, out) = run_to_string(&options);
+        assert_eq!(result.unwrap(), Outcome::Unsolvable);
+        assert!(out.starts_with("Unable to resolve:"), "{out}");
+
+        let options = args(&["--repo", repo.to_str().unwrap(), "@world"]).unwrap();
+        let (result, _) = run_to_string(&options);
+        assert_eq!(result.unwrap_err().exit_code(), EXIT_USAGE);
+
+        fs::write(&repo, "[{\"cpv\": 1}]").unwrap();
+        let options = args(&["--repo", repo.to_str().unwrap(), "app-misc/app"]).unwrap();
+        let (result, _) = run_to_string(&options);
+        assert_eq!(result.unwrap_err().exit_code(), EXIT_INPUT);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cache_and_installed() {
+        let dir = scratch("cache");
+        let repo = dir.join("repo.json");
+        fs::write(&repo, REPO).unwrap();
+        let installed = dir.join("installed.json");
+        fs::write(
+            &installed,
+            r#"{"packages": [[{"cpv": "dev-libs/lib-1.0", "slot": "0"}, "Favored"]]}"#,
+        )
+        .unwrap();
+        let cache = dir.join("provider.cache");
+
+        let options = args(&[
+            "--repo",
+            repo.to_str().unwrap(),
+            "--installed",
+            installed.to_str().unwrap(),
+            "--cache",
+            cache.to_str().unwrap(),
+            "--format",
+            "dot",
+            "@installed",
+        ])
+        .unwrap();
+        let (result, built) = run_to_string(&options);
+        assert_eq!(result.unwrap(), Outcome::Resolved);
+        assert!(built.starts_with("digraph solution {"), "{built}");
+        assert!(
+            built.contains("[label=\"dev-libs/lib-1.0:0\", peripheries=2]"),
+            "{built}"
+        );
+        assert!(cache.exists());
+
+        // The cache alone reproduces the provider, installed set included.
+        let options = args(&[
+            "--cache",
+            cache.to_str().unwrap(),
+            "--format",
+            "dot",
+            "@installed",
+        ])
+        .unwrap();
+        let (result, cached) = run_to_string(&options);
+        assert_eq!(result.unwrap(), Outcome::Resolved);
+        assert_eq!(cached, built);
+
+        // A different USE configuration does not match the cache.
+        let use_config = dir.join("use.json");
+        fs::write(&use_config, r#"{"enabled": ["ssl"]}"#).unwrap();
+        let options = Options {
+            use_config: Some(use_config),
+            ..options
+        };
+        let (result, _) = run_to_string(&options);
+        assert_eq!(result.unwrap_err().exit_code(), EXIT_INPUT);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+//! Cancellation and solve budgets.
+//!
+//! resolvo polls
+//! [`should_cancel_with_value`](resolvo::DependencyProvider::should_cancel_with_value)
+//! before every propagation round and before asking the provider for
+//! candidates or dependencies. The provider uses those checkpoints to honour
+//! a [`CancellationToken`] and a [`SolveBudget`], and to count
+//! [`SolveStats`].
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Shared flag that cancels a running solve.
+///
+/// Clones share the flag, so a clone handed to another thread (a signal
+/// handler, a UI) can stop a solve that owns the provider. The solver
+/// notices at its next checkpoint.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation. Cannot be undone.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Limits on a single solve. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SolveBudget {
+    /// Wall-clock time, measured from the solver's first checkpoint.
+    pub time_limit: Option<Duration>,
+    /// Number of solver decisions, see [`SolveStats::decisions`].
+    pub max_decisions: Option<u64>,
+}
+
+/// Why a solve was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    /// The [`CancellationToken`] was cancelled.
+    Cancelled,
+    /// [`SolveBudget::time_limit`] was reached.
+    TimeLimit,
+    /// [`SolveBudget::max_decisions`] was reached.
+    DecisionLimit,
+}
+
+impl fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CancelReason::Cancelled => write!(f, "cancelled"),
+            CancelReason::TimeLimit => write!(f, "time limit reached"),
+            CancelReason::DecisionLimit => write!(f, "decision limit reached"),
+        }
+    }
+}
+
+/// Progress of the current solve, as seen from the solver's checkpoints.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SolveStats {
+    /// Time from the first checkpoint to the latest one.
+    pub elapsed: Duration,
+    /// Propagation rounds, one per solver decision (including the
+    /// initial propagation of the root requirements).
+    pub decisions: u64,
+    /// Package names whose candidates the solver asked for.
+    pub candidates_requested: u64,
+    /// Solvables whose dependencies the solver asked for.
+    pub dependencies_requested: u64,
+}
+
+/// Checkpoint bookkeeping of a provider.
+#[derive(Debug, Default)]
+pub(crate) struct SolveMonitor {
+    pub(crate) token: Option<CancellationToken>,
+    pub(crate) budget: SolveBudget,
+    started: Cell<Option<Instant>>,
+    elapsed: Cell<Duration>,
+    /// Every checkpoint passed; candidate and dependency requests are
+    /// each preceded by one.
+    checkpoints: Cell<u64>,
+    candidates: Cell<u64>,
+    dependencies: Cell<u64>,
+}
+
+impl SolveMonitor {
+    pub(crate) fn stats(&self) -> SolveStats {
+        SolveStats {
+            elapsed: self.elapsed.get(),
+            decisions: self
+                .checkpoints
+                .get()
+                .saturating_sub(self.candidates.get() + self.dependencies.get()),
+            candidates_requested: self.candidates.get(),
+            dependencies_requested: self.dependencies.get(),
+        }
+    }
+
+    /// Pass a checkpoint, or report why the solve must stop. The clock
+    /// starts at the first checkpoint after construction or
+    /// [`reset`](Self::reset).
+    pub(crate) fn checkpoint(&self) -> Option<CancelReason> {
+        let now = Instant::now();
+        let started = self.started.get().unwrap_or(now);
+        self.started.set(Some(started));
+        self.elapsed.set(now - started);
+
+        if self
+            .token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Some(CancelReason::Cancelled);
+        }
+        if self
+            .budget
+            .time_limit
+            .is_some_and(|limit| self.elapsed.get() >= limit)
+        {
+            return Some(CancelReason::TimeLimit);
+        }
+        if self
+            .budget
+            .max_decisions
+            .is_some_and(|limit| self.stats().decisions >= limit)
+        {
+            return Some(CancelReason::DecisionLimit);
+        }
+        self.checkpoints.set(self.checkpoints.get() + 1);
+        None
+    }
+
+    pub(crate) fn candidates_requested(&self) {
+        self.candidates.set(self.candidates.get() + 1);
+    }
+
+    pub(crate) fn dependencies_requested(&self) {
+        self.dependencies.set(self.dependencies.get() + 1);
+    }
+
+    /// Zero the counters and restart the clock at the next checkpoint.
+    pub(crate) fn reset(&self) {
+        self.started.set(None);
+        self.elapsed.set(Duration::ZERO);
+        self.checkpoints.set(0);
+        self.candidates.set(0);
+        self.dependencies.set(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use portage_atom::{Dep, DepEntry};
+    use resolvo::{ConditionalRequirement, Problem, SolvableId, Solver, UnsolvableOrCancelled};
+
+    use super::*;
+    use crate::error::ResolveError;
+    use crate::provider::PortageDependencyProvider;
+    use crate::tests::{Fixture, pkg};
+
+    /// dev-lib/p0 -> p1 -> ... -> p5, two versions each.
+    fn chain_fixture() -> Fixture {
+        Fixture::new((0..6).flat_map(|i| {
+            ["1.0", "2.0"].map(|version| {
+                let deps = if i < 5 {
+                    DepEntry::parse(&format!("dev-lib/p{}", i + 1)).unwrap()
+                } else {
+                    vec![]
+                };
+                pkg(&format!("dev-lib/p{i}-{version}"), "0", deps)
+            })
+        }))
+    }
+
+    fn solver_for(
+        mut provider: PortageDependencyProvider,
+    ) -> (Solver<PortageDependencyProvider>, ConditionalRequirement) {
+        let req = provider.intern_requirement(&Dep::parse("dev-lib/p0").unwrap());
+        (Solver::new(provider), req)
+    }
+
+    fn cancelled(
+        result: Result<Vec<SolvableId>, UnsolvableOrCancelled>,
+    ) -> (CancelReason, SolveStats) {
+        match result {
+            Err(UnsolvableOrCancelled::Cancelled(value)) => {
+                match *value.downcast::<ResolveError>().unwrap() {
+                    ResolveError::Cancelled { reason, stats } => (reason, stats),
+                    other => panic!("expected cancellation, got {other}"),
+                }
+            }
+            other => panic!("expected cancellation, got {other:?}"),
+        }
+    }
+
+    fn decisions(n: u64) -> SolveBudget {
+        SolveBudget {
+            max_decisions: Some(n),
+            ..SolveBudget::default()
+        }
+    }
+
+    /// Stats of an unbounded solve of the chain.
+    fn full_stats() -> SolveStats {
+        let (mut solver, req) = solver_for(chain_fixture().provider());
+        assert_eq!(
+            solver
+                .solve(Problem::new().requirements(vec![req]))
+                .unwrap()
+                .len(),
+            6
+        );
+        solver.provider().solve_stats()
+    }
+
+    #[test]
+    fn unbounded_solve_reports_stats() {
+        let full = full_stats();
+        assert_eq!(full.candidates_requested, 6);
+        assert_eq!(full.dependencies_requested, 12);
+        assert!(full.decisions > 1, "{full:?}");
+    }
+
+    #[test]
+    fn cancelled_token_stops_at_first_checkpoint() {
+        let token = CancellationToken::new();
+        token.clone().cancel();
+        let (mut solver, req) = solver_for(chain_fixture().provider().with_cancellation(token));
+        let (reason, stats) = cancelled(solver.solve(Problem::new().requirements(vec![req])));
+        assert_eq!(reason, CancelReason::Cancelled);
+        assert_eq!(stats, SolveStats::default());
+    }
+
+    #[test]
+    fn decision_limit_cancels_one_decision_short() {
+        // Exactly enough decisions succeed; one fewer cancels.
+        let full = full_stats();
+        let provider = chain_fixture().provider();
+        let (mut solver, req) = solver_for(provider.with_budget(decisions(full.decisions)));
+        assert!(solver.solve(Problem::new().requirements(vec![req])).is_ok());
+        let provider = chain_fixture().provider();
+        let (mut solver, req) = solver_for(provider.with_budget(decisions(full.decisions - 1)));
+        let (reason, stats) = cancelled(solver.solve(Problem::new().requirements(vec![req])));
+        assert_eq!(reason, CancelReason::DecisionLimit);
+        assert_eq!(stats.decisions, full.decisions - 1);
+        assert!(stats.candidates_requested <= full.candidates_requested);
+    }
+
+    #[test]
+    fn zero_time_limit_cancels_at_first_checkpoint() {
+        // The clock starts at the first checkpoint.
+        let budget = SolveBudget {
+            time_limit: Some(Duration::ZERO),
+            ..SolveBudget::default()
+        };
+        let (mut solver, req) = solver_for(chain_fixture().provider().with_budget(budget));
+        let (reason, _) = cancelled(solver.solve(Problem::new().requirements(vec![req])));
+        assert_eq!(reason, CancelReason::TimeLimit);
+    }
+
+    #[test]
+    fn budget_spans_solves_until_stats_reset() {
+        let full = full_stats();
+        let provider = chain_fixture().provider();
+        let (mut solver, req) = solver_for(provider.with_budget(decisions(full.decisions)));
+        assert!(
+            solver
+                .solve(Problem::new().requirements(vec![req.clone()]))
+                .is_ok()
+        );
+        let (reason, _) = cancelled(solver.solve(Problem::new().requirements(vec![req.clone()])));
+        assert_eq!(reason, CancelReason::DecisionLimit);
+        solver.provider().reset_solve_stats();
+        assert!(solver.solve(Problem::new().requirements(vec![req])).is_ok());
+    }
+
+    #[test]
+    fn cancelled_error_names_the_reason() {
+        let err = ResolveError::Cancelled {
+            reason: CancelReason::DecisionLimit,
+            stats: full_stats(),
+        };
+        assert!(
+            err.to_string()
+                .starts_with("solve stopped (decision limit reached) after")
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! Fluent construction of a [`PortageDependencyProvider`].
+
+use crate::budget::{CancellationToken, SolveBudget};
+use crate::downgrade::DowngradePolicy;
+use crate::error::ResolveError;
+use crate::ordering::CandidateOrdering;
+use crate::pool::{DepClassPolicy, InstalledSet, UseConfig};
+use crate::provider::{DomainConfig, PortageDependencyProvider, Validation};
+use crate::repository::PackageRepository;
+
+/// Where a [`ProviderBuilder`] reads packages from.
+enum Source<'a> {
+    /// Every package is interned by [`build`](ProviderBuilder::build).
+    Eager(&'a dyn PackageRepository),
+    /// Packages are loaded when the solver first asks for them.
+    Lazy(Box<dyn PackageRepository + Send>),
+}
+
+/// Builder for a [`PortageDependencyProvider`].
+///
+/// Starts from a repository, read up front ([`new`](Self::new)) or on
+/// demand ([`lazy`](Self::lazy)); every other setting defaults to the
+/// empty [`UseConfig`], no installed packages, the default
+/// [`DepClassPolicy`], a single [`Domain::Target`](crate::Domain::Target),
+/// [`NewestFirst`](crate::NewestFirst) ordering, downgrades allowed and an
+/// unbounded solve.
+/// [`build`](Self::build) validates the combination and the metadata.
+///
+/// ```
+/// use portage_atom_resolvo::{
+///     DepClassPolicy, InMemoryRepository, InstalledSet, ProviderBuilder, UseConfig,
+/// };
+///
+/// let repo = InMemoryRepository::new();
+/// let use_config = UseConfig::default();
+/// let installed = InstalledSet::new();
+/// let provider = ProviderBuilder::new(&repo)
+///     .use_config(&use_config)
+///     .installed(&installed)
+///     .dep_class_policy(DepClassPolicy::runtime_only())
+///     .build()
+///     .unwrap();
+/// ```
+pub struct ProviderBuilder<'a> {
+    source: Source<'a>,
+    use_config: Option<&'a UseConfig>,
+    installed: Option<&'a InstalledSet>,
+    host: Option<DomainConfig<'a>>,
+    dep_class_policy: DepClassPolicy,
+    ordering: Option<Box<dyn CandidateOrdering>>,
+    downgrade_policy: DowngradePolicy,
+    cancellation: Option<CancellationToken>,
+    budget: SolveBudget,
+    validation: Validation,
+}
+
+impl<'a> ProviderBuilder<'a> {
+    /// Start a provider that interns every package of `repo` when built.
+    pub fn new(repo: &'a dyn PackageRepository) -> Self {
+        Self::with_source(Source::Eager(repo))
+    }
+
+    /// Start a provider that loads packages from `repo` on demand, see
+    /// [`PortageDependencyProvider::lazy`]. Lazy providers cannot have a
+    /// build [`host`](Self::host).
+    pub fn lazy(repo: impl PackageRepository + Send + 'static) -> Self {
+        Self::with_source(Source::Lazy(Box::new(repo)))
+    }
+
+    fn with_source(source: Source<'a>) -> Self {
+        Self {
+            source,
+            use_config: None,
+            installed: None,
+            host: None,
+            dep_class_policy: DepClassPolicy::default(),
+            ordering: None,
+            downgrade_policy: DowngradePolicy::default(),
+            cancellation: None,
+            budget: SolveBudget::default(),
+            validation: Validation::Strict,
+        }
+    }
+
+    /// USE configuration of the target domain.
+    pub fn use_config(mut self, use_config: &'a UseConfig) -> Self {
+        self.use_config = Some(use_config);
+        self
+    }
+
+    /// Packages installed in the target domain.
+    pub fn installed(mut self, installed: &'a InstalledSet) -> Self {
+        self.installed = Some(installed);
+        self
+    }
+
+    /// Resolve `BDEPEND` / `IDEPEND` in a separate build host domain, see
+    /// [`PortageDependencyProvider::cross`].
+    pub fn host(mut self, host: DomainConfig<'a>) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    /// Which dependency classes are included, softened or excluded.
+    pub fn dep_class_policy(mut self, dep_class_policy: DepClassPolicy) -> Self {
+        self.dep_class_policy = dep_class_policy;
+        self
+    }
+
+    /// See [`PortageDependencyProvider::with_ordering`].
+    pub fn ordering(mut self, ordering: impl CandidateOrdering + 'static) -> Self {
+        self.ordering = Some(Box::new(ordering));
+        self
+    }
+
+    /// See [`PortageDependencyProvider::with_downgrade_policy`].
+    pub fn downgrade_policy(mut self, policy: DowngradePolicy) -> Self {
+        self.downgrade_policy = policy;
+        self
+    }
+
+    /// See [`PortageDependencyProvider::with_cancellation`].
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// See [`PortageDependencyProvider::with_budget`].
+    pub fn budget(mut self, budget: SolveBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Accept what only the infallible constructors accept, see
+    /// [`Validation::Lenient`].
+    pub(crate) fn validation(mut self, validation: Validation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Build the provider.
+    ///
+    /// Returns [`ResolveError::IncompatibleOptions`] for settings a lazy
+    /// provider does not support, and otherwise the errors of
+    /// [`PortageDependencyProvider::try_with_installed`].
+    pub fn build(self) -> Result<PortageDependencyProvider, ResolveError> {
+        let default_use_config = UseConfig::default();
+        let default_installed = InstalledSet::default();
+        let use_config = self.use_config.unwrap_or(&default_use_config);
+        let installed = self.installed.unwrap_or(&default_installed);
+
+        let provider = match self.source {
+            Source::Eager(repo) => {
+                let target = DomainConfig {
+                    repo,
+                    use_config,
+                    installed,
+                };
+                PortageDependencyProvider::build(
+                    target,
+                    self.host,
+                    &self.dep_class_policy,
+                    self.validation,
+                )?
+            }
+            Source::Lazy(repo) => {
+                if self.host.is_some() {
+                    return Err(incompatible("a lazy provider cannot cross-compile"));
+                }
+                PortageDependencyProvider::build_lazy(
+                    repo,
+                    use_config,
+                    installed,
+                    &self.dep_class_policy,
+                    self.validation,
+                )?
+            }
+        };
+
+        let mut provider = provider
+            .with_budget(self.budget)
+            .with_downgrade_policy(self.downgrade_policy);
+        provider.ordering = self.ordering;
+        if let Some(token) = self.cancellation {
+            provider = provider.with_cancellation(token);
+        }
+        Ok(provider)
+    }
+}
+
+fn incompatible(reason: &str) -> ResolveError {
+    ResolveError::IncompatibleOptions {
+        reason: reason.to_string(),
+    }
+}
+
+impl PortageDependencyProvider {
+    /// Start a [`ProviderBuilder`] over `repo`, equivalent to
+    /// [`ProviderBuilder::new`].
+    pub fn builder(repo: &dyn PackageRepository) -> ProviderBuilder<'_> {
+        ProviderBuilder::new(repo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use portage_atom::{Dep, DepEntry};
+    use resolvo::{Problem, Solver};
+
+    use super::*;
+    use crate::tests::{Fixture, pkg, pkg_build_run, solve_cpvs};
+
+    /// app-1.0 needs lib at runtime and cmake to build, with lib-1.0
+    /// locked.
+    fn builder_fixture() -> Fixture {
+        let mut app = pkg_build_run("app-misc/app-1.0", "", "dev-lib/lib");
+        app.dependencies.bdepend = DepEntry::parse("dev-util/cmake").unwrap();
+        Fixture::new([
+            app,
+            pkg("dev-lib/lib-1.0", "0", vec![]),
+            pkg("dev-lib/lib-2.0", "0", vec![]),
+            pkg("dev-util/cmake-3.27", "0", vec![]),
+        ])
+        .locked([pkg("dev-lib/lib-1.0", "0", vec![])])
+    }
+
+    fn builder_host() -> Fixture {
+        Fixture::new([pkg("dev-util/cmake-3.28", "0", vec![])])
+    }
+
+    /// The runtime-only solution of `app-misc/app` with lib-1.0 locked.
+    fn builder_runtime_solution() -> HashSet<String> {
+        ["app-misc/app-1.0", "dev-lib/lib-1.0"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn provider_builder_matches_positional_constructor() {
+        let fixture = builder_fixture();
+        let built = ProviderBuilder::new(&*fixture.repo)
+            .use_config(&fixture.use_config)
+            .installed(&fixture.installed)
+            .dep_class_policy(DepClassPolicy::runtime_only())
+            .build()
+            .unwrap();
+        assert_eq!(
+            solve_cpvs(built, &["app-misc/app"]),
+            builder_runtime_solution()
+        );
+        let positional = PortageDependencyProvider::with_dep_class_policy(
+            &*fixture.repo,
+            &fixture.use_config,
+            &fixture.installed,
+            &DepClassPolicy::runtime_only(),
+        );
+        assert_eq!(
+            solve_cpvs(positional, &["app-misc/app"]),
+            builder_runtime_solution()
+        );
+    }
+
+    #[test]
+    fn provider_builder_defaults() {
+        // No installed set, every class, the target domain only.
+        let fixture = builder_fixture();
+        let cpvs = solve_cpvs(
+            PortageDependencyProvider::builder(&*fixture.repo)
+                .build()
+                .unwrap(),
+            &["app-misc/app"],
+        );
+        assert!(cpvs.contains("dev-lib/lib-2.0"));
+        assert!(cpvs.contains("dev-util/cmake-3.27"));
+    }
+
+    #[test]
+    fn provider_builder_cross_compiles() {
+        let fixture = builder_fixture();
+        let host = builder_host();
+        let cross = ProviderBuilder::new(&*fixture.repo)
+            .host(DomainConfig {
+                repo: &*host.repo,
+                use_config: &host.use_config,
+                installed: &host.installed,
+            })
+            .build()
+            .unwrap();
+        assert!(solve_cpvs(cross, &["app-misc/app"]).contains("dev-util/cmake-3.28"));
+    }
+
+    #[test]
+    fn lazy_provider_builder_applies_dep_class_policy() {
+        let fixture = builder_fixture();
+        let lazy = fixture
+            .lazy_builder()
+            .dep_class_policy(DepClassPolicy::runtime_only())
+            .build()
+            .unwrap();
+        assert_eq!(
+            solve_cpvs(lazy, &["app-misc/app"]),
+            builder_runtime_solution()
+        );
+    }
+
+    #[test]
+    fn lazy_provider_builder_rejects_host() {
+        let fixture = builder_fixture();
+        let host = builder_host();
+        let err = fixture
+            .lazy_builder()
+            .host(DomainConfig {
+                repo: &*host.repo,
+                use_config: &host.use_config,
+                installed: &fixture.installed,
+            })
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, ResolveError::IncompatibleOptions { .. }));
+        assert!(err.to_string().contains("cannot cross-compile"), "{err}");
+    }
+
+    #[test]
+    fn lazy_provider_builder_validates_use_config() {
+        let bad = UseConfig {
+            enabled: ["bad flag".into()].into_iter().collect(),
+            ..UseConfig::default()
+        };
+        let err = ProviderBuilder::lazy(Arc::clone(&builder_fixture().repo))
+            .use_config(&bad)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, ResolveError::InvalidFlag { .. }));
+    }
+
+    #[test]
+    fn lazy_provider_builder_keeps_installed() {
+        let fixture = builder_fixture();
+        let lazy = ProviderBuilder::lazy(Arc::clone(&fixture.repo))
+            .installed(&fixture.installed)
+            .build()
+            .unwrap();
+        assert!(solve_cpvs(lazy, &["app-misc/app"]).contains("dev-lib/lib-1.0"));
+    }
+
+    #[test]
+    fn provider_builder_cancellation() {
+        use crate::budget::CancellationToken;
+        use resolvo::UnsolvableOrCancelled;
+
+        let fixture = builder_fixture();
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut provider = ProviderBuilder::new(&*fixture.repo)
+            .cancellation(token)
+            .build()
+            .unwrap();
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        assert!(matches!(
+            solver.solve(Problem::new().requirements(vec![req])),
+            Err(UnsolvableOrCancelled::Cancelled(_))
+        ));
+    }
+}
@@ -0,0 +1,1694 @@
+//! Persistent provider cache.
+//!
+//! Building a [`PortageDependencyProvider`] walks every package and converts
+//! every dependency tree. [`PortageDependencyProvider::write_cache`] stores
+//! the fully built provider in a versioned binary file;
+//! [`PortageDependencyProvider::read_cache`] loads it back, provided it was
+//! built from the same repository and configuration ([`CacheKey`]).
+//!
+//! The encoder destructures the provider field by field, so a field added
+//! later does not compile until it is stored here (bumping
+//! [`CACHE_FORMAT_VERSION`]) or skipped with a reason.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write as _};
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+
+use portage_atom::gentoo_interner::{DefaultInterner, Interned};
+use portage_atom::{
+    Blocker, Cpn, Cpv, Dep, DepEntry, Operator, Slot, SlotDep, SlotOperator, UseDefault, UseDep,
+    UseDepKind, Version,
+};
+use resolvo::{
+    ArenaId, Condition, ConditionId, ConditionalRequirement, KnownDependencies, LogicalOperator,
+    NameId, Requirement, SolvableId, StringId, VersionSetId, VersionSetUnionId,
+};
+
+use crate::budget::SolveMonitor;
+use crate::downgrade::{DowngradeMode, DowngradePolicy};
+use crate::pool::{
+    DepClass, DepClassMode, DepClassModes, DepClassPolicy, Domain, InstalledSet, PackageDeps,
+    PackageMetadata, PackageName, PortagePool, RequirementOrigin, UseConfig, VersionConstraint,
+};
+use crate::provider::{FlagVirtuals, Fnv1a, PortageDependencyProvider, config_fingerprint};
+use crate::repository::{PackageRepository, RepositoryError};
+use crate::update::{DepChange, UseChange};
+
+/// Format version written to and accepted from cache files.
+pub const CACHE_FORMAT_VERSION: u32 = 4;
+
+const MAGIC: &[u8; 8] = b"PARCACHE";
+
+/// Identifies the inputs a cached provider was built from.
+///
+/// A cache is only loaded when both fingerprints match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// Fingerprint of the repository and installed set, e.g. from
+    /// [`repository_fingerprint`] or a cheaper source such as the
+    /// repository's metadata timestamp. Cross-compilation callers combine
+    /// the fingerprints of both domains.
+    pub repository: u64,
+    /// [`config_fingerprint`](PortageDependencyProvider::config_fingerprint)
+    /// of the provider.
+    pub config: u64,
+}
+
+impl CacheKey {
+    /// Key for a provider built from a repository with fingerprint
+    /// `repository`, with `use_config` (plus `host_use_config` when
+    /// cross-compiling) and `dep_class_policy`.
+    pub fn new(
+        repository: u64,
+        use_config: &UseConfig,
+        host_use_config: Option<&UseConfig>,
+        dep_class_policy: &DepClassPolicy,
+    ) -> Self {
+        Self {
+            repository,
+            config: config_fingerprint(use_config, host_use_config, dep_class_policy),
+        }
+    }
+
+    /// Key stored in a cache written by
+    /// [`write_cache`](PortageDependencyProvider::write_cache), for callers
+    /// that only have the cache file and trust it to be current.
+    pub fn read(input: impl Read) -> Result<Self, CacheError> {
+        // Magic, format version and the two fingerprints.
+        let mut header = Vec::new();
+        input
+            .take(MAGIC.len() as u64 + 4 + 16)
+            .read_to_end(&mut header)?;
+        Decoder::new(&header).header()
+    }
+}
+
+/// Fingerprint of every package in `repo` plus the `installed` set.
+///
+/// Reads and hashes all metadata but converts nothing. Callers with a
+/// cheaper identity for the repository, such as its metadata timestamp,
+/// can use that for [`CacheKey::repository`] instead.
+pub fn repository_fingerprint(
+    repo: &dyn PackageRepository,
+    installed: &InstalledSet,
+) -> Result<u64, RepositoryError> {
+    let mut cpns: Vec<Cpn> = repo.all_packages()?.collect();
+    cpns.sort_unstable_by(|a, b| {
+        (a.category.as_str(), a.package.as_str()).cmp(&(b.category.as_str(), b.package.as_str()))
+    });
+    let mut hasher = Fnv1a::default();
+    for cpn in &cpns {
+        let versions = repo.versions_for(cpn)?;
+        let mut versions: Vec<&PackageMetadata> = versions.iter().collect();
+        versions.sort_by(|a, b| a.cpv.version.cmp(&b.cpv.version));
+        for meta in versions {
+            hash_metadata(&mut hasher, "package", meta);
+        }
+    }
+    for (meta, policy) in &installed.packages {
+        hash_metadata(&mut hasher, &format!("installed {policy:?}"), meta);
+    }
+    Ok(hasher.0)
+}
+
+/// Feed a canonical rendering of `meta` to `hasher`.
+fn hash_metadata(hasher: &mut Fnv1a, label: &str, meta: &PackageMetadata) {
+    fn opt(value: &Option<Interned<DefaultInterner>>) -> &str {
+        value.as_ref().map_or("", |v| v.as_str())
+    }
+    let mut use_flags: Vec<&str> = meta.use_flags.iter().map(|f| f.as_str()).collect();
+    use_flags.sort_unstable();
+    let _ = write!(
+        hasher,
+        "{label} {} slot={}/{} repo={} iuse=",
+        meta.cpv,
+        opt(&meta.slot),
+        opt(&meta.subslot),
+        opt(&meta.repo),
+    );
+    for flag in &meta.iuse {
+        let _ = write!(hasher, "{flag},");
+    }
+    let _ = writeln!(hasher, " use={}", use_flags.join(","));
+    for (class, entries) in meta.dependencies.iter_classes() {
+        let _ = write!(hasher, "  {class}");
+        for entry in entries {
+            let _ = write!(hasher, " {entry}");
+        }
+        let _ = writeln!(hasher);
+    }
+}
+
+/// Error reading or writing a provider cache.
+#[derive(Debug)]
+pub enum CacheError {
+    /// Reading or writing the underlying stream failed.
+    Io(io::Error),
+    /// The data is not a provider cache, or is truncated or corrupt.
+    Corrupt(String),
+    /// The cache was written by a different [`CACHE_FORMAT_VERSION`].
+    Version(u32),
+    /// The cache was built from a different repository or configuration.
+    Stale {
+        /// Key stored in the cache.
+        cached: CacheKey,
+        /// Key the caller expected.
+        expected: CacheKey,
+    },
+    /// The provider loads packages on demand and is never fully built.
+    Lazy,
+    /// The provider has a custom
+    /// [`CandidateOrdering`](crate::CandidateOrdering), which cannot be
+    /// stored.
+    CustomOrdering,
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Io(err) => write!(f, "provider cache I/O error: {err}"),
+            CacheError::Corrupt(message) => write!(f, "corrupt provider cache: {message}"),
+            CacheError::Version(found) => write!(
+                f,
+                "provider cache format version {found}, expected {CACHE_FORMAT_VERSION}"
+            ),
+            CacheError::Stale { cached, expected } => write!(
+                f,
+                "stale provider cache: built for repository {:016x} / config {:016x}, \
+                 expected {:016x} / {:016x}",
+                cached.repository, cached.config, expected.repository, expected.config
+            ),
+            CacheError::Lazy => write!(f, "lazy providers cannot be cached"),
+            CacheError::CustomOrdering => {
+                write!(
+                    f,
+                    "providers with a custom candidate ordering cannot be cached"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CacheError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CacheError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(err: io::Error) -> Self {
+        CacheError::Io(err)
+    }
+}
+
+impl PortageDependencyProvider {
+    /// Write the provider to `out` as a binary cache.
+    ///
+    /// `repository` is the fingerprint of the repository and installed set
+    /// the provider was built from; the configuration part of the
+    /// [`CacheKey`] comes from the provider itself. Root requirements
+    /// interned so far are stored too, and so are the settings made after
+    /// building: masks, the [`DowngradePolicy`], released installed
+    /// versions and lock file pins. The cancellation token and budget are
+    /// not; set them on the loaded provider.
+    ///
+    /// Fails with [`CacheError::Lazy`] for a
+    /// [`lazy`](Self::lazy) provider and with
+    /// [`CacheError::CustomOrdering`] for one with a
+    /// [`with_ordering`](Self::with_ordering) ordering.
+    pub fn write_cache(&self, mut out: impl Write, repository: u64) -> Result<(), CacheError> {
+        if self.lazy.is_some() {
+            return Err(CacheError::Lazy);
+        }
+        if self.ordering.is_some() {
+            return Err(CacheError::CustomOrdering);
+        }
+        let key = CacheKey {
+            repository,
+            config: self.config_fingerprint(),
+        };
+        let mut enc = Encoder::default();
+        enc.provider(self);
+
+        out.write_all(MAGIC)?;
+        out.write_all(&CACHE_FORMAT_VERSION.to_le_bytes())?;
+        out.write_all(&key.repository.to_le_bytes())?;
+        out.write_all(&key.config.to_le_bytes())?;
+        out.write_all(&(enc.strings.len() as u32).to_le_bytes())?;
+        for string in &enc.strings {
+            out.write_all(&(string.len() as u32).to_le_bytes())?;
+            out.write_all(string.as_bytes())?;
+        }
+        out.write_all(&enc.body)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Load a provider written by [`write_cache`](Self::write_cache).
+    ///
+    /// Fails with [`CacheError::Stale`] if the cache was built for a
+    /// different `key`, in which case the caller should rebuild the
+    /// provider and rewrite the cache. Truncated data and IDs outside the
+    /// decoded arenas fail with [`CacheError::Corrupt`] rather than
+    /// panicking later.
+    pub fn read_cache(mut input: impl Read, key: &CacheKey) -> Result<Self, CacheError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let mut dec = Decoder::new(&bytes);
+        let cached = dec.header()?;
+        if cached != *key {
+            return Err(CacheError::Stale {
+                cached,
+                expected: *key,
+            });
+        }
+        dec.string_table()?;
+        let provider = dec.provider()?;
+        if dec.pos != bytes.len() {
+            return Err(CacheError::Corrupt("trailing data".to_string()));
+        }
+        Ok(provider)
+    }
+}
+
+/// Binary writer. Strings are deduplicated into a table written ahead of
+/// the body and referenced by index.
+#[derive(Default)]
+struct Encoder {
+    body: Vec<u8>,
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+}
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.body.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.body.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(u8::from(value));
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+
+    fn id(&mut self, id: impl ArenaId) {
+        self.len(id.to_usize());
+    }
+
+    fn ids<I: ArenaId + Copy>(&mut self, ids: &[I]) {
+        self.len(ids.len());
+        for &id in ids {
+            self.id(id);
+        }
+    }
+
+    fn id_set<I: ArenaId + Copy>(&mut self, ids: &HashSet<I>) {
+        self.len(ids.len());
+        for &id in ids {
+            self.id(id);
+        }
+    }
+
+    fn str(&mut self, text: &str) {
+        let index = match self.string_ids.get(text) {
+            Some(&index) => index,
+            None => {
+                let index = self.strings.len() as u32;
+                self.strings.push(text.to_string());
+                self.string_ids.insert(text.to_string(), index);
+                index
+            }
+        };
+        self.u32(index);
+    }
+
+    fn interned(&mut self, value: Interned<DefaultInterner>) {
+        self.str(value.as_str());
+    }
+
+    fn opt_interned(&mut self, value: Option<Interned<DefaultInterner>>) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                self.interned(value);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn flag_set(&mut self, flags: &HashSet<Interned<DefaultInterner>>) {
+        self.len(flags.len());
+        for &flag in flags {
+            self.interned(flag);
+        }
+    }
+
+    fn cpn(&mut self, cpn: &Cpn) {
+        self.interned(cpn.category);
+        self.interned(cpn.package);
+    }
+
+    fn version(&mut self, version: &Version) {
+        self.str(&version.to_string());
+    }
+
+    fn cpv(&mut self, cpv: &Cpv) {
+        self.cpn(&cpv.cpn);
+        self.version(&cpv.version);
+    }
+
+    fn operator(&mut self, operator: Operator) {
+        self.u8(match operator {
+            Operator::Less => 0,
+            Operator::LessOrEqual => 1,
+            Operator::Equal => 2,
+            Operator::Approximate => 3,
+            Operator::GreaterOrEqual => 4,
+            Operator::Greater => 5,
+        });
+    }
+
+    fn blocker(&mut self, blocker: Blocker) {
+        self.u8(match blocker {
+            Blocker::Weak => 0,
+            Blocker::Strong => 1,
+        });
+    }
+
+    fn use_change(&mut self, change: UseChange) {
+        self.u8(match change {
+            UseChange::Enabled => 0,
+            UseChange::Iuse => 1,
+        });
+    }
+
+    fn downgrade_mode(&mut self, mode: DowngradeMode) {
+        self.u8(match mode {
+            DowngradeMode::Allow => 0,
+            DowngradeMode::Report => 1,
+            DowngradeMode::Forbid => 2,
+        });
+    }
+
+    fn slot_operator(&mut self, operator: SlotOperator) {
+        self.u8(match operator {
+            SlotOperator::Equal => 0,
+            SlotOperator::Star => 1,
+        });
+    }
+
+    fn dep(&mut self, dep: &Dep) {
+        self.cpn(&dep.cpn);
+        match dep.blocker {
+            Some(blocker) => {
+                self.u8(1);
+                self.blocker(blocker);
+            }
+            None => self.u8(0),
+        }
+        match dep.op {
+            Some(operator) => {
+                self.u8(1);
+                self.operator(operator);
+            }
+            None => self.u8(0),
+        }
+        match &dep.version {
+            Some(version) => {
+                self.u8(1);
+                self.version(version);
+            }
+            None => self.u8(0),
+        }
+        self.bool(dep.glob);
+        match &dep.slot_dep {
+            None => self.u8(0),
+            Some(SlotDep::Slot { slot, op }) => {
+                self.u8(1);
+                match slot {
+                    Some(slot) => {
+                        self.u8(1);
+                        self.interned(slot.slot);
+                        self.opt_interned(slot.subslot);
+                    }
+                    None => self.u8(0),
+                }
+                match op {
+                    Some(op) => {
+                        self.u8(1);
+                        self.slot_operator(*op);
+                    }
+                    None => self.u8(0),
+                }
+            }
+            Some(SlotDep::Operator(op)) => {
+                self.u8(2);
+                self.slot_operator(*op);
+            }
+        }
+        match &dep.use_deps {
+            Some(use_deps) => {
+                self.u8(1);
+                self.len(use_deps.len());
+                for use_dep in use_deps {
+                    self.interned(use_dep.flag);
+                    self.u8(match use_dep.kind {
+                        UseDepKind::Enabled => 0,
+                        UseDepKind::Disabled => 1,
+                        UseDepKind::Conditional => 2,
+                        UseDepKind::ConditionalInverse => 3,
+                        UseDepKind::Equal => 4,
+                        UseDepKind::EqualInverse => 5,
+                    });
+                    self.u8(match use_dep.default {
+                        None => 0,
+                        Some(UseDefault::Enabled) => 1,
+                        Some(UseDefault::Disabled) => 2,
+                    });
+                }
+            }
+            None => self.u8(0),
+        }
+        self.opt_interned(dep.repo);
+    }
+
+    fn domain(&mut self, domain: Domain) {
+        self.u8(match domain {
+            Domain::Target => 0,
+            Domain::Host => 1,
+        });
+    }
+
+    fn class(&mut self, class: DepClass) {
+        self.u8(match class {
+            DepClass::Depend => 0,
+            DepClass::Rdepend => 1,
+            DepClass::Bdepend => 2,
+            DepClass::Pdepend => 3,
+            DepClass::Idepend => 4,
+        });
+    }
+
+    fn dep_entries(&mut self, entries: &[DepEntry]) {
+        self.len(entries.len());
+        for entry in entries {
+            match entry {
+                DepEntry::Atom(dep) => {
+                    self.u8(0);
+                    self.dep(dep);
+                }
+                DepEntry::UseConditional {
+                    flag,
+                    negate,
+                    children,
+                } => {
+                    self.u8(1);
+                    self.interned(*flag);
+                    self.bool(*negate);
+                    self.dep_entries(children);
+                }
+                DepEntry::AllOf(children) => {
+                    self.u8(2);
+                    self.dep_entries(children);
+                }
+                DepEntry::AnyOf(children) => {
+                    self.u8(3);
+                    self.dep_entries(children);
+                }
+                DepEntry::ExactlyOneOf(children) => {
+                    self.u8(4);
+                    self.dep_entries(children);
+                }
+                DepEntry::AtMostOneOf(children) => {
+                    self.u8(5);
+                    self.dep_entries(children);
+                }
+            }
+        }
+    }
+
+    fn metadata(&mut self, meta: &PackageMetadata) {
+        self.cpv(&meta.cpv);
+        self.opt_interned(meta.slot);
+        self.opt_interned(meta.subslot);
+        self.len(meta.iuse.len());
+        for &flag in &meta.iuse {
+            self.interned(flag);
+        }
+        self.flag_set(&meta.use_flags);
+        self.opt_interned(meta.repo);
+        let deps = &meta.dependencies;
+        for entries in [
+            &deps.depend,
+            &deps.rdepend,
+            &deps.bdepend,
+            &deps.pdepend,
+            &deps.idepend,
+        ] {
+            self.dep_entries(entries);
+        }
+    }
+
+    fn version_constraint(&mut self, vc: &VersionConstraint) {
+        self.cpn(&vc.cpn);
+        self.operator(vc.operator);
+        self.version(&vc.version);
+        self.bool(vc.glob);
+        self.opt_interned(vc.slot);
+        self.opt_interned(vc.subslot);
+        self.opt_interned(vc.repo);
+        self.len(vc.use_constraints.len());
+        for &(flag, enabled) in &vc.use_constraints {
+            self.interned(flag);
+            self.bool(enabled);
+        }
+        self.bool(vc.inverted);
+    }
+
+    fn requirement(&mut self, requirement: &Requirement) {
+        match *requirement {
+            Requirement::Single(vs) => {
+                self.u8(0);
+                self.id(vs);
+            }
+            Requirement::Union(union) => {
+                self.u8(1);
+                self.id(union);
+            }
+        }
+    }
+
+    fn use_config(&mut self, use_config: &UseConfig) {
+        self.flag_set(&use_config.enabled);
+        self.flag_set(&use_config.disabled);
+        self.flag_set(&use_config.solver_decided);
+    }
+
+    fn dep_class_modes(&mut self, modes: &DepClassModes) {
+        for mode in [
+            modes.depend,
+            modes.rdepend,
+            modes.bdepend,
+            modes.pdepend,
+            modes.idepend,
+        ] {
+            self.u8(match mode {
+                DepClassMode::Include => 0,
+                DepClassMode::Soften => 1,
+                DepClassMode::Exclude => 2,
+            });
+        }
+    }
+
+    fn flag_virtuals(&mut self, flag_virtuals: &HashMap<Interned<DefaultInterner>, FlagVirtuals>) {
+        self.len(flag_virtuals.len());
+        for (&flag, fv) in flag_virtuals {
+            self.interned(flag);
+            self.id(fv.on_condition);
+            self.id(fv.off_condition);
+            self.id(fv.on_solvable);
+            self.id(fv.off_solvable);
+            self.id(fv.choice_union);
+        }
+    }
+
+    fn pool(&mut self, pool: &PortagePool) {
+        self.len(pool.names.len());
+        for name in &pool.names {
+            self.cpn(&name.cpn);
+            self.opt_interned(name.slot);
+            self.domain(name.domain);
+        }
+        self.len(pool.solvables.len());
+        for (meta, &name) in pool.solvables.iter().zip(&pool.solvable_names) {
+            self.metadata(meta);
+            self.id(name);
+        }
+        self.len(pool.version_sets.len());
+        for (vc, &name) in pool.version_sets.iter().zip(&pool.version_set_names) {
+            self.version_constraint(vc);
+            self.id(name);
+        }
+        self.len(pool.version_set_unions.len());
+        for union in &pool.version_set_unions {
+            self.ids(union);
+        }
+        self.len(pool.conditions.len());
+        for condition in &pool.conditions {
+            match *condition {
+                Condition::Requirement(vs) => {
+                    self.u8(0);
+                    self.id(vs);
+                }
+                Condition::Binary(op, lhs, rhs) => {
+                    self.u8(match op {
+                        LogicalOperator::And => 1,
+                        LogicalOperator::Or => 2,
+                    });
+                    self.id(lhs);
+                    self.id(rhs);
+                }
+            }
+        }
+        self.len(pool.strings.len());
+        for string in &pool.strings {
+            self.str(string);
+        }
+    }
+
+    fn provider(&mut self, provider: &PortageDependencyProvider) {
+        // Every field is named, so a new one does not compile until it is
+        // stored here and in `Decoder::provider`, or skipped with a reason.
+        let PortageDependencyProvider {
+            pool,
+            candidates,
+            dependencies,
+            cpn_slots,
+            blocker_types,
+            rebuild_triggers,
+            flag_virtuals,
+            host_flag_virtuals,
+            use_config,
+            host_use_config,
+            favored,
+            locked,
+            installed,
+            dep_class_policy,
+            origins,
+            xof_choices,
+            roots,
+            synthetic,
+            xof_counter,
+            use_changes,
+            dep_changes,
+            released,
+            downgrade_policy,
+            downgrade_reasons,
+            masked,
+            pinned_any_of,
+            // `write_cache` refuses lazy providers and custom orderings.
+            lazy: _,
+            ordering: _,
+            // The cancellation token and budget belong to a solve, not to
+            // the built provider.
+            monitor: _,
+            // Memos, recomputed on demand.
+            pulled: _,
+            preferred_unions: _,
+        } = provider;
+
+        self.pool(pool);
+
+        let candidates = candidates.borrow();
+        self.len(candidates.len());
+        for (&name, solvables) in candidates.iter() {
+            self.id(name);
+            self.ids(solvables);
+        }
+        let dependencies = dependencies.borrow();
+        self.len(dependencies.len());
+        for (&sid, deps) in dependencies.iter() {
+            self.id(sid);
+            self.len(deps.requirements.len());
+            for req in &deps.requirements {
+                match req.condition {
+                    Some(condition) => {
+                        self.u8(1);
+                        self.id(condition);
+                    }
+                    None => self.u8(0),
+                }
+                self.requirement(&req.requirement);
+            }
+            self.ids(&deps.constrains);
+        }
+        let cpn_slots = cpn_slots.borrow();
+        self.len(cpn_slots.len());
+        for ((domain, cpn), names) in cpn_slots.iter() {
+            self.domain(*domain);
+            self.cpn(cpn);
+            self.ids(names);
+        }
+        let blocker_types = blocker_types.borrow();
+        self.len(blocker_types.len());
+        for (&vs, blocker) in blocker_types.iter() {
+            self.id(vs);
+            self.blocker(*blocker);
+        }
+        self.id_set(&rebuild_triggers.borrow());
+        self.flag_virtuals(flag_virtuals);
+        self.flag_virtuals(host_flag_virtuals);
+        self.use_config(use_config);
+        match host_use_config {
+            Some(host_use_config) => {
+                self.u8(1);
+                self.use_config(host_use_config);
+            }
+            None => self.u8(0),
+        }
+        for map in [favored.borrow(), locked.borrow()] {
+            self.len(map.len());
+            for (&name, &sid) in map.iter() {
+                self.id(name);
+                self.id(sid);
+            }
+        }
+        self.id_set(&installed.borrow());
+        let use_changes = use_changes.borrow();
+        self.len(use_changes.len());
+        for (&sid, &change) in use_changes.iter() {
+            self.id(sid);
+            self.use_change(change);
+        }
+        let dep_changes = dep_changes.borrow();
+        self.len(dep_changes.len());
+        for (&sid, changes) in dep_changes.iter() {
+            self.id(sid);
+            self.len(changes.len());
+            for change in changes {
+                self.class(change.class);
+                self.dep_entries(&change.added);
+                self.dep_entries(&change.removed);
+            }
+        }
+        self.dep_class_modes(&dep_class_policy.built);
+        self.dep_class_modes(&dep_class_policy.installed);
+        let origins = origins.borrow();
+        self.len(origins.len());
+        for (requirement, origins) in origins.iter() {
+            self.requirement(requirement);
+            self.len(origins.len());
+            for origin in origins {
+                self.id(origin.solvable);
+                self.class(origin.class);
+                self.str(&origin.atom);
+            }
+        }
+        let xof_choices = xof_choices.borrow();
+        self.len(xof_choices.len());
+        for ((owner, class, group), occurrences) in xof_choices.iter() {
+            self.id(*owner);
+            self.class(*class);
+            self.str(group);
+            self.len(occurrences.len());
+            for choices in occurrences {
+                self.ids(choices);
+            }
+        }
+        self.len(roots.len());
+        for (dep, domain) in roots {
+            self.dep(dep);
+            self.domain(*domain);
+        }
+        self.id_set(&synthetic.borrow());
+        self.len(xof_counter.get());
+        self.id_set(released);
+        self.downgrade_mode(downgrade_policy.mode);
+        self.len(downgrade_policy.allowed.len());
+        for dep in &downgrade_policy.allowed {
+            self.dep(dep);
+        }
+        for reasons in [&*downgrade_reasons.borrow(), masked] {
+            self.len(reasons.len());
+            for (&sid, &reason) in reasons {
+                self.id(sid);
+                self.id(reason);
+            }
+        }
+        self.len(pinned_any_of.len());
+        for ((owner, class, group), chosen) in pinned_any_of {
+            self.id(*owner);
+            self.class(*class);
+            self.str(group);
+            self.str(chosen);
+        }
+    }
+}
+
+type DecodeResult<T> = Result<T, CacheError>;
+
+fn corrupt<T>(message: impl Into<String>) -> DecodeResult<T> {
+    Err(CacheError::Corrupt(message.into()))
+}
+
+/// Sizes of the pool arenas decoded so far.
+#[derive(Default)]
+struct ArenaSizes {
+    names: usize,
+    solvables: usize,
+    version_sets: usize,
+    unions: usize,
+    conditions: usize,
+    strings: usize,
+}
+
+/// An arena ID stored in the cache, checked against its decoded arena.
+trait CachedId: ArenaId {
+    /// Arena name for error messages.
+    const ARENA: &'static str;
+
+    fn arena_size(sizes: &ArenaSizes) -> usize;
+}
+
+impl CachedId for NameId {
+    const ARENA: &'static str = "name";
+
+    fn arena_size(sizes: &ArenaSizes) -> usize {
+        sizes.names
+    }
+}
+
+impl CachedId for SolvableId {
+    const ARENA: &'static str = "solvable";
+
+    fn arena_size(sizes: &ArenaSizes) -> usize {
+        sizes.solvables
+    }
+}
+
+impl CachedId for VersionSetId {
+    const ARENA: &'static str = "version set";
+
+    fn arena_size(sizes: &ArenaSizes) -> usize {
+        sizes.version_sets
+    }
+}
+
+impl CachedId for VersionSetUnionId {
+    const ARENA: &'static str = "version set union";
+
+    fn arena_size(sizes: &ArenaSizes) -> usize {
+        sizes.unions
+    }
+}
+
+impl CachedId for ConditionId {
+    const ARENA: &'static str = "condition";
+
+    fn arena_size(sizes: &ArenaSizes) -> usize {
+        sizes.conditions
+    }
+}
+
+impl CachedId for StringId {
+    const ARENA: &'static str = "string";
+
+    fn arena_size(sizes: &ArenaSizes) -> usize {
+        sizes.strings
+    }
+}
+
+/// Binary reader matching [`Encoder`]. Interned strings, versions and atoms
+/// are converted once per string table entry. Arena IDs must index an
+/// arena entry decoded before them, so a loaded provider never looks up a
+/// missing entry.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    strings: Vec<&'a str>,
+    interned: Vec<Option<Interned<DefaultInterner>>>,
+    versions: Vec<Option<Version>>,
+    sizes: ArenaSizes,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            strings: Vec::new(),
+            interned: Vec::new(),
+            versions: Vec::new(),
+            sizes: ArenaSizes::default(),
+        }
+    }
+
+    /// Check the magic and format version and read the [`CacheKey`].
+    fn header(&mut self) -> DecodeResult<CacheKey> {
+        if self.take(MAGIC.len())? != MAGIC {
+            return Err(CacheError::Corrupt("not a provider cache".to_string()));
+        }
+        let version = self.u32()?;
+        if version != CACHE_FORMAT_VERSION {
+            return Err(CacheError::Version(version));
+        }
+        Ok(CacheKey {
+            repository: self.u64()?,
+            config: self.u64()?,
+        })
+    }
+
+    fn take(&mut self, len: usize) -> DecodeResult<&'a [u8]> {
+        let Some(bytes) = self.bytes.get(self.pos..self.pos + len) else {
+            return corrupt("unexpected end of data");
+        };
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> DecodeResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> DecodeResult<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> DecodeResult<u64> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn bool(&mut self) -> DecodeResult<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => corrupt(format!("invalid bool {tag}")),
+        }
+    }
+
+    fn len(&mut self) -> DecodeResult<usize> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn id<I: CachedId>(&mut self) -> DecodeResult<I> {
+        let index = self.len()?;
+        if index >= I::arena_size(&self.sizes) {
+            return corrupt(format!("{} id {index} out of range", I::ARENA));
+        }
+        Ok(I::from_usize(index))
+    }
+
+    fn seq<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> DecodeResult<T>,
+    ) -> DecodeResult<Vec<T>> {
+        let len = self.len()?;
+        let mut items = Vec::with_capacity(len.min(self.bytes.len()));
+        for _ in 0..len {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn each(&mut self, mut item: impl FnMut(&mut Self) -> DecodeResult<()>) -> DecodeResult<()> {
+        for _ in 0..self.len()? {
+            item(self)?;
+        }
+        Ok(())
+    }
+
+    fn map<K: Eq + Hash, V>(
+        &mut self,
+        mut entry: impl FnMut(&mut Self) -> DecodeResult<(K, V)>,
+    ) -> DecodeResult<HashMap<K, V>> {
+        Ok(self.seq(&mut entry)?.into_iter().collect())
+    }
+
+    fn ids<I: CachedId>(&mut self) -> DecodeResult<Vec<I>> {
+        self.seq(Self::id)
+    }
+
+    fn id_set<I: CachedId + Eq + Hash>(&mut self) -> DecodeResult<HashSet<I>> {
+        Ok(self.ids()?.into_iter().collect())
+    }
+
+    fn string_table(&mut self) -> DecodeResult<()> {
+        let strings = self.seq(|dec| {
+            let len = dec.len()?;
+            match std::str::from_utf8(dec.take(len)?) {
+                Ok(text) => Ok(text),
+                Err(_) => corrupt("invalid UTF-8 in string table"),
+            }
+        })?;
+        self.interned = vec![None; strings.len()];
+        self.versions = vec![None; strings.len()];
+        self.strings = strings;
+        Ok(())
+    }
+
+    fn string_index(&mut self) -> DecodeResult<usize> {
+        let index = self.len()?;
+        if index >= self.strings.len() {
+            return corrupt(format!("string index {index} out of range"));
+        }
+        Ok(index)
+    }
+
+    fn str(&mut self) -> DecodeResult<&'a str> {
+        let index = self.string_index()?;
+        Ok(self.strings[index])
+    }
+
+    fn interned(&mut self) -> DecodeResult<Interned<DefaultInterner>> {
+        let index = self.string_index()?;
+        Ok(*self.interned[index].get_or_insert_with(|| Interned::intern(self.strings[index])))
+    }
+
+    fn opt_interned(&mut self) -> DecodeResult<Option<Interned<DefaultInterner>>> {
+        self.option(Self::interned)
+    }
+
+    fn flag_set(&mut self) -> DecodeResult<HashSet<Interned<DefaultInterner>>> {
+        Ok(self.seq(Self::interned)?.into_iter().collect())
+    }
+
+    fn cpn(&mut self) -> DecodeResult<Cpn> {
+        Ok(Cpn {
+            category: self.interned()?,
+            package: self.interned()?,
+        })
+    }
+
+    fn version(&mut self) -> DecodeResult<Version> {
+        let index = self.string_index()?;
+        if let Some(version) = &self.versions[index] {
+            return Ok(version.clone());
+        }
+        let Ok(version) = self.strings[index].parse::<Version>() else {
+            return corrupt(format!("invalid version `{}`", self.strings[index]));
+        };
+        self.versions[index] = Some(version.clone());
+        Ok(version)
+    }
+
+    fn cpv(&mut self) -> DecodeResult<Cpv> {
+        let cpn = self.cpn()?;
+        Ok(Cpv::new(cpn, self.version()?))
+    }
+
+    fn option<T>(
+        &mut self,
+        value: impl FnOnce(&mut Self) -> DecodeResult<T>,
+    ) -> DecodeResult<Option<T>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(value(self)?)),
+            tag => corrupt(format!("invalid option tag {tag}")),
+        }
+    }
+
+    fn operator(&mut self) -> DecodeResult<Operator> {
+        match self.u8()? {
+            0 => Ok(Operator::Less),
+            1 => Ok(Operator::LessOrEqual),
+            2 => Ok(Operator::Equal),
+            3 => Ok(Operator::Approximate),
+            4 => Ok(Operator::GreaterOrEqual),
+            5 => Ok(Operator::Greater),
+            tag => corrupt(format!("invalid operator {tag}")),
+        }
+    }
+
+    fn blocker(&mut self) -> DecodeResult<Blocker> {
+        match self.u8()? {
+            0 => Ok(Blocker::Weak),
+            1 => Ok(Blocker::Strong),
+            tag => corrupt(format!("invalid blocker {tag}")),
+        }
+    }
+
+    fn use_change(&mut self) -> DecodeResult<UseChange> {
+        match self.u8()? {
+            0 => Ok(UseChange::Enabled),
+            1 => Ok(UseChange::Iuse),
+            tag => corrupt(format!("invalid USE change {tag}")),
+        }
+    }
+
+    fn downgrade_mode(&mut self) -> DecodeResult<DowngradeMode> {
+        match self.u8()? {
+            0 => Ok(DowngradeMode::Allow),
+            1 => Ok(DowngradeMode::Report),
+            2 => Ok(DowngradeMode::Forbid),
+            tag => corrupt(format!("invalid downgrade mode {tag}")),
+        }
+    }
+
+    fn slot_operator(&mut self) -> DecodeResult<SlotOperator> {
+        match self.u8()? {
+            0 => Ok(SlotOperator::Equal),
+            1 => Ok(SlotOperator::Star),
+            tag => corrupt(format!("invalid slot operator {tag}")),
+        }
+    }
+
+    fn dep(&mut self) -> DecodeResult<Dep> {
+        let cpn = self.cpn()?;
+        let blocker = self.option(Self::blocker)?;
+        let op = self.option(Self::operator)?;
+        let version = self.option(Self::version)?;
+        let glob = self.bool()?;
+        let slot_dep = match self.u8()? {
+            0 => None,
+            1 => Some(SlotDep::Slot {
+                slot: self.option(|dec| {
+                    Ok(Slot {
+                        slot: dec.interned()?,
+                        subslot: dec.opt_interned()?,
+                    })
+                })?,
+                op: self.option(Self::slot_operator)?,
+            }),
+            2 => Some(SlotDep::Operator(self.slot_operator()?)),
+            tag => return corrupt(format!("invalid slot dependency {tag}")),
+        };
+        let use_deps = self.option(|dec| {
+            dec.seq(|dec| {
+                let flag = dec.interned()?;
+                let kind = match dec.u8()? {
+                    0 => UseDepKind::Enabled,
+                    1 => UseDepKind::Disabled,
+                    2 => UseDepKind::Conditional,
+                    3 => UseDepKind::ConditionalInverse,
+                    4 => UseDepKind::Equal,
+                    5 => UseDepKind::EqualInverse,
+                    tag => return corrupt(format!("invalid USE dependency kind {tag}")),
+                };
+                let default = match dec.u8()? {
+                    0 => None,
+                    1 => Some(UseDefault::Enabled),
+                    2 => Some(UseDefault::Disabled),
+                    tag => return corrupt(format!("invalid USE default {tag}")),
+                };
+                Ok(UseDep {
+                    flag,
+                    kind,
+                    default,
+                })
+            })
+        })?;
+        Ok(Dep {
+            cpn,
+            blocker,
+            op,
+            version,
+            glob,
+            slot_dep,
+            use_deps,
+            repo: self.opt_interned()?,
+        })
+    }
+
+    fn domain(&mut self) -> DecodeResult<Domain> {
+        match self.u8()? {
+            0 => Ok(Domain::Target),
+            1 => Ok(Domain::Host),
+            tag => corrupt(format!("invalid domain {tag}")),
+        }
+    }
+
+    fn class(&mut self) -> DecodeResult<DepClass> {
+        match self.u8()? {
+            0 => Ok(DepClass::Depend),
+            1 => Ok(DepClass::Rdepend),
+            2 => Ok(DepClass::Bdepend),
+            3 => Ok(DepClass::Pdepend),
+            4 => Ok(DepClass::Idepend),
+            tag => corrupt(format!("invalid dependency class {tag}")),
+        }
+    }
+
+    fn dep_entries(&mut self) -> DecodeResult<Vec<DepEntry>> {
+        self.seq(|dec| {
+            Ok(match dec.u8()? {
+                0 => DepEntry::Atom(dec.dep()?),
+                1 => DepEntry::UseConditional {
+                    flag: dec.interned()?,
+                    negate: dec.bool()?,
+                    children: dec.dep_entries()?,
+                },
+                2 => DepEntry::AllOf(dec.dep_entries()?),
+                3 => DepEntry::AnyOf(dec.dep_entries()?),
+                4 => DepEntry::ExactlyOneOf(dec.dep_entries()?),
+                5 => DepEntry::AtMostOneOf(dec.dep_entries()?),
+                tag => return corrupt(format!("invalid dependency entry {tag}")),
+            })
+        })
+    }
+
+    fn metadata(&mut self) -> DecodeResult<PackageMetadata> {
+        Ok(PackageMetadata {
+            cpv: self.cpv()?,
+            slot: self.opt_interned()?,
+            subslot: self.opt_interned()?,
+            iuse: self.seq(Self::interned)?,
+            use_flags: self.flag_set()?,
+            repo: self.opt_interned()?,
+            dependencies: PackageDeps {
+                depend: self.dep_entries()?,
+                rdepend: self.dep_entries()?,
+                bdepend: self.dep_entries()?,
+                pdepend: self.dep_entries()?,
+                idepend: self.dep_entries()?,
+            },
+        })
+    }
+
+    fn version_constraint(&mut self) -> DecodeResult<VersionConstraint> {
+        Ok(VersionConstraint {
+            cpn: self.cpn()?,
+            operator: self.operator()?,
+            version: self.version()?,
+            glob: self.bool()?,
+            slot: self.opt_interned()?,
+            subslot: self.opt_interned()?,
+            repo: self.opt_interned()?,
+            use_constraints: self.seq(|dec| Ok((dec.interned()?, dec.bool()?)))?,
+            inverted: self.bool()?,
+        })
+    }
+
+    fn requirement(&mut self) -> DecodeResult<Requirement> {
+        match self.u8()? {
+            0 => Ok(Requirement::Single(self.id()?)),
+            1 => Ok(Requirement::Union(self.id()?)),
+            tag => corrupt(format!("invalid requirement {tag}")),
+        }
+    }
+
+    fn use_config(&mut self) -> DecodeResult<UseConfig> {
+        Ok(UseConfig {
+            enabled: self.flag_set()?,
+            disabled: self.flag_set()?,
+            solver_decided: self.flag_set()?,
+        })
+    }
+
+    fn dep_class_modes(&mut self) -> DecodeResult<DepClassModes> {
+        let mut mode = || match self.u8()? {
+            0 => Ok(DepClassMode::Include),
+            1 => Ok(DepClassMode::Soften),
+            2 => Ok(DepClassMode::Exclude),
+            tag => corrupt(format!("invalid dependency class mode {tag}")),
+        };
+        Ok(DepClassModes {
+            depend: mode()?,
+            rdepend: mode()?,
+            bdepend: mode()?,
+            pdepend: mode()?,
+            idepend: mode()?,
+        })
+    }
+
+    fn flag_virtuals(&mut self) -> DecodeResult<HashMap<Interned<DefaultInterner>, FlagVirtuals>> {
+        self.map(|dec| {
+            Ok((
+                dec.interned()?,
+                FlagVirtuals {
+                    on_condition: dec.id()?,
+                    off_condition: dec.id()?,
+                    on_solvable: dec.id()?,
+                    off_solvable: dec.id()?,
+                    choice_union: dec.id()?,
+                },
+            ))
+        })
+    }
+
+    /// Rebuild the pool by re-interning every entry in ID order, which
+    /// reproduces the original IDs and reverse maps.
+    fn pool(&mut self) -> DecodeResult<PortagePool> {
+        let pool = PortagePool::new();
+        self.each(|dec| {
+            pool.intern_name(PackageName {
+                cpn: dec.cpn()?,
+                slot: dec.opt_interned()?,
+                domain: dec.domain()?,
+            });
+            Ok(())
+        })?;
+        self.sizes.names = pool.names.len();
+        self.each(|dec| {
+            let meta = dec.metadata()?;
+            pool.intern_solvable(dec.id()?, meta);
+            Ok(())
+        })?;
+        self.sizes.solvables = pool.solvable_count();
+        self.each(|dec| {
+            let constraint = dec.version_constraint()?;
+            pool.intern_version_set(dec.id()?, constraint);
+            Ok(())
+        })?;
+        self.sizes.version_sets = pool.version_set_count();
+        self.each(|dec| {
+            pool.intern_version_set_union(dec.ids()?);
+            Ok(())
+        })?;
+        self.sizes.unions = pool.version_set_unions.len();
+        self.each(|dec| {
+            // Compound conditions refer to earlier ones only.
+            dec.sizes.conditions = pool.conditions.len();
+            let condition = match dec.u8()? {
+                0 => Condition::Requirement(dec.id()?),
+                1 => Condition::Binary(LogicalOperator::And, dec.id()?, dec.id()?),
+                2 => Condition::Binary(LogicalOperator::Or, dec.id()?, dec.id()?),
+                tag => return corrupt(format!("invalid condition {tag}")),
+            };
+            pool.intern_condition(condition);
+            Ok(())
+        })?;
+        self.sizes.conditions = pool.conditions.len();
+        self.each(|dec| {
+            pool.intern_string(dec.str()?.to_string());
+            Ok(())
+        })?;
+        self.sizes.strings = pool.strings.len();
+        Ok(pool)
+    }
+
+    fn provider(&mut self) -> DecodeResult<PortageDependencyProvider> {
+        let pool = self.pool()?;
+        let candidates = self.map(|dec| Ok((dec.id()?, dec.ids()?)))?;
+        let dependencies = self.map(|dec| {
+            let sid = dec.id()?;
+            let requirements = dec.seq(|dec| {
+                Ok(ConditionalRequirement {
+                    condition: dec.option(Self::id)?,
+                    requirement: dec.requirement()?,
+                })
+            })?;
+            let constrains = dec.ids()?;
+            Ok((
+                sid,
+                KnownDependencies {
+                    requirements,
+                    constrains,
+                },
+            ))
+        })?;
+        let cpn_slots = self.map(|dec| Ok(((dec.domain()?, dec.cpn()?), dec.ids()?)))?;
+        let blocker_types = self.map(|dec| Ok((dec.id()?, dec.blocker()?)))?;
+        let rebuild_triggers = self.id_set()?;
+        let flag_virtuals = self.flag_virtuals()?;
+        let host_flag_virtuals = self.flag_virtuals()?;
+        let use_config = self.use_config()?;
+        let host_use_config = self.option(Self::use_config)?;
+        let favored = self.map(|dec| Ok((dec.id()?, dec.id()?)))?;
+        let locked = self.map(|dec| Ok((dec.id()?, dec.id()?)))?;
+        let installed = self.id_set()?;
+        let use_changes = self.map(|dec| Ok((dec.id()?, dec.use_change()?)))?;
+        let dep_changes = self.map(|dec| {
+            let sid = dec.id()?;
+            let changes = dec.seq(|dec| {
+                Ok(DepChange {
+                    class: dec.class()?,
+                    added: dec.dep_entries()?,
+                    removed: dec.dep_entries()?,
+                })
+            })?;
+            Ok((sid, changes))
+        })?;
+        let dep_class_policy = DepClassPolicy {
+            built: self.dep_class_modes()?,
+            installed: self.dep_class_modes()?,
+        };
+        let origins = self.map(|dec| {
+            let requirement = dec.requirement()?;
+            let origins = dec.seq(|dec| {
+                Ok(RequirementOrigin {
+                    solvable: dec.id()?,
+                    class: dec.class()?,
+                    atom: dec.str()?.to_string(),
+                })
+            })?;
+            Ok((requirement, origins))
+        })?;
+        let xof_choices = self.map(|dec| {
+            let key = (dec.id()?, dec.class()?, dec.str()?.to_string());
+            Ok((key, dec.seq(Self::ids)?))
+        })?;
+        let roots = self.seq(|dec| Ok((dec.dep()?, dec.domain()?)))?;
+        let synthetic = self.id_set()?;
+        let xof_counter = self.len()?;
+        let released = self.id_set()?;
+        let downgrade_policy = DowngradePolicy {
+            mode: self.downgrade_mode()?,
+            allowed: self.seq(Self::dep)?,
+        };
+        let downgrade_reasons = self.map(|dec| Ok((dec.id()?, dec.id()?)))?;
+        let masked = self.map(|dec| Ok((dec.id()?, dec.id()?)))?;
+        let pinned_any_of = self.map(|dec| {
+            let key = (dec.id()?, dec.class()?, dec.str()?.to_string());
+            Ok((key, dec.str()?.to_string()))
+        })?;
+
+        Ok(PortageDependencyProvider {
+            pool,
+            candidates: RefCell::new(candidates),
+            dependencies: RefCell::new(dependencies),
+            cpn_slots: RefCell::new(cpn_slots),
+            blocker_types: RefCell::new(blocker_types),
+            rebuild_triggers: RefCell::new(rebuild_triggers),
+            flag_virtuals,
+            host_flag_virtuals,
+            use_config,
+            host_use_config,
+            favored: RefCell::new(favored),
+            locked: RefCell::new(locked),
+            installed: RefCell::new(installed),
+            dep_class_policy,
+            origins: RefCell::new(origins),
+            xof_choices: RefCell::new(xof_choices),
+            roots,
+            synthetic: RefCell::new(synthetic),
+            xof_counter: Cell::new(xof_counter),
+            lazy: None,
+            monitor: SolveMonitor::default(),
+            ordering: None,
+            use_changes: RefCell::new(use_changes),
+            dep_changes: RefCell::new(dep_changes),
+            released,
+            downgrade_policy,
+            downgrade_reasons: RefCell::new(downgrade_reasons),
+            masked,
+            pinned_any_of,
+            pulled: RefCell::default(),
+            preferred_unions: RefCell::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use resolvo::{Problem, Solver};
+
+    use super::*;
+    use crate::repository::InMemoryRepository;
+    use crate::tests::{pkg, pkg_build_run, pkg_subslot, solve_cpvs};
+
+    fn cache_repo() -> (InMemoryRepository, UseConfig) {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "^^ ( dev-lib/a dev-lib/b ) ssl? ( >=dev-lib/openssl-3:= ) !dev-lib/old",
+            "",
+        ));
+        repo.add(pkg("dev-lib/a-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/b-1.0", "0", vec![]));
+        repo.add(pkg_subslot("dev-lib/openssl-3.0.0", "0", "3", vec![]));
+        repo.add(pkg("dev-lib/old-1.0", "0", vec![]));
+        let use_config = UseConfig {
+            enabled: ["ssl"].into_iter().map(Interned::intern).collect(),
+            solver_decided: ["xml"].into_iter().map(Interned::intern).collect(),
+            ..UseConfig::default()
+        };
+        (repo, use_config)
+    }
+
+    /// Writes the provider for [`cache_repo`] to a cache file.
+    fn cache_bytes() -> (Vec<u8>, CacheKey) {
+        let (repo, use_config) = cache_repo();
+        let repository = repository_fingerprint(&repo, &InstalledSet::default()).unwrap();
+        let key = CacheKey::new(repository, &use_config, None, &DepClassPolicy::default());
+        let mut built = PortageDependencyProvider::new(&repo, &use_config);
+        built.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut bytes = Vec::new();
+        built.write_cache(&mut bytes, repository).unwrap();
+        (bytes, key)
+    }
+
+    #[test]
+    fn provider_cache_round_trip() {
+        let (repo, use_config) = cache_repo();
+        let (bytes, key) = cache_bytes();
+        let mut built = PortageDependencyProvider::new(&repo, &use_config);
+        let root = Dep::parse("app-misc/app").unwrap();
+        built.intern_requirement(&root);
+        assert_eq!(key.config, built.config_fingerprint());
+
+        let mut loaded = PortageDependencyProvider::read_cache(bytes.as_slice(), &key).unwrap();
+        assert_eq!(loaded.root_atoms(), built.root_atoms());
+        assert_eq!(
+            loaded.pool().solvable_count(),
+            built.pool().solvable_count()
+        );
+        assert_eq!(loaded.config_fingerprint(), built.config_fingerprint());
+
+        let solve = |provider: PortageDependencyProvider| {
+            let mut provider = provider;
+            let req = provider.intern_requirement(&root);
+            let mut solver = Solver::new(provider);
+            let solution = solver
+                .solve(Problem::new().requirements(vec![req]))
+                .unwrap();
+            let provider = solver.provider();
+            let mut cpvs: Vec<String> = solution
+                .iter()
+                .filter(|&&sid| !provider.is_synthetic(sid))
+                .map(|&sid| provider.package_metadata(sid).cpv.to_string())
+                .collect();
+            cpvs.sort();
+            let tree = provider.render_tree(&solution);
+            (cpvs, tree)
+        };
+        // Interning the root again must reuse the cached version sets.
+        let vs_count = loaded.pool().version_set_count();
+        loaded.intern_requirement(&root);
+        assert_eq!(loaded.pool().version_set_count(), vs_count);
+        assert_eq!(solve(loaded), solve(built));
+    }
+
+    #[test]
+    fn cache_key_read() {
+        let (bytes, key) = cache_bytes();
+        assert_eq!(CacheKey::read(bytes.as_slice()).unwrap(), key);
+        assert!(matches!(
+            CacheKey::read(&bytes[..12]),
+            Err(CacheError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn provider_cache_rejects_stale_key() {
+        let (bytes, key) = cache_bytes();
+        let stale = CacheKey {
+            repository: key.repository ^ 1,
+            ..key
+        };
+        assert!(matches!(
+            PortageDependencyProvider::read_cache(bytes.as_slice(), &stale),
+            Err(CacheError::Stale { .. })
+        ));
+    }
+
+    #[test]
+    fn cache_key_covers_repository_and_configuration() {
+        let (mut repo, use_config) = cache_repo();
+        let installed = InstalledSet::default();
+        let repository = repository_fingerprint(&repo, &installed).unwrap();
+        let key = CacheKey::new(repository, &use_config, None, &DepClassPolicy::default());
+        repo.add(pkg("dev-lib/a-2.0", "0", vec![]));
+        assert_ne!(
+            repository_fingerprint(&repo, &installed).unwrap(),
+            repository
+        );
+        let other = CacheKey::new(
+            repository,
+            &UseConfig::default(),
+            None,
+            &DepClassPolicy::default(),
+        );
+        assert_ne!(other, key);
+    }
+
+    #[test]
+    fn provider_cache_rejects_truncated_file() {
+        let (bytes, key) = cache_bytes();
+        assert!(matches!(
+            PortageDependencyProvider::read_cache(&bytes[..bytes.len() - 1], &key),
+            Err(CacheError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn provider_cache_rejects_future_version() {
+        let (mut bytes, key) = cache_bytes();
+        bytes[8] = 0xff;
+        assert!(matches!(
+            PortageDependencyProvider::read_cache(bytes.as_slice(), &key),
+            Err(CacheError::Version(_))
+        ));
+    }
+
+    #[test]
+    fn provider_cache_rejects_out_of_range_ids() {
+        let (repo, use_config) = cache_repo();
+        let (_, key) = cache_bytes();
+        let corrupted = |corrupt: &dyn Fn(&mut PortageDependencyProvider)| {
+            let mut built = PortageDependencyProvider::new(&repo, &use_config);
+            built.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+            corrupt(&mut built);
+            let mut bytes = Vec::new();
+            built.write_cache(&mut bytes, key.repository).unwrap();
+            match PortageDependencyProvider::read_cache(bytes.as_slice(), &key) {
+                Err(CacheError::Corrupt(message)) => message,
+                other => panic!("expected a corrupt cache, got {:?}", other.err()),
+            }
+        };
+
+        let message = corrupted(&|provider| {
+            let sid = SolvableId::from_usize(provider.pool().solvable_count());
+            provider.installed.borrow_mut().insert(sid);
+        });
+        assert!(message.starts_with("solvable id"), "{message}");
+        let message = corrupted(&|provider| {
+            let reason = StringId::from_usize(provider.pool().strings.len());
+            provider.masked.insert(SolvableId::from_usize(0), reason);
+        });
+        assert!(message.starts_with("string id"), "{message}");
+    }
+
+    #[test]
+    fn provider_cache_keeps_settings() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "^^ ( dev-lib/a dev-lib/b )",
+            "",
+        ));
+        repo.add(pkg("dev-lib/a-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/b-1.0", "0", vec![]));
+        let use_config = UseConfig::default();
+        let key = CacheKey::new(7, &use_config, None, &DepClassPolicy::default());
+
+        let mut built = PortageDependencyProvider::new(&repo, &use_config)
+            .with_downgrade_policy(crate::DowngradePolicy::forbid());
+        let a = built.find_solvables(Domain::Target, &Cpv::parse("dev-lib/a-1.0").unwrap())[0];
+        built.mask(a, "broken");
+        let mut bytes = Vec::new();
+        built.write_cache(&mut bytes, 7).unwrap();
+        let loaded = PortageDependencyProvider::read_cache(bytes.as_slice(), &key).unwrap();
+
+        assert_eq!(loaded.xof_counter.get(), built.xof_counter.get());
+        assert_eq!(loaded.downgrade_policy(), built.downgrade_policy());
+        assert!(loaded.is_masked(a));
+        let cpvs = solve_cpvs(loaded, &["app-misc/app"]);
+        assert!(cpvs.contains("dev-lib/b-1.0"));
+        assert!(!cpvs.contains("dev-lib/a-1.0"));
+    }
+
+    #[test]
+    fn provider_cache_refuses_custom_ordering() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg("dev-lib/a-1.0", "0", vec![]));
+        let provider = PortageDependencyProvider::new(&repo, &UseConfig::default())
+            .with_ordering(crate::OldestFirst);
+        assert!(matches!(
+            provider.write_cache(&mut Vec::new(), 0),
+            Err(CacheError::CustomOrdering)
+        ));
+    }
+}
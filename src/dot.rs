@@ -0,0 +1,413 @@
+//! Graphviz export.
+//!
+//! [`solution_dot`](PortageDependencyProvider::solution_dot) renders the
+//! labeled [`DepEdge`](crate::DepEdge) graph of a solution and
+//! [`conflict_dot`](PortageDependencyProvider::conflict_dot) renders the
+//! conflict graph of a failed solve, both in the DOT language.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use portage_atom::DepEntry;
+use resolvo::conflict::{ConflictCause, ConflictEdge, ConflictGraph, ConflictNode};
+use resolvo::{ArenaId, Interner, Requirement, SolvableId, VersionSetId};
+
+use crate::graph::strongly_connected_components;
+use crate::pool::DepClass;
+use crate::provider::PortageDependencyProvider;
+
+/// Edge color for each dependency class in
+/// [`solution_dot`](PortageDependencyProvider::solution_dot).
+fn class_color(class: DepClass) -> &'static str {
+    match class {
+        DepClass::Depend => "blue",
+        DepClass::Rdepend => "black",
+        DepClass::Bdepend => "purple",
+        DepClass::Pdepend => "gray40",
+        DepClass::Idepend => "darkorange",
+    }
+}
+
+/// Quote `text` as a DOT string.
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl PortageDependencyProvider {
+    /// Render the dependency graph of `solution` in the DOT language.
+    ///
+    /// Nodes are the real packages of the solution; installed packages are
+    /// drawn with a double border. Edges come from
+    /// [`dependency_graph`](Self::dependency_graph), are labeled and colored
+    /// by [`DepClass`], and `PDEPEND` edges are dashed. Members of
+    /// dependency cycles are filled and the edges between them drawn bold,
+    /// whether or not [`install_order`](Self::install_order) can break the
+    /// cycle.
+    pub fn solution_dot(&self, solution: &[SolvableId]) -> String {
+        let nodes: Vec<SolvableId> = solution
+            .iter()
+            .copied()
+            .filter(|&sid| !self.is_synthetic(sid))
+            .collect();
+        let edges = self.dependency_graph(solution);
+
+        let mut in_cycle: HashSet<SolvableId> = HashSet::new();
+        let self_loops = edges.iter().filter(|e| e.from == e.to).map(|e| e.from);
+        in_cycle.extend(self_loops);
+        for component in strongly_connected_components(&nodes, edges.iter().map(|e| (e.from, e.to)))
+        {
+            if component.len() > 1 {
+                in_cycle.extend(component);
+            }
+        }
+
+        let mut out = String::from("digraph solution {\n  node [shape=box];\n");
+        for &sid in &nodes {
+            let mut attrs = vec![format!("label={}", quote(&self.solvable_label(sid)))];
+            if self.is_installed(sid) {
+                attrs.push("peripheries=2".to_string());
+            }
+            if in_cycle.contains(&sid) {
+                attrs.push("style=filled".to_string());
+                attrs.push("fillcolor=\"#ffd6d6\"".to_string());
+            }
+            let _ = writeln!(out, "  s{} [{}];", sid.to_usize(), attrs.join(", "));
+        }
+        for edge in &edges {
+            let mut attrs = vec![
+                format!("label={}", quote(&edge.class.to_string())),
+                format!("color={}", class_color(edge.class)),
+                format!("fontcolor={}", class_color(edge.class)),
+            ];
+            if edge.class == DepClass::Pdepend {
+                attrs.push("style=dashed".to_string());
+            }
+            if in_cycle.contains(&edge.from) && in_cycle.contains(&edge.to) {
+                attrs.push("penwidth=2".to_string());
+            }
+            let _ = writeln!(
+                out,
+                "  s{} -> s{} [{}];",
+                edge.from.to_usize(),
+                edge.to.to_usize(),
+                attrs.join(", ")
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Render a resolvo conflict graph, from
+    /// [`Conflict::graph`](resolvo::conflict::Conflict::graph), in the DOT
+    /// language.
+    ///
+    /// Candidates that resolvo merges for its own report (same name, same
+    /// neighbours) are drawn as one node listing their versions. The
+    /// internal USE-flag, `^^ ( )` / `?? ( )` and `|| ( ( ) )` virtuals are
+    /// shown as `USE="flag"` / `USE="-flag"` and as the group alternative
+    /// they stand for. Requirement edges are black, or red when nothing
+    /// satisfies them; conflict edges (blockers, one version per slot,
+    /// locks, exclusions) are red and dashed.
+    pub fn conflict_dot(&self, graph: &ConflictGraph) -> String {
+        let merged = graph.simplify(self);
+        let nodes = graph.graph.raw_nodes();
+        // Node of the first member of each merged group; the other
+        // members are folded into it.
+        let representative: Vec<usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let ConflictNode::Solvable(id) = node.weight else {
+                    return index;
+                };
+                let Some(group) = id.solvable().and_then(|sid| merged.get(&sid)) else {
+                    return index;
+                };
+                let first = ConflictNode::Solvable(group.ids[0].into());
+                nodes
+                    .iter()
+                    .position(|node| node.weight == first)
+                    .unwrap_or(index)
+            })
+            .collect();
+
+        let mut out = String::from("digraph conflict {\n  node [shape=box];\n");
+        for (index, node) in nodes.iter().enumerate() {
+            if representative[index] != index {
+                continue;
+            }
+            let attrs = match node.weight {
+                ConflictNode::Solvable(id) => match id.solvable() {
+                    None => "label=\"root\", shape=ellipse".to_string(),
+                    Some(sid) => {
+                        let label = match merged.get(&sid) {
+                            Some(group) if group.ids.len() > 1 => {
+                                let versions: Vec<String> = group
+                                    .ids
+                                    .iter()
+                                    .map(|&sid| {
+                                        self.pool.resolve_solvable(sid).cpv.version.to_string()
+                                    })
+                                    .collect();
+                                format!(
+                                    "{} {}",
+                                    self.pool.resolve_name(self.pool.solvable_name(sid)),
+                                    versions.join(" | ")
+                                )
+                            }
+                            _ => self.solvable_label(sid),
+                        };
+                        let mut attrs = format!("label={}", quote(&label));
+                        if self.is_synthetic(sid) {
+                            attrs.push_str(", style=rounded");
+                        }
+                        attrs
+                    }
+                },
+                ConflictNode::UnresolvedDependency => {
+                    "label=\"no candidates\", shape=octagon, color=red".to_string()
+                }
+                ConflictNode::Excluded(reason) => format!(
+                    "label={}, shape=note, color=red",
+                    quote(&format!("excluded: {}", self.pool.resolve_string(reason)))
+                ),
+            };
+            let _ = writeln!(out, "  n{index} [{attrs}];");
+        }
+
+        let mut seen = HashSet::new();
+        for edge in graph.graph.raw_edges() {
+            let from = representative[edge.source().index()];
+            let to = representative[edge.target().index()];
+            if !seen.insert((from, to, edge.weight)) {
+                continue;
+            }
+            let unresolved = Some(edge.target()) == graph.unresolved_node;
+            let attrs = match edge.weight {
+                ConflictEdge::Requires(requirement) => format!(
+                    "label={}, color={}",
+                    quote(&self.requirement_label(requirement)),
+                    if unresolved { "red" } else { "black" }
+                ),
+                ConflictEdge::Conflict(cause) => {
+                    let label = match cause {
+                        ConflictCause::Constrains(vs_id) => {
+                            let constraint = self.version_set_label(vs_id);
+                            match self.blocker_type(vs_id) {
+                                Some(_) => format!("blocked by {constraint}"),
+                                None => format!("constrains {constraint}"),
+                            }
+                        }
+                        ConflictCause::ForbidMultipleInstances => {
+                            "one version per slot".to_string()
+                        }
+                        ConflictCause::Locked(_) => "locked".to_string(),
+                        ConflictCause::Excluded => "excluded".to_string(),
+                    };
+                    format!("label={}, color=red, style=dashed", quote(&label))
+                }
+            };
+            let _ = writeln!(out, "  n{from} -> n{to} [{attrs}];");
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Portage-style label of a solvable: `cat/pkg-ver:slot` for packages,
+    /// the flag or group alternative for internal virtuals.
+    fn solvable_label(&self, sid: SolvableId) -> String {
+        self.virtual_label(sid)
+            .unwrap_or_else(|| self.display_solvable(sid).to_string())
+    }
+
+    /// Readable name of an internal virtual, `None` for real packages.
+    fn virtual_label(&self, sid: SolvableId) -> Option<String> {
+        let meta = self.pool.resolve_solvable(sid);
+        if meta.cpv.cpn.category.as_str() != "virtual" {
+            return None;
+        }
+        let package = meta.cpv.cpn.package.as_str();
+        if let Some(flag) = package.strip_prefix("USE_") {
+            return Some(format!("USE=\"{flag}\""));
+        }
+        if let Some(flag) = package.strip_prefix("NotUSE_") {
+            return Some(format!("USE=\"-{flag}\""));
+        }
+        if let Some(rest) = package.strip_prefix("xof_") {
+            let (group, choice) = rest.rsplit_once('_')?;
+            // The `?? ( )` "none" choice is not recorded with its group;
+            // find the group through the first real alternative.
+            let member = if choice == "none" {
+                let first = format!("xof_{group}_0");
+                (0..self.pool.solvable_count())
+                    .map(SolvableId::from_usize)
+                    .find(|&other| {
+                        let cpn = self.pool.resolve_solvable(other).cpv.cpn;
+                        cpn.category.as_str() == "virtual" && cpn.package.as_str() == first
+                    })?
+            } else {
+                sid
+            };
+            let xof_choices = self.xof_choices.borrow();
+            let (text, index) = xof_choices.iter().find_map(|((_, _, text), occurrences)| {
+                occurrences.iter().find_map(|choices| {
+                    let index = choices.iter().position(|&choice| choice == member)?;
+                    Some((text.clone(), index))
+                })
+            })?;
+            if choice == "none" {
+                return Some(format!("{text}: none"));
+            }
+            let alternative = match DepEntry::parse(&text).ok()?.first()? {
+                DepEntry::ExactlyOneOf(children) | DepEntry::AtMostOneOf(children) => {
+                    children.get(index)?.to_string()
+                }
+                _ => return None,
+            };
+            return Some(format!("{text}: {alternative}"));
+        }
+        if package.starts_with("allof_") {
+            let deps = self.known_dependencies(sid)?;
+            let members: Vec<String> = deps
+                .requirements
+                .iter()
+                .map(|req| self.requirement_label(req.requirement))
+                .collect();
+            return Some(format!("( {} )", members.join(" ")));
+        }
+        None
+    }
+
+    /// Label of a version set, naming internal virtuals readably.
+    fn version_set_label(&self, vs_id: VersionSetId) -> String {
+        let name = self.pool.version_set_name(vs_id);
+        let candidate = self
+            .candidates
+            .borrow()
+            .get(&name)
+            .and_then(|candidates| candidates.first().copied());
+        candidate
+            .and_then(|sid| self.virtual_label(sid))
+            .unwrap_or_else(|| self.display_version_set(vs_id).to_string())
+    }
+
+    /// Label of a requirement; unions are joined with `|`.
+    fn requirement_label(&self, requirement: Requirement) -> String {
+        match requirement {
+            Requirement::Single(vs_id) => self.version_set_label(vs_id),
+            Requirement::Union(union_id) => {
+                let labels: Vec<String> = self
+                    .pool
+                    .resolve_version_set_union(union_id)
+                    .iter()
+                    .map(|&vs_id| self.version_set_label(vs_id))
+                    .collect();
+                labels.join(" | ")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use portage_atom::Dep;
+    use resolvo::{Problem, Solver};
+
+    use super::*;
+    use crate::tests::{Fixture, pkg, pkg_build_run};
+
+    /// app DEPEND lib; lib RDEPEND cyc, cyc PDEPEND lib. bad cannot be
+    /// solved.
+    fn dot_fixture() -> Fixture {
+        let mut cyc = pkg("dev-lib/cyc-1.0", "0", vec![]);
+        cyc.dependencies.pdepend = DepEntry::parse("dev-lib/lib").unwrap();
+        Fixture::new([
+            pkg_build_run("app-misc/app-1.0", "dev-lib/lib", ""),
+            pkg_build_run("dev-lib/lib-1.0", "", "dev-lib/cyc"),
+            cyc,
+            pkg("dev-lib/x-1.0", "0", vec![]),
+            pkg("dev-lib/y-1.0", "0", vec![]),
+            pkg_build_run(
+                "app-misc/bad-1.0",
+                "",
+                "^^ ( dev-lib/x >=dev-lib/y-2 ) !dev-lib/x",
+            ),
+        ])
+    }
+
+    #[test]
+    fn solution_dot_styles_classes_and_cycles() {
+        let mut provider = dot_fixture().provider();
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let provider = solver.provider();
+        let dot = provider.solution_dot(&solution);
+        let id = |cpv: &str| {
+            let sid = solution
+                .iter()
+                .find(|&&sid| provider.package_metadata(sid).cpv.to_string() == cpv)
+                .unwrap();
+            format!("s{}", sid.to_usize())
+        };
+        let (app, lib, cyc) = (
+            id("app-misc/app-1.0"),
+            id("dev-lib/lib-1.0"),
+            id("dev-lib/cyc-1.0"),
+        );
+        assert!(dot.starts_with("digraph solution {"));
+        assert!(dot.contains(&format!("{app} [label=\"app-misc/app-1.0:0\"];")));
+        assert!(dot.contains(&format!(
+            "{lib} [label=\"dev-lib/lib-1.0:0\", style=filled, fillcolor=\"#ffd6d6\"];"
+        )));
+        assert!(dot.contains(&format!(
+            "{app} -> {lib} [label=\"DEPEND\", color=blue, fontcolor=blue];"
+        )));
+        assert!(dot.contains(&format!(
+            "{lib} -> {cyc} [label=\"RDEPEND\", color=black, fontcolor=black, penwidth=2];"
+        )));
+        assert!(dot.contains(&format!(
+            "{cyc} -> {lib} [label=\"PDEPEND\", color=gray40, fontcolor=gray40, \
+             style=dashed, penwidth=2];"
+        )));
+    }
+
+    #[test]
+    fn conflict_dot_labels_causes() {
+        use resolvo::UnsolvableOrCancelled;
+
+        let mut provider = dot_fixture().provider();
+        let req = provider.intern_requirement(&Dep::parse("app-misc/bad").unwrap());
+        let mut solver = Solver::new(provider);
+        let Err(UnsolvableOrCancelled::Unsolvable(conflict)) =
+            solver.solve(Problem::new().requirements(vec![req]))
+        else {
+            panic!("expected a conflict");
+        };
+        let dot = solver.provider().conflict_dot(&conflict.graph(&solver));
+        assert!(dot.starts_with("digraph conflict {"));
+        assert!(dot.contains("label=\"root\""));
+        assert!(
+            dot.contains("^^ ( dev-lib/x >=dev-lib/y-2 ): dev-lib/x"),
+            "{dot}"
+        );
+        assert!(dot.contains("label=\"no candidates\""), "{dot}");
+        assert!(dot.contains("blocked by"), "{dot}");
+        assert!(!dot.contains("virtual/xof_"), "{dot}");
+    }
+}
@@ -0,0 +1,343 @@
+//! Downgrade protection.
+//!
+//! Installed versions are only favored, so resolvo may replace one with an
+//! older version when something requires it. A [`DowngradePolicy`] can
+//! forbid that, with per-atom exceptions, and
+//! [`downgrades`](PortageDependencyProvider::downgrades) lists the
+//! downgrades of a solution together with what forced them.
+
+use portage_atom::Dep;
+use resolvo::{NameId, SolvableId, StringId};
+
+use crate::explain::VersionObstacle;
+use crate::provider::{PortageDependencyProvider, dep_matches_solvable};
+
+/// How a solve treats candidates older than an installed version of the
+/// same name (category, package, slot and domain).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DowngradeMode {
+    /// Downgrades are ordinary candidates (the default).
+    #[default]
+    Allow,
+    /// Downgrades are ordinary candidates, but
+    /// [`downgrades`](PortageDependencyProvider::downgrades) marks the
+    /// ones [`Forbid`](Self::Forbid) would reject, for dry runs.
+    Report,
+    /// Downgrades are excluded unless an allowed atom matches them.
+    Forbid,
+}
+
+/// Downgrade protection of a provider.
+///
+/// ```
+/// use portage_atom::Dep;
+/// use portage_atom_resolvo::DowngradePolicy;
+///
+/// // No downgrades, except of dev-libs/foo below 2.
+/// let policy = DowngradePolicy::forbid().allow(Dep::parse("<dev-libs/foo-2").unwrap());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DowngradePolicy {
+    pub mode: DowngradeMode,
+    /// Atoms whose matching candidates may be downgraded to in every mode.
+    pub allowed: Vec<Dep>,
+}
+
+impl DowngradePolicy {
+    /// [`DowngradeMode::Report`] without exceptions.
+    pub fn report() -> Self {
+        Self {
+            mode: DowngradeMode::Report,
+            allowed: Vec::new(),
+        }
+    }
+
+    /// [`DowngradeMode::Forbid`] without exceptions.
+    pub fn forbid() -> Self {
+        Self {
+            mode: DowngradeMode::Forbid,
+            allowed: Vec::new(),
+        }
+    }
+
+    /// The same policy, also allowing downgrades to candidates matching
+    /// `dep`.
+    pub fn allow(mut self, dep: Dep) -> Self {
+        self.allowed.push(dep);
+        self
+    }
+}
+
+/// A package of a solution that is older than an installed version it
+/// replaces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Downgrade {
+    /// The selected, older package.
+    pub solvable: SolvableId,
+    /// The newest installed version of the same name.
+    pub installed: SolvableId,
+    /// Whether an atom of [`DowngradePolicy::allowed`] matches `solvable`.
+    /// Under [`DowngradeMode::Report`], `false` marks a downgrade that
+    /// [`DowngradeMode::Forbid`] would reject.
+    pub allowed: bool,
+    /// What in the solution rules out `installed`, as
+    /// [`explain_exclusion`](PortageDependencyProvider::explain_exclusion)
+    /// reports it: the requirements that forced the downgrade.
+    pub obstacles: Vec<VersionObstacle>,
+}
+
+impl PortageDependencyProvider {
+    /// Protect installed versions from downgrades as `policy` says.
+    /// Replaces the previous policy; the default is
+    /// [`DowngradeMode::Allow`].
+    pub fn with_downgrade_policy(mut self, policy: DowngradePolicy) -> Self {
+        self.downgrade_policy = policy;
+        self
+    }
+
+    pub fn downgrade_policy(&self) -> &DowngradePolicy {
+        &self.downgrade_policy
+    }
+
+    /// Packages of `solution` older than an installed version of their
+    /// name, in solution order, whatever the [`DowngradePolicy`].
+    pub fn downgrades(&self, solution: &[SolvableId]) -> Vec<Downgrade> {
+        solution
+            .iter()
+            .filter_map(|&sid| {
+                if self.is_installed(sid) {
+                    return None;
+                }
+                let installed = self.newest_installed(self.pool.solvable_name(sid))?;
+                let version = &self.pool.resolve_solvable(sid).cpv.version;
+                if *version >= self.pool.resolve_solvable(installed).cpv.version {
+                    return None;
+                }
+                Some(Downgrade {
+                    solvable: sid,
+                    installed,
+                    allowed: self.downgrade_allowed(sid),
+                    obstacles: self.explain_exclusion(solution, installed),
+                })
+            })
+            .collect()
+    }
+
+    /// Candidates of `name` that [`DowngradeMode::Forbid`] excludes, with
+    /// the reason resolvo reports in conflicts.
+    pub(crate) fn forbidden_downgrades(
+        &self,
+        name: NameId,
+        candidates: &[SolvableId],
+    ) -> Vec<(SolvableId, StringId)> {
+        if self.downgrade_policy.mode != DowngradeMode::Forbid {
+            return Vec::new();
+        }
+        let Some(installed) = self.newest_installed(name) else {
+            return Vec::new();
+        };
+        let older: Vec<SolvableId> = candidates
+            .iter()
+            .copied()
+            .filter(|&sid| self.forbids_downgrade(installed, sid))
+            .collect();
+        if older.is_empty() {
+            return Vec::new();
+        }
+        let reason = *self
+            .downgrade_reasons
+            .borrow_mut()
+            .entry(installed)
+            .or_insert_with(|| {
+                self.pool.intern_string(format!(
+                    "downgrade from installed {} is forbidden",
+                    self.pool.resolve_solvable(installed).cpv
+                ))
+            });
+        older.into_iter().map(|sid| (sid, reason)).collect()
+    }
+
+    /// The installed solvable [`DowngradeMode::Forbid`] protects from
+    /// `candidate`, if any.
+    pub(crate) fn forbidding_downgrade(&self, candidate: SolvableId) -> Option<SolvableId> {
+        if self.downgrade_policy.mode != DowngradeMode::Forbid {
+            return None;
+        }
+        let installed = self.newest_installed(self.pool.solvable_name(candidate))?;
+        self.forbids_downgrade(installed, candidate)
+            .then_some(installed)
+    }
+
+    /// Whether `candidate` is a downgrade from `installed` that no allowed
+    /// atom covers.
+    fn forbids_downgrade(&self, installed: SolvableId, candidate: SolvableId) -> bool {
+        !self.is_installed(candidate)
+            && self.pool.resolve_solvable(candidate).cpv.version
+                < self.pool.resolve_solvable(installed).cpv.version
+            && !self.downgrade_allowed(candidate)
+    }
+
+    /// Newest installed solvable of `name`.
+    fn newest_installed(&self, name: NameId) -> Option<SolvableId> {
+        let installed = self.installed.borrow();
+        self.candidates
+            .borrow()
+            .get(&name)?
+            .iter()
+            .copied()
+            .filter(|sid| installed.contains(sid))
+            .max_by(|&a, &b| {
+                let version = |sid| &self.pool.resolve_solvable(sid).cpv.version;
+                version(a).cmp(version(b))
+            })
+    }
+
+    fn downgrade_allowed(&self, solvable: SolvableId) -> bool {
+        let meta = self.pool.resolve_solvable(solvable);
+        let use_config = self.domain_use_config(self.domain(solvable));
+        self.downgrade_policy
+            .allowed
+            .iter()
+            .any(|dep| dep_matches_solvable(dep, meta, use_config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use resolvo::{Problem, Solver};
+
+    use super::*;
+    use crate::tests::{Fixture, pkg, pkg_build_run};
+
+    /// lib-2.0 is installed and app-1.0 needs `<dev-lib/lib-2`.
+    fn downgrade_fixture() -> Fixture {
+        Fixture::new([
+            pkg_build_run("app-misc/app-1.0", "", "<dev-lib/lib-2"),
+            pkg("dev-lib/lib-1.0", "0", vec![]),
+            pkg("dev-lib/lib-2.0", "0", vec![]),
+        ])
+        .favored([pkg("dev-lib/lib-2.0", "0", vec![])])
+    }
+
+    fn downgrade_provider(policy: DowngradePolicy) -> PortageDependencyProvider {
+        downgrade_fixture()
+            .builder()
+            .downgrade_policy(policy)
+            .build()
+            .unwrap()
+    }
+
+    fn solve_app(
+        mut provider: PortageDependencyProvider,
+    ) -> (
+        Solver<PortageDependencyProvider>,
+        Result<Vec<SolvableId>, resolvo::UnsolvableOrCancelled>,
+    ) {
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        let result = solver.solve(Problem::new().requirements(vec![req]));
+        (solver, result)
+    }
+
+    /// Solves with `policy` and checks that the downgrade is listed with
+    /// the requirement that forced it and is not covered by an allowed atom.
+    fn assert_listed_downgrade(policy: DowngradePolicy) {
+        use crate::explain::VersionObstacle;
+
+        let (solver, result) = solve_app(downgrade_provider(policy));
+        let solution = result.unwrap();
+        let provider = solver.provider();
+        let downgrades = provider.downgrades(&solution);
+        assert_eq!(downgrades.len(), 1);
+        let downgrade = &downgrades[0];
+        assert_eq!(
+            provider
+                .package_metadata(downgrade.solvable)
+                .cpv
+                .to_string(),
+            "dev-lib/lib-1.0"
+        );
+        assert_eq!(
+            provider
+                .package_metadata(downgrade.installed)
+                .cpv
+                .to_string(),
+            "dev-lib/lib-2.0"
+        );
+        assert!(!downgrade.allowed);
+        let [VersionObstacle::Requirement { origins }] = downgrade.obstacles.as_slice() else {
+            panic!("unexpected obstacles: {:?}", downgrade.obstacles);
+        };
+        assert_eq!(origins[0].atom, "<dev-lib/lib-2");
+        assert_eq!(
+            provider
+                .package_metadata(origins[0].solvable)
+                .cpv
+                .to_string(),
+            "app-misc/app-1.0"
+        );
+    }
+
+    fn assert_forbidden_downgrade(provider: PortageDependencyProvider) {
+        use resolvo::UnsolvableOrCancelled;
+
+        let (solver, result) = solve_app(provider);
+        let Err(UnsolvableOrCancelled::Unsolvable(conflict)) = result else {
+            panic!("expected a conflict");
+        };
+        let message = conflict.display_user_friendly(&solver).to_string();
+        assert!(
+            message.contains("downgrade from installed dev-lib/lib-2.0 is forbidden"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn allowed_downgrade_is_listed() {
+        assert_listed_downgrade(DowngradePolicy::default());
+    }
+
+    #[test]
+    fn reported_downgrade_is_listed() {
+        assert_listed_downgrade(DowngradePolicy::report());
+    }
+
+    #[test]
+    fn forbidden_downgrade_is_unsolvable() {
+        assert_forbidden_downgrade(downgrade_provider(DowngradePolicy::forbid()));
+    }
+
+    #[test]
+    fn lazy_forbidden_downgrade_is_unsolvable() {
+        let lazy = downgrade_fixture()
+            .lazy_builder()
+            .downgrade_policy(DowngradePolicy::forbid())
+            .build()
+            .unwrap();
+        assert_forbidden_downgrade(lazy);
+    }
+
+    #[test]
+    fn allowed_atom_permits_downgrade() {
+        // The downgrade goes through, marked as allowed.
+        let policy = DowngradePolicy::forbid().allow(Dep::parse("<dev-lib/lib-2").unwrap());
+        let (solver, result) = solve_app(downgrade_provider(policy));
+        let solution = result.unwrap();
+        let provider = solver.provider();
+        assert_eq!(provider.downgrade_policy().mode, DowngradeMode::Forbid);
+        let downgrades = provider.downgrades(&solution);
+        assert_eq!(downgrades.len(), 1);
+        assert!(downgrades[0].allowed);
+    }
+
+    #[test]
+    fn forbid_without_conflict_downgrades_nothing() {
+        let mut provider = downgrade_provider(DowngradePolicy::forbid());
+        let req = provider.intern_requirement(&Dep::parse("dev-lib/lib").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        assert!(solver.provider().downgrades(&solution).is_empty());
+    }
+}
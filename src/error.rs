@@ -0,0 +1,159 @@
+//! Errors reported while building a provider.
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+
+use portage_atom::Cpv;
+use portage_atom::gentoo_interner::{DefaultInterner, Interned};
+
+use crate::budget::{CancelReason, SolveStats};
+use crate::pool::{DepClass, Domain};
+use crate::repository::RepositoryError;
+
+/// Error returned by the fallible
+/// [`PortageDependencyProvider`](crate::PortageDependencyProvider)
+/// constructors, and carried by the cancellation value of a solve.
+///
+/// Every variant that comes from package metadata names the offending
+/// package and the [`Domain`] it was loaded into.
+/// [`Cancelled`](Self::Cancelled) only comes from a solve.
+#[derive(Debug)]
+pub enum ResolveError {
+    /// The repository backend failed.
+    Repository(RepositoryError),
+    /// A [`UseConfig`](crate::UseConfig) flag is not a valid USE flag name
+    /// (PMS 3.1.4).
+    InvalidFlag { flag: String, domain: Domain },
+    /// A package's metadata is inconsistent, e.g. a sub-slot without a
+    /// slot or a name reserved for internal virtuals.
+    MalformedMetadata {
+        cpv: Cpv,
+        domain: Domain,
+        reason: String,
+    },
+    /// The same CPV from the same repository was listed twice.
+    DuplicateCpv {
+        cpv: Cpv,
+        repo: Option<Interned<DefaultInterner>>,
+        domain: Domain,
+    },
+    /// A dependency uses a construct the solver cannot model.
+    UnsupportedConstruct {
+        cpv: Cpv,
+        domain: Domain,
+        class: DepClass,
+        /// The offending atom as written.
+        atom: String,
+        construct: DepConstruct,
+    },
+    /// A [`ProviderBuilder`](crate::ProviderBuilder) was given settings
+    /// that cannot be combined.
+    IncompatibleOptions { reason: String },
+    /// The solve was stopped by a
+    /// [`CancellationToken`](crate::CancellationToken) or an exhausted
+    /// [`SolveBudget`](crate::SolveBudget).
+    Cancelled {
+        reason: CancelReason,
+        /// Progress when the solve stopped.
+        stats: SolveStats,
+    },
+}
+
+/// Dependency construct rejected by
+/// [`ResolveError::UnsupportedConstruct`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepConstruct {
+    /// A `:=` or `:*` slot operator on a blocker (`!dev-libs/a:=`).
+    BlockerSlotOperator,
+    /// A conditional USE dependency (`[flag?]`, `[!flag?]`, `[flag=]`,
+    /// `[!flag=]`) on a solver-decided flag, whose value is only known
+    /// after solving.
+    SolverDecidedUseDep,
+}
+
+impl fmt::Display for DepConstruct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DepConstruct::BlockerSlotOperator => write!(f, "slot operator on a blocker"),
+            DepConstruct::SolverDecidedUseDep => {
+                write!(f, "conditional USE dependency on a solver-decided flag")
+            }
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::Repository(err) => write!(f, "{err}"),
+            ResolveError::InvalidFlag { flag, domain } => {
+                write!(
+                    f,
+                    "invalid USE flag name `{flag}` in {domain} configuration"
+                )
+            }
+            ResolveError::MalformedMetadata {
+                cpv,
+                domain,
+                reason,
+            } => write!(f, "malformed metadata for {cpv} ({domain}): {reason}"),
+            ResolveError::DuplicateCpv { cpv, repo, domain } => {
+                write!(f, "duplicate package {cpv}")?;
+                if let Some(repo) = repo {
+                    write!(f, "::{repo}")?;
+                }
+                write!(f, " ({domain})")
+            }
+            ResolveError::UnsupportedConstruct {
+                cpv,
+                domain,
+                class,
+                atom,
+                construct,
+            } => write!(
+                f,
+                "unsupported {construct} in {class} of {cpv} ({domain}): `{atom}`"
+            ),
+            ResolveError::IncompatibleOptions { reason } => {
+                write!(f, "incompatible provider options: {reason}")
+            }
+            ResolveError::Cancelled { reason, stats } => write!(
+                f,
+                "solve stopped ({reason}) after {} decisions in {:.3}s",
+                stats.decisions,
+                stats.elapsed.as_secs_f64()
+            ),
+        }
+    }
+}
+
+impl Error for ResolveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ResolveError::Repository(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl ResolveError {
+    /// The error carried by the cancellation value of a solve. A value of
+    /// another type, which a provider of this crate never produces, counts
+    /// as a plain [`Cancelled`](Self::Cancelled).
+    pub(crate) fn from_cancel_value(value: Box<dyn Any>) -> Self {
+        match value.downcast::<ResolveError>() {
+            Ok(err) => *err,
+            Err(_) => ResolveError::Cancelled {
+                reason: CancelReason::Cancelled,
+                stats: SolveStats::default(),
+            },
+        }
+    }
+}
+
+impl From<RepositoryError> for ResolveError {
+    fn from(err: RepositoryError) -> Self {
+        ResolveError::Repository(err)
+    }
+}
@@ -0,0 +1,883 @@
+//! Explanations of solver decisions.
+//!
+//! [`explain_inclusion`](PortageDependencyProvider::explain_inclusion)
+//! answers "why is this package in the solution?" with the shortest
+//! dependency chains from a root atom, and
+//! [`render_tree`](PortageDependencyProvider::render_tree) prints the whole
+//! solution as an `emerge --tree`-style dependency tree.
+//! [`explain_exclusion`](PortageDependencyProvider::explain_exclusion)
+//! answers "why not this version?" with the obstacles a candidate faces.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+use portage_atom::Blocker;
+use portage_atom::gentoo_interner::{DefaultInterner, Interned};
+use resolvo::{
+    Condition, ConditionId, Interner, LogicalOperator, Requirement, SolvableId, VersionSetId,
+};
+
+use crate::pool::{DepClass, RequirementOrigin};
+use crate::provider::{DepWalkEvent, PortageDependencyProvider, dep_matches_solvable};
+
+/// One dependency hop in an [`InclusionChain`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InclusionStep {
+    /// The dependent package.
+    pub from: SolvableId,
+    /// The package pulled in by `atom`.
+    pub to: SolvableId,
+    /// Dependency class the atom appears in.
+    pub class: DepClass,
+    /// Source atom text, e.g. `>=dev-libs/openssl-3:0=`.
+    pub atom: String,
+    /// USE conditionals enclosing the atom, outermost first, rendered as
+    /// `ssl?` / `!ssl?`. Empty when the atom is unconditional.
+    pub use_conditions: Vec<String>,
+}
+
+/// A chain of dependencies from a root atom to a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InclusionChain {
+    /// Root atom, as passed to
+    /// [`intern_requirement`](PortageDependencyProvider::intern_requirement).
+    pub root: String,
+    /// Solvable the root atom selected.
+    pub root_solvable: SolvableId,
+    /// Hops from `root_solvable` to the explained package. Empty when the
+    /// package satisfies the root atom itself.
+    pub steps: Vec<InclusionStep>,
+}
+
+/// Something in a solution that rules out a candidate; see
+/// [`explain_exclusion`](PortageDependencyProvider::explain_exclusion).
+///
+/// `origins` lists the selected packages, dependency classes and atoms
+/// that produced the offending requirement or constrain; it is empty for
+/// synthetic requirements such as USE-flag choices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionObstacle {
+    /// A root atom does not accept the candidate.
+    Root { atom: String },
+    /// A requirement of a selected package does not accept the
+    /// candidate's version, slot, sub-slot or repository.
+    Requirement { origins: Vec<RequirementOrigin> },
+    /// A requirement accepts the candidate's version but its USE
+    /// dependencies do not: each `(flag, required)` pair is a flag the
+    /// candidate has in the wrong state.
+    UseDep {
+        origins: Vec<RequirementOrigin>,
+        flags: Vec<(Interned<DefaultInterner>, bool)>,
+    },
+    /// A blocker of a selected package forbids the candidate.
+    Blocker {
+        origins: Vec<RequirementOrigin>,
+        blocker: Blocker,
+    },
+    /// A non-blocker constrain, e.g. from a softened dependency class
+    /// (see [`DepClassMode::Soften`](crate::DepClassMode::Soften)),
+    /// rejects the candidate.
+    Constraint { origins: Vec<RequirementOrigin> },
+    /// Another installed version of the same name is locked.
+    Locked { installed: SolvableId },
+    /// The candidate is masked, with the reason resolvo reports.
+    Masked { reason: String },
+    /// The candidate is older than `installed` and
+    /// [`DowngradeMode::Forbid`](crate::DowngradeMode::Forbid) protects it.
+    Downgrade { installed: SolvableId },
+    /// A requirement of the candidate itself accepts none of the selected
+    /// packages of its name; taking the candidate would displace
+    /// `selected`.
+    Dependency {
+        origins: Vec<RequirementOrigin>,
+        selected: Vec<SolvableId>,
+    },
+    /// A blocker or constrain of the candidate itself rejects the selected
+    /// packages `selected`.
+    Blocks {
+        origins: Vec<RequirementOrigin>,
+        selected: Vec<SolvableId>,
+    },
+}
+
+impl PortageDependencyProvider {
+    /// Explain why `solvable` is part of `solution`.
+    ///
+    /// Returns up to `limit` shortest chains from a root atom to
+    /// `solvable` over the edges of
+    /// [`dependency_graph`](Self::dependency_graph), so it follows the
+    /// solver's USE and alternative choices. Chains are ordered by root
+    /// interning order, then by dependency order; the first chain is a
+    /// reasonable single answer. A solution full of diamonds has
+    /// exponentially many shortest chains, and the work done is bounded by
+    /// `limit` times the chain length. Returns an empty `Vec` when
+    /// `solvable` is not reachable from any root, e.g. for the internal
+    /// USE-flag and `^^ ( )` choice virtuals.
+    pub fn explain_inclusion(
+        &self,
+        solution: &[SolvableId],
+        solvable: SolvableId,
+        limit: usize,
+    ) -> Vec<InclusionChain> {
+        let steps = self.inclusion_steps(solution);
+        let roots: Vec<(String, SolvableId)> = self
+            .root_matches(solution)
+            .map(|(dep, sid)| (dep.to_string(), sid))
+            .collect();
+
+        // Breadth-first search from every root solvable at once.
+        let mut outgoing: HashMap<SolvableId, Vec<&InclusionStep>> = HashMap::new();
+        let mut incoming: HashMap<SolvableId, Vec<&InclusionStep>> = HashMap::new();
+        for step in &steps {
+            outgoing.entry(step.from).or_default().push(step);
+            incoming.entry(step.to).or_default().push(step);
+        }
+        let mut distance: HashMap<SolvableId, usize> = HashMap::new();
+        let mut queue = VecDeque::new();
+        for &(_, sid) in &roots {
+            if distance.insert(sid, 0).is_none() {
+                queue.push_back(sid);
+            }
+        }
+        while let Some(node) = queue.pop_front() {
+            let next = distance[&node] + 1;
+            for step in outgoing.get(&node).into_iter().flatten() {
+                if let Entry::Vacant(entry) = distance.entry(step.to) {
+                    entry.insert(next);
+                    queue.push_back(step.to);
+                }
+            }
+        }
+        if !distance.contains_key(&solvable) {
+            return Vec::new();
+        }
+        let shortens = |step: &InclusionStep| {
+            distance.get(&step.from).map(|d| d + 1) == distance.get(&step.to).copied()
+        };
+
+        // Packages on some shortest chain to `solvable`, walking back along
+        // edges that shorten the distance by exactly one.
+        let mut on_chain: HashSet<SolvableId> = HashSet::from([solvable]);
+        let mut stack = vec![solvable];
+        while let Some(node) = stack.pop() {
+            for step in incoming.get(&node).into_iter().flatten() {
+                if shortens(step) && on_chain.insert(step.from) {
+                    stack.push(step.from);
+                }
+            }
+        }
+
+        // Depth-first from each root along those edges only, so every
+        // branch taken completes a chain.
+        let mut chains = Vec::new();
+        for (root, root_solvable) in &roots {
+            if !on_chain.contains(root_solvable) {
+                continue;
+            }
+            let mut paths: Vec<Vec<&InclusionStep>> = vec![Vec::new()];
+            while let Some(path) = paths.pop() {
+                if chains.len() == limit {
+                    return chains;
+                }
+                let head = path.last().map_or(*root_solvable, |step| step.to);
+                if head == solvable {
+                    chains.push(InclusionChain {
+                        root: root.clone(),
+                        root_solvable: *root_solvable,
+                        steps: path.into_iter().cloned().collect(),
+                    });
+                    continue;
+                }
+                for step in outgoing.get(&head).into_iter().flatten().rev() {
+                    if shortens(step) && on_chain.contains(&step.to) {
+                        let mut longer = path.clone();
+                        longer.push(step);
+                        paths.push(longer);
+                    }
+                }
+            }
+        }
+        chains
+    }
+
+    /// Render `solution` as an indented dependency tree.
+    ///
+    /// Each root package is printed at the top level, followed by its
+    /// dependencies one indent level deeper, annotated with the dependency
+    /// class, any USE conditionals and the source atom:
+    ///
+    /// ```text
+    /// app-misc/foo-1.0:0
+    ///   dev-libs/bar-1.0:0 [DEPEND ssl? >=dev-libs/bar-1]
+    /// ```
+    ///
+    /// A package whose dependencies were already printed is marked with
+    /// `(see above)` instead of being expanded again, which also keeps
+    /// dependency cycles finite.
+    pub fn render_tree(&self, solution: &[SolvableId]) -> String {
+        let steps = self.inclusion_steps(solution);
+        let mut outgoing: HashMap<SolvableId, Vec<&InclusionStep>> = HashMap::new();
+        for step in &steps {
+            outgoing.entry(step.from).or_default().push(step);
+        }
+
+        let mut out = String::new();
+        let mut expanded: HashSet<SolvableId> = HashSet::new();
+        let mut seen_roots: HashSet<SolvableId> = HashSet::new();
+        for (_, root) in self.root_matches(solution) {
+            if !seen_roots.insert(root) {
+                continue;
+            }
+            // (depth, package, step that pulled it in)
+            let mut stack: Vec<(usize, SolvableId, Option<&InclusionStep>)> = vec![(0, root, None)];
+            while let Some((depth, sid, via)) = stack.pop() {
+                let _ = write!(
+                    out,
+                    "{:indent$}{}",
+                    "",
+                    self.display_solvable(sid),
+                    indent = depth * 2
+                );
+                if let Some(step) = via {
+                    let _ = write!(out, " [{}", step.class);
+                    for condition in &step.use_conditions {
+                        let _ = write!(out, " {condition}");
+                    }
+                    let _ = write!(out, " {}]", step.atom);
+                }
+                if !expanded.insert(sid) {
+                    if outgoing.contains_key(&sid) {
+                        out.push_str(" (see above)");
+                    }
+                    out.push('\n');
+                    continue;
+                }
+                out.push('\n');
+                for &step in outgoing.get(&sid).into_iter().flatten().rev() {
+                    stack.push((depth + 1, step.to, Some(step)));
+                }
+            }
+        }
+        out
+    }
+
+    /// Every edge of the solution's dependency graph with its atom and
+    /// USE conditionals, deduplicated.
+    fn inclusion_steps(&self, solution: &[SolvableId]) -> Vec<InclusionStep> {
+        let mut steps = Vec::new();
+        let mut seen = HashSet::new();
+        self.walk_solution_deps(solution, |event| {
+            let DepWalkEvent::Edge(visit) = event else {
+                return;
+            };
+            let step = InclusionStep {
+                from: visit.from,
+                to: visit.to,
+                class: visit.class,
+                atom: visit.dep.to_string(),
+                use_conditions: visit.conditions.iter().map(ToString::to_string).collect(),
+            };
+            if seen.insert(step.clone()) {
+                steps.push(step);
+            }
+        });
+        steps
+    }
+
+    /// Explain why `candidate` is not part of `solution`.
+    ///
+    /// Re-evaluates the candidate against every active requirement and
+    /// constrain of the selected packages, the root atoms and the
+    /// installed-package locks, masks and downgrade protection, and lists
+    /// each one that rules it out. A
+    /// requirement only counts when it is currently satisfied by a package
+    /// of the candidate's name, i.e. when the candidate would have to take
+    /// that package's place. The candidate's own requirements, blockers and
+    /// constrains are checked against the selected packages the same way.
+    /// Obstacles are listed lock, mask and downgrade protection first, then
+    /// root atoms, then per selected package in solution order, then the
+    /// candidate's own dependencies.
+    ///
+    /// Returns an empty `Vec` if `candidate` is part of `solution` or
+    /// nothing in it stands in the way.
+    pub fn explain_exclusion(
+        &self,
+        solution: &[SolvableId],
+        candidate: SolvableId,
+    ) -> Vec<VersionObstacle> {
+        if solution.contains(&candidate) {
+            return Vec::new();
+        }
+        let name = self.pool.solvable_name(candidate);
+        let candidate_meta = self.pool.resolve_solvable(candidate);
+        let mut obstacles = Vec::new();
+        let mut push = |obstacle: VersionObstacle| {
+            if !obstacles.contains(&obstacle) {
+                obstacles.push(obstacle);
+            }
+        };
+
+        if let Some(installed) = self.locked_solvable(name) {
+            if installed != candidate {
+                push(VersionObstacle::Locked { installed });
+            }
+        }
+        if let Some(&reason) = self.masked.get(&candidate) {
+            push(VersionObstacle::Masked {
+                reason: self.pool.resolve_string(reason).to_string(),
+            });
+        }
+        if let Some(installed) = self.forbidding_downgrade(candidate) {
+            push(VersionObstacle::Downgrade { installed });
+        }
+
+        // Only satisfiers of the candidate's name would be displaced.
+        let displaced = |satisfiers: &mut dyn Iterator<Item = SolvableId>| {
+            let mut any = false;
+            for sid in satisfiers {
+                if self.pool.solvable_name(sid) != name {
+                    return false;
+                }
+                any = true;
+            }
+            any
+        };
+
+        let domain = self.domain(candidate);
+        for (dep, root_domain) in self.root_atoms() {
+            if *root_domain != domain || dep.cpn != candidate_meta.cpv.cpn {
+                continue;
+            }
+            let use_config = self.domain_use_config(domain);
+            let mut satisfiers = solution.iter().copied().filter(|&sid| {
+                self.domain(sid) == domain
+                    && dep_matches_solvable(dep, self.pool.resolve_solvable(sid), use_config)
+            });
+            if displaced(&mut satisfiers) && !dep_matches_solvable(dep, candidate_meta, use_config)
+            {
+                push(VersionObstacle::Root {
+                    atom: dep.to_string(),
+                });
+            }
+        }
+
+        for &owner in solution {
+            let Some(deps) = self.known_dependencies(owner) else {
+                continue;
+            };
+            for req in &deps.requirements {
+                if let Some(condition) = req.condition {
+                    if !self.condition_holds(condition, solution) {
+                        continue;
+                    }
+                }
+                let sets: Vec<VersionSetId> = match req.requirement {
+                    Requirement::Single(vs_id) => vec![vs_id],
+                    Requirement::Union(union_id) => {
+                        self.pool.resolve_version_set_union(union_id).to_vec()
+                    }
+                };
+                if !sets
+                    .iter()
+                    .any(|&vs_id| self.pool.version_set_name(vs_id) == name)
+                {
+                    continue;
+                }
+                let mut satisfiers = solution
+                    .iter()
+                    .copied()
+                    .filter(|&sid| sets.iter().any(|&vs_id| self.selects(sid, vs_id)));
+                if !displaced(&mut satisfiers) {
+                    continue;
+                }
+
+                let origins = self.selected_origins(req.requirement, solution);
+                let accepting: Vec<VersionSetId> = sets
+                    .iter()
+                    .copied()
+                    .filter(|&vs_id| self.selects(candidate, vs_id))
+                    .collect();
+                if accepting.is_empty() {
+                    push(VersionObstacle::Requirement { origins });
+                    continue;
+                }
+                let mismatches: Vec<Vec<(Interned<DefaultInterner>, bool)>> = accepting
+                    .iter()
+                    .map(|&vs_id| {
+                        self.pool
+                            .resolve_version_set(vs_id)
+                            .use_constraints
+                            .iter()
+                            .copied()
+                            .filter(|(flag, required)| {
+                                candidate_meta.use_flags.contains(flag) != *required
+                            })
+                            .collect()
+                    })
+                    .collect();
+                if mismatches.iter().all(|flags| !flags.is_empty()) {
+                    push(VersionObstacle::UseDep {
+                        origins,
+                        flags: mismatches.into_iter().next().unwrap_or_default(),
+                    });
+                }
+            }
+
+            for &vs_id in &deps.constrains {
+                if self.pool.version_set_name(vs_id) != name
+                    || self.version_set_matches(candidate, vs_id)
+                {
+                    continue;
+                }
+                let origins = self.selected_origins(Requirement::Single(vs_id), solution);
+                push(match self.blocker_type(vs_id) {
+                    Some(blocker) => VersionObstacle::Blocker { origins, blocker },
+                    None => VersionObstacle::Constraint { origins },
+                });
+            }
+        }
+
+        for obstacle in self.own_obstacles(solution, candidate) {
+            push(obstacle);
+        }
+        obstacles
+    }
+
+    /// Requirements of `candidate` that no selected package meets while
+    /// every name they accept is taken, and blockers or constrains of
+    /// `candidate` that reject a selected package.
+    fn own_obstacles(
+        &self,
+        solution: &[SolvableId],
+        candidate: SolvableId,
+    ) -> Vec<VersionObstacle> {
+        if self.lazy.is_some() && self.known_dependencies(candidate).is_none() {
+            self.convert_solvable(candidate);
+        }
+        let Some(deps) = self.known_dependencies(candidate) else {
+            return Vec::new();
+        };
+        let own_origins = |requirement: Requirement| -> Vec<RequirementOrigin> {
+            self.requirement_origins(requirement)
+                .iter()
+                .filter(|origin| origin.solvable == candidate)
+                .cloned()
+                .collect()
+        };
+        let mut obstacles = Vec::new();
+        for req in &deps.requirements {
+            if let Some(condition) = req.condition {
+                if !self.condition_holds(condition, solution) {
+                    continue;
+                }
+            }
+            let sets: Vec<VersionSetId> = match req.requirement {
+                Requirement::Single(vs_id) => vec![vs_id],
+                Requirement::Union(union_id) => {
+                    self.pool.resolve_version_set_union(union_id).to_vec()
+                }
+            };
+            if solution
+                .iter()
+                .any(|&sid| sets.iter().any(|&vs_id| self.selects(sid, vs_id)))
+            {
+                continue;
+            }
+            // A name nothing is selected for could still be added.
+            let selected_of = |vs_id: VersionSetId| {
+                let name = self.pool.version_set_name(vs_id);
+                solution
+                    .iter()
+                    .copied()
+                    .filter(move |&sid| self.pool.solvable_name(sid) == name)
+            };
+            if sets
+                .iter()
+                .any(|&vs_id| selected_of(vs_id).next().is_none())
+            {
+                continue;
+            }
+            let mut selected: Vec<SolvableId> = Vec::new();
+            for sid in sets.iter().flat_map(|&vs_id| selected_of(vs_id)) {
+                if !selected.contains(&sid) {
+                    selected.push(sid);
+                }
+            }
+            obstacles.push(VersionObstacle::Dependency {
+                origins: own_origins(req.requirement),
+                selected,
+            });
+        }
+        for &vs_id in &deps.constrains {
+            let name = self.pool.version_set_name(vs_id);
+            let selected: Vec<SolvableId> = solution
+                .iter()
+                .copied()
+                .filter(|&sid| {
+                    self.pool.solvable_name(sid) == name && !self.version_set_matches(sid, vs_id)
+                })
+                .collect();
+            if !selected.is_empty() {
+                obstacles.push(VersionObstacle::Blocks {
+                    origins: own_origins(Requirement::Single(vs_id)),
+                    selected,
+                });
+            }
+        }
+        obstacles
+    }
+
+    /// Whether `solvable` has the name of `vs_id` and matches it.
+    fn selects(&self, solvable: SolvableId, vs_id: VersionSetId) -> bool {
+        self.pool.solvable_name(solvable) == self.pool.version_set_name(vs_id)
+            && self.version_set_matches(solvable, vs_id)
+    }
+
+    /// Evaluate a requirement condition against `solution`.
+    fn condition_holds(&self, condition: ConditionId, solution: &[SolvableId]) -> bool {
+        match *self.pool.resolve_condition(condition) {
+            Condition::Requirement(vs_id) => solution.iter().any(|&sid| self.selects(sid, vs_id)),
+            Condition::Binary(LogicalOperator::And, a, b) => {
+                self.condition_holds(a, solution) && self.condition_holds(b, solution)
+            }
+            Condition::Binary(LogicalOperator::Or, a, b) => {
+                self.condition_holds(a, solution) || self.condition_holds(b, solution)
+            }
+        }
+    }
+
+    /// Origins of `requirement` whose owner is part of `solution`.
+    fn selected_origins(
+        &self,
+        requirement: Requirement,
+        solution: &[SolvableId],
+    ) -> Vec<RequirementOrigin> {
+        self.requirement_origins(requirement)
+            .iter()
+            .filter(|origin| solution.contains(&origin.solvable))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use portage_atom::{Cpv, Dep};
+    use resolvo::{ArenaId, Problem, Solver};
+
+    use super::*;
+    use crate::pool::{Domain, InstalledSet, UseConfig};
+    use crate::repository::InMemoryRepository;
+    use crate::tests::{pkg, pkg_build_run};
+
+    #[test]
+    fn explain_inclusion_shortest_chains() {
+        // app DEPEND lib mid; lib RDEPEND ssl? ( rt ); mid RDEPEND rt.
+        // rt is reachable through two equally short chains.
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "dev-lib/lib dev-lib/mid",
+            "",
+        ));
+        repo.add(pkg_build_run("dev-lib/lib-1.0", "", "ssl? ( dev-lib/rt )"));
+        repo.add(pkg_build_run("dev-lib/mid-1.0", "", "dev-lib/rt"));
+        repo.add(pkg("dev-lib/rt-1.0", "0", vec![]));
+        let use_config = UseConfig::from(HashSet::from([Interned::intern("ssl")]));
+
+        let mut provider = PortageDependencyProvider::new(&repo, &use_config);
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let provider = solver.provider();
+        let find = |name: &str| {
+            *solution
+                .iter()
+                .find(|&&sid| provider.package_metadata(sid).cpv.cpn.package == name)
+                .unwrap()
+        };
+
+        let chains = provider.explain_inclusion(&solution, find("rt"), usize::MAX);
+        assert_eq!(chains.len(), 2, "chains: {chains:?}");
+        for chain in &chains {
+            assert_eq!(chain.root, "app-misc/app");
+            assert_eq!(chain.root_solvable, find("app"));
+            assert_eq!(chain.steps.len(), 2);
+            assert_eq!(chain.steps[0].class, DepClass::Depend);
+            assert_eq!(chain.steps[1].to, find("rt"));
+        }
+        let via_lib = chains
+            .iter()
+            .find(|c| c.steps[0].to == find("lib"))
+            .unwrap();
+        assert_eq!(via_lib.steps[1].class, DepClass::Rdepend);
+        assert_eq!(via_lib.steps[1].atom, "dev-lib/rt");
+        assert_eq!(via_lib.steps[1].use_conditions, ["ssl?"]);
+
+        // A root package is explained by the root atom alone.
+        let chains = provider.explain_inclusion(&solution, find("app"), usize::MAX);
+        assert_eq!(chains.len(), 1);
+        assert!(chains[0].steps.is_empty());
+    }
+
+    #[test]
+    fn explain_inclusion_bounded_on_diamonds() {
+        // A chain of 20 diamonds has 2^20 shortest chains to the bottom.
+        let mut repo = InMemoryRepository::new();
+        for level in 0..20 {
+            let next = format!("dev-lib/d{}", level + 1);
+            repo.add(pkg_build_run(
+                &format!("dev-lib/d{level}-1.0"),
+                &format!("dev-lib/l{level} dev-lib/r{level}"),
+                "",
+            ));
+            repo.add(pkg_build_run(&format!("dev-lib/l{level}-1.0"), &next, ""));
+            repo.add(pkg_build_run(&format!("dev-lib/r{level}-1.0"), &next, ""));
+        }
+        repo.add(pkg("dev-lib/d20-1.0", "0", vec![]));
+
+        let mut provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+        let req = provider.intern_requirement(&Dep::parse("dev-lib/d0").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let provider = solver.provider();
+        let bottom = *solution
+            .iter()
+            .find(|&&sid| provider.package_metadata(sid).cpv.cpn.package == "d20")
+            .unwrap();
+
+        let chains = provider.explain_inclusion(&solution, bottom, 1);
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].steps.len(), 40);
+        let chains = provider.explain_inclusion(&solution, bottom, 5);
+        assert_eq!(chains.len(), 5);
+        assert!(chains.iter().all(|chain| chain.steps.len() == 40));
+        assert!(provider.explain_inclusion(&solution, bottom, 0).is_empty());
+    }
+
+    #[test]
+    fn render_tree_marks_repeats() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "dev-lib/lib dev-lib/mid",
+            "",
+        ));
+        repo.add(pkg_build_run("dev-lib/lib-1.0", "", "ssl? ( dev-lib/rt )"));
+        repo.add(pkg_build_run("dev-lib/mid-1.0", "", "dev-lib/lib"));
+        repo.add(pkg("dev-lib/rt-1.0", "0", vec![]));
+        let use_config = UseConfig::from(HashSet::from([Interned::intern("ssl")]));
+
+        let mut provider = PortageDependencyProvider::new(&repo, &use_config);
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+
+        assert_eq!(
+            solver.provider().render_tree(&solution),
+            "app-misc/app-1.0:0\n\
+             \x20 dev-lib/lib-1.0:0 [DEPEND dev-lib/lib]\n\
+             \x20   dev-lib/rt-1.0:0 [RDEPEND ssl? dev-lib/rt]\n\
+             \x20 dev-lib/mid-1.0:0 [DEPEND dev-lib/mid]\n\
+             \x20   dev-lib/lib-1.0:0 [RDEPEND dev-lib/lib] (see above)\n"
+        );
+    }
+
+    /// Helper: solve `roots` and explain why `dev-lib/foo-<version>` is
+    /// not in the solution.
+    fn explain_foo_exclusion(
+        repo: &InMemoryRepository,
+        installed: &InstalledSet,
+        roots: &[&str],
+        version: &str,
+    ) -> Vec<VersionObstacle> {
+        let provider =
+            PortageDependencyProvider::with_installed(repo, &UseConfig::default(), installed);
+        explain_foo_exclusion_in(provider, roots, version)
+    }
+
+    fn explain_foo_exclusion_in(
+        mut provider: PortageDependencyProvider,
+        roots: &[&str],
+        version: &str,
+    ) -> Vec<VersionObstacle> {
+        let reqs = roots
+            .iter()
+            .map(|r| provider.intern_requirement(&Dep::parse(r).unwrap()))
+            .collect();
+        let mut solver = Solver::new(provider);
+        let solution = solver.solve(Problem::new().requirements(reqs)).unwrap();
+        let provider = solver.provider();
+        let candidate = (0..provider.pool.solvable_count())
+            .map(SolvableId::from_usize)
+            .find(|&sid| {
+                provider.package_metadata(sid).cpv.to_string() == format!("dev-lib/foo-{version}")
+            })
+            .unwrap();
+        provider.explain_exclusion(&solution, candidate)
+    }
+
+    #[test]
+    fn explain_exclusion_requirement_and_blocker() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run("app-misc/app-1.0", "<dev-lib/foo-1.4", ""));
+        repo.add(pkg_build_run(
+            "app-misc/other-1.0",
+            "dev-lib/foo !>=dev-lib/foo-1.4",
+            "",
+        ));
+        repo.add(pkg("dev-lib/foo-1.2", "0", vec![]));
+        repo.add(pkg("dev-lib/foo-1.4", "0", vec![]));
+        let roots = ["app-misc/app", "app-misc/other"];
+
+        let obstacles = explain_foo_exclusion(&repo, &InstalledSet::new(), &roots, "1.4");
+        assert_eq!(obstacles.len(), 2, "obstacles: {obstacles:?}");
+        let origins = obstacles
+            .iter()
+            .find_map(|o| match o {
+                VersionObstacle::Requirement { origins } => Some(origins),
+                _ => None,
+            })
+            .expect("requirement obstacle");
+        assert_eq!(origins.len(), 1);
+        assert_eq!(origins[0].class, DepClass::Depend);
+        assert_eq!(origins[0].atom, "<dev-lib/foo-1.4");
+        let (origins, blocker) = obstacles
+            .iter()
+            .find_map(|o| match o {
+                VersionObstacle::Blocker { origins, blocker } => Some((origins, blocker)),
+                _ => None,
+            })
+            .expect("blocker obstacle");
+        assert_eq!(*blocker, Blocker::Weak);
+        assert_eq!(origins[0].atom, "!>=dev-lib/foo-1.4");
+
+        // The selected version has no obstacles.
+        assert!(explain_foo_exclusion(&repo, &InstalledSet::new(), &roots, "1.2").is_empty());
+    }
+
+    #[test]
+    fn explain_exclusion_own_dependency() {
+        // foo-2.0 is only excluded because it needs a bar the root forbids.
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg("dev-lib/foo-1.2", "0", vec![]));
+        repo.add(pkg_build_run("dev-lib/foo-2.0", "", ">=dev-lib/bar-2"));
+        repo.add(pkg("dev-lib/bar-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/bar-2.0", "0", vec![]));
+        let provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+
+        let obstacles =
+            explain_foo_exclusion_in(provider, &["dev-lib/foo", "<dev-lib/bar-2"], "2.0");
+        let [VersionObstacle::Dependency { origins, selected }] = obstacles.as_slice() else {
+            panic!("expected a dependency obstacle: {obstacles:?}");
+        };
+        assert_eq!(origins.len(), 1);
+        assert_eq!(origins[0].class, DepClass::Rdepend);
+        assert_eq!(origins[0].atom, ">=dev-lib/bar-2");
+        assert_eq!(selected.len(), 1);
+    }
+
+    #[test]
+    fn explain_exclusion_own_blocker() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg("dev-lib/foo-1.2", "0", vec![]));
+        repo.add(pkg_build_run("dev-lib/foo-2.0", "", "!dev-lib/baz"));
+        repo.add(pkg("dev-lib/baz-1.0", "0", vec![]));
+        let provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+
+        let obstacles = explain_foo_exclusion_in(provider, &["dev-lib/foo", "dev-lib/baz"], "2.0");
+        let [VersionObstacle::Blocks { origins, selected }] = obstacles.as_slice() else {
+            panic!("expected a blocks obstacle: {obstacles:?}");
+        };
+        assert_eq!(origins[0].atom, "!dev-lib/baz");
+        assert_eq!(selected.len(), 1);
+    }
+
+    #[test]
+    fn explain_exclusion_root_use_dep_and_lock() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run("app-misc/app-1.0", "dev-lib/foo[ssl]", ""));
+        let mut with_ssl = pkg("dev-lib/foo-1.2", "0", vec![]);
+        with_ssl.use_flags.insert(Interned::intern("ssl"));
+        repo.add(with_ssl.clone());
+        repo.add(pkg("dev-lib/foo-1.4", "0", vec![]));
+
+        let mut installed = InstalledSet::new();
+        installed.add_locked(with_ssl);
+        let obstacles = explain_foo_exclusion(
+            &repo,
+            &installed,
+            &["app-misc/app", "=dev-lib/foo-1.2"],
+            "1.4",
+        );
+        assert_eq!(obstacles.len(), 3, "obstacles: {obstacles:?}");
+        assert!(matches!(obstacles[0], VersionObstacle::Locked { .. }));
+        assert_eq!(
+            obstacles[1],
+            VersionObstacle::Root {
+                atom: "=dev-lib/foo-1.2".to_string()
+            }
+        );
+        let VersionObstacle::UseDep { flags, .. } = &obstacles[2] else {
+            panic!("expected a USE dep obstacle: {obstacles:?}");
+        };
+        assert_eq!(flags, &[(Interned::intern("ssl"), true)]);
+    }
+
+    #[test]
+    fn explain_exclusion_masked() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg("dev-lib/foo-1.2", "0", vec![]));
+        repo.add(pkg("dev-lib/foo-1.4", "0", vec![]));
+        let mut provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+        let masked =
+            provider.find_solvables(Domain::Target, &Cpv::parse("dev-lib/foo-1.4").unwrap());
+        provider.mask(masked[0], "package.mask");
+        assert!(provider.is_masked(masked[0]));
+
+        let obstacles = explain_foo_exclusion_in(provider, &["dev-lib/foo"], "1.4");
+        assert_eq!(
+            obstacles,
+            [VersionObstacle::Masked {
+                reason: "package.mask".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn explain_exclusion_forbidden_downgrade() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg("dev-lib/foo-1.2", "0", vec![]));
+        repo.add(pkg("dev-lib/foo-1.4", "0", vec![]));
+        let mut installed = InstalledSet::new();
+        installed.add_favored(pkg("dev-lib/foo-1.4", "0", vec![]));
+        let mut provider =
+            PortageDependencyProvider::with_installed(&repo, &UseConfig::default(), &installed)
+                .with_downgrade_policy(crate::DowngradePolicy::forbid());
+        let req = provider.intern_requirement(&Dep::parse("dev-lib/foo").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let provider = solver.provider();
+        let older =
+            provider.find_solvables(Domain::Target, &Cpv::parse("dev-lib/foo-1.2").unwrap());
+
+        let obstacles = provider.explain_exclusion(&solution, older[0]);
+        let [VersionObstacle::Downgrade { installed }] = obstacles.as_slice() else {
+            panic!("expected a downgrade obstacle: {obstacles:?}");
+        };
+        assert!(provider.is_installed(*installed));
+        assert_eq!(
+            provider.package_metadata(*installed).cpv.to_string(),
+            "dev-lib/foo-1.4"
+        );
+    }
+}
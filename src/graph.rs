@@ -0,0 +1,126 @@
+//! Graph algorithms over solution dependency edges.
+//!
+//! Helpers shared by install ordering, scheduling and graph export. All
+//! functions operate on plain [`SolvableId`] adjacency and are independent
+//! of the provider.
+
+use std::collections::HashMap;
+
+use resolvo::SolvableId;
+
+/// Compute the strongly connected components of a directed graph.
+///
+/// `edges` are `(from, to)` pairs; endpoints not listed in `nodes` are
+/// ignored. Components are returned in reverse topological order of the
+/// condensation (Tarjan's algorithm), each with its members in discovery
+/// order. Every node appears in exactly one component.
+pub(crate) fn strongly_connected_components(
+    nodes: &[SolvableId],
+    edges: impl IntoIterator<Item = (SolvableId, SolvableId)>,
+) -> Vec<Vec<SolvableId>> {
+    let index_of: HashMap<SolvableId, usize> =
+        nodes.iter().enumerate().map(|(i, &sid)| (sid, i)).collect();
+    let mut adj: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+    for (from, to) in edges {
+        if let (Some(&f), Some(&t)) = (index_of.get(&from), index_of.get(&to)) {
+            adj[f].push(t);
+        }
+    }
+
+    const UNVISITED: usize = usize::MAX;
+    let mut index = vec![UNVISITED; nodes.len()];
+    let mut lowlink = vec![0; nodes.len()];
+    let mut on_stack = vec![false; nodes.len()];
+    let mut stack: Vec<usize> = Vec::new();
+    let mut next_index = 0;
+    let mut components = Vec::new();
+
+    // Iterative DFS: each frame is (node, next child position).
+    for root in 0..nodes.len() {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        let mut frames: Vec<(usize, usize)> = vec![(root, 0)];
+        index[root] = next_index;
+        lowlink[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some(&mut (v, ref mut child)) = frames.last_mut() {
+            if let Some(&w) = adj[v].get(*child) {
+                *child += 1;
+                if index[w] == UNVISITED {
+                    index[w] = next_index;
+                    lowlink[w] = next_index;
+                    next_index += 1;
+                    stack.push(w);
+                    on_stack[w] = true;
+                    frames.push((w, 0));
+                } else if on_stack[w] {
+                    lowlink[v] = lowlink[v].min(index[w]);
+                }
+                continue;
+            }
+
+            frames.pop();
+            if let Some(&(parent, _)) = frames.last() {
+                lowlink[parent] = lowlink[parent].min(lowlink[v]);
+            }
+            if lowlink[v] == index[v] {
+                let mut component = Vec::new();
+                while let Some(w) = stack.pop() {
+                    on_stack[w] = false;
+                    component.push(nodes[w]);
+                    if w == v {
+                        break;
+                    }
+                }
+                component.reverse();
+                components.push(component);
+            }
+        }
+    }
+
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resolvo::ArenaId;
+
+    fn s(i: usize) -> SolvableId {
+        SolvableId::from_usize(i)
+    }
+
+    #[test]
+    fn acyclic_graph_has_singleton_components() {
+        let nodes = [s(0), s(1), s(2)];
+        let sccs = strongly_connected_components(&nodes, [(s(0), s(1)), (s(1), s(2))]);
+        assert_eq!(sccs.len(), 3);
+        assert!(sccs.iter().all(|c| c.len() == 1));
+        // Reverse topological order: the sink comes first.
+        assert_eq!(sccs[0], vec![s(2)]);
+    }
+
+    #[test]
+    fn cycle_is_one_component() {
+        let nodes = [s(0), s(1), s(2), s(3)];
+        let sccs = strongly_connected_components(
+            &nodes,
+            [(s(0), s(1)), (s(1), s(2)), (s(2), s(0)), (s(2), s(3))],
+        );
+        assert_eq!(sccs.len(), 2);
+        let mut big = sccs.iter().find(|c| c.len() == 3).unwrap().clone();
+        big.sort();
+        assert_eq!(big, vec![s(0), s(1), s(2)]);
+    }
+
+    #[test]
+    fn unknown_endpoints_ignored() {
+        let nodes = [s(0)];
+        let sccs = strongly_connected_components(&nodes, [(s(0), s(7))]);
+        assert_eq!(sccs, vec![vec![s(0)]]);
+    }
+}
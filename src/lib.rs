@@ -4,37 +4,83 @@
 //! resolvo's generic solver interface, enabling SAT-based dependency resolution
 //! for Gentoo-style package managers.
 
+mod any_of;
+mod autounmask;
+mod budget;
+mod builder;
+mod cache;
+mod dot;
+mod downgrade;
+mod error;
+mod explain;
+mod graph;
+mod lockfile;
+mod ordering;
 mod pool;
 mod provider;
 mod repository;
+mod schedule;
+#[cfg(feature = "serde")]
+mod serde_support;
+mod update;
 mod version_match;
 
+pub use autounmask::{
+    Autounmask, AutounmaskReport, ConfigChange, ConfigFile, MaskReason, Visibility,
+};
+pub use budget::{CancelReason, CancellationToken, SolveBudget, SolveStats};
+pub use builder::ProviderBuilder;
+pub use cache::{CACHE_FORMAT_VERSION, CacheError, CacheKey, repository_fingerprint};
+pub use downgrade::{Downgrade, DowngradeMode, DowngradePolicy};
+pub use error::{DepConstruct, ResolveError};
+pub use explain::{InclusionChain, InclusionStep, VersionObstacle};
+pub use lockfile::{
+    LOCK_FILE_VERSION, LockDrift, LockFile, LockFileError, LockReplay, LockedChoice, LockedFlag,
+    LockedPackage, LockedRoot,
+};
+pub use ordering::{
+    AvoidLive, BinaryFirst, Candidate, CandidateOrdering, InstalledFirst, KeepSubslot, NewestFirst,
+    OldestFirst, OrderingChain, OrderingContext, RepositoryPriority, StableFirst,
+};
 pub use pool::{
-    DepClass, DepEdge, InstalledPolicy, InstalledSet, PackageDeps, PackageMetadata, PackageName,
-    PortagePool, UseConfig, VersionConstraint,
+    DepClass, DepClassMode, DepClassModes, DepClassPolicy, DepEdge, DependencyCycle, Domain,
+    InstalledPolicy, InstalledSet, PackageDeps, PackageMetadata, PackageName, PortagePool,
+    RequirementOrigin, UseConfig, VersionConstraint,
 };
 pub use portage_atom::DepEntry;
 pub use portage_atom::gentoo_interner as interner;
-pub use provider::PortageDependencyProvider;
-pub use repository::{InMemoryRepository, PackageRepository};
+pub use provider::{DomainConfig, PortageDependencyProvider};
+pub use repository::{InMemoryRepository, PackageRepository, RepositoryError, RepositoryResult};
+pub use schedule::{MergeJob, MergeSchedule};
+pub use update::{DepChange, UpdateMode, UseChange, UseChanges};
 pub use version_match::version_matches;
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
+    use std::sync::Arc;
 
     use crate::interner::Interned;
     use portage_atom::{Blocker, Cpv, Dep};
-    use resolvo::{ArenaId, Problem, Solver, VersionSetId};
-
-    use crate::pool::{DepClass, InstalledSet, PackageDeps, PackageMetadata, UseConfig};
-    use crate::provider::PortageDependencyProvider;
+    use resolvo::{
+        ArenaId, Problem, Requirement, SolvableId, Solver, VersionSetId, VersionSetUnionId,
+    };
+
+    use crate::cache::{CacheError, CacheKey, repository_fingerprint};
+    use crate::error::{DepConstruct, ResolveError};
+
+    use crate::builder::ProviderBuilder;
+    use crate::pool::{
+        DepClass, DepClassMode, DepClassModes, DepClassPolicy, Domain, InstalledSet, PackageDeps,
+        PackageMetadata, UseConfig,
+    };
+    use crate::provider::{DomainConfig, PortageDependencyProvider};
     use crate::repository::InMemoryRepository;
     use portage_atom::DepEntry;
 
     /// Helper: build a [`PackageMetadata`] from a CPV string.
     /// Deps are placed in `depend` (build-time) for simplicity.
-    fn pkg(cpv: &str, slot: &str, deps: Vec<DepEntry>) -> PackageMetadata {
+    pub(crate) fn pkg(cpv: &str, slot: &str, deps: Vec<DepEntry>) -> PackageMetadata {
         PackageMetadata {
             cpv: Cpv::parse(cpv).unwrap(),
             slot: Some(Interned::intern(slot)),
@@ -50,7 +96,12 @@ mod tests {
     }
 
     /// Helper: build a [`PackageMetadata`] with a sub-slot.
-    fn pkg_subslot(cpv: &str, slot: &str, subslot: &str, deps: Vec<DepEntry>) -> PackageMetadata {
+    pub(crate) fn pkg_subslot(
+        cpv: &str,
+        slot: &str,
+        subslot: &str,
+        deps: Vec<DepEntry>,
+    ) -> PackageMetadata {
         PackageMetadata {
             cpv: Cpv::parse(cpv).unwrap(),
             slot: Some(slot.into()),
@@ -454,7 +505,7 @@ mod tests {
         repo.add(pkg("dev-lib/openssl-3.0.0", "0", vec![]));
 
         let use_config = UseConfig {
-            solver_decided: ["ssl"].into_iter().map(|s| Interned::intern(s)).collect(),
+            solver_decided: ["ssl"].into_iter().map(Interned::intern).collect(),
             ..UseConfig::default()
         };
         let mut provider = PortageDependencyProvider::new(&repo, &use_config);
@@ -496,7 +547,7 @@ mod tests {
         repo.add(pkg("dev-lib/libressl-3.9.0", "0", vec![]));
 
         let use_config = UseConfig {
-            solver_decided: ["ssl"].into_iter().map(|s| Interned::intern(s)).collect(),
+            solver_decided: ["ssl"].into_iter().map(Interned::intern).collect(),
             ..UseConfig::default()
         };
         let mut provider = PortageDependencyProvider::new(&repo, &use_config);
@@ -555,7 +606,7 @@ mod tests {
         repo.add(pkg("dev-lib/libressl-3.9.0", "0", vec![]));
 
         let use_config = UseConfig {
-            solver_decided: ["ssl"].into_iter().map(|s| Interned::intern(s)).collect(),
+            solver_decided: ["ssl"].into_iter().map(Interned::intern).collect(),
             ..UseConfig::default()
         };
         let mut provider = PortageDependencyProvider::new(&repo, &use_config);
@@ -626,7 +677,7 @@ mod tests {
         ));
 
         let use_config = UseConfig {
-            solver_decided: ["ssl"].into_iter().map(|s| Interned::intern(s)).collect(),
+            solver_decided: ["ssl"].into_iter().map(Interned::intern).collect(),
             ..UseConfig::default()
         };
         let mut provider = PortageDependencyProvider::new(&repo, &use_config);
@@ -677,7 +728,7 @@ mod tests {
         repo.add(pkg("dev-lib/bar-1.0", "0", vec![]));
 
         let use_config = UseConfig {
-            solver_decided: ["ssl"].into_iter().map(|s| Interned::intern(s)).collect(),
+            solver_decided: ["ssl"].into_iter().map(Interned::intern).collect(),
             ..UseConfig::default()
         };
         let mut provider = PortageDependencyProvider::new(&repo, &use_config);
@@ -1090,6 +1141,499 @@ mod tests {
         assert_eq!(solution.len(), 2);
     }
 
+    // ── Dep class policy tests ───────────────────────────────────────
+
+    /// Helper: build a [`PackageMetadata`] from `DEPEND` and `RDEPEND` strings.
+    pub(crate) fn pkg_build_run(cpv: &str, depend: &str, rdepend: &str) -> PackageMetadata {
+        PackageMetadata {
+            cpv: Cpv::parse(cpv).unwrap(),
+            slot: Some("0".into()),
+            subslot: None,
+            iuse: vec![],
+            use_flags: HashSet::new(),
+            repo: None,
+            dependencies: PackageDeps {
+                depend: DepEntry::parse(depend).unwrap(),
+                rdepend: DepEntry::parse(rdepend).unwrap(),
+                ..PackageDeps::default()
+            },
+        }
+    }
+
+    pub(crate) fn solve_cpvs(
+        provider: PortageDependencyProvider,
+        roots: &[&str],
+    ) -> HashSet<String> {
+        let mut provider = provider;
+        let reqs = roots
+            .iter()
+            .map(|r| provider.intern_requirement(&Dep::parse(r).unwrap()))
+            .collect();
+        let mut solver = Solver::new(provider);
+        let solution = solver.solve(Problem::new().requirements(reqs)).unwrap();
+        solution
+            .iter()
+            .map(|&sid| solver.provider().package_metadata(sid).cpv.to_string())
+            .collect()
+    }
+
+    /// Helper: a repository with its installed set and USE configuration,
+    /// for tests that build several providers over the same packages.
+    #[derive(Default)]
+    pub(crate) struct Fixture {
+        pub(crate) repo: Arc<InMemoryRepository>,
+        pub(crate) installed: InstalledSet,
+        pub(crate) use_config: UseConfig,
+    }
+
+    impl Fixture {
+        /// A fixture over `packages`, with nothing installed.
+        pub(crate) fn new(packages: impl IntoIterator<Item = PackageMetadata>) -> Self {
+            let mut repo = InMemoryRepository::new();
+            for meta in packages {
+                repo.add(meta);
+            }
+            Self {
+                repo: Arc::new(repo),
+                ..Self::default()
+            }
+        }
+
+        /// Adds `packages` to the installed set as favored.
+        pub(crate) fn favored(
+            mut self,
+            packages: impl IntoIterator<Item = PackageMetadata>,
+        ) -> Self {
+            for meta in packages {
+                self.installed.add_favored(meta);
+            }
+            self
+        }
+
+        /// Adds `packages` to the installed set as locked.
+        pub(crate) fn locked(
+            mut self,
+            packages: impl IntoIterator<Item = PackageMetadata>,
+        ) -> Self {
+            for meta in packages {
+                self.installed.add_locked(meta);
+            }
+            self
+        }
+
+        /// Enables `flags` globally.
+        pub(crate) fn use_flags(mut self, flags: &[&str]) -> Self {
+            self.use_config = UseConfig::from(
+                flags
+                    .iter()
+                    .map(|&f| Interned::intern(f))
+                    .collect::<HashSet<_>>(),
+            );
+            self
+        }
+
+        /// An eager provider builder over the fixture.
+        pub(crate) fn builder(&self) -> ProviderBuilder<'_> {
+            ProviderBuilder::new(&*self.repo)
+                .use_config(&self.use_config)
+                .installed(&self.installed)
+        }
+
+        /// A lazy provider builder over the fixture.
+        pub(crate) fn lazy_builder(&self) -> ProviderBuilder<'_> {
+            ProviderBuilder::lazy(Arc::clone(&self.repo))
+                .use_config(&self.use_config)
+                .installed(&self.installed)
+        }
+
+        /// An eager provider over the fixture with default settings.
+        pub(crate) fn provider(&self) -> PortageDependencyProvider {
+            self.builder().build().unwrap()
+        }
+
+        /// The cache key of a provider built with default settings.
+        pub(crate) fn cache_key(&self) -> (u64, CacheKey) {
+            let repository = repository_fingerprint(&*self.repo, &self.installed).unwrap();
+            let key = CacheKey::new(
+                repository,
+                &self.use_config,
+                None,
+                &DepClassPolicy::default(),
+            );
+            (repository, key)
+        }
+    }
+
+    #[test]
+    fn dep_class_policy_runtime_only_drops_build_deps() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/foo-1.0",
+            "dev-util/cmake",
+            "dev-lib/bar",
+        ));
+        repo.add(pkg("dev-util/cmake-3.28", "0", vec![]));
+        repo.add(pkg("dev-lib/bar-1.0", "0", vec![]));
+
+        let provider = PortageDependencyProvider::with_dep_class_policy(
+            &repo,
+            &UseConfig::default(),
+            &InstalledSet::new(),
+            &DepClassPolicy::runtime_only(),
+        );
+        let cpvs = solve_cpvs(provider, &["app-misc/foo"]);
+        assert_eq!(cpvs.len(), 2);
+        assert!(cpvs.contains("dev-lib/bar-1.0"));
+        assert!(!cpvs.contains("dev-util/cmake-3.28"));
+    }
+
+    #[test]
+    fn dep_class_policy_installed_differs_from_built() {
+        // foo is installed: its DEPEND is excluded. baz is to be built: its
+        // DEPEND is still required.
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run("app-misc/foo-1.0", "dev-util/cmake", ""));
+        repo.add(pkg_build_run("app-misc/baz-1.0", "dev-util/meson", ""));
+        repo.add(pkg("dev-util/cmake-3.28", "0", vec![]));
+        repo.add(pkg("dev-util/meson-1.4", "0", vec![]));
+
+        let mut installed = InstalledSet::new();
+        installed.add_favored(pkg_build_run("app-misc/foo-1.0", "dev-util/cmake", ""));
+
+        let policy = DepClassPolicy {
+            built: DepClassModes::default(),
+            installed: DepClassModes::default().with(DepClass::Depend, DepClassMode::Exclude),
+        };
+        let provider = PortageDependencyProvider::with_dep_class_policy(
+            &repo,
+            &UseConfig::default(),
+            &installed,
+            &policy,
+        );
+        let cpvs = solve_cpvs(provider, &["app-misc/foo", "app-misc/baz"]);
+        assert!(cpvs.contains("dev-util/meson-1.4"));
+        assert!(!cpvs.contains("dev-util/cmake-3.28"));
+    }
+
+    #[test]
+    fn dep_class_policy_soften_constrains_without_pulling_in() {
+        // foo DEPEND >=cmake-3.28 softened: cmake is not pulled in by foo,
+        // but when something else needs cmake, the version must match.
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/foo-1.0",
+            ">=dev-util/cmake-3.28",
+            "",
+        ));
+        repo.add(pkg_build_run("app-misc/baz-1.0", "", "dev-util/cmake"));
+        repo.add(pkg("dev-util/cmake-3.27", "0", vec![]));
+        repo.add(pkg("dev-util/cmake-3.28", "0", vec![]));
+
+        let mut installed = InstalledSet::new();
+        installed.add_favored(pkg("dev-util/cmake-3.27", "0", vec![]));
+
+        let policy = DepClassPolicy {
+            built: DepClassModes::default().with(DepClass::Depend, DepClassMode::Soften),
+            installed: DepClassModes::default(),
+        };
+        let build = |installed: &InstalledSet| {
+            PortageDependencyProvider::with_dep_class_policy(
+                &repo,
+                &UseConfig::default(),
+                installed,
+                &policy,
+            )
+        };
+
+        let cpvs = solve_cpvs(build(&InstalledSet::new()), &["app-misc/foo"]);
+        assert_eq!(cpvs.len(), 1);
+
+        // The favored 3.27 would normally be kept; the softened DEPEND
+        // forces 3.28 once baz pulls cmake in.
+        let cpvs = solve_cpvs(build(&installed), &["app-misc/foo", "app-misc/baz"]);
+        assert!(cpvs.contains("dev-util/cmake-3.28"));
+        assert!(!cpvs.contains("dev-util/cmake-3.27"));
+    }
+
+    fn soft_python_repo(depend: &str) -> InMemoryRepository {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run("app-misc/foo-1.0", depend, ""));
+        repo.add(pkg("dev-lang/python-3.11.9", "3.11", vec![]));
+        repo.add(pkg("dev-lang/python-3.12.0", "3.12", vec![]));
+        repo.add(pkg("dev-lang/python-3.12.1", "3.12", vec![]));
+        repo
+    }
+
+    fn soft_depend_policy() -> DepClassPolicy {
+        DepClassPolicy {
+            built: DepClassModes::default().with(DepClass::Depend, DepClassMode::Soften),
+            installed: DepClassModes::default(),
+        }
+    }
+
+    #[test]
+    fn dep_class_policy_soften_constrains_slotted_atom() {
+        // Softened, the atom pulls nothing in but still bounds its slot
+        // when something else pulls it in.
+        let repo = soft_python_repo(">=dev-lang/python-3.12.1:3.12");
+        let mut installed = InstalledSet::new();
+        installed.add_favored(pkg("dev-lang/python-3.12.0", "3.12", vec![]));
+        let build = |installed: &InstalledSet| {
+            PortageDependencyProvider::with_dep_class_policy(
+                &repo,
+                &UseConfig::default(),
+                installed,
+                &soft_depend_policy(),
+            )
+        };
+
+        let cpvs = solve_cpvs(build(&InstalledSet::new()), &["app-misc/foo"]);
+        assert_eq!(cpvs.len(), 1);
+
+        let cpvs = solve_cpvs(build(&installed), &["app-misc/foo", "dev-lang/python:3.12"]);
+        assert!(cpvs.contains("dev-lang/python-3.12.1"));
+        assert!(!cpvs.contains("dev-lang/python-3.12.0"));
+    }
+
+    #[test]
+    fn dep_class_policy_soften_drops_multi_slot_union() {
+        // The unslotted atom spans slots 3.11 and 3.12. As a requirement it
+        // allows python:3.11 next to a matching 3.12, so softened it must
+        // not forbid 3.11.
+        let repo = soft_python_repo(">=dev-lang/python-3.12.1");
+        let provider = PortageDependencyProvider::with_dep_class_policy(
+            &repo,
+            &UseConfig::default(),
+            &InstalledSet::new(),
+            &soft_depend_policy(),
+        );
+        let cpvs = solve_cpvs(provider, &["app-misc/foo", "dev-lang/python:3.11"]);
+        assert!(cpvs.contains("dev-lang/python-3.11.9"), "{cpvs:?}");
+    }
+
+    #[test]
+    fn dep_class_policy_soften_keeps_solver_decided_condition() {
+        // The constrain behind `py?` applies only when the solver turns
+        // `py` on, here forced by requiring its flag virtual.
+        let repo = soft_python_repo("py? ( >=dev-lang/python-3.12.1:3.12 )");
+        let use_config = UseConfig {
+            solver_decided: ["py"].into_iter().map(Interned::intern).collect(),
+            ..UseConfig::default()
+        };
+        let mut installed = InstalledSet::new();
+        installed.add_favored(pkg("dev-lang/python-3.12.0", "3.12", vec![]));
+        let build = || {
+            PortageDependencyProvider::with_dep_class_policy(
+                &repo,
+                &use_config,
+                &installed,
+                &soft_depend_policy(),
+            )
+        };
+
+        let cpvs = solve_cpvs(build(), &["app-misc/foo", "dev-lang/python:3.12"]);
+        assert!(cpvs.contains("dev-lang/python-3.12.0"));
+        assert!(!cpvs.iter().any(|cpv| cpv.starts_with("virtual/soft_")));
+
+        let cpvs = solve_cpvs(
+            build(),
+            &["app-misc/foo", "dev-lang/python:3.12", "virtual/USE_py"],
+        );
+        assert!(cpvs.contains("dev-lang/python-3.12.1"));
+        assert!(!cpvs.contains("dev-lang/python-3.12.0"));
+        assert!(cpvs.iter().any(|cpv| cpv.starts_with("virtual/soft_")));
+    }
+
+    // ── Cross-compilation domain tests ───────────────────────────────
+
+    #[test]
+    fn cross_bdepend_resolves_on_host() {
+        // foo BDEPEND cmake, RDEPEND bar. cmake must come from the host
+        // repository; bar from the target repository.
+        let mut target_repo = InMemoryRepository::new();
+        target_repo.add(PackageMetadata {
+            cpv: Cpv::parse("app-misc/foo-1.0").unwrap(),
+            slot: Some("0".into()),
+            subslot: None,
+            iuse: vec![],
+            use_flags: HashSet::new(),
+            repo: None,
+            dependencies: PackageDeps {
+                bdepend: DepEntry::parse("dev-util/cmake").unwrap(),
+                rdepend: DepEntry::parse("dev-lib/bar").unwrap(),
+                ..PackageDeps::default()
+            },
+        });
+        target_repo.add(pkg("dev-lib/bar-1.0", "0", vec![]));
+        target_repo.add(pkg("dev-util/cmake-3.27", "0", vec![]));
+
+        let mut host_repo = InMemoryRepository::new();
+        host_repo.add(pkg("dev-util/cmake-3.28", "0", vec![]));
+
+        let use_config = UseConfig::default();
+        let installed = InstalledSet::new();
+        let mut provider = PortageDependencyProvider::cross(
+            DomainConfig {
+                repo: &target_repo,
+                use_config: &use_config,
+                installed: &installed,
+            },
+            DomainConfig {
+                repo: &host_repo,
+                use_config: &use_config,
+                installed: &installed,
+            },
+            &DepClassPolicy::default(),
+        );
+        let req = provider.intern_requirement(&Dep::parse("app-misc/foo").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let provider = solver.provider();
+
+        let tagged: HashSet<(String, Domain)> = solution
+            .iter()
+            .map(|&sid| {
+                (
+                    provider.package_metadata(sid).cpv.to_string(),
+                    provider.domain(sid),
+                )
+            })
+            .collect();
+        assert_eq!(tagged.len(), 3);
+        assert!(tagged.contains(&("app-misc/foo-1.0".into(), Domain::Target)));
+        assert!(tagged.contains(&("dev-lib/bar-1.0".into(), Domain::Target)));
+        assert!(tagged.contains(&("dev-util/cmake-3.28".into(), Domain::Host)));
+
+        // The cross-domain BDEPEND edge orders host cmake before foo.
+        let order = provider.install_order(&solution).unwrap();
+        let pos = |cpv: &str| {
+            order
+                .iter()
+                .position(|&sid| provider.package_metadata(sid).cpv.to_string() == cpv)
+                .unwrap()
+        };
+        assert!(pos("dev-util/cmake-3.28") < pos("app-misc/foo-1.0"));
+        let edges = provider.dependency_graph(&solution);
+        assert!(
+            edges
+                .iter()
+                .any(|e| e.class == DepClass::Bdepend && provider.domain(e.to) == Domain::Host)
+        );
+    }
+
+    #[test]
+    fn cross_root_use_deps_follow_domain_use_config() {
+        // tool[ssl?]: ssl is enabled on the target only, so only the target
+        // root asks for tool[ssl].
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg("dev-util/tool-1.0", "0", vec![]));
+        let target_use = UseConfig::from(HashSet::from([Interned::intern("ssl")]));
+        let host_use = UseConfig::default();
+        let installed = InstalledSet::new();
+        let mut provider = PortageDependencyProvider::cross(
+            DomainConfig {
+                repo: &repo,
+                use_config: &target_use,
+                installed: &installed,
+            },
+            DomainConfig {
+                repo: &repo,
+                use_config: &host_use,
+                installed: &installed,
+            },
+            &DepClassPolicy::default(),
+        );
+        let tool = Dep::parse("dev-util/tool:0[ssl?]").unwrap();
+        let mut use_constraints = |domain: Domain| {
+            let req = provider.intern_requirement_in(&tool, domain);
+            let Requirement::Single(vs) = req.requirement else {
+                panic!("expected a single version set");
+            };
+            provider
+                .pool()
+                .resolve_version_set(vs)
+                .use_constraints
+                .clone()
+        };
+        assert_eq!(
+            use_constraints(Domain::Target),
+            [(Interned::intern("ssl"), true)]
+        );
+        assert!(use_constraints(Domain::Host).is_empty());
+    }
+
+    #[test]
+    fn cross_host_uses_own_use_config() {
+        // foo BDEPEND ssl? ( openssl ). ssl is enabled on the target only,
+        // so the conditional is evaluated with the target config even
+        // though openssl resolves on the host. The host copy of foo (a
+        // host root) does not see ssl.
+        let foo = PackageMetadata {
+            cpv: Cpv::parse("app-misc/foo-1.0").unwrap(),
+            slot: Some("0".into()),
+            subslot: None,
+            iuse: vec!["ssl".into()],
+            use_flags: HashSet::new(),
+            repo: None,
+            dependencies: PackageDeps {
+                bdepend: DepEntry::parse("ssl? ( dev-libs/openssl )").unwrap(),
+                ..PackageDeps::default()
+            },
+        };
+        let mut repo = InMemoryRepository::new();
+        repo.add(foo);
+        repo.add(pkg("dev-libs/openssl-3.2.1", "0", vec![]));
+
+        let target_use = UseConfig::from(
+            ["ssl"]
+                .into_iter()
+                .map(Interned::intern)
+                .collect::<HashSet<_>>(),
+        );
+        let host_use = UseConfig::default();
+        let installed = InstalledSet::new();
+        let build = || {
+            PortageDependencyProvider::cross(
+                DomainConfig {
+                    repo: &repo,
+                    use_config: &target_use,
+                    installed: &installed,
+                },
+                DomainConfig {
+                    repo: &repo,
+                    use_config: &host_use,
+                    installed: &installed,
+                },
+                &DepClassPolicy::default(),
+            )
+        };
+        let foo_dep = Dep::parse("app-misc/foo").unwrap();
+
+        let mut provider = build();
+        let req = provider.intern_requirement(&foo_dep);
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        assert_eq!(solution.len(), 2);
+        let openssl = solution
+            .iter()
+            .find(|&&sid| solver.provider().package_metadata(sid).cpv.cpn.package == "openssl")
+            .unwrap();
+        assert_eq!(solver.provider().domain(*openssl), Domain::Host);
+
+        let mut provider = build();
+        let req = provider.intern_requirement_in(&foo_dep, Domain::Host);
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        assert_eq!(solution.len(), 1);
+        assert_eq!(solver.provider().domain(solution[0]), Domain::Host);
+    }
+
     // ── Circular dependency / install ordering tests ─────────────────
 
     #[test]
@@ -1224,6 +1768,209 @@ mod tests {
         );
     }
 
+    /// Helper: build a two-package cycle `aaa <class_a> bbb`, `bbb <class_b> aaa`
+    /// and return the solver plus its solution.
+    pub(crate) fn solve_cycle(
+        class_a: DepClass,
+        class_b: DepClass,
+        installed: &InstalledSet,
+    ) -> (Solver<PortageDependencyProvider>, Vec<SolvableId>) {
+        let with_dep = |cpv: &str, class: DepClass, atom: &str| {
+            let mut deps = PackageDeps::default();
+            let entries = vec![DepEntry::Atom(Dep::parse(atom).unwrap())];
+            match class {
+                DepClass::Depend => deps.depend = entries,
+                DepClass::Rdepend => deps.rdepend = entries,
+                DepClass::Bdepend => deps.bdepend = entries,
+                DepClass::Pdepend => deps.pdepend = entries,
+                DepClass::Idepend => deps.idepend = entries,
+            }
+            PackageMetadata {
+                cpv: Cpv::parse(cpv).unwrap(),
+                slot: Some("0".into()),
+                subslot: None,
+                iuse: vec![],
+                use_flags: HashSet::new(),
+                repo: None,
+                dependencies: deps,
+            }
+        };
+        let mut repo = InMemoryRepository::new();
+        repo.add(with_dep("app-misc/aaa-1.0", class_a, "app-misc/bbb"));
+        repo.add(with_dep("app-misc/bbb-1.0", class_b, "app-misc/aaa"));
+
+        let mut provider =
+            PortageDependencyProvider::with_installed(&repo, &UseConfig::default(), installed);
+        let req = provider.intern_requirement(&Dep::parse("app-misc/aaa").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        (solver, solution)
+    }
+
+    pub(crate) fn order_names(
+        provider: &PortageDependencyProvider,
+        order: &[SolvableId],
+    ) -> Vec<String> {
+        order
+            .iter()
+            .map(|&sid| provider.package_metadata(sid).cpv.cpn.package.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn install_order_relaxes_rdepend_cycle() {
+        // aaa RDEPEND bbb, bbb DEPEND aaa: the RDEPEND edge is deferred, so
+        // aaa is built first.
+        let (solver, solution) =
+            solve_cycle(DepClass::Rdepend, DepClass::Depend, &InstalledSet::new());
+        let order = solver.provider().install_order(&solution).unwrap();
+        assert_eq!(order_names(solver.provider(), &order), ["aaa", "bbb"]);
+    }
+
+    #[test]
+    fn install_order_relaxes_rdepend_before_idepend() {
+        // aaa IDEPEND bbb, bbb RDEPEND aaa: RDEPEND is softer, so bbb's
+        // edge is dropped and bbb is merged before aaa.
+        let (solver, solution) =
+            solve_cycle(DepClass::Idepend, DepClass::Rdepend, &InstalledSet::new());
+        let order = solver.provider().install_order(&solution).unwrap();
+        assert_eq!(order_names(solver.provider(), &order), ["bbb", "aaa"]);
+    }
+
+    #[test]
+    fn install_order_installed_satisfier_breaks_cycle() {
+        // aaa DEPEND bbb, bbb DEPEND aaa, bbb already installed: the edge to
+        // bbb is satisfied, so only bbb -> aaa remains.
+        let mut installed = InstalledSet::new();
+        installed.add_favored(PackageMetadata {
+            cpv: Cpv::parse("app-misc/bbb-1.0").unwrap(),
+            slot: Some("0".into()),
+            subslot: None,
+            iuse: vec![],
+            use_flags: HashSet::new(),
+            repo: None,
+            dependencies: PackageDeps::default(),
+        });
+        let (solver, solution) = solve_cycle(DepClass::Depend, DepClass::Depend, &installed);
+        let order = solver.provider().install_order(&solution).unwrap();
+        assert_eq!(order_names(solver.provider(), &order), ["aaa", "bbb"]);
+    }
+
+    #[test]
+    fn install_order_reports_hard_cycle() {
+        // aaa DEPEND bbb, bbb BDEPEND aaa: nothing can be relaxed.
+        let (solver, solution) =
+            solve_cycle(DepClass::Depend, DepClass::Bdepend, &InstalledSet::new());
+        let cycles = solver.provider().install_order(&solution).unwrap_err();
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(
+            order_names(solver.provider(), &cycles[0].members),
+            ["aaa", "bbb"]
+        );
+        let mut classes: Vec<DepClass> = cycles[0].edges.iter().map(|e| e.class).collect();
+        classes.sort_by_key(|c| c.to_string());
+        assert_eq!(classes, [DepClass::Bdepend, DepClass::Depend]);
+    }
+
+    /// Helper: solve `roots` and return the `DEPEND` edge targets of
+    /// `from` by package name.
+    fn graph_targets(
+        repo: &InMemoryRepository,
+        use_config: &UseConfig,
+        roots: &[&str],
+        from: &str,
+    ) -> Vec<String> {
+        let mut provider = PortageDependencyProvider::new(repo, use_config);
+        let reqs = roots
+            .iter()
+            .map(|r| provider.intern_requirement(&Dep::parse(r).unwrap()))
+            .collect();
+        let mut solver = Solver::new(provider);
+        let solution = solver.solve(Problem::new().requirements(reqs)).unwrap();
+        let provider = solver.provider();
+        let targets: Vec<SolvableId> = provider
+            .dependency_graph(&solution)
+            .into_iter()
+            .filter(|e| provider.package_metadata(e.from).cpv.cpn.package == from)
+            .map(|e| e.to)
+            .collect();
+        let mut names = order_names(provider, &targets);
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn dependency_graph_follows_solver_flag_choice() {
+        // ssl is solver-decided and left off, but openssl is in the
+        // solution anyway: foo's ssl? edge must not appear, its !ssl? edge
+        // must.
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/foo-1.0",
+            "ssl? ( dev-lib/openssl ) !ssl? ( dev-lib/libressl )",
+            "",
+        ));
+        repo.add(pkg("dev-lib/openssl-3.0.0", "0", vec![]));
+        repo.add(pkg("dev-lib/libressl-3.9.0", "0", vec![]));
+        let use_config = UseConfig {
+            solver_decided: ["ssl"].into_iter().map(Interned::intern).collect(),
+            ..UseConfig::default()
+        };
+
+        let targets = graph_targets(
+            &repo,
+            &use_config,
+            &["app-misc/foo", "dev-lib/openssl"],
+            "foo",
+        );
+        assert_eq!(targets, ["libressl"]);
+    }
+
+    #[test]
+    fn dependency_graph_follows_chosen_alternative() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/any-1.0",
+            "|| ( dev-lib/bar dev-lib/baz )",
+            "",
+        ));
+        repo.add(pkg_build_run(
+            "app-misc/one-1.0",
+            "^^ ( dev-lib/bar dev-lib/baz )",
+            "",
+        ));
+        repo.add(pkg("dev-lib/bar-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/baz-1.0", "0", vec![]));
+        let roots = ["app-misc/any", "app-misc/one", "dev-lib/bar", "dev-lib/baz"];
+
+        // || follows the left-most satisfied alternative only.
+        let targets = graph_targets(&repo, &UseConfig::default(), &roots, "any");
+        assert_eq!(targets, ["bar"]);
+
+        // ^^ follows the alternative the solver picked, not both.
+        let targets = graph_targets(&repo, &UseConfig::default(), &roots, "one");
+        assert_eq!(targets.len(), 1, "targets: {targets:?}");
+    }
+
+    #[test]
+    fn unmask_restores_a_masked_candidate() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg("dev-lib/foo-1.2", "0", vec![]));
+        repo.add(pkg("dev-lib/foo-1.4", "0", vec![]));
+        let mut provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+        let newest =
+            provider.find_solvables(Domain::Target, &Cpv::parse("dev-lib/foo-1.4").unwrap());
+
+        provider.mask(newest[0], "package.mask");
+        assert!(provider.unmask(newest[0]));
+        assert!(!provider.unmask(newest[0]));
+        assert!(!provider.is_masked(newest[0]));
+        let cpvs = solve_cpvs(provider, &["dev-lib/foo"]);
+        assert!(cpvs.contains("dev-lib/foo-1.4"), "{cpvs:?}");
+    }
+
     #[test]
     fn dependency_graph_labels() {
         // A has DEPEND B, RDEPEND C, PDEPEND D.
@@ -1278,6 +2025,77 @@ mod tests {
         assert_eq!(find_edge("ddd"), DepClass::Pdepend);
     }
 
+    #[test]
+    fn requirement_origins_recorded() {
+        // A has DEPEND B, RDEPEND || ( C D ), and a weak blocker on E.
+        let mut repo = InMemoryRepository::new();
+        repo.add(PackageMetadata {
+            cpv: Cpv::parse("app-misc/aaa-1.0").unwrap(),
+            slot: Some("0".into()),
+            subslot: None,
+            iuse: vec![],
+            use_flags: HashSet::new(),
+            repo: None,
+            dependencies: PackageDeps {
+                depend: vec![
+                    DepEntry::Atom(Dep::parse(">=dev-lib/bbb-1.0").unwrap()),
+                    DepEntry::Atom(Dep::parse("!dev-lib/eee").unwrap()),
+                ],
+                rdepend: vec![DepEntry::AnyOf(vec![
+                    DepEntry::Atom(Dep::parse("dev-lib/ccc").unwrap()),
+                    DepEntry::Atom(Dep::parse("dev-lib/ddd").unwrap()),
+                ])],
+                ..PackageDeps::default()
+            },
+        });
+        repo.add(pkg("dev-lib/bbb-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/ccc-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/ddd-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/eee-1.0", "0", vec![]));
+
+        let provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+        let pool = provider.pool();
+        let find_vs = |package: &str| {
+            (0..pool.version_set_count())
+                .map(VersionSetId::from_usize)
+                .find(|&vs| pool.resolve_version_set(vs).cpn.package == package)
+                .unwrap()
+        };
+
+        let origins = provider.requirement_origins(Requirement::Single(find_vs("bbb")));
+        assert_eq!(origins.len(), 1);
+        assert_eq!(
+            provider
+                .package_metadata(origins[0].solvable)
+                .cpv
+                .to_string(),
+            "app-misc/aaa-1.0"
+        );
+        assert_eq!(origins[0].class, DepClass::Depend);
+        assert_eq!(origins[0].atom, ">=dev-lib/bbb-1.0");
+
+        // Blockers are looked up as single requirements.
+        let origins = provider.requirement_origins(Requirement::Single(find_vs("eee")));
+        assert_eq!(origins.len(), 1);
+        assert_eq!(origins[0].class, DepClass::Depend);
+        assert_eq!(origins[0].atom, "!dev-lib/eee");
+
+        let union = Requirement::Union(VersionSetUnionId::from_usize(0));
+        assert_eq!(
+            pool.resolve_version_set_union(VersionSetUnionId::from_usize(0)),
+            &[find_vs("ccc"), find_vs("ddd")]
+        );
+        let origins = provider.requirement_origins(union);
+        assert_eq!(origins.len(), 1);
+        assert_eq!(origins[0].class, DepClass::Rdepend);
+        assert_eq!(origins[0].atom, "|| ( dev-lib/ccc dev-lib/ddd )");
+
+        // Root requirements have no origin.
+        let mut provider = provider;
+        let root = provider.intern_requirement(&Dep::parse("app-misc/aaa").unwrap());
+        assert!(provider.requirement_origins(root.requirement).is_empty());
+    }
+
     // ── Installed-package / favored / locked tests ──────────────────
 
     #[test]
@@ -1784,4 +2602,376 @@ mod tests {
             assert!(cpvs.contains("dev-python/sphinx-7.0.0"));
         }
     }
+
+    #[test]
+    fn lazy_provider_matches_eager() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "dev-lang/python ^^ ( dev-lib/a dev-lib/b ) ssl? ( dev-lib/openssl ) \
+             xml? ( dev-lib/libxml2 ) || ( dev-lib/missing ( dev-lib/b dev-lib/c ) )",
+            ">=dev-lib/a-1",
+        ));
+        repo.add(pkg("dev-lang/python-3.11.7", "3.11", vec![]));
+        repo.add(pkg("dev-lang/python-3.12.1", "3.12", vec![]));
+        repo.add(pkg("dev-lib/a-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/a-1.1", "0", vec![]));
+        repo.add(pkg("dev-lib/b-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/c-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/openssl-3.0.0", "0", vec![]));
+        repo.add(pkg("dev-lib/libxml2-2.12", "2", vec![]));
+        repo.add(pkg_build_run("dev-lib/unused-1.0", "dev-lib/a", ""));
+        let repo = std::sync::Arc::new(repo);
+        let use_config = UseConfig {
+            enabled: ["ssl"].into_iter().map(Interned::intern).collect(),
+            solver_decided: ["xml"].into_iter().map(Interned::intern).collect(),
+            ..UseConfig::default()
+        };
+        let mut installed = InstalledSet::new();
+        installed.add_favored(pkg("dev-lib/a-1.0", "0", vec![]));
+        installed.add_favored(pkg("dev-lang/python-3.10.9", "3.10", vec![]));
+
+        let solve = |provider: PortageDependencyProvider| {
+            let mut provider = provider;
+            let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+            let mut solver = Solver::new(provider);
+            let solution = solver
+                .solve(Problem::new().requirements(vec![req]))
+                .unwrap();
+            let provider = solver.provider();
+            let mut cpvs: Vec<String> = solution
+                .iter()
+                .filter(|&&sid| !provider.is_synthetic(sid))
+                .map(|&sid| provider.package_metadata(sid).cpv.to_string())
+                .collect();
+            cpvs.sort();
+            let tree = provider.render_tree(&solution);
+            let interned: Vec<String> = (0..provider.pool().solvable_count())
+                .map(|i| {
+                    provider
+                        .package_metadata(SolvableId::from_usize(i))
+                        .cpv
+                        .to_string()
+                })
+                .collect();
+            (cpvs, tree, interned)
+        };
+
+        let eager = PortageDependencyProvider::with_installed(&*repo, &use_config, &installed);
+        let lazy = PortageDependencyProvider::lazy(repo.clone(), &use_config, &installed);
+        assert_eq!(
+            lazy.pool().solvable_count(),
+            2,
+            "only flag virtuals up front"
+        );
+        assert!(matches!(
+            lazy.write_cache(Vec::new(), 0),
+            Err(CacheError::Lazy)
+        ));
+
+        let (eager_cpvs, eager_tree, eager_interned) = solve(eager);
+        let (lazy_cpvs, lazy_tree, lazy_interned) = solve(lazy);
+        assert_eq!(lazy_cpvs, eager_cpvs);
+        assert_eq!(lazy_tree, eager_tree);
+        assert!(lazy_cpvs.contains(&"dev-lang/python-3.11.7".to_string()));
+        assert!(
+            lazy_cpvs.contains(&"dev-lib/a-1.0".to_string()),
+            "installed is favored"
+        );
+        assert!(lazy_cpvs.contains(&"dev-lib/openssl-3.0.0".to_string()));
+        assert!(eager_interned.contains(&"dev-lib/unused-1.0".to_string()));
+        assert!(!lazy_interned.contains(&"dev-lib/unused-1.0".to_string()));
+        assert!(lazy_interned.contains(&"dev-lang/python-3.10.9".to_string()));
+    }
+
+    #[test]
+    fn repository_errors_propagate() {
+        use std::borrow::Cow;
+
+        use portage_atom::Cpn;
+        use resolvo::UnsolvableOrCancelled;
+
+        use crate::repository::{PackageRepository, RepositoryError, RepositoryResult};
+
+        /// Repository whose `dev-lib/broken` metadata cannot be read.
+        struct Flaky(InMemoryRepository);
+
+        impl PackageRepository for Flaky {
+            fn all_packages(&self) -> RepositoryResult<Box<dyn Iterator<Item = Cpn> + '_>> {
+                self.0.all_packages()
+            }
+
+            fn versions_for(&self, cpn: &Cpn) -> RepositoryResult<Cow<'_, [PackageMetadata]>> {
+                if *cpn == Cpn::new("dev-lib", "broken") {
+                    return Err(RepositoryError::new("dev-lib/broken: corrupt metadata"));
+                }
+                self.0.versions_for(cpn)
+            }
+
+            fn slots_for(
+                &self,
+                _cpn: &Cpn,
+            ) -> RepositoryResult<Vec<Option<Interned<crate::interner::DefaultInterner>>>>
+            {
+                Ok(vec![Some(Interned::intern("0"))])
+            }
+        }
+
+        let mut inner = InMemoryRepository::new();
+        inner.add(pkg_build_run("app-misc/app-1.0", "dev-lib/broken", ""));
+        inner.add(pkg("dev-lib/broken-1.0", "0", vec![]));
+        let repo = Flaky(inner);
+        let use_config = UseConfig::default();
+
+        let err = PortageDependencyProvider::try_new(&repo, &use_config)
+            .err()
+            .expect("eager construction reads every package");
+        assert!(matches!(err, ResolveError::Repository(_)));
+        assert!(err.to_string().contains("corrupt metadata"));
+
+        let mut provider = PortageDependencyProvider::lazy(repo, &use_config, &InstalledSet::new());
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        match solver.solve(Problem::new().requirements(vec![req])) {
+            Err(UnsolvableOrCancelled::Cancelled(value)) => {
+                let err = value.downcast::<ResolveError>().unwrap();
+                assert!(err.to_string().contains("corrupt metadata"));
+            }
+            other => panic!("expected cancellation, got {other:?}"),
+        }
+    }
+
+    fn construction_error(packages: Vec<PackageMetadata>, use_config: &UseConfig) -> ResolveError {
+        let mut repo = InMemoryRepository::new();
+        for meta in packages {
+            repo.add(meta);
+        }
+        PortageDependencyProvider::try_new(&repo, use_config)
+            .err()
+            .expect("construction should fail")
+    }
+
+    fn solver_decided_ssl() -> UseConfig {
+        UseConfig {
+            solver_decided: ["ssl"].into_iter().map(Interned::intern).collect(),
+            ..UseConfig::default()
+        }
+    }
+
+    #[test]
+    fn construction_rejects_invalid_solver_flag() {
+        // A solver-decided flag ends up in a virtual package name.
+        let bad_flag = UseConfig {
+            solver_decided: ["-ssl"].into_iter().map(Interned::intern).collect(),
+            ..UseConfig::default()
+        };
+        let err = construction_error(vec![], &bad_flag);
+        assert!(
+            matches!(&err, ResolveError::InvalidFlag { flag, domain: Domain::Target } if flag == "-ssl")
+        );
+    }
+
+    #[test]
+    fn construction_rejects_subslot_without_slot() {
+        let mut orphan_subslot = pkg("dev-lib/a-1.0", "0", vec![]);
+        orphan_subslot.slot = None;
+        orphan_subslot.subslot = Some("1".into());
+        let err = construction_error(vec![orphan_subslot], &UseConfig::default());
+        assert!(
+            matches!(&err, ResolveError::MalformedMetadata { cpv, .. } if cpv.to_string() == "dev-lib/a-1.0")
+        );
+    }
+
+    #[test]
+    fn construction_rejects_reserved_name() {
+        let err = construction_error(
+            vec![pkg("virtual/USE_ssl-1.0", "0", vec![])],
+            &UseConfig::default(),
+        );
+        assert!(err.to_string().contains("reserved"));
+    }
+
+    #[test]
+    fn construction_rejects_duplicate_cpv() {
+        let err = construction_error(
+            vec![
+                pkg("dev-lib/a-1.0", "0", vec![]),
+                pkg("dev-lib/a-1.0", "0", vec![]),
+            ],
+            &UseConfig::default(),
+        );
+        assert!(
+            matches!(&err, ResolveError::DuplicateCpv { cpv, repo: None, .. } if cpv.to_string() == "dev-lib/a-1.0")
+        );
+    }
+
+    #[test]
+    fn construction_accepts_cpv_from_two_repositories() {
+        let mut repo = InMemoryRepository::new();
+        let mut overlay = pkg("dev-lib/a-1.0", "0", vec![]);
+        overlay.repo = Some("overlay".into());
+        repo.add(pkg("dev-lib/a-1.0", "0", vec![]));
+        repo.add(overlay);
+        assert!(PortageDependencyProvider::try_new(&repo, &UseConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn construction_rejects_solver_decided_use_dep() {
+        let err = construction_error(
+            vec![pkg_build_run("app-misc/app-1.0", "", "dev-lib/bar[ssl?]")],
+            &solver_decided_ssl(),
+        );
+        match &err {
+            ResolveError::UnsupportedConstruct {
+                cpv,
+                class,
+                atom,
+                construct,
+                ..
+            } => {
+                assert_eq!(cpv.to_string(), "app-misc/app-1.0");
+                assert_eq!(*construct, DepConstruct::SolverDecidedUseDep);
+                assert_eq!(*class, DepClass::Rdepend);
+                assert_eq!(atom, "dev-lib/bar[ssl?]");
+            }
+            other => panic!("expected unsupported construct, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn new_accepts_solver_decided_use_dep() {
+        // The conditional follows the configured USE, where ssl is off.
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run("app-misc/app-1.0", "", "dev-lib/bar[ssl?]"));
+        repo.add(pkg("dev-lib/bar-1.0", "0", vec![]));
+        let provider = PortageDependencyProvider::new(&repo, &solver_decided_ssl());
+        let cpvs = solve_cpvs(provider, &["app-misc/app"]);
+        assert!(cpvs.contains("dev-lib/bar-1.0"), "{cpvs:?}");
+    }
+
+    #[test]
+    fn new_keeps_last_duplicate_cpv() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run("dev-lib/a-1.0", "", "dev-lib/first"));
+        repo.add(pkg_build_run("dev-lib/a-1.0", "", "dev-lib/last"));
+        repo.add(pkg("dev-lib/first-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/last-1.0", "0", vec![]));
+        let provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+        let cpvs = solve_cpvs(provider, &["dev-lib/a"]);
+        assert!(cpvs.contains("dev-lib/last-1.0"), "{cpvs:?}");
+        assert!(!cpvs.contains("dev-lib/first-1.0"), "{cpvs:?}");
+    }
+
+    #[test]
+    fn construction_rejects_slot_operator_on_blocker() {
+        let mut blocker = Dep::parse("dev-lib/old:=").unwrap();
+        blocker.blocker = Some(Blocker::Weak);
+        let err = construction_error(
+            vec![pkg(
+                "app-misc/app-1.0",
+                "0",
+                vec![DepEntry::AnyOf(vec![DepEntry::Atom(blocker)])],
+            )],
+            &UseConfig::default(),
+        );
+        assert!(err.to_string().contains("slot operator on a blocker"));
+    }
+
+    #[test]
+    fn construction_rejects_installed_duplicates() {
+        let mut installed = InstalledSet::new();
+        installed.add_favored(pkg("dev-lib/a-1.0", "0", vec![]));
+        installed.add_locked(pkg("dev-lib/a-1.0", "0", vec![]));
+        assert!(matches!(
+            PortageDependencyProvider::try_lazy(
+                InMemoryRepository::new(),
+                &UseConfig::default(),
+                &installed
+            ),
+            Err(ResolveError::DuplicateCpv { .. })
+        ));
+    }
+
+    #[test]
+    fn lazy_construction_error_cancels_solve() {
+        use resolvo::UnsolvableOrCancelled;
+
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run("app-misc/app-1.0", "", "dev-lib/bar[ssl=]"));
+        let mut provider =
+            PortageDependencyProvider::try_lazy(repo, &solver_decided_ssl(), &InstalledSet::new())
+                .unwrap();
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        match solver.solve(Problem::new().requirements(vec![req])) {
+            Err(UnsolvableOrCancelled::Cancelled(value)) => {
+                let err = value.downcast::<ResolveError>().unwrap();
+                assert!(matches!(*err, ResolveError::UnsupportedConstruct { .. }));
+            }
+            other => panic!("expected cancellation, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn provider_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<PortageDependencyProvider>();
+        assert_send::<crate::pool::PortagePool>();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        use crate::pool::{InstalledPolicy, InstalledSet, VersionConstraint};
+        use portage_atom::{Cpn, Operator};
+
+        let mut meta = pkg(
+            "dev-libs/foo-1.2-r1",
+            "0",
+            DepEntry::parse("ssl? ( >=dev-libs/openssl-3:= ) || ( dev-libs/a dev-libs/b )")
+                .unwrap(),
+        );
+        meta.iuse = vec![Interned::intern("ssl")];
+        meta.use_flags.insert(Interned::intern("ssl"));
+        meta.repo = Some(Interned::intern("gentoo"));
+
+        let json = serde_json::to_string(&meta).unwrap();
+        assert!(json.contains("\"cpv\":\"dev-libs/foo-1.2-r1\""));
+        let back: PackageMetadata = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.cpv, meta.cpv);
+        assert_eq!(back.slot, meta.slot);
+        assert_eq!(back.repo, meta.repo);
+        assert_eq!(back.use_flags, meta.use_flags);
+        assert_eq!(back.dependencies.depend, meta.dependencies.depend);
+        assert!(back.dependencies.rdepend.is_empty());
+
+        let constraint = VersionConstraint {
+            cpn: Cpn::parse("dev-libs/openssl").unwrap(),
+            operator: Operator::GreaterOrEqual,
+            version: "3.0.1".parse().unwrap(),
+            glob: false,
+            slot: Some(Interned::intern("0")),
+            subslot: None,
+            repo: None,
+            use_constraints: vec![(Interned::intern("asm"), true)],
+            inverted: false,
+        };
+        let json = serde_json::to_string(&constraint).unwrap();
+        assert!(json.contains("\"operator\":\">=\""));
+        let back: VersionConstraint = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, constraint);
+
+        let mut installed = InstalledSet::new();
+        installed.add_locked(meta);
+        let json = serde_json::to_string(&installed).unwrap();
+        let back: InstalledSet = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.packages.len(), 1);
+        assert_eq!(back.packages[0].1, InstalledPolicy::Locked);
+
+        let mut use_config = UseConfig::default();
+        use_config.disabled.insert(Interned::intern("X"));
+        let json = serde_json::to_string(&use_config).unwrap();
+        let back: UseConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.disabled, use_config.disabled);
+    }
 }
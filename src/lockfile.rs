@@ -0,0 +1,915 @@
+//! Lock files: freeze a resolution and replay it.
+//!
+//! [`PortageDependencyProvider::lock`] records a solution as a
+//! [`LockFile`]; [`PortageDependencyProvider::apply_lock`] pins a freshly
+//! built provider to a lock file and reports how the repository drifted
+//! from it.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use portage_atom::gentoo_interner::Interned;
+use portage_atom::{Cpv, Dep, DepEntry};
+use resolvo::{ConditionalRequirement, SolvableId};
+
+use crate::pool::{DepClass, Domain, PackageMetadata};
+use crate::provider::{DepWalkEvent, PortageDependencyProvider};
+
+/// Format version written to and accepted from lock files.
+pub const LOCK_FILE_VERSION: u32 = 2;
+
+/// A frozen resolution.
+///
+/// Records the exact CPVs (plus slot, sub-slot, repository, USE flags and
+/// dependency trees), the alternatives picked from `||` / `^^` / `??` groups, the
+/// solver-decided flag choices, the root atoms and the
+/// [`config_fingerprint`](PortageDependencyProvider::config_fingerprint).
+/// `Display` and [`parse`](Self::parse) use a line-based, diff-friendly
+/// text format:
+///
+/// ```text
+/// # portage-atom-resolvo lock file
+/// version 2
+/// config 5d3e2a2b9c1f0e47
+/// root ROOT app-misc/app
+/// flag ROOT ssl off
+/// package ROOT dev-libs/foo-1.2
+///   slot 0/1.2
+///   repo gentoo
+///   use ssl xml
+///   deps RDEPEND || ( dev-libs/a dev-libs/b ) ssl? ( dev-libs/openssl )
+///   choice RDEPEND || ( dev-libs/a dev-libs/b ) => dev-libs/a
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockFile {
+    /// [`config_fingerprint`](PortageDependencyProvider::config_fingerprint)
+    /// of the provider that produced the solution.
+    pub config_fingerprint: u64,
+    /// Root atoms, in interning order.
+    pub roots: Vec<LockedRoot>,
+    /// Solver-decided USE flag choices.
+    pub flags: Vec<LockedFlag>,
+    /// Every package of the solution, sorted by domain and CPV.
+    pub packages: Vec<LockedPackage>,
+}
+
+/// A root atom of a [`LockFile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedRoot {
+    pub domain: Domain,
+    pub atom: Dep,
+}
+
+/// The solver's choice for a solver-decided USE flag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedFlag {
+    pub domain: Domain,
+    pub flag: String,
+    pub enabled: bool,
+}
+
+/// One package of a [`LockFile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedPackage {
+    pub domain: Domain,
+    pub cpv: Cpv,
+    pub slot: Option<String>,
+    pub subslot: Option<String>,
+    pub repo: Option<String>,
+    /// Enabled USE flags, sorted.
+    pub use_flags: Vec<String>,
+    /// Rendered dependency tree of every non-empty class.
+    pub dependencies: Vec<(DepClass, String)>,
+    /// Alternatives picked from the package's `||` / `^^` / `??` groups.
+    pub choices: Vec<LockedChoice>,
+}
+
+/// An alternative picked from a `||`, `^^` or `??` group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedChoice {
+    pub class: DepClass,
+    /// The whole group, e.g. `|| ( dev-libs/a dev-libs/b )`.
+    pub group: String,
+    /// The alternative that was followed, e.g. `dev-libs/a`.
+    pub chosen: String,
+}
+
+/// How the current repository and configuration differ from a
+/// [`LockFile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockDrift {
+    /// The configuration fingerprint changed since the lock was written.
+    Config { locked: u64, current: u64 },
+    /// A locked package is no longer available.
+    Missing { domain: Domain, cpv: Cpv },
+    /// A locked package is available but one of its fields changed.
+    Changed {
+        domain: Domain,
+        cpv: Cpv,
+        field: &'static str,
+        locked: String,
+        current: String,
+    },
+    /// A locked package's dependency tree of one class changed.
+    Dependencies {
+        domain: Domain,
+        cpv: Cpv,
+        class: DepClass,
+        locked: String,
+        current: String,
+    },
+    /// A locked package no longer has the group or the alternative of a
+    /// recorded choice.
+    Choice {
+        domain: Domain,
+        cpv: Cpv,
+        choice: LockedChoice,
+    },
+    /// A locked flag is no longer solver-decided.
+    Flag { domain: Domain, flag: String },
+}
+
+/// Result of [`PortageDependencyProvider::apply_lock`].
+#[derive(Debug, Clone)]
+pub struct LockReplay {
+    /// The lock file's root atoms, interned into the provider.
+    pub requirements: Vec<ConditionalRequirement>,
+    /// Every difference between the lock file and the provider.
+    pub drift: Vec<LockDrift>,
+}
+
+/// Error returned when parsing a malformed lock file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockFileError {
+    /// 1-based line number.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for LockFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lock file line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for LockFileError {}
+
+impl PortageDependencyProvider {
+    /// Record `solution` as a [`LockFile`].
+    ///
+    /// Internal USE-flag and choice virtuals are recorded as flag choices
+    /// and package choices rather than as packages. Roots are every atom
+    /// interned with [`intern_requirement`](Self::intern_requirement) on
+    /// this provider.
+    pub fn lock(&self, solution: &[SolvableId]) -> LockFile {
+        let mut choices: HashMap<SolvableId, Vec<LockedChoice>> = HashMap::new();
+        self.walk_solution_deps(solution, |event| {
+            let DepWalkEvent::Choice(visit) = event else {
+                return;
+            };
+            let choice = LockedChoice {
+                class: visit.class,
+                group: visit.group.to_string(),
+                chosen: visit.chosen.to_string(),
+            };
+            let owner = choices.entry(visit.from).or_default();
+            if !owner.contains(&choice) {
+                owner.push(choice);
+            }
+        });
+
+        let mut packages: Vec<LockedPackage> = solution
+            .iter()
+            .filter(|&&sid| !self.is_synthetic(sid))
+            .map(|&sid| {
+                let meta = self.package_metadata(sid);
+                let mut use_flags: Vec<String> =
+                    meta.use_flags.iter().map(ToString::to_string).collect();
+                use_flags.sort();
+                LockedPackage {
+                    domain: self.domain(sid),
+                    cpv: meta.cpv.clone(),
+                    slot: meta.slot.map(|s| s.to_string()),
+                    subslot: meta.subslot.map(|s| s.to_string()),
+                    repo: meta.repo.map(|s| s.to_string()),
+                    use_flags,
+                    dependencies: rendered_dependencies(meta),
+                    choices: choices.remove(&sid).unwrap_or_default(),
+                }
+            })
+            .collect();
+        packages.sort_by_cached_key(|p| (p.domain, p.cpv.to_string()));
+
+        let selected: HashSet<SolvableId> = solution.iter().copied().collect();
+        let mut flags = Vec::new();
+        for domain in [Domain::Target, Domain::Host] {
+            for (flag, on, off) in self.flag_solvables(domain) {
+                let enabled = if selected.contains(&on) {
+                    true
+                } else if selected.contains(&off) {
+                    false
+                } else {
+                    continue;
+                };
+                flags.push(LockedFlag {
+                    domain,
+                    flag: flag.to_string(),
+                    enabled,
+                });
+            }
+        }
+        flags.sort_by(|a, b| (a.domain, &a.flag).cmp(&(b.domain, &b.flag)));
+
+        LockFile {
+            config_fingerprint: self.config_fingerprint(),
+            roots: self
+                .root_atoms()
+                .iter()
+                .map(|(atom, domain)| LockedRoot {
+                    domain: *domain,
+                    atom: atom.clone(),
+                })
+                .collect(),
+            flags,
+            packages,
+        }
+    }
+
+    /// Pin this provider to `lock` for a deterministic replay.
+    ///
+    /// Every locked package is locked the same way an
+    /// [`InstalledPolicy::Locked`](crate::InstalledPolicy::Locked) package
+    /// is, and the root atoms are interned as requirements. Flag choices
+    /// and `^^ ( )` / `?? ( )` choices are pinned by masking the choices
+    /// not taken; a `|| ( )` choice is pinned by reducing the group to the
+    /// recorded alternative. Anything the provider can no longer honour
+    /// exactly is reported as [`LockDrift`]; packages whose metadata
+    /// changed are still pinned by CPV, while missing packages, flags and
+    /// choices are left to the solver. Solving the returned requirements
+    /// fails if the pinned set no longer satisfies the current dependency
+    /// trees.
+    pub fn apply_lock(&mut self, lock: &LockFile) -> LockReplay {
+        let mut drift = Vec::new();
+        let current = self.config_fingerprint();
+        if lock.config_fingerprint != current {
+            drift.push(LockDrift::Config {
+                locked: lock.config_fingerprint,
+                current,
+            });
+        }
+
+        let mut found_packages = Vec::new();
+        for package in &lock.packages {
+            let found = self.find_solvables(package.domain, &package.cpv);
+            let Some(&sid) = found
+                .iter()
+                .find(|&&sid| {
+                    self.package_metadata(sid).repo.map(|r| r.to_string()) == package.repo
+                })
+                .or(found.first())
+            else {
+                drift.push(LockDrift::Missing {
+                    domain: package.domain,
+                    cpv: package.cpv.clone(),
+                });
+                continue;
+            };
+            drift.extend(package_drift(package, self.package_metadata(sid)));
+            self.lock_solvable(sid);
+            found_packages.push((sid, package));
+        }
+
+        let mut xof_pins = Vec::new();
+        for &(sid, package) in &found_packages {
+            let mut pinned_any_of = false;
+            for choice in &package.choices {
+                let Some((group, index)) = self.find_choice(sid, choice) else {
+                    drift.push(LockDrift::Choice {
+                        domain: package.domain,
+                        cpv: package.cpv.clone(),
+                        choice: choice.clone(),
+                    });
+                    continue;
+                };
+                let key = (sid, choice.class, group);
+                if key.2.starts_with("||") {
+                    self.pinned_any_of.insert(key, choice.chosen.clone());
+                    pinned_any_of = true;
+                } else {
+                    xof_pins.push((key, index));
+                }
+            }
+            if pinned_any_of {
+                self.reconvert_solvable(sid);
+            }
+        }
+        for (key, index) in xof_pins {
+            if !self.dependencies.get_mut().contains_key(&key.0) {
+                self.convert_solvable(key.0);
+            }
+            // Absent when the group is under an inactive USE conditional.
+            let chosen: Vec<SolvableId> = self
+                .xof_choices
+                .get_mut()
+                .get(&key)
+                .into_iter()
+                .flatten()
+                .map(|choices| choices[index])
+                .collect();
+            for choice in chosen {
+                for sibling in self.sibling_choices(choice) {
+                    self.mask(sibling, "not chosen in the lock file");
+                }
+            }
+        }
+
+        for locked in &lock.flags {
+            let virtuals = self
+                .flag_solvables(locked.domain)
+                .find(|(flag, _, _)| flag.as_str() == locked.flag);
+            match virtuals {
+                Some((_, on, off)) => self.mask(
+                    if locked.enabled { off } else { on },
+                    "not chosen in the lock file",
+                ),
+                None => drift.push(LockDrift::Flag {
+                    domain: locked.domain,
+                    flag: locked.flag.clone(),
+                }),
+            }
+        }
+
+        let requirements = lock
+            .roots
+            .iter()
+            .map(|root| self.intern_requirement_in(&root.atom, root.domain))
+            .collect();
+        LockReplay {
+            requirements,
+            drift,
+        }
+    }
+}
+
+impl PortageDependencyProvider {
+    /// The text of the group `choice` was picked from in `owner`'s current
+    /// dependency tree, and the index of the picked alternative.
+    fn find_choice(&self, owner: SolvableId, choice: &LockedChoice) -> Option<(String, usize)> {
+        let deps = &self.package_metadata(owner).dependencies;
+        let (_, entries) = deps.iter_classes().find(|(c, _)| *c == choice.class)?;
+        let alternatives = find_group(entries, &choice.group)?;
+        let index = alternatives
+            .iter()
+            .position(|alt| alt.to_string() == choice.chosen)?;
+        Some((choice.group.clone(), index))
+    }
+}
+
+/// Alternatives of the `||`, `^^` or `??` group rendered as `group`,
+/// searched through every nesting level of `entries`.
+fn find_group<'e>(entries: &'e [DepEntry], group: &str) -> Option<&'e [DepEntry]> {
+    entries.iter().find_map(|entry| match entry {
+        DepEntry::AnyOf(alternatives)
+        | DepEntry::ExactlyOneOf(alternatives)
+        | DepEntry::AtMostOneOf(alternatives) => {
+            if entry.to_string() == group {
+                Some(alternatives.as_slice())
+            } else {
+                find_group(alternatives, group)
+            }
+        }
+        DepEntry::UseConditional { children, .. } | DepEntry::AllOf(children) => {
+            find_group(children, group)
+        }
+        DepEntry::Atom(_) => None,
+    })
+}
+
+/// Every non-empty dependency class of `meta`, rendered.
+fn rendered_dependencies(meta: &PackageMetadata) -> Vec<(DepClass, String)> {
+    meta.dependencies
+        .iter_classes()
+        .map(|(class, entries)| {
+            let text: Vec<String> = entries.iter().map(ToString::to_string).collect();
+            (class, text.join(" "))
+        })
+        .collect()
+}
+
+/// Field-by-field differences between a locked package and its current
+/// metadata.
+fn package_drift(locked: &LockedPackage, meta: &PackageMetadata) -> Vec<LockDrift> {
+    let text = |value: Option<Interned<_>>| value.map(|v| v.to_string());
+    let mut use_flags: Vec<String> = meta.use_flags.iter().map(ToString::to_string).collect();
+    use_flags.sort();
+    let fields = [
+        ("slot", locked.slot.clone(), text(meta.slot)),
+        ("subslot", locked.subslot.clone(), text(meta.subslot)),
+        ("repo", locked.repo.clone(), text(meta.repo)),
+        (
+            "use",
+            Some(locked.use_flags.join(" ")),
+            Some(use_flags.join(" ")),
+        ),
+    ];
+    let mut drift: Vec<LockDrift> = fields
+        .into_iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old, new)| LockDrift::Changed {
+            domain: locked.domain,
+            cpv: locked.cpv.clone(),
+            field,
+            locked: old.unwrap_or_default(),
+            current: new.unwrap_or_default(),
+        })
+        .collect();
+
+    let current = rendered_dependencies(meta);
+    let text = |deps: &[(DepClass, String)], class| {
+        deps.iter()
+            .find(|(c, _)| *c == class)
+            .map(|(_, text)| text.clone())
+            .unwrap_or_default()
+    };
+    for class in [
+        DepClass::Depend,
+        DepClass::Rdepend,
+        DepClass::Bdepend,
+        DepClass::Pdepend,
+        DepClass::Idepend,
+    ] {
+        let (old, new) = (text(&locked.dependencies, class), text(&current, class));
+        if old != new {
+            drift.push(LockDrift::Dependencies {
+                domain: locked.domain,
+                cpv: locked.cpv.clone(),
+                class,
+                locked: old,
+                current: new,
+            });
+        }
+    }
+    drift
+}
+
+impl fmt::Display for LockFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# portage-atom-resolvo lock file")?;
+        writeln!(f, "version {LOCK_FILE_VERSION}")?;
+        writeln!(f, "config {:016x}", self.config_fingerprint)?;
+        for root in &self.roots {
+            writeln!(f, "root {} {}", root.domain, root.atom)?;
+        }
+        for flag in &self.flags {
+            let state = if flag.enabled { "on" } else { "off" };
+            writeln!(f, "flag {} {} {state}", flag.domain, flag.flag)?;
+        }
+        for package in &self.packages {
+            writeln!(f, "package {} {}", package.domain, package.cpv)?;
+            if let Some(slot) = &package.slot {
+                match &package.subslot {
+                    Some(subslot) => writeln!(f, "  slot {slot}/{subslot}")?,
+                    None => writeln!(f, "  slot {slot}")?,
+                }
+            }
+            if let Some(repo) = &package.repo {
+                writeln!(f, "  repo {repo}")?;
+            }
+            if !package.use_flags.is_empty() {
+                writeln!(f, "  use {}", package.use_flags.join(" "))?;
+            }
+            for (class, text) in &package.dependencies {
+                writeln!(f, "  deps {class} {text}")?;
+            }
+            for choice in &package.choices {
+                writeln!(
+                    f,
+                    "  choice {} {} => {}",
+                    choice.class, choice.group, choice.chosen
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LockFile {
+    /// Parse a lock file written by [`LockFile`]'s `Display` impl.
+    pub fn parse(input: &str) -> Result<Self, LockFileError> {
+        let mut version = None;
+        let mut config_fingerprint = None;
+        let mut roots = Vec::new();
+        let mut flags = Vec::new();
+        let mut packages: Vec<LockedPackage> = Vec::new();
+
+        for (index, raw) in input.lines().enumerate() {
+            let line = index + 1;
+            let error = |message: String| LockFileError { line, message };
+            if raw.trim().is_empty() || raw.starts_with('#') {
+                continue;
+            }
+
+            if let Some(attribute) = raw.strip_prefix("  ") {
+                let package = packages
+                    .last_mut()
+                    .ok_or_else(|| error("attribute outside of a package".into()))?;
+                let (key, value) = split_key(attribute);
+                match key {
+                    "slot" => match value.split_once('/') {
+                        Some((slot, subslot)) => {
+                            package.slot = Some(slot.to_string());
+                            package.subslot = Some(subslot.to_string());
+                        }
+                        None => package.slot = Some(value.to_string()),
+                    },
+                    "repo" => package.repo = Some(value.to_string()),
+                    "use" => {
+                        package.use_flags = value.split_whitespace().map(String::from).collect()
+                    }
+                    "deps" => {
+                        let (class, text) = split_key(value);
+                        package
+                            .dependencies
+                            .push((parse_class(class).map_err(error)?, text.to_string()));
+                    }
+                    "choice" => {
+                        let (class, rest) = split_key(value);
+                        let (group, chosen) = rest
+                            .rsplit_once(" => ")
+                            .ok_or_else(|| error(format!("malformed choice `{value}`")))?;
+                        package.choices.push(LockedChoice {
+                            class: parse_class(class).map_err(error)?,
+                            group: group.to_string(),
+                            chosen: chosen.to_string(),
+                        });
+                    }
+                    _ => return Err(error(format!("unknown package attribute `{key}`"))),
+                }
+                continue;
+            }
+
+            let (key, value) = split_key(raw);
+            match key {
+                "version" => {
+                    let v: u32 = value
+                        .parse()
+                        .map_err(|_| error(format!("invalid version `{value}`")))?;
+                    if v != LOCK_FILE_VERSION {
+                        return Err(error(format!("unsupported lock file version {v}")));
+                    }
+                    version = Some(v);
+                }
+                "config" => {
+                    config_fingerprint = Some(
+                        u64::from_str_radix(value, 16)
+                            .map_err(|_| error(format!("invalid config fingerprint `{value}`")))?,
+                    );
+                }
+                "root" => {
+                    let (domain, atom) = split_key(value);
+                    roots.push(LockedRoot {
+                        domain: parse_domain(domain).map_err(error)?,
+                        atom: Dep::parse(atom)
+                            .map_err(|e| error(format!("invalid root atom `{atom}`: {e}")))?,
+                    });
+                }
+                "flag" => {
+                    let mut parts = value.split_whitespace();
+                    let (Some(domain), Some(flag), Some(state), None) =
+                        (parts.next(), parts.next(), parts.next(), parts.next())
+                    else {
+                        return Err(error(format!("malformed flag `{value}`")));
+                    };
+                    let enabled = match state {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(error(format!("invalid flag state `{state}`"))),
+                    };
+                    flags.push(LockedFlag {
+                        domain: parse_domain(domain).map_err(error)?,
+                        flag: flag.to_string(),
+                        enabled,
+                    });
+                }
+                "package" => {
+                    let (domain, cpv) = split_key(value);
+                    packages.push(LockedPackage {
+                        domain: parse_domain(domain).map_err(error)?,
+                        cpv: Cpv::parse(cpv)
+                            .map_err(|e| error(format!("invalid CPV `{cpv}`: {e}")))?,
+                        slot: None,
+                        subslot: None,
+                        repo: None,
+                        use_flags: Vec::new(),
+                        dependencies: Vec::new(),
+                        choices: Vec::new(),
+                    });
+                }
+                _ => return Err(error(format!("unknown entry `{key}`"))),
+            }
+        }
+
+        let missing = |what: &str| LockFileError {
+            line: input.lines().count(),
+            message: format!("missing `{what}` entry"),
+        };
+        version.ok_or_else(|| missing("version"))?;
+        Ok(LockFile {
+            config_fingerprint: config_fingerprint.ok_or_else(|| missing("config"))?,
+            roots,
+            flags,
+            packages,
+        })
+    }
+}
+
+impl FromStr for LockFile {
+    type Err = LockFileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Split `key rest` at the first space.
+fn split_key(text: &str) -> (&str, &str) {
+    text.split_once(' ').unwrap_or((text, ""))
+}
+
+fn parse_domain(text: &str) -> Result<Domain, String> {
+    match text {
+        "ROOT" => Ok(Domain::Target),
+        "BROOT" => Ok(Domain::Host),
+        _ => Err(format!("invalid domain `{text}`")),
+    }
+}
+
+fn parse_class(text: &str) -> Result<DepClass, String> {
+    match text {
+        "DEPEND" => Ok(DepClass::Depend),
+        "RDEPEND" => Ok(DepClass::Rdepend),
+        "BDEPEND" => Ok(DepClass::Bdepend),
+        "PDEPEND" => Ok(DepClass::Pdepend),
+        "IDEPEND" => Ok(DepClass::Idepend),
+        _ => Err(format!("invalid dependency class `{text}`")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use resolvo::{Problem, Solver};
+
+    use super::*;
+    use crate::pool::{InstalledSet, UseConfig};
+    use crate::repository::InMemoryRepository;
+    use crate::tests::{pkg, pkg_build_run, solve_cpvs};
+
+    #[test]
+    fn lock_file_round_trip() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "|| ( dev-lib/a dev-lib/b ) ssl? ( dev-lib/openssl )",
+            "",
+        ));
+        let mut a = pkg("dev-lib/a-1.0", "0", vec![]);
+        a.subslot = Some("1".into());
+        a.repo = Some("gentoo".into());
+        a.use_flags.insert(Interned::intern("xml"));
+        repo.add(a);
+        repo.add(pkg("dev-lib/b-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/openssl-3.0.0", "0", vec![]));
+        let use_config = UseConfig {
+            solver_decided: ["ssl"].into_iter().map(Interned::intern).collect(),
+            ..UseConfig::default()
+        };
+
+        let mut provider = PortageDependencyProvider::new(&repo, &use_config);
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let lock = solver.provider().lock(&solution);
+
+        let text = lock.to_string();
+        assert!(text.contains("root ROOT app-misc/app\n"), "{text}");
+        assert!(text.contains("flag ROOT ssl off\n"), "{text}");
+        assert!(
+            text.contains("package ROOT dev-lib/a-1.0\n  slot 0/1\n  repo gentoo\n  use xml\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("  choice DEPEND || ( dev-lib/a dev-lib/b ) => dev-lib/a\n"),
+            "{text}"
+        );
+        assert!(!text.contains("virtual/"), "{text}");
+        assert_eq!(LockFile::parse(&text).unwrap(), lock);
+
+        let err = LockFile::parse("version 2\nbogus entry\n").unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn apply_lock_pins_versions_and_reports_drift() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "dev-lib/foo dev-lib/gone",
+            "",
+        ));
+        repo.add(pkg("dev-lib/foo-1.2", "0", vec![]));
+        repo.add(pkg("dev-lib/gone-1.0", "0", vec![]));
+
+        let mut provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let lock = solver.provider().lock(&solution);
+
+        // foo-1.4 appears, foo-1.2 gains a USE flag, gone-1.0 is replaced.
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "dev-lib/foo dev-lib/gone",
+            "",
+        ));
+        let mut foo = pkg("dev-lib/foo-1.2", "0", vec![]);
+        foo.use_flags.insert(Interned::intern("ssl"));
+        repo.add(foo);
+        repo.add(pkg("dev-lib/foo-1.4", "0", vec![]));
+        repo.add(pkg("dev-lib/gone-2.0", "0", vec![]));
+
+        let mut provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+        let replay = provider.apply_lock(&lock);
+        assert_eq!(
+            replay.drift,
+            [
+                LockDrift::Changed {
+                    domain: Domain::Target,
+                    cpv: Cpv::parse("dev-lib/foo-1.2").unwrap(),
+                    field: "use",
+                    locked: String::new(),
+                    current: "ssl".to_string(),
+                },
+                LockDrift::Missing {
+                    domain: Domain::Target,
+                    cpv: Cpv::parse("dev-lib/gone-1.0").unwrap(),
+                },
+            ]
+        );
+
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(replay.requirements))
+            .unwrap();
+        let cpvs: HashSet<String> = solution
+            .iter()
+            .map(|&sid| solver.provider().package_metadata(sid).cpv.to_string())
+            .collect();
+        assert!(cpvs.contains("dev-lib/foo-1.2"), "{cpvs:?}");
+        assert!(cpvs.contains("dev-lib/gone-2.0"), "{cpvs:?}");
+    }
+
+    /// Repository for the lock choice tests: `app` picks from a `||`, a
+    /// `^^` and a solver-decided flag.
+    /// c is only installable with `z`.
+    fn lock_choice_repo(z: &str) -> InMemoryRepository {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "|| ( dev-lib/a dev-lib/b ) ^^ ( dev-lib/c dev-lib/d ) ssl? ( dev-lib/openssl )",
+            "",
+        ));
+        repo.add(pkg_build_run("dev-lib/c-1.0", ">=dev-lib/z-2", ""));
+        for cpv in ["dev-lib/a-1.0", "dev-lib/b-1.0", "dev-lib/d-1.0", z] {
+            repo.add(pkg(cpv, "0", vec![]));
+        }
+        repo.add(pkg("dev-lib/openssl-3.0.0", "0", vec![]));
+        repo
+    }
+
+    #[test]
+    fn apply_lock_replays_choices() {
+        let use_config = UseConfig {
+            solver_decided: ["ssl"].into_iter().map(Interned::intern).collect(),
+            ..UseConfig::default()
+        };
+        // The installed b wins the `||`; without z-2, d wins the `^^`.
+        let mut installed = InstalledSet::new();
+        installed.add_favored(pkg("dev-lib/b-1.0", "0", vec![]));
+        let mut provider = PortageDependencyProvider::with_installed(
+            &lock_choice_repo("dev-lib/z-1.0"),
+            &use_config,
+            &installed,
+        );
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let mut lock = solver.provider().lock(&solution);
+        let text = lock.to_string();
+        assert!(text.contains("=> dev-lib/b\n"), "{text}");
+        assert!(text.contains("=> dev-lib/d\n"), "{text}");
+        assert!(
+            text.contains("  deps DEPEND || ( dev-lib/a dev-lib/b ) ^^ ("),
+            "{text}"
+        );
+        assert!(text.contains("flag ROOT ssl off\n"), "{text}");
+        lock.flags[0].enabled = true;
+
+        // Left alone, a fresh solve would take a and c instead.
+        let repo = lock_choice_repo("dev-lib/z-2.0");
+        let cpvs = solve_cpvs(
+            PortageDependencyProvider::new(&repo, &use_config),
+            &["app-misc/app"],
+        );
+        assert!(cpvs.contains("dev-lib/a-1.0"), "{cpvs:?}");
+        assert!(cpvs.contains("dev-lib/c-1.0"), "{cpvs:?}");
+
+        let mut provider = PortageDependencyProvider::new(&repo, &use_config);
+        let replay = provider.apply_lock(&lock);
+        assert_eq!(replay.drift, []);
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(replay.requirements))
+            .unwrap();
+        let cpvs: HashSet<String> = solution
+            .iter()
+            .map(|&sid| solver.provider().package_metadata(sid).cpv.to_string())
+            .collect();
+        for cpv in ["dev-lib/b-1.0", "dev-lib/d-1.0", "dev-lib/openssl-3.0.0"] {
+            assert!(cpvs.contains(cpv), "{cpvs:?}");
+        }
+        assert!(!cpvs.contains("dev-lib/a-1.0"), "{cpvs:?}");
+        assert!(!cpvs.contains("dev-lib/c-1.0"), "{cpvs:?}");
+    }
+
+    #[test]
+    fn apply_lock_reports_choice_drift() {
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "|| ( dev-lib/a dev-lib/b )",
+            "",
+        ));
+        repo.add(pkg("dev-lib/a-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/b-1.0", "0", vec![]));
+        let mut provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let lock = solver.provider().lock(&solution);
+
+        // The ebuild drops a from the group the lock picked it from.
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "|| ( dev-lib/b dev-lib/c )",
+            "",
+        ));
+        repo.add(pkg("dev-lib/a-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/b-1.0", "0", vec![]));
+        let mut provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+        let replay = provider.apply_lock(&lock);
+        let app = Cpv::parse("app-misc/app-1.0").unwrap();
+        assert_eq!(
+            replay.drift,
+            [
+                LockDrift::Dependencies {
+                    domain: Domain::Target,
+                    cpv: app.clone(),
+                    class: DepClass::Depend,
+                    locked: "|| ( dev-lib/a dev-lib/b )".to_string(),
+                    current: "|| ( dev-lib/b dev-lib/c )".to_string(),
+                },
+                LockDrift::Choice {
+                    domain: Domain::Target,
+                    cpv: app,
+                    choice: crate::LockedChoice {
+                        class: DepClass::Depend,
+                        group: "|| ( dev-lib/a dev-lib/b )".to_string(),
+                        chosen: "dev-lib/a".to_string(),
+                    },
+                },
+            ]
+        );
+
+        // The drifted choice is left to the solver.
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(replay.requirements))
+            .unwrap();
+        let cpvs: HashSet<String> = solution
+            .iter()
+            .map(|&sid| solver.provider().package_metadata(sid).cpv.to_string())
+            .collect();
+        assert!(cpvs.contains("dev-lib/b-1.0"), "{cpvs:?}");
+    }
+}
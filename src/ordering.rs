@@ -0,0 +1,464 @@
+//! Candidate ordering policies.
+//!
+//! resolvo tries the candidates of a package name in the order
+//! [`sort_candidates`](resolvo::DependencyProvider::sort_candidates) leaves
+//! them, so the ordering decides which version a solve prefers whenever
+//! several would do. [`CandidateOrdering`] compares two candidates;
+//! [`OrderingChain`] composes several criteria, each breaking the ties of
+//! the one before.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use portage_atom::Version;
+use portage_atom::gentoo_interner::{DefaultInterner, Interned};
+
+use crate::pool::PackageMetadata;
+
+/// A candidate being ordered.
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate<'a> {
+    pub metadata: &'a PackageMetadata,
+    /// Whether the candidate is an [`InstalledSet`](crate::InstalledSet)
+    /// entry.
+    pub installed: bool,
+}
+
+/// What an ordering knows besides the two candidates.
+#[derive(Debug, Clone, Copy)]
+pub struct OrderingContext<'a> {
+    /// Installed packages with the same name (category, package, slot and
+    /// domain) as the candidates.
+    pub installed: &'a [&'a PackageMetadata],
+}
+
+/// A criterion for ordering the candidates of one package name.
+///
+/// [`compare`](Self::compare) returns [`Ordering::Less`] when `a` should be
+/// tried before `b`. Candidates that compare [`Ordering::Equal`] keep their
+/// relative order, so a criterion only needs to rank what it cares about
+/// and leave the rest to the next link of an [`OrderingChain`].
+pub trait CandidateOrdering: Send {
+    fn compare(&self, a: Candidate<'_>, b: Candidate<'_>, context: OrderingContext<'_>)
+    -> Ordering;
+}
+
+/// Highest version first; the default ordering.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NewestFirst;
+
+impl CandidateOrdering for NewestFirst {
+    fn compare(&self, a: Candidate<'_>, b: Candidate<'_>, _: OrderingContext<'_>) -> Ordering {
+        b.metadata.cpv.version.cmp(&a.metadata.cpv.version)
+    }
+}
+
+/// Lowest version first, for testing that the minimum versions a package
+/// declares actually work.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OldestFirst;
+
+impl CandidateOrdering for OldestFirst {
+    fn compare(&self, a: Candidate<'_>, b: Candidate<'_>, _: OrderingContext<'_>) -> Ordering {
+        a.metadata.cpv.version.cmp(&b.metadata.cpv.version)
+    }
+}
+
+/// Installed candidates first, like emerge without `--update`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InstalledFirst;
+
+impl CandidateOrdering for InstalledFirst {
+    fn compare(&self, a: Candidate<'_>, b: Candidate<'_>, _: OrderingContext<'_>) -> Ordering {
+        b.installed.cmp(&a.installed)
+    }
+}
+
+/// Candidates with the sub-slot of an installed package first, so
+/// packages with `:=` dependencies on it need no rebuild.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeepSubslot;
+
+impl CandidateOrdering for KeepSubslot {
+    fn compare(
+        &self,
+        a: Candidate<'_>,
+        b: Candidate<'_>,
+        context: OrderingContext<'_>,
+    ) -> Ordering {
+        let keeps = |candidate: Candidate<'_>| {
+            candidate.metadata.subslot.is_some()
+                && context
+                    .installed
+                    .iter()
+                    .any(|meta| meta.subslot == candidate.metadata.subslot)
+        };
+        keeps(b).cmp(&keeps(a))
+    }
+}
+
+/// Candidates the predicate calls stable first.
+///
+/// [`PackageMetadata`] does not carry `KEYWORDS`; the predicate answers
+/// from whatever keyword data the caller has, e.g. whether the CPV is
+/// stable for the configured `ARCH`.
+pub struct StableFirst {
+    is_stable: Box<dyn Fn(&PackageMetadata) -> bool + Send>,
+}
+
+impl StableFirst {
+    pub fn new(is_stable: impl Fn(&PackageMetadata) -> bool + Send + 'static) -> Self {
+        Self {
+            is_stable: Box::new(is_stable),
+        }
+    }
+}
+
+impl CandidateOrdering for StableFirst {
+    fn compare(&self, a: Candidate<'_>, b: Candidate<'_>, _: OrderingContext<'_>) -> Ordering {
+        (self.is_stable)(b.metadata).cmp(&(self.is_stable)(a.metadata))
+    }
+}
+
+/// Candidates the predicate calls binary packages first.
+///
+/// Whether a candidate has a prebuilt package is not part of
+/// [`PackageMetadata`]; typically binary packages come from their own
+/// repository and the predicate checks [`PackageMetadata::repo`].
+pub struct BinaryFirst {
+    is_binary: Box<dyn Fn(&PackageMetadata) -> bool + Send>,
+}
+
+impl BinaryFirst {
+    pub fn new(is_binary: impl Fn(&PackageMetadata) -> bool + Send + 'static) -> Self {
+        Self {
+            is_binary: Box::new(is_binary),
+        }
+    }
+}
+
+impl CandidateOrdering for BinaryFirst {
+    fn compare(&self, a: Candidate<'_>, b: Candidate<'_>, _: OrderingContext<'_>) -> Ordering {
+        (self.is_binary)(b.metadata).cmp(&(self.is_binary)(a.metadata))
+    }
+}
+
+/// Candidates from higher-priority repositories first.
+///
+/// Repositories are listed from highest to lowest priority; candidates
+/// from unlisted repositories, or without a repository, come last.
+#[derive(Debug, Clone, Default)]
+pub struct RepositoryPriority {
+    rank: HashMap<Interned<DefaultInterner>, usize>,
+}
+
+impl RepositoryPriority {
+    pub fn new<I, S>(repos: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut rank = HashMap::new();
+        for (index, repo) in repos.into_iter().enumerate() {
+            rank.entry(Interned::intern(repo.as_ref())).or_insert(index);
+        }
+        Self { rank }
+    }
+
+    fn rank(&self, candidate: Candidate<'_>) -> usize {
+        candidate
+            .metadata
+            .repo
+            .and_then(|repo| self.rank.get(&repo).copied())
+            .unwrap_or(usize::MAX)
+    }
+}
+
+impl CandidateOrdering for RepositoryPriority {
+    fn compare(&self, a: Candidate<'_>, b: Candidate<'_>, _: OrderingContext<'_>) -> Ordering {
+        self.rank(a).cmp(&self.rank(b))
+    }
+}
+
+/// Live ebuilds (a `9999`-style version component) last.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AvoidLive;
+
+impl AvoidLive {
+    /// Whether a version has a component of four or more nines.
+    pub fn is_live(version: &Version) -> bool {
+        version.numbers.iter().any(|&number| {
+            let digits = number.to_string();
+            digits.len() >= 4 && digits.bytes().all(|digit| digit == b'9')
+        })
+    }
+}
+
+impl CandidateOrdering for AvoidLive {
+    fn compare(&self, a: Candidate<'_>, b: Candidate<'_>, _: OrderingContext<'_>) -> Ordering {
+        Self::is_live(&a.metadata.cpv.version).cmp(&Self::is_live(&b.metadata.cpv.version))
+    }
+}
+
+/// Criteria applied in turn, each breaking the ties of the previous ones.
+///
+/// ```
+/// use portage_atom_resolvo::{AvoidLive, InstalledFirst, KeepSubslot, NewestFirst, OrderingChain};
+///
+/// // emerge without --update: keep what is installed, then avoid
+/// // rebuilds, then take the newest non-live version.
+/// let ordering = OrderingChain::new()
+///     .then(InstalledFirst)
+///     .then(KeepSubslot)
+///     .then(AvoidLive)
+///     .then(NewestFirst);
+/// ```
+#[derive(Default)]
+pub struct OrderingChain {
+    links: Vec<Box<dyn CandidateOrdering>>,
+}
+
+impl OrderingChain {
+    /// An empty chain, which keeps the candidates in their current order.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a criterion consulted when all earlier ones tie.
+    pub fn then(mut self, ordering: impl CandidateOrdering + 'static) -> Self {
+        self.links.push(Box::new(ordering));
+        self
+    }
+}
+
+impl CandidateOrdering for OrderingChain {
+    fn compare(
+        &self,
+        a: Candidate<'_>,
+        b: Candidate<'_>,
+        context: OrderingContext<'_>,
+    ) -> Ordering {
+        self.links
+            .iter()
+            .map(|link| link.compare(a, b, context))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use portage_atom::{Cpv, Dep};
+    use resolvo::{Problem, Solver};
+
+    use super::*;
+    use crate::tests::{Fixture, pkg, pkg_build_run, pkg_subslot, solve_cpvs};
+
+    /// `dev-lib/lib` candidates from three repositories, including a live
+    /// version.
+    fn ordering_fixture() -> Fixture {
+        let lib = |version: &str, repo: &str| {
+            let mut meta = pkg(&format!("dev-lib/lib-{version}"), "0", vec![]);
+            meta.repo = Some(repo.into());
+            meta
+        };
+        Fixture::new([
+            pkg_build_run("app-misc/app-1.0", "", "dev-lib/lib"),
+            lib("1.0", "gentoo"),
+            lib("2.0", "gentoo"),
+            lib("9999", "gentoo"),
+            lib("1.5", "overlay"),
+            lib("2.0", "binpkgs"),
+        ])
+    }
+
+    /// The `dev-lib/lib` candidate chosen for [`ordering_fixture`], as
+    /// `cpv::repo`.
+    fn chosen_lib(ordering: OrderingChain) -> String {
+        let mut provider = ordering_fixture()
+            .builder()
+            .ordering(ordering)
+            .build()
+            .unwrap();
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let meta = solution
+            .iter()
+            .map(|&sid| solver.provider().package_metadata(sid))
+            .find(|meta| meta.cpv.cpn.package.as_str() == "lib")
+            .unwrap();
+        format!("{}::{}", meta.cpv, meta.repo.unwrap())
+    }
+
+    fn newest_not_live() -> OrderingChain {
+        OrderingChain::new().then(AvoidLive).then(NewestFirst)
+    }
+
+    #[test]
+    fn default_ordering_is_newest_first() {
+        // Live versions included.
+        assert_eq!(
+            solve_cpvs(ordering_fixture().provider(), &["app-misc/app"]),
+            ["app-misc/app-1.0", "dev-lib/lib-9999"]
+                .into_iter()
+                .map(String::from)
+                .collect()
+        );
+    }
+
+    #[test]
+    fn ordering_avoid_live() {
+        assert_eq!(chosen_lib(newest_not_live()), "dev-lib/lib-2.0::gentoo");
+    }
+
+    #[test]
+    fn ordering_oldest_first() {
+        assert_eq!(
+            chosen_lib(OrderingChain::new().then(OldestFirst)),
+            "dev-lib/lib-1.0::gentoo"
+        );
+    }
+
+    #[test]
+    fn ordering_repository_priority() {
+        assert_eq!(
+            chosen_lib(newest_not_live().then(RepositoryPriority::new(["binpkgs", "gentoo"]))),
+            "dev-lib/lib-2.0::binpkgs"
+        );
+        assert_eq!(
+            chosen_lib(
+                OrderingChain::new()
+                    .then(RepositoryPriority::new(["overlay"]))
+                    .then(NewestFirst)
+            ),
+            "dev-lib/lib-1.5::overlay"
+        );
+        assert_eq!(
+            chosen_lib(newest_not_live().then(RepositoryPriority::new(["gentoo"]))),
+            "dev-lib/lib-2.0::gentoo"
+        );
+    }
+
+    #[test]
+    fn ordering_binary_first() {
+        let binary_first = |meta: &PackageMetadata| meta.repo.is_some_and(|r| r == "binpkgs");
+        assert_eq!(
+            chosen_lib(
+                OrderingChain::new()
+                    .then(BinaryFirst::new(binary_first))
+                    .then(OldestFirst)
+            ),
+            "dev-lib/lib-2.0::binpkgs"
+        );
+    }
+
+    #[test]
+    fn ordering_stable_first() {
+        // Stable keywords are the caller's knowledge; here 2.0 and up are
+        // testing.
+        let stable =
+            |meta: &PackageMetadata| meta.cpv.version < Cpv::parse("a/b-2").unwrap().version;
+        assert_eq!(
+            chosen_lib(
+                OrderingChain::new()
+                    .then(StableFirst::new(stable))
+                    .then(NewestFirst)
+            ),
+            "dev-lib/lib-1.5::overlay"
+        );
+    }
+
+    /// An installed `dev-lib/ssl-1.1` and two candidates: one in the same
+    /// subslot, one in a new subslot.
+    fn ssl_candidates() -> [PackageMetadata; 3] {
+        [
+            pkg_subslot("dev-lib/ssl-1.1", "0", "1.1", vec![]),
+            pkg_subslot("dev-lib/ssl-1.1.1", "0", "1.1", vec![]),
+            pkg_subslot("dev-lib/ssl-3.0", "0", "3", vec![]),
+        ]
+    }
+
+    #[test]
+    fn ordering_keep_subslot() {
+        let [old, same_subslot, new_subslot] = ssl_candidates();
+        let installed = [&old];
+        let context = OrderingContext {
+            installed: &installed,
+        };
+        let candidate = |metadata, installed| Candidate {
+            metadata,
+            installed,
+        };
+        let keep = OrderingChain::new().then(KeepSubslot).then(NewestFirst);
+        let mut order = [
+            candidate(&new_subslot, false),
+            candidate(&old, true),
+            candidate(&same_subslot, false),
+        ];
+        order.sort_by(|&a, &b| keep.compare(a, b, context));
+        let versions: Vec<String> = order.iter().map(|c| c.metadata.cpv.to_string()).collect();
+        assert_eq!(
+            versions,
+            ["dev-lib/ssl-1.1.1", "dev-lib/ssl-1.1", "dev-lib/ssl-3.0"]
+        );
+    }
+
+    #[test]
+    fn ordering_installed_first() {
+        let [old, _, new_subslot] = ssl_candidates();
+        let installed = [&old];
+        let context = OrderingContext {
+            installed: &installed,
+        };
+        assert_eq!(
+            InstalledFirst.compare(
+                Candidate {
+                    metadata: &old,
+                    installed: true
+                },
+                Candidate {
+                    metadata: &new_subslot,
+                    installed: false
+                },
+                context
+            ),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn empty_ordering_chain_is_neutral() {
+        let [old, _, new_subslot] = ssl_candidates();
+        let installed = [&old];
+        let context = OrderingContext {
+            installed: &installed,
+        };
+        assert_eq!(
+            OrderingChain::new().compare(
+                Candidate {
+                    metadata: &old,
+                    installed: true
+                },
+                Candidate {
+                    metadata: &new_subslot,
+                    installed: false
+                },
+                context
+            ),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn avoid_live_detects_live_versions() {
+        assert!(AvoidLive::is_live(
+            &Cpv::parse("a/b-1.2.9999").unwrap().version
+        ));
+        assert!(!AvoidLive::is_live(
+            &Cpv::parse("a/b-20249999").unwrap().version
+        ));
+    }
+}
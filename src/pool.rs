@@ -5,7 +5,9 @@
 //! portage-atom value. The provider and interner implementations index into
 //! this pool.
 
+use std::cell::{Cell, OnceCell, RefCell};
 use std::collections::{HashMap, HashSet};
+use std::ops::Index;
 
 use portage_atom::gentoo_interner::{DefaultInterner, Interned};
 use portage_atom::{Cpn, Cpv, DepEntry, Operator, Version};
@@ -20,6 +22,7 @@ use resolvo::{
 /// to give callers the full dep-class–annotated graph needed for install
 /// ordering and cycle analysis.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DepEdge {
     /// The depending solvable.
     pub from: SolvableId,
@@ -29,6 +32,43 @@ pub struct DepEdge {
     pub class: DepClass,
 }
 
+/// A dependency cycle that [`install_order`](crate::PortageDependencyProvider::install_order)
+/// could not break.
+///
+/// `members` is one strongly connected component of the solution graph;
+/// `edges` are the labeled edges between members that remained after every
+/// relaxable edge was dropped, i.e. the hard (`DEPEND`/`BDEPEND`) edges that
+/// keep the component cyclic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DependencyCycle {
+    /// Solvables in the cycle.
+    pub members: Vec<SolvableId>,
+    /// Edges between members that could not be relaxed.
+    pub edges: Vec<DepEdge>,
+}
+
+/// Where an interned requirement or constrain came from.
+///
+/// Recorded by [`PortageDependencyProvider`](crate::PortageDependencyProvider)
+/// while converting dependency trees, so that conflict reporting and
+/// "why is this pulled in" queries can map a resolvo [`Requirement`](resolvo::Requirement) back to
+/// the package, dependency class, and atom that produced it.
+///
+/// Version sets are deduplicated in the pool, so a single requirement may
+/// have several origins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RequirementOrigin {
+    /// The real (non-virtual) solvable whose dependency tree produced it.
+    pub solvable: SolvableId,
+    /// Which dependency class the entry was listed in.
+    pub class: DepClass,
+    /// Source text of the originating [`DepEntry`] (e.g. `>=dev-libs/openssl-3:0=`
+    /// or `|| ( dev-libs/openssl dev-libs/libressl )`).
+    pub atom: String,
+}
+
 /// Configuration for USE flag evaluation.
 ///
 /// Controls how USE-conditional dependency groups (`use? ( deps )`) are
@@ -44,6 +84,8 @@ pub struct DepEdge {
 ///   `!use? ( deps )` on solver-decided flags are included **unconditionally**
 ///   (conservative: resolvo conditions have no NOT operator).
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct UseConfig {
     pub enabled: HashSet<Interned<DefaultInterner>>,
     pub disabled: HashSet<Interned<DefaultInterner>>,
@@ -60,15 +102,60 @@ impl From<HashSet<Interned<DefaultInterner>>> for UseConfig {
     }
 }
 
+/// Resolution domain of a package.
+///
+/// Cross-compilation resolves two package sets at once: the target system
+/// (`ROOT`) and the build host (`BROOT`).  Without a host domain
+/// everything lives in [`Domain::Target`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Domain {
+    /// Packages installed into `ROOT` for the target system.
+    #[default]
+    Target,
+    /// Packages installed into `BROOT` and executed on the build host.
+    Host,
+}
+
+impl Domain {
+    /// Domain that a dependency of `class`, declared by a package in
+    /// `self`, resolves in.
+    ///
+    /// `BDEPEND` and `IDEPEND` of target packages must be executable on the
+    /// build host; every other dependency stays in the owner's domain.
+    pub fn for_dependency(self, class: DepClass) -> Domain {
+        match (self, class) {
+            (Domain::Target, DepClass::Bdepend | DepClass::Idepend) => Domain::Host,
+            _ => self,
+        }
+    }
+}
+
+impl std::fmt::Display for Domain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Domain::Target => write!(f, "ROOT"),
+            Domain::Host => write!(f, "BROOT"),
+        }
+    }
+}
+
 /// Package name used as the resolvo name axis.
 ///
 /// Slots are encoded into the name so that packages in different slots
 /// (e.g. `dev-lang/python:3.11` vs `dev-lang/python:3.12`) are treated
-/// as independent names by the solver.
+/// as independent names by the solver.  The [`Domain`] is encoded the
+/// same way, so host and target copies of a package resolve independently.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PackageName {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serde_support::display_fromstr")
+    )]
     pub cpn: Cpn,
     pub slot: Option<Interned<DefaultInterner>>,
+    pub domain: Domain,
 }
 
 impl std::fmt::Display for PackageName {
@@ -77,18 +164,29 @@ impl std::fmt::Display for PackageName {
         if let Some(slot) = &self.slot {
             write!(f, ":{}", slot)?;
         }
+        if self.domain == Domain::Host {
+            write!(f, " [{}]", self.domain)?;
+        }
         Ok(())
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PackageMetadata {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serde_support::display_fromstr")
+    )]
     pub cpv: Cpv,
     pub slot: Option<Interned<DefaultInterner>>,
     pub subslot: Option<Interned<DefaultInterner>>,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub iuse: Vec<Interned<DefaultInterner>>,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub use_flags: HashSet<Interned<DefaultInterner>>,
     pub repo: Option<Interned<DefaultInterner>>,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub dependencies: PackageDeps,
 }
 
@@ -105,16 +203,23 @@ pub struct PackageMetadata {
 /// entries are flagged so the package manager can schedule them after the
 /// dependent package.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct PackageDeps {
     /// Build-time dependencies (`DEPEND`).
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::dep_entries"))]
     pub depend: Vec<DepEntry>,
     /// Runtime dependencies (`RDEPEND`).
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::dep_entries"))]
     pub rdepend: Vec<DepEntry>,
     /// Build host dependencies for cross-compilation (`BDEPEND`).
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::dep_entries"))]
     pub bdepend: Vec<DepEntry>,
     /// Post-merge dependencies (`PDEPEND`).
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::dep_entries"))]
     pub pdepend: Vec<DepEntry>,
     /// Install-time dependencies (`IDEPEND`).
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::dep_entries"))]
     pub idepend: Vec<DepEntry>,
 }
 
@@ -135,6 +240,7 @@ impl PackageDeps {
 
 /// PMS dependency class.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DepClass {
     /// `DEPEND` — build-time.
     Depend,
@@ -160,6 +266,122 @@ impl std::fmt::Display for DepClass {
     }
 }
 
+/// How a [`DepClass`] is treated when converting dependencies for the solver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DepClassMode {
+    /// Entries become hard requirements (the default).
+    #[default]
+    Include,
+    /// Entries never pull packages in, but plain atoms still restrict the
+    /// versions that may be selected: each atom naming a single slot
+    /// becomes a resolvo `constrains` entry ("if present, must match").
+    /// Any-of groups (apart from their blockers) and unslotted atoms that
+    /// span several slots have no constrain form and are dropped.
+    Soften,
+    /// Entries are ignored entirely.
+    Exclude,
+}
+
+/// Per-class [`DepClassMode`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DepClassModes {
+    pub depend: DepClassMode,
+    pub rdepend: DepClassMode,
+    pub bdepend: DepClassMode,
+    pub pdepend: DepClassMode,
+    pub idepend: DepClassMode,
+}
+
+impl DepClassModes {
+    /// Use the same mode for every class.
+    pub const fn all(mode: DepClassMode) -> Self {
+        Self {
+            depend: mode,
+            rdepend: mode,
+            bdepend: mode,
+            pdepend: mode,
+            idepend: mode,
+        }
+    }
+
+    /// Return the mode for `class`.
+    pub fn mode(&self, class: DepClass) -> DepClassMode {
+        match class {
+            DepClass::Depend => self.depend,
+            DepClass::Rdepend => self.rdepend,
+            DepClass::Bdepend => self.bdepend,
+            DepClass::Pdepend => self.pdepend,
+            DepClass::Idepend => self.idepend,
+        }
+    }
+
+    /// Return a copy with the mode for `class` replaced.
+    pub fn with(mut self, class: DepClass, mode: DepClassMode) -> Self {
+        match class {
+            DepClass::Depend => self.depend = mode,
+            DepClass::Rdepend => self.rdepend = mode,
+            DepClass::Bdepend => self.bdepend = mode,
+            DepClass::Pdepend => self.pdepend = mode,
+            DepClass::Idepend => self.idepend = mode,
+        }
+        self
+    }
+}
+
+/// Which dependency classes the solver honours.
+///
+/// Packages that would be built from the repository and packages already
+/// present in the [`InstalledSet`] get separate [`DepClassModes`], since an
+/// installed package no longer needs its build-time dependencies.
+///
+/// The default includes every class for every package, i.e. all classes
+/// are hard requirements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DepClassPolicy {
+    /// Modes for packages to be built from the repository.
+    pub built: DepClassModes,
+    /// Modes for packages already installed.
+    pub installed: DepClassModes,
+}
+
+impl DepClassPolicy {
+    /// Runtime closure only: `RDEPEND`, `PDEPEND` and `IDEPEND`.
+    ///
+    /// Suitable for binary package installs and for depclean with
+    /// `--with-bdeps=n`, where `DEPEND` and `BDEPEND` are irrelevant.
+    pub fn runtime_only() -> Self {
+        let modes = DepClassModes::default()
+            .with(DepClass::Depend, DepClassMode::Exclude)
+            .with(DepClass::Bdepend, DepClassMode::Exclude);
+        Self {
+            built: modes,
+            installed: modes,
+        }
+    }
+
+    /// Build container: only `BDEPEND`, resolved for the build host.
+    pub fn build_host() -> Self {
+        let modes = DepClassModes::all(DepClassMode::Exclude)
+            .with(DepClass::Bdepend, DepClassMode::Include);
+        Self {
+            built: modes,
+            installed: modes,
+        }
+    }
+
+    /// Return the mode for `class` on a package that is (or is not) installed.
+    pub fn mode(&self, class: DepClass, installed: bool) -> DepClassMode {
+        if installed {
+            self.installed.mode(class)
+        } else {
+            self.built.mode(class)
+        }
+    }
+}
+
 /// Version constraint derived from a [`DepEntry`].
 ///
 /// For normal (non-blocker) dependencies the constraint is used directly:
@@ -182,9 +404,19 @@ impl std::fmt::Display for DepClass {
 /// See [`crate::PortageDependencyProvider`]'s `filter_candidates` for the
 /// evaluation logic.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VersionConstraint {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serde_support::display_fromstr")
+    )]
     pub cpn: Cpn,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::operator"))]
     pub operator: Operator,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::serde_support::display_fromstr")
+    )]
     pub version: Version,
     pub glob: bool,
     pub slot: Option<Interned<DefaultInterner>>,
@@ -232,59 +464,66 @@ impl std::fmt::Display for VersionConstraint {
 
 /// Arena-based storage for all resolvo-interned objects.
 ///
-/// Every resolvo ID type is backed by a `Vec` here, indexed by the ID's
-/// inner `usize`. Reverse-lookup `HashMap`s prevent duplicate interning.
+/// Every resolvo ID type is backed by an append-only arena here, indexed by
+/// the ID's inner `usize`. Reverse-lookup `HashMap`s prevent duplicate
+/// interning.
+///
+/// Interning only needs `&self`: arena elements never move once pushed, so
+/// the references returned by the `resolve_*` methods stay valid while the
+/// pool grows. This lets a lazily built
+/// [`PortageDependencyProvider`](crate::PortageDependencyProvider) intern
+/// packages from inside resolvo's callbacks. The pool is `Send` but not
+/// `Sync`.
 pub struct PortagePool {
     // NameId arena
-    pub(crate) names: Vec<PackageName>,
-    pub(crate) names_rev: HashMap<PackageName, NameId>,
+    pub(crate) names: AppendVec<PackageName>,
+    pub(crate) names_rev: RefCell<HashMap<PackageName, NameId>>,
 
     // SolvableId arena
-    pub(crate) solvables: Vec<PackageMetadata>,
-    pub(crate) solvable_names: Vec<NameId>,
+    pub(crate) solvables: AppendVec<PackageMetadata>,
+    pub(crate) solvable_names: AppendVec<NameId>,
 
     // VersionSetId arena
-    pub(crate) version_sets: Vec<VersionConstraint>,
-    pub(crate) version_set_names: Vec<NameId>,
-    pub(crate) version_sets_rev: HashMap<(NameId, VersionConstraint), VersionSetId>,
+    pub(crate) version_sets: AppendVec<VersionConstraint>,
+    pub(crate) version_set_names: AppendVec<NameId>,
+    pub(crate) version_sets_rev: RefCell<HashMap<(NameId, VersionConstraint), VersionSetId>>,
 
     // VersionSetUnionId arena
-    pub(crate) version_set_unions: Vec<Vec<VersionSetId>>,
+    pub(crate) version_set_unions: AppendVec<Vec<VersionSetId>>,
 
     // ConditionId arena (reserved for future USE-as-conditions)
-    pub(crate) conditions: Vec<resolvo::Condition>,
+    pub(crate) conditions: AppendVec<resolvo::Condition>,
 
     // StringId arena
-    pub(crate) strings: Vec<String>,
+    pub(crate) strings: AppendVec<String>,
 }
 
 impl PortagePool {
     /// Create an empty pool.
     pub fn new() -> Self {
         Self {
-            names: Vec::new(),
-            names_rev: HashMap::new(),
-            solvables: Vec::new(),
-            solvable_names: Vec::new(),
-            version_sets: Vec::new(),
-            version_set_names: Vec::new(),
-            version_sets_rev: HashMap::new(),
-            version_set_unions: Vec::new(),
-            conditions: Vec::new(),
-            strings: Vec::new(),
+            names: AppendVec::new(),
+            names_rev: RefCell::new(HashMap::new()),
+            solvables: AppendVec::new(),
+            solvable_names: AppendVec::new(),
+            version_sets: AppendVec::new(),
+            version_set_names: AppendVec::new(),
+            version_sets_rev: RefCell::new(HashMap::new()),
+            version_set_unions: AppendVec::new(),
+            conditions: AppendVec::new(),
+            strings: AppendVec::new(),
         }
     }
 
     // --- NameId ---
 
     /// Intern a package name, returning the existing ID if already interned.
-    pub fn intern_name(&mut self, name: PackageName) -> NameId {
-        if let Some(&id) = self.names_rev.get(&name) {
+    pub fn intern_name(&self, name: PackageName) -> NameId {
+        if let Some(&id) = self.names_rev.borrow().get(&name) {
             return id;
         }
-        let id = NameId::from_usize(self.names.len());
-        self.names_rev.insert(name.clone(), id);
-        self.names.push(name);
+        let id = NameId::from_usize(self.names.push(name.clone()));
+        self.names_rev.borrow_mut().insert(name, id);
         id
     }
 
@@ -296,9 +535,8 @@ impl PortagePool {
     // --- SolvableId ---
 
     /// Add a solvable (concrete package version) to the pool.
-    pub fn intern_solvable(&mut self, name_id: NameId, meta: PackageMetadata) -> SolvableId {
-        let id = SolvableId::from_usize(self.solvables.len());
-        self.solvables.push(meta);
+    pub fn intern_solvable(&self, name_id: NameId, meta: PackageMetadata) -> SolvableId {
+        let id = SolvableId::from_usize(self.solvables.push(meta));
         self.solvable_names.push(name_id);
         id
     }
@@ -313,22 +551,26 @@ impl PortagePool {
         self.solvable_names[id.to_usize()]
     }
 
+    /// Return the number of interned solvables.
+    pub fn solvable_count(&self) -> usize {
+        self.solvables.len()
+    }
+
     // --- VersionSetId ---
 
     /// Intern a version constraint, deduplicating by value.
     pub fn intern_version_set(
-        &mut self,
+        &self,
         name_id: NameId,
         constraint: VersionConstraint,
     ) -> VersionSetId {
-        let key = (name_id, constraint.clone());
-        if let Some(&id) = self.version_sets_rev.get(&key) {
+        let key = (name_id, constraint);
+        if let Some(&id) = self.version_sets_rev.borrow().get(&key) {
             return id;
         }
-        let id = VersionSetId::from_usize(self.version_sets.len());
-        self.version_sets_rev.insert(key, id);
-        self.version_sets.push(constraint);
+        let id = VersionSetId::from_usize(self.version_sets.push(key.1.clone()));
         self.version_set_names.push(name_id);
+        self.version_sets_rev.borrow_mut().insert(key, id);
         id
     }
 
@@ -350,10 +592,8 @@ impl PortagePool {
     // --- VersionSetUnionId ---
 
     /// Intern a union (OR) of version sets.
-    pub fn intern_version_set_union(&mut self, sets: Vec<VersionSetId>) -> VersionSetUnionId {
-        let id = VersionSetUnionId::from_usize(self.version_set_unions.len());
-        self.version_set_unions.push(sets);
-        id
+    pub fn intern_version_set_union(&self, sets: Vec<VersionSetId>) -> VersionSetUnionId {
+        VersionSetUnionId::from_usize(self.version_set_unions.push(sets))
     }
 
     /// Look up the version sets in a union.
@@ -364,10 +604,8 @@ impl PortagePool {
     // --- ConditionId ---
 
     /// Intern a condition.
-    pub fn intern_condition(&mut self, condition: resolvo::Condition) -> ConditionId {
-        let id = ConditionId::from_usize(self.conditions.len());
-        self.conditions.push(condition);
-        id
+    pub fn intern_condition(&self, condition: resolvo::Condition) -> ConditionId {
+        ConditionId::from_usize(self.conditions.push(condition))
     }
 
     /// Look up a condition.
@@ -378,10 +616,8 @@ impl PortagePool {
     // --- StringId ---
 
     /// Intern a string (used for solver error messages).
-    pub fn intern_string(&mut self, s: String) -> StringId {
-        let id = StringId::from_usize(self.strings.len());
-        self.strings.push(s);
-        id
+    pub fn intern_string(&self, s: String) -> StringId {
+        StringId::from_usize(self.strings.push(s))
     }
 
     /// Look up an interned string.
@@ -400,6 +636,7 @@ impl Default for PortagePool {
 ///
 /// See [`InstalledSet`] for usage.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InstalledPolicy {
     /// Solver prefers this version but may choose a different one.
     Favored,
@@ -421,6 +658,7 @@ pub enum InstalledPolicy {
 /// let provider = PortageDependencyProvider::with_installed(&repo, &use_config, &installed);
 /// ```
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InstalledSet {
     pub(crate) packages: Vec<(PackageMetadata, InstalledPolicy)>,
 }
@@ -447,16 +685,122 @@ impl InstalledSet {
     }
 }
 
+/// Number of elements in the first [`AppendVec`] chunk, as a power of two.
+const FIRST_CHUNK_BITS: u32 = 6;
+
+/// Append-only vector that grows through `&self`.
+///
+/// Elements live in chunks that are allocated once and never move, so
+/// references returned by indexing stay valid while more elements are
+/// pushed. Chunk `k` holds `64 << k` elements, giving the same amortized
+/// growth as a `Vec` without ever reallocating.
+pub(crate) struct AppendVec<T> {
+    len: Cell<usize>,
+    chunks: [OnceCell<Box<[OnceCell<T>]>>; (usize::BITS - FIRST_CHUNK_BITS) as usize],
+}
+
+impl<T> AppendVec<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            len: Cell::new(0),
+            chunks: std::array::from_fn(|_| OnceCell::new()),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// Chunk and offset of `index`.
+    fn locate(index: usize) -> (usize, usize) {
+        let biased = index + (1 << FIRST_CHUNK_BITS);
+        let chunk = usize::BITS - 1 - biased.leading_zeros() - FIRST_CHUNK_BITS;
+        (chunk as usize, biased - (1 << (chunk + FIRST_CHUNK_BITS)))
+    }
+
+    /// Append `value`, returning its index.
+    pub(crate) fn push(&self, value: T) -> usize {
+        let index = self.len.get();
+        let (chunk, offset) = Self::locate(index);
+        let slots = self.chunks[chunk].get_or_init(|| {
+            (0..1usize << (chunk as u32 + FIRST_CHUNK_BITS))
+                .map(|_| OnceCell::new())
+                .collect()
+        });
+        assert!(slots[offset].set(value).is_ok(), "slot {index} already set");
+        self.len.set(index + 1);
+        index
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len.get() {
+            return None;
+        }
+        let (chunk, offset) = Self::locate(index);
+        self.chunks[chunk].get()?[offset].get()
+    }
+
+    pub(crate) fn iter(&self) -> AppendVecIter<'_, T> {
+        AppendVecIter {
+            vec: self,
+            index: 0,
+        }
+    }
+}
+
+impl<T> Default for AppendVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a AppendVec<T> {
+    type Item = &'a T;
+    type IntoIter = AppendVecIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the elements of an [`AppendVec`] pushed so far.
+pub(crate) struct AppendVecIter<'a, T> {
+    vec: &'a AppendVec<T>,
+    index: usize,
+}
+
+impl<'a, T> Iterator for AppendVecIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let value = self.vec.get(self.index)?;
+        self.index += 1;
+        Some(value)
+    }
+}
+
+impl<T> Index<usize> for AppendVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        match self.get(index) {
+            Some(value) => value,
+            None => panic!("index {index} out of bounds (len {})", self.len()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn intern_name_roundtrip() {
-        let mut pool = PortagePool::new();
+        let pool = PortagePool::new();
         let name = PackageName {
             cpn: Cpn::new("dev-lang", "rust"),
             slot: None,
+            domain: Domain::Target,
         };
         let id = pool.intern_name(name.clone());
         assert_eq!(pool.resolve_name(id), &name);
@@ -464,10 +808,11 @@ mod tests {
 
     #[test]
     fn intern_name_dedup() {
-        let mut pool = PortagePool::new();
+        let pool = PortagePool::new();
         let name = PackageName {
             cpn: Cpn::new("dev-lang", "rust"),
             slot: None,
+            domain: Domain::Target,
         };
         let id1 = pool.intern_name(name.clone());
         let id2 = pool.intern_name(name);
@@ -476,24 +821,62 @@ mod tests {
 
     #[test]
     fn intern_name_different_slots() {
-        let mut pool = PortagePool::new();
+        let pool = PortagePool::new();
         let a = pool.intern_name(PackageName {
             cpn: Cpn::new("dev-lang", "python"),
             slot: Some(Interned::intern("3.11")),
+            domain: Domain::Target,
         });
         let b = pool.intern_name(PackageName {
             cpn: Cpn::new("dev-lang", "python"),
             slot: Some(Interned::intern("3.12")),
+            domain: Domain::Target,
         });
         assert_ne!(a, b);
     }
 
+    #[test]
+    fn intern_name_different_domains() {
+        let pool = PortagePool::new();
+        let cpn = Cpn::new("dev-lang", "rust");
+        let target = pool.intern_name(PackageName {
+            cpn,
+            slot: None,
+            domain: Domain::Target,
+        });
+        let host = pool.intern_name(PackageName {
+            cpn,
+            slot: None,
+            domain: Domain::Host,
+        });
+        assert_ne!(target, host);
+        assert_eq!(pool.resolve_name(host).to_string(), "dev-lang/rust [BROOT]");
+    }
+
+    #[test]
+    fn domain_for_dependency() {
+        assert_eq!(
+            Domain::Target.for_dependency(DepClass::Bdepend),
+            Domain::Host
+        );
+        assert_eq!(
+            Domain::Target.for_dependency(DepClass::Idepend),
+            Domain::Host
+        );
+        assert_eq!(
+            Domain::Target.for_dependency(DepClass::Rdepend),
+            Domain::Target
+        );
+        assert_eq!(Domain::Host.for_dependency(DepClass::Depend), Domain::Host);
+    }
+
     #[test]
     fn intern_solvable_roundtrip() {
-        let mut pool = PortagePool::new();
+        let pool = PortagePool::new();
         let name_id = pool.intern_name(PackageName {
             cpn: Cpn::new("dev-lang", "rust"),
             slot: None,
+            domain: Domain::Target,
         });
         let meta = PackageMetadata {
             cpv: Cpv::parse("dev-lang/rust-1.75.0").unwrap(),
@@ -514,10 +897,11 @@ mod tests {
 
     #[test]
     fn intern_version_set_dedup() {
-        let mut pool = PortagePool::new();
+        let pool = PortagePool::new();
         let name_id = pool.intern_name(PackageName {
             cpn: Cpn::new("dev-lang", "rust"),
             slot: None,
+            domain: Domain::Target,
         });
         let c = VersionConstraint {
             cpn: Cpn::new("dev-lang", "rust"),
@@ -535,9 +919,28 @@ mod tests {
         assert_eq!(id1, id2);
     }
 
+    #[test]
+    fn append_vec_keeps_references_across_chunks() {
+        let vec = AppendVec::new();
+        let first: &String = {
+            vec.push("0".to_string());
+            &vec[0]
+        };
+        for i in 1..1000 {
+            assert_eq!(vec.push(i.to_string()), i);
+        }
+        assert_eq!(first, "0");
+        assert_eq!(vec.len(), 1000);
+        assert_eq!(vec[63], "63");
+        assert_eq!(vec[64], "64");
+        assert_eq!(vec[999], "999");
+        assert!(vec.get(1000).is_none());
+        assert_eq!(vec.iter().count(), 1000);
+    }
+
     #[test]
     fn intern_string_roundtrip() {
-        let mut pool = PortagePool::new();
+        let pool = PortagePool::new();
         let id = pool.intern_string("hello".into());
         assert_eq!(pool.resolve_string(id), "hello");
     }
@@ -1,10 +1,12 @@
 //! Bridge between portage-atom and resolvo's [`DependencyProvider`] trait.
 //!
-//! [`PortageDependencyProvider`] pre-populates a [`PortagePool`] from a
-//! [`PackageRepository`] and implements both [`Interner`] and
+//! [`PortageDependencyProvider`] populates a [`PortagePool`] from a
+//! [`PackageRepository`], up front or on solver demand, and implements both [`Interner`] and
 //! [`DependencyProvider`] so that [`resolvo::Solver`] can resolve
 //! Portage-style dependencies.
 
+use std::any::Any;
+use std::cell::{Cell, Ref, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 
@@ -18,11 +20,19 @@ use resolvo::{
     StringId, VersionSetId, VersionSetUnionId,
 };
 
+use crate::budget::{CancellationToken, SolveBudget, SolveMonitor, SolveStats};
+use crate::builder::ProviderBuilder;
+use crate::downgrade::DowngradePolicy;
+use crate::error::{DepConstruct, ResolveError};
+use crate::graph::strongly_connected_components;
+use crate::ordering::{Candidate, CandidateOrdering, NewestFirst, OrderingContext};
 use crate::pool::{
-    DepClass, DepEdge, InstalledPolicy, InstalledSet, PackageDeps, PackageMetadata, PackageName,
-    PortagePool, UseConfig, VersionConstraint,
+    DepClass, DepClassMode, DepClassPolicy, DepEdge, DependencyCycle, Domain, InstalledPolicy,
+    InstalledSet, PackageDeps, PackageMetadata, PackageName, PortagePool, RequirementOrigin,
+    UseConfig, VersionConstraint,
 };
 use crate::repository::PackageRepository;
+use crate::update::{DepChange, UseChange, dep_changes, use_change};
 use crate::version_match::version_matches;
 
 /// Internal data for a solver-decided USE flag.
@@ -32,21 +42,31 @@ use crate::version_match::version_matches;
 /// exclusion.  Packages that reference the flag get a
 /// `|| ( NotUSE_<flag> USE_<flag> )` requirement so the solver is forced to
 /// pick exactly one.
-struct FlagVirtuals {
+pub(crate) struct FlagVirtuals {
     /// Condition true when the flag is ON (`virtual/USE_<flag>` selected).
-    on_condition: ConditionId,
+    pub(crate) on_condition: ConditionId,
     /// Condition true when the flag is OFF (`virtual/NotUSE_<flag>` selected).
-    off_condition: ConditionId,
+    pub(crate) off_condition: ConditionId,
+    /// The `virtual/USE_<flag>` solvable.
+    pub(crate) on_solvable: SolvableId,
+    /// The `virtual/NotUSE_<flag>` solvable.
+    pub(crate) off_solvable: SolvableId,
     /// Pre-computed union `|| ( NotUSE_<flag> USE_<flag> )` — injected into
     /// every solvable that references the flag.  `NotUSE` is listed first
     /// to bias the solver toward flag-off (minimal deps).
-    choice_union: VersionSetUnionId,
+    pub(crate) choice_union: VersionSetUnionId,
 }
 
+/// Identifies a `^^ ( )` / `?? ( )` group: owning solvable, dependency
+/// class and rendered group text.
+pub(crate) type XofKey = (SolvableId, DepClass, String);
+
 /// Mutable state threaded through dependency tree conversion.
 struct ConvertContext<'a> {
-    pool: &'a mut PortagePool,
-    cpn_slots: &'a mut HashMap<Cpn, Vec<NameId>>,
+    pool: &'a PortagePool,
+    /// Repository to discover slots from when the provider is lazy.
+    lazy: Option<&'a LazySource>,
+    cpn_slots: &'a mut HashMap<(Domain, Cpn), Vec<NameId>>,
     blocker_types: &'a mut HashMap<VersionSetId, Blocker>,
     rebuild_triggers: &'a mut HashSet<VersionSetId>,
     flag_virtuals: &'a HashMap<Interned<DefaultInterner>, FlagVirtuals>,
@@ -55,35 +75,223 @@ struct ConvertContext<'a> {
     candidates: &'a mut HashMap<NameId, Vec<SolvableId>>,
     dep_map: &'a mut HashMap<SolvableId, KnownDependencies>,
     xof_counter: &'a mut usize,
+    origins: &'a mut HashMap<Requirement, Vec<RequirementOrigin>>,
+    xof_choices: &'a mut HashMap<XofKey, Vec<Vec<SolvableId>>>,
+    pinned_any_of: &'a HashMap<XofKey, String>,
+    synthetic: &'a mut HashSet<SolvableId>,
+    /// Real solvable whose dependency tree is being converted.
+    owner: SolvableId,
+    /// Dependency class of the entries currently being converted.
+    class: DepClass,
+    /// Domain the atoms currently being converted resolve in.
+    domain: Domain,
+}
+
+impl ConvertContext<'_> {
+    /// Make sure the slots of `cpn` in the current domain are known.
+    fn discover_slots(&mut self, cpn: Cpn) {
+        if let Some(lazy) = self.lazy {
+            lazy.discover_slots(self.pool, self.cpn_slots, self.domain, cpn);
+        }
+    }
+
+    /// The alternatives of a `|| ( )` group to convert: only the pinned
+    /// one when a lock file pins the group, all of them otherwise.
+    fn pinned_alternatives<'e>(&self, alternatives: &'e [DepEntry]) -> &'e [DepEntry] {
+        if self.pinned_any_of.is_empty() {
+            return alternatives;
+        }
+        let key = (self.owner, self.class, group_text("||", alternatives));
+        self.pinned_any_of
+            .get(&key)
+            .and_then(|chosen| alternatives.iter().find(|alt| alt.to_string() == *chosen))
+            .map_or(alternatives, std::slice::from_ref)
+    }
+
+    /// Record that `requirement` was produced by `atom` in the current
+    /// owner's current dependency class.
+    fn record_origin(&mut self, requirement: Requirement, atom: &str) {
+        let origins = self.origins.entry(requirement).or_default();
+        if !origins
+            .iter()
+            .any(|o| o.solvable == self.owner && o.class == self.class && o.atom == atom)
+        {
+            origins.push(RequirementOrigin {
+                solvable: self.owner,
+                class: self.class,
+                atom: atom.to_string(),
+            });
+        }
+    }
+}
+
+/// Repository, USE configuration and installed set of one resolution
+/// [`Domain`].
+#[derive(Clone, Copy)]
+pub struct DomainConfig<'a> {
+    pub repo: &'a dyn PackageRepository,
+    pub use_config: &'a UseConfig,
+    pub installed: &'a InstalledSet,
 }
 
 /// Dependency provider bridging portage-atom types to the resolvo solver.
 ///
-/// Construction eagerly walks every package in the repository, interns all
-/// solvables and dependency trees into the pool, and pre-computes
-/// [`KnownDependencies`] for each solvable. The resulting provider is
-/// read-only and suitable for passing to [`resolvo::Solver::new`].
+/// The eager constructors walk every package in the repository, intern all
+/// solvables and dependency trees into the pool, and pre-compute
+/// [`KnownDependencies`] for each solvable. [`lazy`](Self::lazy) instead
+/// interns packages and converts their dependencies the first time the
+/// solver asks for them. Either way the provider is suitable for passing
+/// to [`resolvo::Solver::new`].
+///
+/// The tables filled during conversion live behind `RefCell`s so the lazy
+/// mode can grow them from resolvo's `&self` callbacks; the provider is
+/// therefore `Send` but not `Sync`, in eager mode too. Move it into the
+/// thread that solves with it, or build one provider per thread.
 pub struct PortageDependencyProvider {
     pub(crate) pool: PortagePool,
-    /// Pre-computed candidates per name.
-    candidates: HashMap<NameId, Vec<SolvableId>>,
-    /// Pre-computed dependencies per solvable.
-    dependencies: HashMap<SolvableId, KnownDependencies>,
-    /// Map from unversioned CPN to all slotted NameIds known for that CPN.
-    cpn_slots: HashMap<Cpn, Vec<NameId>>,
+    /// Candidates per name.
+    pub(crate) candidates: RefCell<HashMap<NameId, Vec<SolvableId>>>,
+    /// Converted dependencies per solvable.
+    pub(crate) dependencies: RefCell<HashMap<SolvableId, KnownDependencies>>,
+    /// Map from domain and unversioned CPN to all slotted NameIds known
+    /// for that CPN. An empty list means the CPN has no packages.
+    pub(crate) cpn_slots: RefCell<HashMap<(Domain, Cpn), Vec<NameId>>>,
     /// Blocker type for each version set that came from a blocker dep.
     /// Only populated for `constrains` entries; absent means not a blocker.
-    blocker_types: HashMap<VersionSetId, Blocker>,
+    pub(crate) blocker_types: RefCell<HashMap<VersionSetId, Blocker>>,
     /// Version sets that carry a `:=` slot operator (rebuild trigger).
     /// When the dependency's slot or sub-slot changes, the dependent
     /// package must be rebuilt.
-    rebuild_triggers: HashSet<VersionSetId>,
-    flag_virtuals: HashMap<Interned<DefaultInterner>, FlagVirtuals>,
-    use_config: UseConfig,
+    pub(crate) rebuild_triggers: RefCell<HashSet<VersionSetId>>,
+    pub(crate) flag_virtuals: HashMap<Interned<DefaultInterner>, FlagVirtuals>,
+    /// Flag virtuals for the build host domain (cross-compilation only).
+    pub(crate) host_flag_virtuals: HashMap<Interned<DefaultInterner>, FlagVirtuals>,
+    pub(crate) use_config: UseConfig,
+    /// USE configuration of the build host; `Some` iff cross-compiling.
+    pub(crate) host_use_config: Option<UseConfig>,
     /// SolvableId to favor per NameId (installed, soft preference).
-    favored: HashMap<NameId, SolvableId>,
+    pub(crate) favored: RefCell<HashMap<NameId, SolvableId>>,
     /// SolvableId to lock per NameId (installed, hard constraint).
-    locked: HashMap<NameId, SolvableId>,
+    pub(crate) locked: RefCell<HashMap<NameId, SolvableId>>,
+    /// Solvables that correspond to an [`InstalledSet`] entry.
+    pub(crate) installed: RefCell<HashSet<SolvableId>>,
+    pub(crate) dep_class_policy: DepClassPolicy,
+    /// Origin (owning solvable, dep class, source atom) of every
+    /// requirement and constrain produced from a dependency tree.
+    pub(crate) origins: RefCell<HashMap<Requirement, Vec<RequirementOrigin>>>,
+    /// Choice solvables of every `^^ ( )` / `?? ( )` group, one per real
+    /// alternative, so the graph can follow the alternative the solver
+    /// picked. A key lists one entry per occurrence of the group.
+    pub(crate) xof_choices: RefCell<HashMap<XofKey, Vec<Vec<SolvableId>>>>,
+    /// Root atoms passed to [`intern_requirement_in`](Self::intern_requirement_in),
+    /// in interning order.
+    pub(crate) roots: Vec<(Dep, Domain)>,
+    /// USE-flag, `^^ ( )` / `?? ( )` choice, `|| ( ( ) )` all-of and
+    /// softened conditional virtuals.
+    pub(crate) synthetic: RefCell<HashSet<SolvableId>>,
+    /// Number of `^^ ( )` / `?? ( )` / `|| ( ( ) )` and softened
+    /// conditional virtuals created so far, used to name the next one.
+    pub(crate) xof_counter: Cell<usize>,
+    /// Repository packages are loaded from on demand; `None` for eagerly
+    /// built providers.
+    pub(crate) lazy: Option<LazySource>,
+    /// Cancellation token, budget and progress counters of the solve.
+    pub(crate) monitor: SolveMonitor,
+    /// Order in which the solver tries the candidates of a name; `None`
+    /// for [`NewestFirst`].
+    pub(crate) ordering: Option<Box<dyn CandidateOrdering>>,
+    /// Installed repository packages whose ebuild would now be built with
+    /// different USE.
+    pub(crate) use_changes: RefCell<HashMap<SolvableId, UseChange>>,
+    /// Installed repository packages whose ebuild's runtime dependencies
+    /// changed since they were built.
+    pub(crate) dep_changes: RefCell<HashMap<SolvableId, Vec<DepChange>>>,
+    /// Names whose favored installed version the current
+    /// [`UpdateMode`](crate::UpdateMode) lets the solver replace.
+    pub(crate) released: HashSet<NameId>,
+    pub(crate) downgrade_policy: DowngradePolicy,
+    /// Exclusion reason interned for each installed solvable that
+    /// [`DowngradeMode::Forbid`](crate::DowngradeMode::Forbid) protected.
+    pub(crate) downgrade_reasons: RefCell<HashMap<SolvableId, StringId>>,
+    /// Solvables the solver must not select, with the reason resolvo
+    /// reports in conflicts.
+    pub(crate) masked: HashMap<SolvableId, StringId>,
+    /// `|| ( )` alternatives pinned by
+    /// [`apply_lock`](Self::apply_lock): the group's only alternative
+    /// when it is converted, by owner, class and group text.
+    pub(crate) pinned_any_of: HashMap<XofKey, String>,
+    /// Names the root atoms pull into the graph, see
+    /// [`any_of`](crate::any_of); `None` until first needed.
+    pub(crate) pulled: RefCell<Option<HashSet<NameId>>>,
+    /// Any-of unions reordered by alternative preference.
+    pub(crate) preferred_unions: RefCell<HashMap<VersionSetUnionId, VersionSetUnionId>>,
+}
+
+/// How construction treats inputs the solver cannot model exactly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Validation {
+    /// Reject them with a [`ResolveError`], as the `try_*` constructors and
+    /// [`ProviderBuilder`] do.
+    Strict,
+    /// Accept them, as the infallible constructors always have: conditional
+    /// USE deps on solver-decided flags follow the configured USE, blockers
+    /// keep their slot operator, and of a CPV listed twice the last entry
+    /// wins.
+    Lenient,
+}
+
+/// Package source of a [`lazy`](PortageDependencyProvider::lazy) provider.
+pub(crate) struct LazySource {
+    repo: Box<dyn PackageRepository + Send>,
+    /// Checks applied to packages as they are loaded.
+    validation: Validation,
+    /// Installed packages per CPN.
+    installed: HashMap<Cpn, Vec<(PackageMetadata, InstalledPolicy)>>,
+    /// CPNs whose packages have been interned.
+    loaded: RefCell<HashSet<Cpn>>,
+    /// First error hit while loading; cancels the solve.
+    error: RefCell<Option<ResolveError>>,
+}
+
+impl LazySource {
+    /// Record the slotted names of `cpn` in `cpn_slots` unless they are
+    /// already known, without loading its packages. Repository slots come
+    /// first, followed by slots only found in the installed set; a CPN
+    /// with no packages gets an empty entry.
+    fn discover_slots(
+        &self,
+        pool: &PortagePool,
+        cpn_slots: &mut HashMap<(Domain, Cpn), Vec<NameId>>,
+        domain: Domain,
+        cpn: Cpn,
+    ) {
+        if cpn_slots.contains_key(&(domain, cpn)) {
+            return;
+        }
+        let mut slots = self.record(self.repo.slots_for(&cpn)).unwrap_or_default();
+        for (meta, _) in self.installed.get(&cpn).into_iter().flatten() {
+            if !slots.contains(&meta.slot) {
+                slots.push(meta.slot);
+            }
+        }
+        let names = slots
+            .into_iter()
+            .map(|slot| pool.intern_name(PackageName { cpn, slot, domain }))
+            .collect();
+        cpn_slots.insert((domain, cpn), names);
+    }
+
+    /// Pass on a successful result; keep the first error for
+    /// [`should_cancel_with_value`](resolvo::DependencyProvider::should_cancel_with_value).
+    fn record<T, E: Into<ResolveError>>(&self, result: Result<T, E>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.error.borrow_mut().get_or_insert(err.into());
+                None
+            }
+        }
+    }
 }
 
 impl PortageDependencyProvider {
@@ -94,10 +302,25 @@ impl PortageDependencyProvider {
     /// Flags listed in [`UseConfig::solver_decided`] create virtual
     /// `virtual/USE_<flag>` solvables and resolvo conditions so the SAT
     /// solver can decide whether to activate them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the repository reports an error or the configuration or
+    /// metadata is invalid; use [`try_new`](Self::try_new) to get a
+    /// [`ResolveError`] instead.
     pub fn new(repo: &dyn PackageRepository, use_config: &UseConfig) -> Self {
         Self::with_installed(repo, use_config, &InstalledSet::default())
     }
 
+    /// Build a provider like [`new`](Self::new), returning the first
+    /// [`ResolveError`] instead of panicking.
+    pub fn try_new(
+        repo: &dyn PackageRepository,
+        use_config: &UseConfig,
+    ) -> Result<Self, ResolveError> {
+        Self::try_with_installed(repo, use_config, &InstalledSet::default())
+    }
+
     /// Build a provider from a repository, a [`UseConfig`], and an
     /// [`InstalledSet`] describing packages already installed on the system.
     ///
@@ -105,102 +328,512 @@ impl PortageDependencyProvider {
     /// marked as [`InstalledPolicy::Favored`] or [`InstalledPolicy::Locked`].
     /// Installed packages *not* in the repository are injected as extra
     /// solvables so the solver can reference them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the repository reports an error or the configuration or
+    /// metadata is invalid; use [`try_with_installed`](Self::try_with_installed)
+    /// to get a [`ResolveError`] instead.
     pub fn with_installed(
         repo: &dyn PackageRepository,
         use_config: &UseConfig,
         installed: &InstalledSet,
     ) -> Self {
-        let mut pool = PortagePool::new();
-        let mut candidates: HashMap<NameId, Vec<SolvableId>> = HashMap::new();
-        let mut dep_map: HashMap<SolvableId, KnownDependencies> = HashMap::new();
-        let mut cpn_slots: HashMap<Cpn, Vec<NameId>> = HashMap::new();
-        let mut blocker_types: HashMap<VersionSetId, Blocker> = HashMap::new();
-        let mut rebuild_triggers: HashSet<VersionSetId> = HashSet::new();
-        let mut favored: HashMap<NameId, SolvableId> = HashMap::new();
-        let mut locked: HashMap<NameId, SolvableId> = HashMap::new();
+        Self::with_dep_class_policy(repo, use_config, installed, &DepClassPolicy::default())
+    }
 
-        // Build an index of installed packages by CPV.
-        let mut installed_index: HashMap<Cpv, InstalledPolicy> = HashMap::new();
+    /// Build a provider like [`with_installed`](Self::with_installed),
+    /// returning the first [`ResolveError`] instead of panicking.
+    ///
+    /// Besides repository failures this rejects USE flags that are not
+    /// valid flag names, malformed package metadata, the same CPV listed
+    /// twice by one repository or installed set, and dependencies the
+    /// solver cannot model (slot operators on blockers, conditional USE
+    /// deps on solver-decided flags). The infallible constructors accept
+    /// those last two kinds of input as before: a conditional USE dep
+    /// follows the configured USE, and of a duplicated CPV the last entry
+    /// wins.
+    pub fn try_with_installed(
+        repo: &dyn PackageRepository,
+        use_config: &UseConfig,
+        installed: &InstalledSet,
+    ) -> Result<Self, ResolveError> {
+        Self::try_with_dep_class_policy(repo, use_config, installed, &DepClassPolicy::default())
+    }
+
+    /// Build a provider like [`with_installed`](Self::with_installed), with a
+    /// [`DepClassPolicy`] selecting which dependency classes are included,
+    /// softened or excluded.
+    ///
+    /// Installed solvables use [`DepClassPolicy::installed`]; everything
+    /// else uses [`DepClassPolicy::built`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the repository reports an error or the configuration or
+    /// metadata is invalid; use
+    /// [`try_with_dep_class_policy`](Self::try_with_dep_class_policy) to get
+    /// a [`ResolveError`] instead.
+    pub fn with_dep_class_policy(
+        repo: &dyn PackageRepository,
+        use_config: &UseConfig,
+        installed: &InstalledSet,
+        dep_class_policy: &DepClassPolicy,
+    ) -> Self {
+        ProviderBuilder::new(repo)
+            .use_config(use_config)
+            .installed(installed)
+            .dep_class_policy(*dep_class_policy)
+            .validation(Validation::Lenient)
+            .build()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Build a provider like
+    /// [`with_dep_class_policy`](Self::with_dep_class_policy), returning the
+    /// first [`ResolveError`] instead of panicking.
+    pub fn try_with_dep_class_policy(
+        repo: &dyn PackageRepository,
+        use_config: &UseConfig,
+        installed: &InstalledSet,
+        dep_class_policy: &DepClassPolicy,
+    ) -> Result<Self, ResolveError> {
+        ProviderBuilder::new(repo)
+            .use_config(use_config)
+            .installed(installed)
+            .dep_class_policy(*dep_class_policy)
+            .build()
+    }
+
+    /// Build a cross-compilation provider with separate target (`ROOT`) and
+    /// build host (`BROOT`) domains.
+    ///
+    /// Every package is interned once per domain from that domain's
+    /// repository and installed set.  Target packages resolve `DEPEND`,
+    /// `RDEPEND` and `PDEPEND` in the target domain and `BDEPEND` /
+    /// `IDEPEND` in the host domain (see [`Domain::for_dependency`]); host
+    /// packages resolve everything in the host domain.  USE conditionals are
+    /// evaluated with the owning package's domain configuration.
+    ///
+    /// Root requirements from [`intern_requirement`](Self::intern_requirement)
+    /// target `ROOT`; use [`intern_requirement_in`](Self::intern_requirement_in)
+    /// for host roots.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either repository reports an error or either domain's
+    /// configuration or metadata is invalid; use [`try_cross`](Self::try_cross)
+    /// to get a [`ResolveError`] instead.
+    pub fn cross(
+        target: DomainConfig<'_>,
+        host: DomainConfig<'_>,
+        dep_class_policy: &DepClassPolicy,
+    ) -> Self {
+        ProviderBuilder::new(target.repo)
+            .use_config(target.use_config)
+            .installed(target.installed)
+            .host(host)
+            .dep_class_policy(*dep_class_policy)
+            .validation(Validation::Lenient)
+            .build()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Build a cross-compilation provider like [`cross`](Self::cross),
+    /// returning the first [`ResolveError`] instead of panicking.
+    pub fn try_cross(
+        target: DomainConfig<'_>,
+        host: DomainConfig<'_>,
+        dep_class_policy: &DepClassPolicy,
+    ) -> Result<Self, ResolveError> {
+        ProviderBuilder::new(target.repo)
+            .use_config(target.use_config)
+            .installed(target.installed)
+            .host(host)
+            .dep_class_policy(*dep_class_policy)
+            .build()
+    }
+
+    /// Build a provider that loads packages from `repo` on demand.
+    ///
+    /// Nothing is read from the repository up front. The versions of a
+    /// package are fetched and interned the first time the solver asks for
+    /// its candidates, and a solvable's dependency trees are converted the
+    /// first time the solver asks for its dependencies, so only the part of
+    /// the repository reachable from the root requirements is ever touched.
+    /// Unslotted atoms learn the slots of their package from
+    /// [`PackageRepository::slots_for`] plus the installed set.
+    ///
+    /// A repository error or invalid package met while loading cancels the
+    /// solve: the solver returns [`UnsolvableOrCancelled::Cancelled`]
+    /// holding the [`ResolveError`].
+    ///
+    /// Lazy providers resolve a single [`Domain::Target`] with the default
+    /// [`DepClassPolicy`], and cannot be written to a cache.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the USE configuration or installed set is invalid; use
+    /// [`try_lazy`](Self::try_lazy) to get a [`ResolveError`] instead.
+    ///
+    /// [`UnsolvableOrCancelled::Cancelled`]: resolvo::UnsolvableOrCancelled::Cancelled
+    pub fn lazy(
+        repo: impl PackageRepository + Send + 'static,
+        use_config: &UseConfig,
+        installed: &InstalledSet,
+    ) -> Self {
+        ProviderBuilder::lazy(repo)
+            .use_config(use_config)
+            .installed(installed)
+            .validation(Validation::Lenient)
+            .build()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Build a provider like [`lazy`](Self::lazy), returning a
+    /// [`ResolveError`] for an invalid USE configuration or installed set
+    /// instead of panicking. Repository packages are only checked when
+    /// they are loaded.
+    pub fn try_lazy(
+        repo: impl PackageRepository + Send + 'static,
+        use_config: &UseConfig,
+        installed: &InstalledSet,
+    ) -> Result<Self, ResolveError> {
+        ProviderBuilder::lazy(repo)
+            .use_config(use_config)
+            .installed(installed)
+            .build()
+    }
+
+    /// Lazy construction behind [`try_lazy`](Self::try_lazy) and
+    /// [`ProviderBuilder::lazy`].
+    pub(crate) fn build_lazy(
+        repo: Box<dyn PackageRepository + Send>,
+        use_config: &UseConfig,
+        installed: &InstalledSet,
+        dep_class_policy: &DepClassPolicy,
+        validation: Validation,
+    ) -> Result<Self, ResolveError> {
+        validate_use_config(use_config, Domain::Target)?;
+        let mut by_cpn: HashMap<Cpn, Vec<(PackageMetadata, InstalledPolicy)>> = HashMap::new();
         for (meta, policy) in &installed.packages {
-            installed_index.insert(meta.cpv.clone(), *policy);
+            validate_package(meta, Domain::Target, use_config, validation)?;
+            let entries = by_cpn.entry(meta.cpv.cpn).or_default();
+            if let Some(pos) = entries.iter().position(|(other, _)| other.cpv == meta.cpv) {
+                if validation == Validation::Strict {
+                    return Err(ResolveError::DuplicateCpv {
+                        cpv: meta.cpv.clone(),
+                        repo: meta.repo,
+                        domain: Domain::Target,
+                    });
+                }
+                entries.remove(pos);
+            }
+            entries.push((meta.clone(), *policy));
+        }
+        let mut provider = Self::empty(use_config, None, dep_class_policy);
+        provider.flag_virtuals = provider.create_flag_virtuals(use_config, Domain::Target);
+        provider.lazy = Some(LazySource {
+            repo,
+            validation,
+            installed: by_cpn,
+            loaded: RefCell::new(HashSet::new()),
+            error: RefCell::new(None),
+        });
+        Ok(provider)
+    }
+
+    /// A provider with no packages.
+    fn empty(
+        use_config: &UseConfig,
+        host_use_config: Option<&UseConfig>,
+        dep_class_policy: &DepClassPolicy,
+    ) -> Self {
+        Self {
+            pool: PortagePool::new(),
+            candidates: RefCell::default(),
+            dependencies: RefCell::default(),
+            cpn_slots: RefCell::default(),
+            blocker_types: RefCell::default(),
+            rebuild_triggers: RefCell::default(),
+            flag_virtuals: HashMap::new(),
+            host_flag_virtuals: HashMap::new(),
+            use_config: use_config.clone(),
+            host_use_config: host_use_config.cloned(),
+            favored: RefCell::default(),
+            locked: RefCell::default(),
+            installed: RefCell::default(),
+            dep_class_policy: *dep_class_policy,
+            origins: RefCell::default(),
+            xof_choices: RefCell::default(),
+            roots: Vec::new(),
+            synthetic: RefCell::default(),
+            xof_counter: Cell::new(0),
+            lazy: None,
+            monitor: SolveMonitor::default(),
+            ordering: None,
+            use_changes: RefCell::default(),
+            dep_changes: RefCell::default(),
+            released: HashSet::new(),
+            downgrade_policy: DowngradePolicy::default(),
+            downgrade_reasons: RefCell::default(),
+            masked: HashMap::new(),
+            pinned_any_of: HashMap::new(),
+            pulled: RefCell::default(),
+            preferred_unions: RefCell::default(),
         }
+    }
+
+    /// Eager construction behind the other constructors and
+    /// [`ProviderBuilder::build`].
+    pub(crate) fn build(
+        target: DomainConfig<'_>,
+        host: Option<DomainConfig<'_>>,
+        dep_class_policy: &DepClassPolicy,
+        validation: Validation,
+    ) -> Result<Self, ResolveError> {
+        validate_use_config(target.use_config, Domain::Target)?;
+        if let Some(host) = host {
+            validate_use_config(host.use_config, Domain::Host)?;
+        }
+        let mut provider = Self::empty(
+            target.use_config,
+            host.map(|h| h.use_config),
+            dep_class_policy,
+        );
 
         // Phase 1: intern all real solvables.
-        let mut solvable_meta: Vec<(SolvableId, PackageDeps)> = Vec::new();
-        let mut found_installed: HashSet<Cpv> = HashSet::new();
+        let mut solvables = provider.intern_domain(target, Domain::Target, validation)?;
+        if let Some(host) = host {
+            solvables.extend(provider.intern_domain(host, Domain::Host, validation)?);
+        }
 
-        for cpn in repo.all_packages() {
-            for meta in repo.versions_for(&cpn) {
-                let pkg_name = PackageName {
-                    cpn: meta.cpv.cpn,
-                    slot: meta.slot,
-                };
-                let name_id = pool.intern_name(pkg_name);
+        // Phase 1.5: create virtual solvables for solver-decided USE flags.
+        provider.flag_virtuals = provider.create_flag_virtuals(target.use_config, Domain::Target);
+        if let Some(host) = host {
+            provider.host_flag_virtuals =
+                provider.create_flag_virtuals(host.use_config, Domain::Host);
+        }
 
-                // Track all slotted NameIds per CPN.
-                let slot_list = cpn_slots.entry(meta.cpv.cpn).or_default();
-                if !slot_list.contains(&name_id) {
-                    slot_list.push(name_id);
-                }
+        // Phase 2: convert dependency trees into resolvo requirements.
+        for sid in solvables {
+            provider.convert_solvable(sid);
+        }
+
+        Ok(provider)
+    }
+
+    /// Intern every package of one domain's repository, plus installed
+    /// packages missing from it, returning their solvables for conversion.
+    fn intern_domain(
+        &mut self,
+        config: DomainConfig<'_>,
+        domain: Domain,
+        validation: Validation,
+    ) -> Result<Vec<SolvableId>, ResolveError> {
+        // Build an index of installed packages by CPV.
+        let mut installed_index: HashMap<Cpv, (&PackageMetadata, InstalledPolicy)> = HashMap::new();
+        for (meta, policy) in &config.installed.packages {
+            validate_package(meta, domain, config.use_config, validation)?;
+            if installed_index
+                .insert(meta.cpv.clone(), (meta, *policy))
+                .is_some()
+                && validation == Validation::Strict
+            {
+                return Err(ResolveError::DuplicateCpv {
+                    cpv: meta.cpv.clone(),
+                    repo: meta.repo,
+                    domain,
+                });
+            }
+        }
 
-                let pkg_deps = meta.dependencies.clone();
-                let sid = pool.intern_solvable(name_id, meta.clone());
-                candidates.entry(name_id).or_default().push(sid);
-                solvable_meta.push((sid, pkg_deps));
+        let mut solvables = Vec::new();
+        let mut found_installed: HashSet<Cpv> = HashSet::new();
+        let mut seen: HashSet<(Cpv, Option<Interned<DefaultInterner>>)> = HashSet::new();
 
-                // Check if this solvable matches an installed package.
-                if let Some(&policy) = installed_index.get(&meta.cpv) {
+        for cpn in config.repo.all_packages()? {
+            let mut versions = config.repo.versions_for(&cpn)?.into_owned();
+            if validation == Validation::Lenient {
+                keep_last_duplicates(&mut versions);
+            }
+            for meta in versions {
+                validate_package(&meta, domain, config.use_config, validation)?;
+                if !seen.insert((meta.cpv.clone(), meta.repo)) {
+                    return Err(ResolveError::DuplicateCpv {
+                        cpv: meta.cpv,
+                        repo: meta.repo,
+                        domain,
+                    });
+                }
+                let installed = installed_index.get(&meta.cpv).copied();
+                if installed.is_some() {
                     found_installed.insert(meta.cpv.clone());
-                    match policy {
-                        InstalledPolicy::Favored => {
-                            favored.insert(name_id, sid);
-                        }
-                        InstalledPolicy::Locked => {
-                            locked.insert(name_id, sid);
-                        }
-                    }
                 }
+                let change = installed
+                    .and_then(|(installed, _)| use_change(installed, &meta, config.use_config));
+                let dep_change = installed
+                    .map(|(installed, _)| {
+                        dep_changes(installed, &meta, &self.dep_class_policy.installed)
+                    })
+                    .unwrap_or_default();
+                let sid = self.intern_package(meta, domain, installed.map(|(_, policy)| policy));
+                if let Some(change) = change {
+                    self.use_changes.borrow_mut().insert(sid, change);
+                }
+                if !dep_change.is_empty() {
+                    self.dep_changes.borrow_mut().insert(sid, dep_change);
+                }
+                solvables.push(sid);
             }
         }
 
-        // Inject installed packages not found in the repository.
-        for (meta, policy) in &installed.packages {
-            if found_installed.contains(&meta.cpv) {
+        // Inject installed packages not found in the repository, the last
+        // entry of a duplicated CPV only.
+        for (meta, policy) in &config.installed.packages {
+            if found_installed.contains(&meta.cpv)
+                || !std::ptr::eq(installed_index[&meta.cpv].0, meta)
+            {
                 continue;
             }
-            let pkg_name = PackageName {
-                cpn: meta.cpv.cpn,
-                slot: meta.slot,
-            };
-            let name_id = pool.intern_name(pkg_name);
+            solvables.push(self.intern_package(meta.clone(), domain, Some(*policy)));
+        }
+
+        Ok(solvables)
+    }
 
-            let slot_list = cpn_slots.entry(meta.cpv.cpn).or_default();
-            if !slot_list.contains(&name_id) {
-                slot_list.push(name_id);
+    /// Intern the repository and installed packages of `cpn` if the
+    /// provider is lazy and has not loaded them yet.
+    pub(crate) fn load_cpn(&self, cpn: Cpn) {
+        let Some(lazy) = &self.lazy else {
+            return;
+        };
+        if !lazy.loaded.borrow_mut().insert(cpn) {
+            return;
+        }
+        let installed = lazy
+            .installed
+            .get(&cpn)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let mut found_installed: HashSet<Cpv> = HashSet::new();
+        let mut seen: HashSet<(Cpv, Option<Interned<DefaultInterner>>)> = HashSet::new();
+        let mut versions = lazy
+            .record(lazy.repo.versions_for(&cpn))
+            .unwrap_or_default()
+            .into_owned();
+        if lazy.validation == Validation::Lenient {
+            keep_last_duplicates(&mut versions);
+        }
+        for meta in versions {
+            // Invalid packages are skipped; the recorded error cancels
+            // the solve.
+            if lazy
+                .record(validate_package(
+                    &meta,
+                    Domain::Target,
+                    &self.use_config,
+                    lazy.validation,
+                ))
+                .is_none()
+            {
+                continue;
+            }
+            if !seen.insert((meta.cpv.clone(), meta.repo)) {
+                lazy.record::<(), _>(Err(ResolveError::DuplicateCpv {
+                    cpv: meta.cpv,
+                    repo: meta.repo,
+                    domain: Domain::Target,
+                }));
+                continue;
+            }
+            let installed = installed
+                .iter()
+                .rev()
+                .find(|(installed, _)| installed.cpv == meta.cpv);
+            if installed.is_some() {
+                found_installed.insert(meta.cpv.clone());
+            }
+            let change =
+                installed.and_then(|(installed, _)| use_change(installed, &meta, &self.use_config));
+            let dep_change = installed
+                .map(|(installed, _)| {
+                    dep_changes(installed, &meta, &self.dep_class_policy.installed)
+                })
+                .unwrap_or_default();
+            let sid =
+                self.intern_package(meta, Domain::Target, installed.map(|&(_, policy)| policy));
+            if let Some(change) = change {
+                self.use_changes.borrow_mut().insert(sid, change);
+            }
+            if !dep_change.is_empty() {
+                self.dep_changes.borrow_mut().insert(sid, dep_change);
             }
+        }
+        for (meta, policy) in installed {
+            if !found_installed.contains(&meta.cpv) {
+                self.intern_package(meta.clone(), Domain::Target, Some(*policy));
+            }
+        }
+    }
+
+    /// Intern one real package as a solvable in `domain`, recording its
+    /// installed policy if any.
+    fn intern_package(
+        &self,
+        meta: PackageMetadata,
+        domain: Domain,
+        installed_policy: Option<InstalledPolicy>,
+    ) -> SolvableId {
+        let pkg_name = PackageName {
+            cpn: meta.cpv.cpn,
+            slot: meta.slot,
+            domain,
+        };
+        let name_id = self.pool.intern_name(pkg_name);
+
+        // Track all slotted NameIds per CPN.
+        let mut cpn_slots = self.cpn_slots.borrow_mut();
+        let slot_list = cpn_slots.entry((domain, meta.cpv.cpn)).or_default();
+        if !slot_list.contains(&name_id) {
+            slot_list.push(name_id);
+        }
 
-            let pkg_deps = meta.dependencies.clone();
-            let sid = pool.intern_solvable(name_id, meta.clone());
-            candidates.entry(name_id).or_default().push(sid);
-            solvable_meta.push((sid, pkg_deps));
+        let sid = self.pool.intern_solvable(name_id, meta);
+        self.candidates
+            .borrow_mut()
+            .entry(name_id)
+            .or_default()
+            .push(sid);
 
+        if let Some(policy) = installed_policy {
+            self.installed.borrow_mut().insert(sid);
             match policy {
                 InstalledPolicy::Favored => {
-                    favored.insert(name_id, sid);
+                    self.favored.borrow_mut().insert(name_id, sid);
                 }
                 InstalledPolicy::Locked => {
-                    locked.insert(name_id, sid);
+                    self.locked.borrow_mut().insert(name_id, sid);
                 }
             }
         }
 
-        // Phase 1.5: create virtual solvables for solver-decided USE flags.
-        //
-        // For each flag we create two virtual packages that mutually exclude
-        // each other.  Selecting `virtual/USE_<flag>` means the flag is ON;
-        // selecting `virtual/NotUSE_<flag>` means the flag is OFF.
+        sid
+    }
+
+    /// Create the virtual solvables for every solver-decided USE flag of one
+    /// domain.
+    ///
+    /// For each flag we create two virtual packages that mutually exclude
+    /// each other.  Selecting `virtual/USE_<flag>` means the flag is ON;
+    /// selecting `virtual/NotUSE_<flag>` means the flag is OFF.
+    fn create_flag_virtuals(
+        &mut self,
+        use_config: &UseConfig,
+        domain: Domain,
+    ) -> HashMap<Interned<DefaultInterner>, FlagVirtuals> {
         let mut flag_virtuals: HashMap<Interned<DefaultInterner>, FlagVirtuals> = HashMap::new();
-        let version_zero = Version::parse("0").unwrap();
+        let version_zero = Version::new(&[0]);
 
         for flag in &use_config.solver_decided {
             // --- ON virtual: virtual/USE_<flag>-1.0 ---
@@ -208,12 +841,17 @@ impl PortageDependencyProvider {
             let on_name = PackageName {
                 cpn: on_cpn,
                 slot: None,
+                domain,
             };
-            let on_name_id = pool.intern_name(on_name);
-            cpn_slots.entry(on_cpn).or_default().push(on_name_id);
+            let on_name_id = self.pool.intern_name(on_name);
+            self.cpn_slots
+                .get_mut()
+                .entry((domain, on_cpn))
+                .or_default()
+                .push(on_name_id);
 
             let on_meta = PackageMetadata {
-                cpv: Cpv::parse(&format!("virtual/USE_{flag}-1.0")).unwrap(),
+                cpv: Cpv::new(on_cpn, Version::new(&[1, 0])),
                 slot: None,
                 subslot: None,
                 iuse: vec![],
@@ -221,8 +859,13 @@ impl PortageDependencyProvider {
                 repo: None,
                 dependencies: PackageDeps::default(),
             };
-            let on_sid = pool.intern_solvable(on_name_id, on_meta);
-            candidates.entry(on_name_id).or_default().push(on_sid);
+            let on_sid = self.pool.intern_solvable(on_name_id, on_meta);
+            self.synthetic.get_mut().insert(on_sid);
+            self.candidates
+                .get_mut()
+                .entry(on_name_id)
+                .or_default()
+                .push(on_sid);
 
             let on_constraint = VersionConstraint {
                 cpn: on_cpn,
@@ -235,20 +878,25 @@ impl PortageDependencyProvider {
                 use_constraints: vec![],
                 inverted: false,
             };
-            let on_vs = pool.intern_version_set(on_name_id, on_constraint);
-            let on_cond = pool.intern_condition(Condition::Requirement(on_vs));
+            let on_vs = self.pool.intern_version_set(on_name_id, on_constraint);
+            let on_cond = self.pool.intern_condition(Condition::Requirement(on_vs));
 
             // --- OFF virtual: virtual/NotUSE_<flag>-1.0 ---
             let off_cpn = Cpn::new("virtual", format!("NotUSE_{flag}"));
             let off_name = PackageName {
                 cpn: off_cpn,
                 slot: None,
+                domain,
             };
-            let off_name_id = pool.intern_name(off_name);
-            cpn_slots.entry(off_cpn).or_default().push(off_name_id);
+            let off_name_id = self.pool.intern_name(off_name);
+            self.cpn_slots
+                .get_mut()
+                .entry((domain, off_cpn))
+                .or_default()
+                .push(off_name_id);
 
             let off_meta = PackageMetadata {
-                cpv: Cpv::parse(&format!("virtual/NotUSE_{flag}-1.0")).unwrap(),
+                cpv: Cpv::new(off_cpn, Version::new(&[1, 0])),
                 slot: None,
                 subslot: None,
                 iuse: vec![],
@@ -256,8 +904,13 @@ impl PortageDependencyProvider {
                 repo: None,
                 dependencies: PackageDeps::default(),
             };
-            let off_sid = pool.intern_solvable(off_name_id, off_meta);
-            candidates.entry(off_name_id).or_default().push(off_sid);
+            let off_sid = self.pool.intern_solvable(off_name_id, off_meta);
+            self.synthetic.get_mut().insert(off_sid);
+            self.candidates
+                .get_mut()
+                .entry(off_name_id)
+                .or_default()
+                .push(off_sid);
 
             let off_constraint = VersionConstraint {
                 cpn: off_cpn,
@@ -270,18 +923,18 @@ impl PortageDependencyProvider {
                 use_constraints: vec![],
                 inverted: false,
             };
-            let off_vs = pool.intern_version_set(off_name_id, off_constraint);
-            let off_cond = pool.intern_condition(Condition::Requirement(off_vs));
+            let off_vs = self.pool.intern_version_set(off_name_id, off_constraint);
+            let off_cond = self.pool.intern_condition(Condition::Requirement(off_vs));
 
             // --- Mutual exclusion: each virtual blocks the other ---
-            dep_map.insert(
+            self.dependencies.get_mut().insert(
                 on_sid,
                 KnownDependencies {
                     requirements: vec![],
                     constrains: vec![off_vs],
                 },
             );
-            dep_map.insert(
+            self.dependencies.get_mut().insert(
                 off_sid,
                 KnownDependencies {
                     requirements: vec![],
@@ -291,72 +944,96 @@ impl PortageDependencyProvider {
 
             // --- Choice union: || ( NotUSE_<flag> USE_<flag> ) ---
             // NotUSE listed first to bias the solver toward flag-off.
-            let choice_union = pool.intern_version_set_union(vec![off_vs, on_vs]);
+            let choice_union = self.pool.intern_version_set_union(vec![off_vs, on_vs]);
 
             flag_virtuals.insert(
                 *flag,
                 FlagVirtuals {
                     on_condition: on_cond,
                     off_condition: off_cond,
+                    on_solvable: on_sid,
+                    off_solvable: off_sid,
                     choice_union,
                 },
             );
         }
 
-        // Phase 2: convert dependency trees into resolvo requirements.
-        let mut xof_counter: usize = 0;
-        for (sid, pkg_deps) in solvable_meta {
-            let mut requirements = Vec::new();
-            let mut constrains = Vec::new();
-
-            let mut ctx = ConvertContext {
-                pool: &mut pool,
-                cpn_slots: &mut cpn_slots,
-                blocker_types: &mut blocker_types,
-                rebuild_triggers: &mut rebuild_triggers,
-                flag_virtuals: &flag_virtuals,
-                use_config,
-                encountered_flags: HashSet::new(),
-                candidates: &mut candidates,
-                dep_map: &mut dep_map,
-                xof_counter: &mut xof_counter,
-            };
-            for (_class, entries) in pkg_deps.iter_classes() {
-                Self::convert_deps(entries, &mut ctx, &mut requirements, &mut constrains);
-            }
+        flag_virtuals
+    }
 
-            // Inject choice requirements for each solver-decided flag
-            // referenced by this solvable's dependency tree.
-            for flag in &ctx.encountered_flags {
-                if let Some(fv) = ctx.flag_virtuals.get(flag) {
-                    requirements.push(ConditionalRequirement {
-                        condition: None,
-                        requirement: Requirement::Union(fv.choice_union),
-                    });
+    /// Convert one real solvable's dependency trees into resolvo
+    /// requirements and constrains.
+    pub(crate) fn convert_solvable(&self, sid: SolvableId) {
+        let mut requirements = Vec::new();
+        let mut constrains = Vec::new();
+
+        let pkg_deps = &self.pool.resolve_solvable(sid).dependencies;
+        let owner_domain = self.domain(sid);
+        let is_installed = self.is_installed(sid);
+        let (use_config, flag_virtuals) = match (owner_domain, &self.host_use_config) {
+            (Domain::Host, Some(host_use_config)) => (host_use_config, &self.host_flag_virtuals),
+            _ => (&self.use_config, &self.flag_virtuals),
+        };
+        let cross = self.host_use_config.is_some();
+
+        let mut xof_counter = self.xof_counter.get();
+        let mut ctx = ConvertContext {
+            pool: &self.pool,
+            lazy: self.lazy.as_ref(),
+            cpn_slots: &mut self.cpn_slots.borrow_mut(),
+            blocker_types: &mut self.blocker_types.borrow_mut(),
+            rebuild_triggers: &mut self.rebuild_triggers.borrow_mut(),
+            flag_virtuals,
+            use_config,
+            encountered_flags: HashSet::new(),
+            candidates: &mut self.candidates.borrow_mut(),
+            dep_map: &mut self.dependencies.borrow_mut(),
+            xof_counter: &mut xof_counter,
+            origins: &mut self.origins.borrow_mut(),
+            xof_choices: &mut self.xof_choices.borrow_mut(),
+            pinned_any_of: &self.pinned_any_of,
+            synthetic: &mut self.synthetic.borrow_mut(),
+            owner: sid,
+            class: DepClass::Depend,
+            domain: owner_domain,
+        };
+        for (class, entries) in pkg_deps.iter_classes() {
+            ctx.class = class;
+            ctx.domain = if cross {
+                owner_domain.for_dependency(class)
+            } else {
+                Domain::Target
+            };
+            match self.dep_class_policy.mode(class, is_installed) {
+                DepClassMode::Include => {
+                    Self::convert_deps(entries, &mut ctx, &mut requirements, &mut constrains);
+                }
+                DepClassMode::Soften => {
+                    Self::convert_soft(entries, &mut ctx, &mut requirements, &mut constrains);
                 }
+                DepClassMode::Exclude => {}
             }
-
-            ctx.dep_map.insert(
-                sid,
-                KnownDependencies {
-                    requirements,
-                    constrains,
-                },
-            );
         }
 
-        Self {
-            pool,
-            candidates,
-            dependencies: dep_map,
-            cpn_slots,
-            blocker_types,
-            rebuild_triggers,
-            flag_virtuals,
-            use_config: use_config.clone(),
-            favored,
-            locked,
+        // Inject choice requirements for each solver-decided flag
+        // referenced by this solvable's dependency tree.
+        for flag in &ctx.encountered_flags {
+            if let Some(fv) = ctx.flag_virtuals.get(flag) {
+                requirements.push(ConditionalRequirement {
+                    condition: None,
+                    requirement: Requirement::Union(fv.choice_union),
+                });
+            }
         }
+
+        ctx.dep_map.insert(
+            sid,
+            KnownDependencies {
+                requirements,
+                constrains,
+            },
+        );
+        self.xof_counter.set(xof_counter);
     }
 
     /// Recursively convert a slice of [`DepEntry`]s into resolvo requirements
@@ -401,6 +1078,7 @@ impl PortageDependencyProvider {
                     }
                 }
                 DepEntry::AnyOf(alternatives) => {
+                    let alternatives = ctx.pinned_alternatives(alternatives);
                     Self::convert_any_of(alternatives, ctx, requirements, constrains);
                 }
                 DepEntry::ExactlyOneOf(alternatives) => {
@@ -416,6 +1094,142 @@ impl PortageDependencyProvider {
         }
     }
 
+    /// Convert the entries of a softened dependency class: plain atoms
+    /// become constrains on every slot they range over and blockers stay
+    /// blockers, but nothing pulls a package in. Atoms behind a
+    /// solver-decided flag go to a `virtual/soft_N` that holds their
+    /// constrains and is required under the flag's condition.
+    fn convert_soft(
+        entries: &[DepEntry],
+        ctx: &mut ConvertContext<'_>,
+        requirements: &mut Vec<ConditionalRequirement>,
+        constrains: &mut Vec<VersionSetId>,
+    ) {
+        for entry in entries {
+            match entry {
+                DepEntry::Atom(dep) => {
+                    let mut atom_reqs = Vec::new();
+                    Self::convert_atom(dep, ctx, &mut atom_reqs, constrains);
+                    let atom = dep.to_string();
+                    for req in atom_reqs {
+                        // A constrain per slot would forbid every slot the
+                        // atom does not match, which the requirement never
+                        // did, so unions over several slots are dropped.
+                        let vs_id = match req.requirement {
+                            Requirement::Single(vs_id) => vs_id,
+                            Requirement::Union(union) => {
+                                match ctx.pool.resolve_version_set_union(union) {
+                                    [vs_id] => *vs_id,
+                                    _ => continue,
+                                }
+                            }
+                        };
+                        ctx.record_origin(Requirement::Single(vs_id), &atom);
+                        constrains.push(vs_id);
+                    }
+                }
+                DepEntry::UseConditional {
+                    flag,
+                    negate,
+                    children,
+                } => {
+                    if let Some(fv) = ctx.flag_virtuals.get(flag) {
+                        ctx.encountered_flags.insert(*flag);
+                        let cond_id = if *negate {
+                            fv.off_condition
+                        } else {
+                            fv.on_condition
+                        };
+                        let mut soft_reqs = Vec::new();
+                        let mut soft_constrains = Vec::new();
+                        Self::convert_soft(children, ctx, &mut soft_reqs, &mut soft_constrains);
+                        if soft_reqs.is_empty() && soft_constrains.is_empty() {
+                            continue;
+                        }
+                        let (sid, vs_id) = Self::intern_dependency_virtual("soft", ctx);
+                        ctx.dep_map.insert(
+                            sid,
+                            KnownDependencies {
+                                requirements: soft_reqs,
+                                constrains: soft_constrains,
+                            },
+                        );
+                        requirements.push(ConditionalRequirement {
+                            condition: Some(cond_id),
+                            requirement: Requirement::Single(vs_id),
+                        });
+                    } else {
+                        let flag_active = ctx.use_config.enabled.contains(flag);
+                        if flag_active != *negate {
+                            Self::convert_soft(children, ctx, requirements, constrains);
+                        }
+                    }
+                }
+                DepEntry::AllOf(children) => {
+                    Self::convert_soft(children, ctx, requirements, constrains);
+                }
+                DepEntry::AnyOf(_) | DepEntry::ExactlyOneOf(_) | DepEntry::AtMostOneOf(_) => {
+                    // Alternatives only pull packages in; keep their blockers.
+                    Self::convert_deps(
+                        std::slice::from_ref(entry),
+                        ctx,
+                        &mut Vec::new(),
+                        constrains,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Intern a synthetic `virtual/<kind>_N` package with a single version
+    /// for a group of dependencies, returning its solvable and a version
+    /// set matching it. The caller records the group as its dependencies.
+    fn intern_dependency_virtual(
+        kind: &str,
+        ctx: &mut ConvertContext<'_>,
+    ) -> (SolvableId, VersionSetId) {
+        let id = *ctx.xof_counter;
+        *ctx.xof_counter += 1;
+
+        let cpn = Cpn::new("virtual", format!("{kind}_{id}"));
+        let pkg_name = PackageName {
+            cpn,
+            slot: None,
+            domain: ctx.domain,
+        };
+        let name_id = ctx.pool.intern_name(pkg_name);
+        ctx.cpn_slots
+            .entry((ctx.domain, cpn))
+            .or_default()
+            .push(name_id);
+
+        let meta = PackageMetadata {
+            cpv: Cpv::new(cpn, Version::new(&[1, 0])),
+            slot: None,
+            subslot: None,
+            iuse: vec![],
+            use_flags: HashSet::new(),
+            repo: None,
+            dependencies: PackageDeps::default(),
+        };
+        let sid = ctx.pool.intern_solvable(name_id, meta);
+        ctx.candidates.entry(name_id).or_default().push(sid);
+        ctx.synthetic.insert(sid);
+
+        let constraint = VersionConstraint {
+            cpn,
+            operator: Operator::GreaterOrEqual,
+            version: Version::new(&[0]),
+            glob: false,
+            slot: None,
+            subslot: None,
+            repo: None,
+            use_constraints: vec![],
+            inverted: false,
+        };
+        (sid, ctx.pool.intern_version_set(name_id, constraint))
+    }
+
     /// Convert a `^^ ( )` or `?? ( )` group into virtual choice solvables
     /// with pairwise mutual exclusion.
     ///
@@ -447,7 +1261,7 @@ impl PortageDependencyProvider {
         let group_id = *ctx.xof_counter;
         *ctx.xof_counter += 1;
 
-        let version_zero = Version::parse("0").unwrap();
+        let version_zero = Version::new(&[0]);
 
         // (solvable_id, version_set_id, child_requirements, child_constrains)
         let mut choices: Vec<(
@@ -462,12 +1276,19 @@ impl PortageDependencyProvider {
         // first for solver-decided flags).
         if allow_none {
             let cpn = Cpn::new("virtual", format!("xof_{group_id}_none"));
-            let pkg_name = PackageName { cpn, slot: None };
+            let pkg_name = PackageName {
+                cpn,
+                slot: None,
+                domain: ctx.domain,
+            };
             let name_id = ctx.pool.intern_name(pkg_name);
-            ctx.cpn_slots.entry(cpn).or_default().push(name_id);
+            ctx.cpn_slots
+                .entry((ctx.domain, cpn))
+                .or_default()
+                .push(name_id);
 
             let meta = PackageMetadata {
-                cpv: Cpv::parse(&format!("virtual/xof_{group_id}_none-1.0")).unwrap(),
+                cpv: Cpv::new(cpn, Version::new(&[1, 0])),
                 slot: None,
                 subslot: None,
                 iuse: vec![],
@@ -476,6 +1297,7 @@ impl PortageDependencyProvider {
                 dependencies: PackageDeps::default(),
             };
             let sid = ctx.pool.intern_solvable(name_id, meta);
+            ctx.synthetic.insert(sid);
             ctx.candidates.entry(name_id).or_default().push(sid);
 
             let constraint = VersionConstraint {
@@ -497,12 +1319,19 @@ impl PortageDependencyProvider {
         // Create one virtual choice solvable per real alternative.
         for (i, alt) in alternatives.iter().enumerate() {
             let cpn = Cpn::new("virtual", format!("xof_{group_id}_{i}"));
-            let pkg_name = PackageName { cpn, slot: None };
+            let pkg_name = PackageName {
+                cpn,
+                slot: None,
+                domain: ctx.domain,
+            };
             let name_id = ctx.pool.intern_name(pkg_name);
-            ctx.cpn_slots.entry(cpn).or_default().push(name_id);
+            ctx.cpn_slots
+                .entry((ctx.domain, cpn))
+                .or_default()
+                .push(name_id);
 
             let meta = PackageMetadata {
-                cpv: Cpv::parse(&format!("virtual/xof_{group_id}_{i}-1.0")).unwrap(),
+                cpv: Cpv::new(cpn, Version::new(&[1, 0])),
                 slot: None,
                 subslot: None,
                 iuse: vec![],
@@ -511,6 +1340,7 @@ impl PortageDependencyProvider {
                 dependencies: PackageDeps::default(),
             };
             let sid = ctx.pool.intern_solvable(name_id, meta);
+            ctx.synthetic.insert(sid);
             ctx.candidates.entry(name_id).or_default().push(sid);
 
             let constraint = VersionConstraint {
@@ -539,6 +1369,18 @@ impl PortageDependencyProvider {
             choices.push((sid, vs_id, child_reqs, child_constrains));
         }
 
+        let prefix = if allow_none { "??" } else { "^^" };
+        let text = group_text(prefix, alternatives);
+        let real_choices = choices
+            .iter()
+            .skip(usize::from(allow_none))
+            .map(|(sid, _, _, _)| *sid)
+            .collect();
+        ctx.xof_choices
+            .entry((ctx.owner, ctx.class, text.clone()))
+            .or_default()
+            .push(real_choices);
+
         // Wire pairwise exclusion: each choice blocks every other choice.
         let all_vs_ids: Vec<VersionSetId> = choices.iter().map(|(_, vs_id, _, _)| *vs_id).collect();
 
@@ -559,18 +1401,16 @@ impl PortageDependencyProvider {
         }
 
         // Push the union requirement to the parent.
-        if all_vs_ids.len() == 1 {
-            requirements.push(ConditionalRequirement {
-                condition: None,
-                requirement: Requirement::Single(all_vs_ids[0]),
-            });
+        let requirement = if all_vs_ids.len() == 1 {
+            Requirement::Single(all_vs_ids[0])
         } else {
-            let union_id = ctx.pool.intern_version_set_union(all_vs_ids);
-            requirements.push(ConditionalRequirement {
-                condition: None,
-                requirement: Requirement::Union(union_id),
-            });
-        }
+            Requirement::Union(ctx.pool.intern_version_set_union(all_vs_ids))
+        };
+        ctx.record_origin(requirement, &text);
+        requirements.push(ConditionalRequirement {
+            condition: None,
+            requirement,
+        });
     }
 
     /// Convert a single dependency atom into requirements/constrains.
@@ -585,6 +1425,8 @@ impl PortageDependencyProvider {
         requirements: &mut Vec<ConditionalRequirement>,
         constrains: &mut Vec<VersionSetId>,
     ) {
+        let req_start = requirements.len();
+        let con_start = constrains.len();
         let (slot, subslot) = extract_slot(dep);
         let repo = dep.repo;
         let use_constraints = resolve_use_deps(dep, ctx.use_config);
@@ -592,6 +1434,9 @@ impl PortageDependencyProvider {
         let is_blocker = blocker.is_some();
         let is_rebuild_trigger = has_slot_equal_op(dep);
         let (op, version) = dep_op_version(dep);
+        if slot.is_none() {
+            ctx.discover_slots(dep.cpn);
+        }
 
         // Helper: push a version set as a blocker constrain, recording its type.
         let mut push_blocker = |vs_id: VersionSetId| {
@@ -613,6 +1458,7 @@ impl PortageDependencyProvider {
             let pkg_name = PackageName {
                 cpn: dep.cpn,
                 slot: Some(*slot_val),
+                domain: ctx.domain,
             };
             let name_id = ctx.pool.intern_name(pkg_name);
             let constraint = VersionConstraint {
@@ -639,7 +1485,10 @@ impl PortageDependencyProvider {
             }
         } else {
             // Unslotted dep — union over all known slots.
-            let slot_names = ctx.cpn_slots.get(&dep.cpn);
+            let slot_names = ctx
+                .cpn_slots
+                .get(&(ctx.domain, dep.cpn))
+                .filter(|names| !names.is_empty());
 
             match slot_names {
                 Some(names) if names.len() == 1 => {
@@ -714,6 +1563,14 @@ impl PortageDependencyProvider {
                 }
             }
         }
+
+        let atom = dep.to_string();
+        for req in &requirements[req_start..] {
+            ctx.record_origin(req.requirement, &atom);
+        }
+        for &vs_id in &constrains[con_start..] {
+            ctx.record_origin(Requirement::Single(vs_id), &atom);
+        }
     }
 
     /// Convert an `|| ( ... )` group into a `Requirement::Union`.
@@ -741,6 +1598,7 @@ impl PortageDependencyProvider {
                         let pkg_name = PackageName {
                             cpn: dep.cpn,
                             slot: Some(*slot_val),
+                            domain: ctx.domain,
                         };
                         let name_id = ctx.pool.intern_name(pkg_name);
                         let constraint = VersionConstraint {
@@ -757,7 +1615,8 @@ impl PortageDependencyProvider {
                         vs_ids.push(ctx.pool.intern_version_set(name_id, constraint));
                     } else {
                         // Unslotted — add one VS per known slot.
-                        if let Some(names) = ctx.cpn_slots.get(&dep.cpn) {
+                        ctx.discover_slots(dep.cpn);
+                        if let Some(names) = ctx.cpn_slots.get(&(ctx.domain, dep.cpn)) {
                             for &name_id in names {
                                 let constraint = VersionConstraint {
                                     cpn: dep.cpn,
@@ -815,38 +1674,7 @@ impl PortageDependencyProvider {
                     Self::convert_one_of_group(nested, true, ctx, requirements, constrains);
                 }
                 DepEntry::AllOf(children) => {
-                    let allof_id = *ctx.xof_counter;
-                    *ctx.xof_counter += 1;
-
-                    let cpn = Cpn::new("virtual", format!("allof_{allof_id}"));
-                    let pkg_name = PackageName { cpn, slot: None };
-                    let name_id = ctx.pool.intern_name(pkg_name);
-                    ctx.cpn_slots.entry(cpn).or_default().push(name_id);
-
-                    let meta = PackageMetadata {
-                        cpv: Cpv::parse(&format!("virtual/allof_{allof_id}-1.0")).unwrap(),
-                        slot: None,
-                        subslot: None,
-                        iuse: vec![],
-                        use_flags: HashSet::new(),
-                        repo: None,
-                        dependencies: PackageDeps::default(),
-                    };
-                    let sid = ctx.pool.intern_solvable(name_id, meta);
-                    ctx.candidates.entry(name_id).or_default().push(sid);
-
-                    let constraint = VersionConstraint {
-                        cpn,
-                        operator: Operator::GreaterOrEqual,
-                        version: Version::parse("0").unwrap(),
-                        glob: false,
-                        slot: None,
-                        subslot: None,
-                        repo: None,
-                        use_constraints: vec![],
-                        inverted: false,
-                    };
-                    let vs_id = ctx.pool.intern_version_set(name_id, constraint);
+                    let (sid, vs_id) = Self::intern_dependency_virtual("allof", ctx);
 
                     let mut child_reqs = Vec::new();
                     let mut child_constrains = Vec::new();
@@ -865,18 +1693,16 @@ impl PortageDependencyProvider {
             }
         }
 
-        if vs_ids.len() == 1 {
-            requirements.push(ConditionalRequirement {
-                condition: None,
-                requirement: Requirement::Single(vs_ids[0]),
-            });
-        } else if vs_ids.len() > 1 {
-            let union_id = ctx.pool.intern_version_set_union(vs_ids);
-            requirements.push(ConditionalRequirement {
-                condition: None,
-                requirement: Requirement::Union(union_id),
-            });
-        }
+        let requirement = match vs_ids.len() {
+            0 => return,
+            1 => Requirement::Single(vs_ids[0]),
+            _ => Requirement::Union(ctx.pool.intern_version_set_union(vs_ids)),
+        };
+        ctx.record_origin(requirement, &group_text("||", alternatives));
+        requirements.push(ConditionalRequirement {
+            condition: None,
+            requirement,
+        });
     }
 
     /// Intern a root requirement for use in [`resolvo::Problem`].
@@ -885,15 +1711,27 @@ impl PortageDependencyProvider {
     /// then pass the resulting [`ConditionalRequirement`]s to
     /// [`resolvo::Problem::requirements`].
     pub fn intern_requirement(&mut self, dep: &Dep) -> ConditionalRequirement {
+        self.intern_requirement_in(dep, Domain::Target)
+    }
+
+    /// Intern a root requirement resolved in a specific [`Domain`].
+    ///
+    /// Only meaningful for providers built with [`cross`](Self::cross);
+    /// otherwise every package lives in [`Domain::Target`].
+    pub fn intern_requirement_in(&mut self, dep: &Dep, domain: Domain) -> ConditionalRequirement {
+        self.roots.push((dep.clone(), domain));
+        self.pulled.get_mut().take();
+        self.preferred_unions.get_mut().clear();
         let (slot, subslot) = extract_slot(dep);
         let (op, version) = dep_op_version(dep);
-        let use_constraints = resolve_use_deps(dep, &self.use_config);
+        let use_constraints = resolve_use_deps(dep, self.domain_use_config(domain));
 
         if let Some(ref slot_val) = slot {
             // Slotted — single NameId.
             let pkg_name = PackageName {
                 cpn: dep.cpn,
                 slot: Some(*slot_val),
+                domain,
             };
             let name_id = self.pool.intern_name(pkg_name);
             let constraint = VersionConstraint {
@@ -914,7 +1752,15 @@ impl PortageDependencyProvider {
             }
         } else {
             // Unslotted — union over all known slots.
-            let slot_names = self.cpn_slots.get(&dep.cpn).cloned();
+            let mut cpn_slots = self.cpn_slots.borrow_mut();
+            if let Some(lazy) = &self.lazy {
+                lazy.discover_slots(&self.pool, &mut cpn_slots, domain, dep.cpn);
+            }
+            let slot_names = cpn_slots
+                .get(&(domain, dep.cpn))
+                .filter(|names| !names.is_empty())
+                .cloned();
+            drop(cpn_slots);
 
             match slot_names {
                 Some(names) if names.len() == 1 => {
@@ -964,6 +1810,7 @@ impl PortageDependencyProvider {
                     let pkg_name = PackageName {
                         cpn: dep.cpn,
                         slot: None,
+                        domain,
                     };
                     let name_id = self.pool.intern_name(pkg_name);
                     let constraint = VersionConstraint {
@@ -995,7 +1842,7 @@ impl PortageDependencyProvider {
     /// Debug: return display names for all NameIds that have no candidates.
     pub fn debug_empty_candidates(&self) -> Vec<String> {
         let mut empty = Vec::new();
-        for (name_id, solvables) in &self.candidates {
+        for (name_id, solvables) in self.candidates.borrow().iter() {
             if solvables.is_empty() {
                 let pkg_name = self.pool.resolve_name(*name_id);
                 empty.push(format!("{}", pkg_name));
@@ -1015,14 +1862,299 @@ impl PortageDependencyProvider {
     ///
     /// Returns `None` for version-sets that are not blockers.
     pub fn blocker_type(&self, vs_id: VersionSetId) -> Option<Blocker> {
-        self.blocker_types.get(&vs_id).copied()
+        self.blocker_types.borrow().get(&vs_id).copied()
     }
 
     /// Check whether a version-set carries a `:=` slot operator,
     /// meaning the dependent package must be rebuilt when the
     /// dependency's slot or sub-slot changes.
     pub fn is_rebuild_trigger(&self, vs_id: VersionSetId) -> bool {
-        self.rebuild_triggers.contains(&vs_id)
+        self.rebuild_triggers.borrow().contains(&vs_id)
+    }
+
+    /// Return the [`Domain`] a solvable was interned in.
+    pub fn domain(&self, solvable: SolvableId) -> Domain {
+        self.pool
+            .resolve_name(self.pool.solvable_name(solvable))
+            .domain
+    }
+
+    /// Domain in which `class` dependencies of `owner` resolve.
+    fn dependency_domain(&self, owner: SolvableId, class: DepClass) -> Domain {
+        if self.host_use_config.is_some() {
+            self.domain(owner).for_dependency(class)
+        } else {
+            Domain::Target
+        }
+    }
+
+    /// USE configuration of the domain `owner` belongs to.
+    fn owner_use_config(&self, owner: SolvableId) -> &UseConfig {
+        self.domain_use_config(self.domain(owner))
+    }
+
+    /// USE configuration of `domain`.
+    pub(crate) fn domain_use_config(&self, domain: Domain) -> &UseConfig {
+        match (domain, &self.host_use_config) {
+            (Domain::Host, Some(host_use_config)) => host_use_config,
+            _ => &self.use_config,
+        }
+    }
+
+    /// Whether `solvable` is in `version_set`, as seen by
+    /// `filter_candidates` (USE dependencies are not checked).
+    pub(crate) fn version_set_matches(
+        &self,
+        solvable: SolvableId,
+        version_set: VersionSetId,
+    ) -> bool {
+        let constraint = self.pool.resolve_version_set(version_set);
+        let meta = self.pool.resolve_solvable(solvable);
+        let matches = version_matches(
+            &meta.cpv.version,
+            &constraint.operator,
+            constraint.glob,
+            &constraint.version,
+        ) && slot_matches(meta, constraint);
+
+        // Blocker constrains store the *blocked* operator with
+        // `inverted = true`.  Flipping the match here means resolvo's
+        // own `inverse` flag (used for constrains) ends up forbidding
+        // candidates that *match* the blocker — exactly what we want.
+        // See [`VersionConstraint`] for the full explanation.
+        matches != constraint.inverted
+    }
+
+    /// Converted dependencies of `solvable`; `None` if a lazy provider
+    /// has not converted it yet.
+    pub(crate) fn known_dependencies(
+        &self,
+        solvable: SolvableId,
+    ) -> Option<Ref<'_, KnownDependencies>> {
+        Ref::filter_map(self.dependencies.borrow(), |deps| deps.get(&solvable)).ok()
+    }
+
+    /// Installed solvable locked for `name`, if any.
+    pub(crate) fn locked_solvable(&self, name: NameId) -> Option<SolvableId> {
+        self.locked.borrow().get(&name).copied()
+    }
+
+    /// Solvables of `domain` whose CPV is exactly `cpv`.
+    pub(crate) fn find_solvables(&self, domain: Domain, cpv: &Cpv) -> Vec<SolvableId> {
+        self.load_cpn(cpv.cpn);
+        let candidates = self.candidates.borrow();
+        self.cpn_slots
+            .borrow()
+            .get(&(domain, cpv.cpn))
+            .into_iter()
+            .flatten()
+            .filter_map(|name| candidates.get(name))
+            .flatten()
+            .copied()
+            .filter(|&sid| self.pool.resolve_solvable(sid).cpv == *cpv)
+            .collect()
+    }
+
+    /// Lock `solvable` as the only acceptable candidate for its name.
+    pub(crate) fn lock_solvable(&mut self, solvable: SolvableId) {
+        self.locked
+            .get_mut()
+            .insert(self.pool.solvable_name(solvable), solvable);
+    }
+
+    /// Exclude `solvable` from every solve on this provider, like a
+    /// `package.mask` entry. resolvo reports `reason` in conflicts and
+    /// [`explain_exclusion`](Self::explain_exclusion) as a
+    /// [`Masked`](crate::VersionObstacle::Masked) obstacle.
+    pub fn mask(&mut self, solvable: SolvableId, reason: impl Into<String>) {
+        let reason = self.pool.intern_string(reason.into());
+        self.masked.insert(solvable, reason);
+    }
+
+    /// Undo [`mask`](Self::mask); returns whether `solvable` was masked.
+    pub fn unmask(&mut self, solvable: SolvableId) -> bool {
+        self.masked.remove(&solvable).is_some()
+    }
+
+    /// Whether `solvable` is [masked](Self::mask).
+    pub fn is_masked(&self, solvable: SolvableId) -> bool {
+        self.masked.contains_key(&solvable)
+    }
+
+    /// Convert `solvable`'s dependency trees again, e.g. after a `|| ( )`
+    /// group of it was pinned. Unconverted solvables of a lazy provider
+    /// are left alone.
+    pub(crate) fn reconvert_solvable(&mut self, solvable: SolvableId) {
+        if self.dependencies.get_mut().remove(&solvable).is_none() {
+            return;
+        }
+        self.xof_choices
+            .get_mut()
+            .retain(|(owner, _, _), _| *owner != solvable);
+        *self.pulled.get_mut() = None;
+        self.convert_solvable(solvable);
+    }
+
+    /// The other choice virtuals of the `^^ ( )` / `?? ( )` group
+    /// `choice` belongs to, including the `??` group's "none" virtual.
+    pub(crate) fn sibling_choices(&self, choice: SolvableId) -> Vec<SolvableId> {
+        let dependencies = self.dependencies.borrow();
+        let Some(deps) = dependencies.get(&choice) else {
+            return Vec::new();
+        };
+        let candidates = self.candidates.borrow();
+        let synthetic = self.synthetic.borrow();
+        deps.constrains
+            .iter()
+            .filter_map(|&vs| candidates.get(&self.pool.version_set_name(vs)))
+            .flatten()
+            .copied()
+            .filter(|sid| synthetic.contains(sid))
+            .collect()
+    }
+
+    /// Solver-decided flags of `domain` with their `(on, off)` virtual
+    /// solvables.
+    pub(crate) fn flag_solvables(
+        &self,
+        domain: Domain,
+    ) -> impl Iterator<Item = (Interned<DefaultInterner>, SolvableId, SolvableId)> + '_ {
+        let flag_virtuals = match domain {
+            Domain::Target => &self.flag_virtuals,
+            Domain::Host => &self.host_flag_virtuals,
+        };
+        flag_virtuals
+            .iter()
+            .map(|(&flag, fv)| (flag, fv.on_solvable, fv.off_solvable))
+    }
+
+    /// Root requirement fixing the solver-decided target `flag` to
+    /// `enabled`; `None` if the flag is not solver-decided.
+    pub(crate) fn flag_requirement(
+        &self,
+        flag: Interned<DefaultInterner>,
+        enabled: bool,
+    ) -> Option<ConditionalRequirement> {
+        let fv = self.flag_virtuals.get(&flag)?;
+        // The choice union lists the off virtual first.
+        let sets = self.pool.resolve_version_set_union(fv.choice_union);
+        Some(ConditionalRequirement {
+            condition: None,
+            requirement: Requirement::Single(sets[usize::from(enabled)]),
+        })
+    }
+
+    /// Stable fingerprint of the resolution configuration: the USE
+    /// configuration of every domain and the [`DepClassPolicy`].
+    ///
+    /// Two providers with the same fingerprint resolve the same repository
+    /// the same way.
+    pub fn config_fingerprint(&self) -> u64 {
+        config_fingerprint(
+            &self.use_config,
+            self.host_use_config.as_ref(),
+            &self.dep_class_policy,
+        )
+    }
+
+    /// Root atoms passed to
+    /// [`intern_requirement_in`](Self::intern_requirement_in), with the
+    /// domain they resolve in.
+    pub(crate) fn root_atoms(&self) -> &[(Dep, Domain)] {
+        &self.roots
+    }
+
+    /// Root atoms matched by each solvable of `solution`, as
+    /// `(root atom, solvable)` pairs in root interning order.
+    pub(crate) fn root_matches<'a>(
+        &'a self,
+        solution: &'a [SolvableId],
+    ) -> impl Iterator<Item = (&'a Dep, SolvableId)> + 'a {
+        self.roots.iter().flat_map(move |(dep, domain)| {
+            let use_config = self.domain_use_config(*domain);
+            solution
+                .iter()
+                .copied()
+                .filter(move |&sid| {
+                    self.domain(sid) == *domain
+                        && dep_matches_solvable(dep, self.pool.resolve_solvable(sid), use_config)
+                })
+                .map(move |sid| (dep, sid))
+        })
+    }
+
+    /// Check whether a solvable is an internal virtual created for a
+    /// solver-decided USE flag, a `^^ ( )` / `?? ( )` group, an all-of
+    /// alternative of a `|| ( )` group or a softened conditional, rather
+    /// than a repository or installed package.
+    pub fn is_synthetic(&self, solvable: SolvableId) -> bool {
+        self.synthetic.borrow().contains(&solvable)
+    }
+
+    /// Check whether a solvable corresponds to an [`InstalledSet`] entry.
+    pub fn is_installed(&self, solvable: SolvableId) -> bool {
+        self.installed.borrow().contains(&solvable)
+    }
+
+    /// Return the [`DepClassPolicy`] the provider was built with.
+    pub fn dep_class_policy(&self) -> &DepClassPolicy {
+        &self.dep_class_policy
+    }
+
+    /// Stop solving once `token` is cancelled.
+    ///
+    /// The solver checks the token before every propagation round and
+    /// before fetching candidates or dependencies, and returns
+    /// [`UnsolvableOrCancelled::Cancelled`] holding a
+    /// [`ResolveError::Cancelled`] with the [`SolveStats`] so far.
+    ///
+    /// [`UnsolvableOrCancelled::Cancelled`]: resolvo::UnsolvableOrCancelled::Cancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.monitor.token = Some(token);
+        self
+    }
+
+    /// Stop solving once `budget` is exhausted, the same way as
+    /// [`with_cancellation`](Self::with_cancellation). The budget counts
+    /// from the first solver checkpoint after construction or
+    /// [`reset_solve_stats`](Self::reset_solve_stats).
+    pub fn with_budget(mut self, budget: SolveBudget) -> Self {
+        self.monitor.budget = budget;
+        self
+    }
+
+    /// Try the candidates of each name in the order `ordering` gives
+    /// instead of [`NewestFirst`]. Installed candidates are still favored
+    /// by resolvo (see [`InstalledPolicy::Favored`]) whatever the ordering.
+    pub fn with_ordering(mut self, ordering: impl CandidateOrdering + 'static) -> Self {
+        self.ordering = Some(Box::new(ordering));
+        self
+    }
+
+    /// Progress of the current solve; after a solve finishes, its totals.
+    pub fn solve_stats(&self) -> SolveStats {
+        self.monitor.stats()
+    }
+
+    /// Zero the [`SolveStats`] so the next solve gets a fresh
+    /// [`SolveBudget`].
+    pub fn reset_solve_stats(&self) {
+        self.monitor.reset();
+    }
+
+    /// Look up where a requirement or constrain came from.
+    ///
+    /// Returns one [`RequirementOrigin`] per (solvable, dep class, atom)
+    /// that produced `requirement`; blockers are looked up as
+    /// `Requirement::Single(vs_id)`. Root requirements from
+    /// [`intern_requirement`](Self::intern_requirement) and synthetic
+    /// USE-flag choices have no origin and return an empty list.
+    pub fn requirement_origins(&self, requirement: Requirement) -> Vec<RequirementOrigin> {
+        self.origins
+            .borrow()
+            .get(&requirement)
+            .cloned()
+            .unwrap_or_default()
     }
 
     pub fn flag_condition(&self, flag: Interned<DefaultInterner>) -> Option<ConditionId> {
@@ -1037,29 +2169,100 @@ impl PortageDependencyProvider {
     ///
     /// For each solvable in `solution`, walks its structured dependency
     /// tree and emits a [`DepEdge`] for every non-blocker atom that
-    /// matches another solvable in the solution. USE-conditional groups
-    /// are evaluated against the provider's [`UseConfig`].
+    /// matches another solvable in the solution. Atoms only match
+    /// solvables in the [`Domain`] the class resolves in, so
+    /// cross-compilation edges point from target packages to host
+    /// packages. Atoms are looked up in a per-CPN index of the solution,
+    /// so construction scales with the number of atoms rather than
+    /// atoms × solution size.
+    ///
+    /// The walk follows the decisions the solver made rather than every
+    /// possible branch:
+    ///
+    /// - `flag? ( )` groups use the owning domain's [`UseConfig`]; for
+    ///   solver-decided flags, the `virtual/USE_<flag>` /
+    ///   `virtual/NotUSE_<flag>` solvable in `solution` decides.
+    /// - `|| ( )` only follows the left-most alternative satisfied by the
+    ///   solution.
+    /// - `^^ ( )` / `?? ( )` only follow the alternative whose choice
+    ///   solvable was selected.
+    ///
+    /// Classes excluded by the [`DepClassPolicy`] produce no edges;
+    /// softened classes still do, since a present dependency must still
+    /// be merged first.
     pub fn dependency_graph(&self, solution: &[SolvableId]) -> Vec<DepEdge> {
         let mut edges = Vec::new();
+        self.walk_solution_deps(solution, |event| {
+            if let DepWalkEvent::Edge(visit) = event {
+                edges.push(DepEdge {
+                    from: visit.from,
+                    to: visit.to,
+                    class: visit.class,
+                });
+            }
+        });
+        edges
+    }
+
+    /// Walk the dependency trees of `solution` the way
+    /// [`dependency_graph`](Self::dependency_graph) does, reporting every
+    /// edge together with the atom and USE conditionals that produced it,
+    /// and every alternative picked from a `||`, `^^` or `??` group.
+    pub(crate) fn walk_solution_deps(
+        &self,
+        solution: &[SolvableId],
+        mut visit: impl FnMut(DepWalkEvent<'_>),
+    ) {
+        let mut by_cpn: HashMap<(Domain, Cpn), Vec<SolvableId>> = HashMap::new();
+        for &sid in solution {
+            let cpn = self.pool.resolve_solvable(sid).cpv.cpn;
+            by_cpn.entry((self.domain(sid), cpn)).or_default().push(sid);
+        }
+        let walk = SolutionWalk {
+            selected: solution.iter().copied().collect(),
+            by_cpn,
+        };
+        let mut conditions = Vec::new();
 
         for &from in solution {
             let meta = self.pool.resolve_solvable(from);
+            let is_installed = self.is_installed(from);
             for (class, entries) in meta.dependencies.iter_classes() {
-                self.collect_dep_edges(from, class, entries, solution, &mut edges);
+                if self.dep_class_policy.mode(class, is_installed) == DepClassMode::Exclude {
+                    continue;
+                }
+                let scope = EdgeScope {
+                    from,
+                    class,
+                    domain: self.dependency_domain(from, class),
+                    use_config: self.owner_use_config(from),
+                    flag_virtuals: self.owner_flag_virtuals(from),
+                };
+                self.collect_dep_edges(&scope, entries, &walk, &mut conditions, &mut visit);
             }
         }
+    }
 
-        edges
+    /// Flag virtuals of the domain `owner` belongs to.
+    fn owner_flag_virtuals(
+        &self,
+        owner: SolvableId,
+    ) -> &HashMap<Interned<DefaultInterner>, FlagVirtuals> {
+        match (self.domain(owner), &self.host_use_config) {
+            (Domain::Host, Some(_)) => &self.host_flag_virtuals,
+            _ => &self.flag_virtuals,
+        }
     }
 
-    /// Recursively walk dep entries and emit edges.
-    fn collect_dep_edges(
+    /// Recursively walk dep entries and emit edges. `conditions` holds the
+    /// enclosing USE conditionals, outermost first.
+    fn collect_dep_edges<F: FnMut(DepWalkEvent<'_>)>(
         &self,
-        from: SolvableId,
-        class: DepClass,
+        scope: &EdgeScope<'_>,
         entries: &[DepEntry],
-        solution: &[SolvableId],
-        edges: &mut Vec<DepEdge>,
+        walk: &SolutionWalk,
+        conditions: &mut Vec<UseCondition>,
+        visit: &mut F,
     ) {
         for entry in entries {
             match entry {
@@ -1068,14 +2271,14 @@ impl PortageDependencyProvider {
                     if dep.blocker.is_some() {
                         continue;
                     }
-                    for &to in solution {
-                        if to == from {
-                            continue;
-                        }
-                        let to_meta = self.pool.resolve_solvable(to);
-                        if dep_matches_solvable(dep, to_meta, &self.use_config) {
-                            edges.push(DepEdge { from, to, class });
-                        }
+                    for to in self.solution_matches(scope, dep, walk) {
+                        visit(DepWalkEvent::Edge(EdgeVisit {
+                            from: scope.from,
+                            to,
+                            class: scope.class,
+                            dep,
+                            conditions,
+                        }));
                     }
                 }
                 DepEntry::UseConditional {
@@ -1083,42 +2286,241 @@ impl PortageDependencyProvider {
                     negate,
                     children,
                 } => {
-                    let flag_active = self.use_config.enabled.contains(flag)
-                        || self.use_config.solver_decided.contains(flag);
-                    let include = if *negate { !flag_active } else { flag_active };
-                    if include {
-                        self.collect_dep_edges(from, class, children, solution, edges);
+                    if self.conditional_active(scope, *flag, *negate, walk) {
+                        conditions.push(UseCondition {
+                            flag: *flag,
+                            negate: *negate,
+                        });
+                        self.collect_dep_edges(scope, children, walk, conditions, visit);
+                        conditions.pop();
                     }
                 }
-                DepEntry::AnyOf(alternatives)
-                | DepEntry::ExactlyOneOf(alternatives)
-                | DepEntry::AtMostOneOf(alternatives)
-                | DepEntry::AllOf(alternatives) => {
-                    self.collect_dep_edges(from, class, alternatives, solution, edges);
+                DepEntry::AnyOf(alternatives) => {
+                    if let Some(chosen) = alternatives
+                        .iter()
+                        .find(|alt| self.alternative_satisfied(scope, alt, walk))
+                    {
+                        visit(DepWalkEvent::Choice(ChoiceVisit {
+                            from: scope.from,
+                            class: scope.class,
+                            group: entry,
+                            chosen,
+                        }));
+                        self.collect_dep_edges(
+                            scope,
+                            std::slice::from_ref(chosen),
+                            walk,
+                            conditions,
+                            visit,
+                        );
+                    }
                 }
+                DepEntry::ExactlyOneOf(alternatives) | DepEntry::AtMostOneOf(alternatives) => {
+                    for chosen in self.chosen_xof_alternatives(scope, entry, alternatives, walk) {
+                        visit(DepWalkEvent::Choice(ChoiceVisit {
+                            from: scope.from,
+                            class: scope.class,
+                            group: entry,
+                            chosen,
+                        }));
+                        self.collect_dep_edges(
+                            scope,
+                            std::slice::from_ref(chosen),
+                            walk,
+                            conditions,
+                            visit,
+                        );
+                    }
+                }
+                DepEntry::AllOf(children) => {
+                    self.collect_dep_edges(scope, children, walk, conditions, visit);
+                }
+            }
+        }
+    }
+
+    /// Solvables in the solution (other than the owner) matched by `dep`.
+    fn solution_matches<'w>(
+        &'w self,
+        scope: &'w EdgeScope<'_>,
+        dep: &'w Dep,
+        walk: &'w SolutionWalk,
+    ) -> impl Iterator<Item = SolvableId> + 'w {
+        walk.by_cpn
+            .get(&(scope.domain, dep.cpn))
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(move |&to| {
+                to != scope.from
+                    && dep_matches_solvable(dep, self.pool.resolve_solvable(to), scope.use_config)
+            })
+    }
+
+    /// Whether a `flag? ( )` / `!flag? ( )` group applies in the solution.
+    fn conditional_active(
+        &self,
+        scope: &EdgeScope<'_>,
+        flag: Interned<DefaultInterner>,
+        negate: bool,
+        walk: &SolutionWalk,
+    ) -> bool {
+        match scope.flag_virtuals.get(&flag) {
+            Some(fv) => {
+                let chosen = if negate {
+                    fv.off_solvable
+                } else {
+                    fv.on_solvable
+                };
+                walk.selected.contains(&chosen)
             }
+            None => scope.use_config.enabled.contains(&flag) != negate,
+        }
+    }
+
+    /// Alternatives of a `^^ ( )` / `?? ( )` group whose choice solvable is
+    /// in the solution.
+    fn chosen_xof_alternatives<'e>(
+        &self,
+        scope: &EdgeScope<'_>,
+        group: &DepEntry,
+        alternatives: &'e [DepEntry],
+        walk: &SolutionWalk,
+    ) -> Vec<&'e DepEntry> {
+        let prefix = match group {
+            DepEntry::AtMostOneOf(_) => "??",
+            _ => "^^",
+        };
+        let key = (scope.from, scope.class, group_text(prefix, alternatives));
+        // Absent when the group was never converted, e.g. under an
+        // inactive USE conditional.
+        let xof_choices = self.xof_choices.borrow();
+        let occurrences = xof_choices.get(&key).map(Vec::as_slice);
+        alternatives
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| {
+                occurrences
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|choices| walk.selected.contains(&choices[i]))
+            })
+            .map(|(_, alt)| alt)
+            .collect()
+    }
+
+    /// Whether the solution satisfies one `|| ( )` alternative. Inactive
+    /// USE conditionals do not count as satisfied, so they are never
+    /// picked over a real alternative.
+    fn alternative_satisfied(
+        &self,
+        scope: &EdgeScope<'_>,
+        entry: &DepEntry,
+        walk: &SolutionWalk,
+    ) -> bool {
+        match entry {
+            DepEntry::Atom(dep) => {
+                dep.blocker.is_none() && self.solution_matches(scope, dep, walk).next().is_some()
+            }
+            DepEntry::UseConditional {
+                flag,
+                negate,
+                children,
+            } => {
+                self.conditional_active(scope, *flag, *negate, walk)
+                    && children
+                        .iter()
+                        .all(|child| self.alternative_satisfied(scope, child, walk))
+            }
+            DepEntry::AnyOf(alternatives) => alternatives
+                .iter()
+                .any(|alt| self.alternative_satisfied(scope, alt, walk)),
+            DepEntry::ExactlyOneOf(alternatives) | DepEntry::AtMostOneOf(alternatives) => self
+                .chosen_xof_alternatives(scope, entry, alternatives, walk)
+                .into_iter()
+                .all(|alt| self.alternative_satisfied(scope, alt, walk)),
+            DepEntry::AllOf(children) => children
+                .iter()
+                .all(|child| self.alternative_satisfied(scope, child, walk)),
         }
     }
 
     /// Compute an install order from a solver solution.
     ///
     /// Returns `Ok(ordered)` with solvables in installation order
-    /// (dependencies before dependents), or `Err(cycle_members)` if
-    /// there is a hard cycle that cannot be broken by deferring
-    /// `PDEPEND` edges.
+    /// (dependencies before dependents), or `Err(cycles)` describing every
+    /// dependency cycle that cannot be broken.
+    ///
+    /// Cycles are broken the way Portage does, by deferring edges in
+    /// priority order (softest first):
     ///
-    /// The algorithm uses Kahn's topological sort on the non-PDEPEND
-    /// edges. PDEPEND edges are inherently deferrable (they represent
-    /// "install after me" rather than "must exist before me"), so
-    /// excluding them naturally breaks cycles that Portage handles via
-    /// post-merge installation.
+    /// 1. `PDEPEND` — always deferred ("install after me").
+    /// 2. Any edge whose target is already installed — the dependency is
+    ///    already present on the system.
+    /// 3. `RDEPEND` — only needed once the dependent is used.
+    /// 4. `IDEPEND` — needed while the dependent is being merged.
+    ///
+    /// `DEPEND` and `BDEPEND` edges to packages that are not installed are
+    /// never relaxed. Relaxation is applied per strongly connected
+    /// component, one rank at a time, so edges outside cycles always keep
+    /// their ordering. The remaining edges are sorted with Kahn's
+    /// algorithm.
     pub fn install_order(
         &self,
         solution: &[SolvableId],
-    ) -> Result<Vec<SolvableId>, Vec<SolvableId>> {
-        let all_edges = self.dependency_graph(solution);
+    ) -> Result<Vec<SolvableId>, Vec<DependencyCycle>> {
+        let mut edges: Vec<DepEdge> = self
+            .dependency_graph(solution)
+            .into_iter()
+            .filter(|edge| edge.class != DepClass::Pdepend) // defer PDEPEND edges
+            .collect();
 
-        // Build adjacency list and in-degree map, excluding PDEPEND edges.
+        // Relax the softest edges inside each cycle until every strongly
+        // connected component is trivial or only hard edges remain.
+        let cycles = loop {
+            let mut cycles = Vec::new();
+            let mut relaxed = false;
+            let sccs =
+                strongly_connected_components(solution, edges.iter().map(|e| (e.from, e.to)));
+            for scc in sccs.into_iter().filter(|scc| scc.len() > 1) {
+                let members: HashSet<SolvableId> = scc.iter().copied().collect();
+                let internal =
+                    |edge: &DepEdge| members.contains(&edge.from) && members.contains(&edge.to);
+                let softest = edges
+                    .iter()
+                    .filter(|edge| internal(edge))
+                    .filter_map(|edge| self.relax_rank(edge))
+                    .min();
+                match softest {
+                    Some(rank) => {
+                        edges.retain(|edge| {
+                            !(internal(edge) && self.relax_rank(edge) == Some(rank))
+                        });
+                        relaxed = true;
+                    }
+                    None => {
+                        let mut members = scc;
+                        self.sort_by_cpv(&mut members);
+                        cycles.push(DependencyCycle {
+                            members,
+                            edges: edges
+                                .iter()
+                                .filter(|edge| internal(edge))
+                                .cloned()
+                                .collect(),
+                        });
+                    }
+                }
+            }
+            if !relaxed {
+                break cycles;
+            }
+        };
+        if !cycles.is_empty() {
+            return Err(cycles);
+        }
+
+        // Build adjacency list and in-degree map.
         let mut adj: HashMap<SolvableId, Vec<SolvableId>> = HashMap::new();
         let mut in_degree: HashMap<SolvableId, usize> = HashMap::new();
 
@@ -1128,10 +2530,7 @@ impl PortageDependencyProvider {
             in_degree.entry(sid).or_insert(0);
         }
 
-        for edge in &all_edges {
-            if edge.class == DepClass::Pdepend {
-                continue; // defer PDEPEND edges
-            }
+        for edge in &edges {
             adj.entry(edge.from).or_default();
             // edge.from depends on edge.to, so edge.to → edge.from in the
             // install order graph (to must be installed before from).
@@ -1148,11 +2547,7 @@ impl PortageDependencyProvider {
 
         // Sort the initial queue for deterministic output.
         let mut sorted_queue: Vec<SolvableId> = queue.drain(..).collect();
-        sorted_queue.sort_by(|a, b| {
-            let ma = self.pool.resolve_solvable(*a);
-            let mb = self.pool.resolve_solvable(*b);
-            ma.cpv.cmp(&mb.cpv)
-        });
+        self.sort_by_cpv(&mut sorted_queue);
         queue.extend(sorted_queue);
 
         let mut order = Vec::with_capacity(solution.len());
@@ -1170,28 +2565,37 @@ impl PortageDependencyProvider {
                     }
                 }
                 // Sort for deterministic output.
-                next.sort_by(|a, b| {
-                    let ma = self.pool.resolve_solvable(*a);
-                    let mb = self.pool.resolve_solvable(*b);
-                    ma.cpv.cmp(&mb.cpv)
-                });
+                self.sort_by_cpv(&mut next);
                 queue.extend(next);
             }
         }
 
-        if order.len() == solution.len() {
-            Ok(order)
-        } else {
-            // Remaining nodes form hard cycles.
-            let ordered_set: HashSet<SolvableId> = order.iter().copied().collect();
-            let cycle_members: Vec<SolvableId> = solution
-                .iter()
-                .copied()
-                .filter(|sid| !ordered_set.contains(sid))
-                .collect();
-            Err(cycle_members)
+        debug_assert_eq!(order.len(), solution.len());
+        Ok(order)
+    }
+
+    /// Rank of an install-order edge for cycle breaking: lower ranks are
+    /// deferred first, `None` means the edge can never be deferred.
+    fn relax_rank(&self, edge: &DepEdge) -> Option<u8> {
+        if self.is_installed(edge.to) {
+            return Some(0);
+        }
+        match edge.class {
+            DepClass::Pdepend => Some(0),
+            DepClass::Rdepend => Some(1),
+            DepClass::Idepend => Some(2),
+            DepClass::Depend | DepClass::Bdepend => None,
         }
     }
+
+    /// Sort solvables by CPV for deterministic output.
+    pub(crate) fn sort_by_cpv(&self, solvables: &mut [SolvableId]) {
+        solvables.sort_by(|a, b| {
+            let ma = self.pool.resolve_solvable(*a);
+            let mb = self.pool.resolve_solvable(*b);
+            ma.cpv.cmp(&mb.cpv)
+        });
+    }
 }
 
 // --- Display wrappers ---
@@ -1205,6 +2609,10 @@ impl fmt::Display for DisplaySolvable<'_> {
         if let Some(slot) = &meta.slot {
             write!(f, ":{}", slot)?;
         }
+        let domain = self.0.resolve_name(self.0.solvable_name(self.1)).domain;
+        if domain == Domain::Host {
+            write!(f, " [{domain}]")?;
+        }
         Ok(())
     }
 }
@@ -1220,18 +2628,20 @@ impl fmt::Display for DisplayName<'_> {
         // CPN do, append a hint so the user knows the package exists in a
         // different slot.
         if pkg_name.slot.is_some() {
-            let has_candidates = self
-                .0
-                .candidates
-                .get(&self.1)
-                .is_some_and(|c| !c.is_empty());
+            let candidates = self.0.candidates.borrow();
+            let has_candidates = candidates.get(&self.1).is_some_and(|c| !c.is_empty());
 
             if !has_candidates {
-                if let Some(slot_names) = self.0.cpn_slots.get(&pkg_name.cpn) {
+                if let Some(slot_names) = self
+                    .0
+                    .cpn_slots
+                    .borrow()
+                    .get(&(pkg_name.domain, pkg_name.cpn))
+                {
                     let available: Vec<_> = slot_names
                         .iter()
                         .filter(|&&nid| nid != self.1)
-                        .filter(|&&nid| self.0.candidates.get(&nid).is_some_and(|c| !c.is_empty()))
+                        .filter(|&&nid| candidates.get(&nid).is_some_and(|c| !c.is_empty()))
                         .filter_map(|&nid| self.0.pool.resolve_name(nid).slot.as_deref())
                         .collect();
 
@@ -1316,23 +2726,75 @@ impl resolvo::Interner for PortageDependencyProvider {
 
 impl resolvo::DependencyProvider for PortageDependencyProvider {
     async fn get_candidates(&self, name: NameId) -> Option<Candidates> {
-        let solvables = self.candidates.get(&name)?;
+        self.monitor.candidates_requested();
+        if !self.candidates.borrow().contains_key(&name) {
+            self.load_cpn(self.pool.resolve_name(name).cpn);
+        }
+        let solvables = self.candidates.borrow().get(&name)?.clone();
+        let mut excluded = self.forbidden_downgrades(name, &solvables);
+        excluded.extend(
+            solvables
+                .iter()
+                .filter_map(|sid| Some((*sid, *self.masked.get(sid)?))),
+        );
         Some(Candidates {
-            candidates: solvables.clone(),
-            favored: self.favored.get(&name).copied(),
-            locked: self.locked.get(&name).copied(),
-            hint_dependencies_available: HintDependenciesAvailable::All,
-            excluded: Vec::new(),
+            candidates: solvables,
+            favored: self
+                .favored
+                .borrow()
+                .get(&name)
+                .copied()
+                .filter(|_| !self.released.contains(&name)),
+            locked: self.locked.borrow().get(&name).copied(),
+            hint_dependencies_available: if self.lazy.is_some() {
+                HintDependenciesAvailable::None
+            } else {
+                HintDependenciesAvailable::All
+            },
+            excluded,
         })
     }
 
+    fn should_cancel_with_value(&self) -> Option<Box<dyn Any>> {
+        if let Some(err) = self
+            .lazy
+            .as_ref()
+            .and_then(|lazy| lazy.error.borrow_mut().take())
+        {
+            return Some(Box::new(err));
+        }
+        let reason = self.monitor.checkpoint()?;
+        Some(Box::new(ResolveError::Cancelled {
+            reason,
+            stats: self.monitor.stats(),
+        }))
+    }
+
     async fn sort_candidates(&self, _solver: &SolverCache<Self>, solvables: &mut [SolvableId]) {
-        // Sort newest first so the solver prefers newer versions.
-        solvables.sort_by(|a, b| {
-            let va = &self.pool.resolve_solvable(*a).cpv.version;
-            let vb = &self.pool.resolve_solvable(*b).cpv.version;
-            vb.cmp(va) // descending
-        });
+        // All solvables share a name; the ordering sees its installed
+        // versions.
+        let Some(&first) = solvables.first() else {
+            return;
+        };
+        let installed = self.installed.borrow();
+        let installed_versions: Vec<&PackageMetadata> = self
+            .candidates
+            .borrow()
+            .get(&self.pool.solvable_name(first))
+            .into_iter()
+            .flatten()
+            .filter(|sid| installed.contains(sid))
+            .map(|&sid| self.pool.resolve_solvable(sid))
+            .collect();
+        let context = OrderingContext {
+            installed: &installed_versions,
+        };
+        let candidate = |sid: SolvableId| Candidate {
+            metadata: self.pool.resolve_solvable(sid),
+            installed: installed.contains(&sid),
+        };
+        let ordering: &dyn CandidateOrdering = self.ordering.as_deref().unwrap_or(&NewestFirst);
+        solvables.sort_by(|&a, &b| ordering.compare(candidate(a), candidate(b), context));
     }
 
     async fn filter_candidates(
@@ -1341,37 +2803,21 @@ impl resolvo::DependencyProvider for PortageDependencyProvider {
         version_set: VersionSetId,
         inverse: bool,
     ) -> Vec<SolvableId> {
-        let constraint = self.pool.resolve_version_set(version_set);
-
         candidates
             .iter()
             .copied()
-            .filter(|&sid| {
-                let meta = self.pool.resolve_solvable(sid);
-                let mut matches = version_matches(
-                    &meta.cpv.version,
-                    &constraint.operator,
-                    constraint.glob,
-                    &constraint.version,
-                ) && slot_matches(meta, constraint);
-
-                // Blocker constrains store the *blocked* operator with
-                // `inverted = true`.  Flipping the match here means resolvo's
-                // own `inverse` flag (used for constrains) ends up forbidding
-                // candidates that *match* the blocker — exactly what we want.
-                // See [`VersionConstraint`] for the full explanation.
-                if constraint.inverted {
-                    matches = !matches;
-                }
-
-                if inverse { !matches } else { matches }
-            })
+            .filter(|&sid| self.version_set_matches(sid, version_set) != inverse)
             .collect()
     }
 
     async fn get_dependencies(&self, solvable: SolvableId) -> Dependencies {
-        match self.dependencies.get(&solvable) {
-            Some(deps) => Dependencies::Known(deps.clone()),
+        self.monitor.dependencies_requested();
+        if self.lazy.is_some() && !self.dependencies.borrow().contains_key(&solvable) {
+            self.convert_solvable(solvable);
+        }
+        let deps = self.dependencies.borrow().get(&solvable).cloned();
+        match deps {
+            Some(deps) => Dependencies::Known(self.prefer_alternatives(deps)),
             None => Dependencies::Known(KnownDependencies {
                 requirements: Vec::new(),
                 constrains: Vec::new(),
@@ -1387,7 +2833,7 @@ impl resolvo::DependencyProvider for PortageDependencyProvider {
 /// Returns `(slot, subslot)`. `:*` and `:=` return `(None, None)`,
 /// which makes `slot_matches` accept all candidates regardless of
 /// their slot.
-fn extract_slot(
+pub(crate) fn extract_slot(
     dep: &Dep,
 ) -> (
     Option<Interned<DefaultInterner>>,
@@ -1418,6 +2864,280 @@ fn has_slot_equal_op(dep: &Dep) -> bool {
     )
 }
 
+/// A solver solution indexed for dependency graph walks.
+struct SolutionWalk {
+    selected: HashSet<SolvableId>,
+    /// Solution members per domain and CPN, in solution order, so atoms
+    /// are only matched against packages with the right name.
+    by_cpn: HashMap<(Domain, Cpn), Vec<SolvableId>>,
+}
+
+/// A USE conditional (`flag?` or `!flag?`) enclosing a dependency atom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UseCondition {
+    pub(crate) flag: Interned<DefaultInterner>,
+    pub(crate) negate: bool,
+}
+
+impl fmt::Display for UseCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negate {
+            write!(f, "!")?;
+        }
+        write!(f, "{}?", self.flag)
+    }
+}
+
+/// Something reported by
+/// [`PortageDependencyProvider::walk_solution_deps`].
+pub(crate) enum DepWalkEvent<'a> {
+    Edge(EdgeVisit<'a>),
+    Choice(ChoiceVisit<'a>),
+}
+
+/// A dependency edge found while walking a solution.
+pub(crate) struct EdgeVisit<'a> {
+    pub(crate) from: SolvableId,
+    pub(crate) to: SolvableId,
+    pub(crate) class: DepClass,
+    /// Atom that matched `to`.
+    pub(crate) dep: &'a Dep,
+    /// USE conditionals enclosing `dep`, outermost first.
+    pub(crate) conditions: &'a [UseCondition],
+}
+
+/// An alternative the walk followed out of a `||`, `^^` or `??` group.
+pub(crate) struct ChoiceVisit<'a> {
+    pub(crate) from: SolvableId,
+    pub(crate) class: DepClass,
+    pub(crate) group: &'a DepEntry,
+    pub(crate) chosen: &'a DepEntry,
+}
+
+/// Owner, class and domain context of one dependency tree walk.
+struct EdgeScope<'a> {
+    from: SolvableId,
+    class: DepClass,
+    /// Domain the class's atoms resolve in.
+    domain: Domain,
+    use_config: &'a UseConfig,
+    flag_virtuals: &'a HashMap<Interned<DefaultInterner>, FlagVirtuals>,
+}
+
+/// 64-bit FNV-1a hash; stable across platforms and Rust releases.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::default();
+    hasher.update(bytes);
+    hasher.0
+}
+
+/// Incremental [`fnv1a`], also fed through [`fmt::Write`] so rendered
+/// values can be hashed without building strings.
+pub(crate) struct Fnv1a(pub(crate) u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        self.0 = bytes.iter().fold(self.0, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    }
+}
+
+impl fmt::Write for Fnv1a {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.update(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Fingerprint of a resolution configuration; see
+/// [`PortageDependencyProvider::config_fingerprint`].
+pub(crate) fn config_fingerprint(
+    use_config: &UseConfig,
+    host_use_config: Option<&UseConfig>,
+    dep_class_policy: &DepClassPolicy,
+) -> u64 {
+    let mut text = String::new();
+    let mut push_use_config = |label: &str, use_config: &UseConfig| {
+        for (kind, flags) in [
+            ("enabled", &use_config.enabled),
+            ("disabled", &use_config.disabled),
+            ("solver_decided", &use_config.solver_decided),
+        ] {
+            let mut flags: Vec<&str> = flags.iter().map(|f| f.as_str()).collect();
+            flags.sort_unstable();
+            text.push_str(&format!("{label}.{kind}={}\n", flags.join(",")));
+        }
+    };
+    push_use_config("target", use_config);
+    if let Some(host_use_config) = host_use_config {
+        push_use_config("host", host_use_config);
+    }
+    text.push_str(&format!("policy={dep_class_policy:?}\n"));
+    fnv1a(text.as_bytes())
+}
+
+/// Render a group of dep entries as `<prefix> ( a b c )`.
+fn group_text(prefix: &str, entries: &[DepEntry]) -> String {
+    let mut text = format!("{prefix} (");
+    for entry in entries {
+        text.push(' ');
+        text.push_str(&entry.to_string());
+    }
+    text.push_str(" )");
+    text
+}
+
+/// Package name prefixes in the `virtual` category used by the provider's
+/// internal USE-flag and choice virtuals.
+const RESERVED_VIRTUAL_PREFIXES: [&str; 5] = ["USE_", "NotUSE_", "xof_", "allof_", "soft_"];
+
+/// Whether `name` is a valid USE flag name (PMS 3.1.4).
+fn is_valid_flag_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '_' | '@' | '-'))
+}
+
+/// Whether `name` is a valid slot or sub-slot name (PMS 3.1.3).
+fn is_valid_slot_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '_' | '.' | '-'))
+}
+
+/// Reject [`UseConfig`] flags that are not valid USE flag names; a
+/// solver-decided flag becomes part of a virtual package name.
+fn validate_use_config(use_config: &UseConfig, domain: Domain) -> Result<(), ResolveError> {
+    let flags = use_config
+        .enabled
+        .iter()
+        .chain(&use_config.disabled)
+        .chain(&use_config.solver_decided);
+    for flag in flags {
+        if !is_valid_flag_name(flag) {
+            return Err(ResolveError::InvalidFlag {
+                flag: flag.to_string(),
+                domain,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Check one package's metadata and dependency trees before it is
+/// interned into `domain`.
+fn validate_package(
+    meta: &PackageMetadata,
+    domain: Domain,
+    use_config: &UseConfig,
+    validation: Validation,
+) -> Result<(), ResolveError> {
+    let malformed = |reason: String| ResolveError::MalformedMetadata {
+        cpv: meta.cpv.clone(),
+        domain,
+        reason,
+    };
+
+    let package = meta.cpv.cpn.package.as_str();
+    if meta.cpv.cpn.category.as_str() == "virtual"
+        && RESERVED_VIRTUAL_PREFIXES
+            .iter()
+            .any(|prefix| package.starts_with(prefix))
+    {
+        return Err(malformed(format!(
+            "package name `{package}` is reserved for internal virtuals"
+        )));
+    }
+    if meta.subslot.is_some() && meta.slot.is_none() {
+        return Err(malformed("sub-slot without a slot".to_string()));
+    }
+    for (what, name) in [("slot", meta.slot), ("sub-slot", meta.subslot)] {
+        if let Some(name) = name.filter(|name| !is_valid_slot_name(name)) {
+            return Err(malformed(format!("invalid {what} name `{name}`")));
+        }
+    }
+    for flag in &meta.iuse {
+        let name = flag.strip_prefix(['+', '-']).unwrap_or(flag);
+        if !is_valid_flag_name(name) {
+            return Err(malformed(format!("invalid IUSE flag `{flag}`")));
+        }
+    }
+    if validation == Validation::Lenient {
+        return Ok(());
+    }
+
+    for (class, entries) in meta.dependencies.iter_classes() {
+        check_dep_entries(entries, &mut |dep| {
+            let construct = if dep.blocker.is_some() && dep_slot_operator(dep).is_some() {
+                Some(DepConstruct::BlockerSlotOperator)
+            } else if dep.use_deps.iter().flatten().any(|ud| {
+                !matches!(ud.kind, UseDepKind::Enabled | UseDepKind::Disabled)
+                    && use_config.solver_decided.contains(&ud.flag)
+            }) {
+                Some(DepConstruct::SolverDecidedUseDep)
+            } else {
+                None
+            };
+            match construct {
+                Some(construct) => Err(ResolveError::UnsupportedConstruct {
+                    cpv: meta.cpv.clone(),
+                    domain,
+                    class,
+                    atom: dep.to_string(),
+                    construct,
+                }),
+                None => Ok(()),
+            }
+        })?;
+    }
+    Ok(())
+}
+
+/// Drop every entry of `versions` whose CPV and repository a later entry
+/// repeats.
+fn keep_last_duplicates(versions: &mut Vec<PackageMetadata>) {
+    let mut seen = HashSet::new();
+    versions.reverse();
+    versions.retain(|meta| seen.insert((meta.cpv.clone(), meta.repo)));
+    versions.reverse();
+}
+
+/// Call `check` on every atom of a dependency tree.
+fn check_dep_entries(
+    entries: &[DepEntry],
+    check: &mut impl FnMut(&Dep) -> Result<(), ResolveError>,
+) -> Result<(), ResolveError> {
+    for entry in entries {
+        match entry {
+            DepEntry::Atom(dep) => check(dep)?,
+            DepEntry::UseConditional { children, .. }
+            | DepEntry::AnyOf(children)
+            | DepEntry::ExactlyOneOf(children)
+            | DepEntry::AtMostOneOf(children)
+            | DepEntry::AllOf(children) => check_dep_entries(children, check)?,
+        }
+    }
+    Ok(())
+}
+
+/// The slot operator (`:=` / `:*`, bare or after a slot name) of a dep.
+fn dep_slot_operator(dep: &Dep) -> Option<SlotOperator> {
+    match &dep.slot_dep {
+        Some(SlotDep::Operator(op)) => Some(*op),
+        Some(SlotDep::Slot { op, .. }) => *op,
+        None => None,
+    }
+}
+
 /// Extract operator and bare version from a dep (defaults to `>=0` for unversioned).
 fn dep_op_version(dep: &Dep) -> (Operator, Version) {
     match &dep.version {
@@ -1425,7 +3145,7 @@ fn dep_op_version(dep: &Dep) -> (Operator, Version) {
             let op = dep.op.unwrap_or(Operator::Equal);
             (op, v.clone())
         }
-        None => (Operator::GreaterOrEqual, Version::parse("0").unwrap()),
+        None => (Operator::GreaterOrEqual, Version::new(&[0])),
     }
 }
 
@@ -1458,7 +3178,11 @@ fn slot_matches(meta: &PackageMetadata, constraint: &VersionConstraint) -> bool
 /// This is the post-solve counterpart of `filter_candidates`: it tests
 /// CPN, version operator, slot, sub-slot, repository, and USE dep
 /// constraints against a [`PackageMetadata`].
-fn dep_matches_solvable(dep: &Dep, meta: &PackageMetadata, use_config: &UseConfig) -> bool {
+pub(crate) fn dep_matches_solvable(
+    dep: &Dep,
+    meta: &PackageMetadata,
+    use_config: &UseConfig,
+) -> bool {
     // CPN must match.
     if dep.cpn != meta.cpv.cpn {
         return false;
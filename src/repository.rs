@@ -3,19 +3,143 @@
 //! [`PackageRepository`] provides read-only access to a package database.
 //! [`InMemoryRepository`] is a simple implementation for testing.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
 
-use portage_atom::Cpn;
+use portage_atom::gentoo_interner::{DefaultInterner, Interned};
+use portage_atom::{Cpn, Cpv};
 
 use crate::pool::PackageMetadata;
 
+/// Result of a [`PackageRepository`] query.
+pub type RepositoryResult<T> = Result<T, RepositoryError>;
+
+/// Error reported by a [`PackageRepository`] backend, e.g. an unreadable
+/// metadata cache or an unreachable binhost.
+#[derive(Debug)]
+pub struct RepositoryError {
+    message: String,
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl RepositoryError {
+    /// Error with a message and no underlying cause.
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Error with a message caused by `source`.
+    pub fn with_source(
+        message: impl Into<String>,
+        source: impl Into<Box<dyn Error + Send + Sync>>,
+    ) -> Self {
+        Self {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "repository error: {}", self.message)?;
+        if let Some(source) = &self.source {
+            write!(f, ": {source}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RepositoryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|source| source as _)
+    }
+}
+
 /// Read-only package database.
+///
+/// Only [`all_packages`](Self::all_packages) and
+/// [`versions_for`](Self::versions_for) are required; the other lookups
+/// have default implementations built on them that backends with an index
+/// can override.
 pub trait PackageRepository {
-    /// Return all distinct category/package names in the repository.
-    fn all_packages(&self) -> Vec<Cpn>;
+    /// Iterate over all distinct category/package names in the repository.
+    fn all_packages(&self) -> RepositoryResult<Box<dyn Iterator<Item = Cpn> + '_>>;
+
+    /// Return every version available for the given category/package,
+    /// borrowed when the backend keeps them in memory. An unknown package
+    /// has no versions.
+    fn versions_for(&self, cpn: &Cpn) -> RepositoryResult<Cow<'_, [PackageMetadata]>>;
+
+    /// Whether the repository has any version of the given
+    /// category/package.
+    fn contains(&self, cpn: &Cpn) -> RepositoryResult<bool> {
+        Ok(!self.versions_for(cpn)?.is_empty())
+    }
+
+    /// Look up one exact version.
+    fn get(&self, cpv: &Cpv) -> RepositoryResult<Option<PackageMetadata>> {
+        Ok(self
+            .versions_for(&cpv.cpn)?
+            .iter()
+            .find(|meta| meta.cpv == *cpv)
+            .cloned())
+    }
+
+    /// Return the distinct slots of the given category/package, in the
+    /// order they first appear in [`versions_for`](Self::versions_for).
+    ///
+    /// Used by lazily built providers to expand unslotted atoms without
+    /// loading the package.
+    fn slots_for(&self, cpn: &Cpn) -> RepositoryResult<Vec<Option<Interned<DefaultInterner>>>> {
+        let mut slots = Vec::new();
+        for meta in self.versions_for(cpn)?.iter() {
+            if !slots.contains(&meta.slot) {
+                slots.push(meta.slot);
+            }
+        }
+        Ok(slots)
+    }
+
+    /// Return the categories that have at least one package, sorted.
+    fn categories(&self) -> RepositoryResult<Vec<Interned<DefaultInterner>>> {
+        let mut categories: Vec<_> = self.all_packages()?.map(|cpn| cpn.category).collect();
+        categories.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+        categories.dedup();
+        Ok(categories)
+    }
+}
+
+impl<R: PackageRepository + ?Sized> PackageRepository for Arc<R> {
+    fn all_packages(&self) -> RepositoryResult<Box<dyn Iterator<Item = Cpn> + '_>> {
+        (**self).all_packages()
+    }
+
+    fn versions_for(&self, cpn: &Cpn) -> RepositoryResult<Cow<'_, [PackageMetadata]>> {
+        (**self).versions_for(cpn)
+    }
+
+    fn contains(&self, cpn: &Cpn) -> RepositoryResult<bool> {
+        (**self).contains(cpn)
+    }
+
+    fn get(&self, cpv: &Cpv) -> RepositoryResult<Option<PackageMetadata>> {
+        (**self).get(cpv)
+    }
+
+    fn slots_for(&self, cpn: &Cpn) -> RepositoryResult<Vec<Option<Interned<DefaultInterner>>>> {
+        (**self).slots_for(cpn)
+    }
 
-    /// Return every version available for the given category/package.
-    fn versions_for(&self, cpn: &Cpn) -> Vec<PackageMetadata>;
+    fn categories(&self) -> RepositoryResult<Vec<Interned<DefaultInterner>>> {
+        (**self).categories()
+    }
 }
 
 /// In-memory repository backed by a `HashMap`, useful for tests.
@@ -44,12 +168,21 @@ impl Default for InMemoryRepository {
 }
 
 impl PackageRepository for InMemoryRepository {
-    fn all_packages(&self) -> Vec<Cpn> {
-        self.packages.keys().cloned().collect()
+    fn all_packages(&self) -> RepositoryResult<Box<dyn Iterator<Item = Cpn> + '_>> {
+        Ok(Box::new(self.packages.keys().copied()))
+    }
+
+    fn versions_for(&self, cpn: &Cpn) -> RepositoryResult<Cow<'_, [PackageMetadata]>> {
+        Ok(Cow::Borrowed(
+            self.packages
+                .get(cpn)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+        ))
     }
 
-    fn versions_for(&self, cpn: &Cpn) -> Vec<PackageMetadata> {
-        self.packages.get(cpn).cloned().unwrap_or_default()
+    fn contains(&self, cpn: &Cpn) -> RepositoryResult<bool> {
+        Ok(self.packages.contains_key(cpn))
     }
 }
 
@@ -75,18 +208,106 @@ mod tests {
         repo.add(meta);
 
         let cpn = Cpn::new("dev-lang", "rust");
-        let pkgs = repo.all_packages();
-        assert_eq!(pkgs.len(), 1);
-        assert_eq!(pkgs[0], cpn);
+        let pkgs: Vec<Cpn> = repo.all_packages().unwrap().collect();
+        assert_eq!(pkgs, [cpn]);
 
-        let versions = repo.versions_for(&cpn);
+        let versions = repo.versions_for(&cpn).unwrap();
         assert_eq!(versions.len(), 1);
+        assert!(matches!(versions, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn default_lookups() {
+        /// Repository implementing only the required methods.
+        struct Minimal(InMemoryRepository);
+
+        impl PackageRepository for Minimal {
+            fn all_packages(&self) -> RepositoryResult<Box<dyn Iterator<Item = Cpn> + '_>> {
+                self.0.all_packages()
+            }
+
+            fn versions_for(&self, cpn: &Cpn) -> RepositoryResult<Cow<'_, [PackageMetadata]>> {
+                Ok(Cow::Owned(self.0.versions_for(cpn)?.into_owned()))
+            }
+        }
+
+        let mut repo = InMemoryRepository::new();
+        for cpv in [
+            "dev-lang/rust-1.75.0",
+            "dev-lang/rust-1.76.0",
+            "app-misc/foo-1",
+        ] {
+            repo.add(PackageMetadata {
+                cpv: Cpv::parse(cpv).unwrap(),
+                slot: Some("0".into()),
+                subslot: None,
+                iuse: vec![],
+                use_flags: HashSet::new(),
+                repo: None,
+                dependencies: PackageDeps::default(),
+            });
+        }
+        let repo = Minimal(repo);
+
+        let rust = Cpn::new("dev-lang", "rust");
+        assert!(repo.contains(&rust).unwrap());
+        assert!(!repo.contains(&Cpn::new("dev-lang", "go")).unwrap());
+        let cpv = Cpv::parse("dev-lang/rust-1.76.0").unwrap();
+        assert_eq!(repo.get(&cpv).unwrap().unwrap().cpv, cpv);
+        let missing = Cpv::parse("dev-lang/rust-1.77.0").unwrap();
+        assert!(repo.get(&missing).unwrap().is_none());
+        assert_eq!(repo.slots_for(&rust).unwrap(), [Some("0".into())]);
+        let categories = repo.categories().unwrap();
+        let categories: Vec<&str> = categories.iter().map(|c| c.as_str()).collect();
+        assert_eq!(categories, ["app-misc", "dev-lang"]);
+    }
+
+    #[test]
+    fn repository_error_keeps_source() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "metadata/md5-cache");
+        let err = RepositoryError::with_source("cannot read dev-lang/rust", io);
+        assert_eq!(
+            err.to_string(),
+            "repository error: cannot read dev-lang/rust: metadata/md5-cache"
+        );
+        assert!(err.source().is_some());
+        assert!(RepositoryError::new("offline").source().is_none());
+    }
+
+    #[test]
+    fn slots_for_in_first_seen_order() {
+        let mut repo = InMemoryRepository::new();
+        for (cpv, slot) in [
+            ("dev-lang/python-3.12.1", "3.12"),
+            ("dev-lang/python-3.11.7", "3.11"),
+            ("dev-lang/python-3.12.2", "3.12"),
+        ] {
+            repo.add(PackageMetadata {
+                cpv: Cpv::parse(cpv).unwrap(),
+                slot: Some(slot.into()),
+                subslot: None,
+                iuse: vec![],
+                use_flags: HashSet::new(),
+                repo: None,
+                dependencies: PackageDeps::default(),
+            });
+        }
+
+        assert_eq!(
+            repo.slots_for(&Cpn::new("dev-lang", "python")).unwrap(),
+            [Some("3.12".into()), Some("3.11".into())]
+        );
+        assert!(
+            repo.slots_for(&Cpn::new("dev-lang", "rust"))
+                .unwrap()
+                .is_empty()
+        );
     }
 
     #[test]
     fn versions_for_unknown_package() {
         let repo = InMemoryRepository::new();
-        let versions = repo.versions_for(&Cpn::new("dev-lang", "rust"));
+        let versions = repo.versions_for(&Cpn::new("dev-lang", "rust")).unwrap();
         assert!(versions.is_empty());
     }
 }
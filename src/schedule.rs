@@ -0,0 +1,310 @@
+//! Parallel merge scheduling.
+//!
+//! [`MergeSchedule`] turns the labeled [`DepEdge`] graph of a solution into
+//! a job DAG suitable for `--jobs N` builds, together with level-by-level
+//! batches of packages that can be merged concurrently.
+
+use std::collections::{HashMap, HashSet};
+
+use resolvo::SolvableId;
+
+use crate::graph::strongly_connected_components;
+use crate::pool::{DepClass, DepEdge, DependencyCycle};
+use crate::provider::PortageDependencyProvider;
+
+/// One package merge in a [`MergeSchedule`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeJob {
+    /// The package to build and merge.
+    pub solvable: SolvableId,
+    /// Jobs that must have finished before this one may *start*.
+    pub prerequisites: Vec<SolvableId>,
+    /// Critical-path weight: this job's cost plus the heaviest chain of
+    /// jobs waiting on it. Higher values should be started first.
+    pub priority: u64,
+}
+
+/// A dependency-respecting parallel merge plan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeSchedule {
+    /// Every job, in an order where prerequisites come first. Within a
+    /// level, jobs are sorted by descending priority.
+    pub jobs: Vec<MergeJob>,
+    /// Level-by-level batches: every job in `batches[n]` only depends on
+    /// jobs in earlier batches, so a batch can be merged in parallel.
+    pub batches: Vec<Vec<SolvableId>>,
+}
+
+impl MergeSchedule {
+    /// Look up the job for a solvable.
+    pub fn job(&self, solvable: SolvableId) -> Option<&MergeJob> {
+        self.jobs.iter().find(|job| job.solvable == solvable)
+    }
+}
+
+impl PortageDependencyProvider {
+    /// Build a [`MergeSchedule`] where every job has unit cost.
+    ///
+    /// See [`merge_schedule_weighted`](Self::merge_schedule_weighted).
+    pub fn merge_schedule(
+        &self,
+        solution: &[SolvableId],
+    ) -> Result<MergeSchedule, Vec<DependencyCycle>> {
+        self.merge_schedule_weighted(solution, |_| 1)
+    }
+
+    /// Build a [`MergeSchedule`] from a solver solution, weighting each job
+    /// with `cost` for critical-path ordering.
+    ///
+    /// Prerequisites follow each class's semantics:
+    ///
+    /// - `DEPEND`, `BDEPEND` and `IDEPEND` targets must be *usable* before
+    ///   the dependent starts: the target itself and, transitively, its
+    ///   `RDEPEND`s must be merged.
+    /// - `RDEPEND` targets only need to be merged before the dependent is
+    ///   *used*, so they become prerequisites of whoever build-depends on
+    ///   the dependent, not of the dependent itself.
+    /// - `PDEPEND` never constrains the schedule.
+    ///
+    /// Installed targets are already present and never wait. Returns the
+    /// cycles of the prerequisite graph if a job transitively waits on
+    /// itself.
+    pub fn merge_schedule_weighted(
+        &self,
+        solution: &[SolvableId],
+        cost: impl Fn(SolvableId) -> u64,
+    ) -> Result<MergeSchedule, Vec<DependencyCycle>> {
+        let edges = self.dependency_graph(solution);
+
+        let mut runtime: HashMap<SolvableId, Vec<SolvableId>> = HashMap::new();
+        let mut build: HashMap<SolvableId, Vec<&DepEdge>> = HashMap::new();
+        for edge in &edges {
+            if self.is_installed(edge.to) {
+                continue;
+            }
+            match edge.class {
+                DepClass::Rdepend => runtime.entry(edge.from).or_default().push(edge.to),
+                DepClass::Depend | DepClass::Bdepend | DepClass::Idepend => {
+                    build.entry(edge.from).or_default().push(edge)
+                }
+                DepClass::Pdepend => {}
+            }
+        }
+
+        // Prerequisites: the runtime closure of every build-time target.
+        let mut prerequisites: HashMap<SolvableId, Vec<SolvableId>> = HashMap::new();
+        for &sid in solution {
+            let mut seen: HashSet<SolvableId> = HashSet::new();
+            let mut stack: Vec<SolvableId> = build
+                .get(&sid)
+                .into_iter()
+                .flatten()
+                .map(|edge| edge.to)
+                .collect();
+            while let Some(next) = stack.pop() {
+                if next == sid || !seen.insert(next) {
+                    continue;
+                }
+                stack.extend(runtime.get(&next).into_iter().flatten().copied());
+            }
+            let mut prereqs: Vec<SolvableId> = seen.into_iter().collect();
+            self.sort_by_cpv(&mut prereqs);
+            prerequisites.insert(sid, prereqs);
+        }
+
+        let sccs = strongly_connected_components(
+            solution,
+            prerequisites
+                .iter()
+                .flat_map(|(&from, prereqs)| prereqs.iter().map(move |&to| (from, to))),
+        );
+        let cycles: Vec<DependencyCycle> = sccs
+            .into_iter()
+            .filter(|scc| scc.len() > 1)
+            .map(|mut members| {
+                self.sort_by_cpv(&mut members);
+                let member_set: HashSet<SolvableId> = members.iter().copied().collect();
+                let edges = edges
+                    .iter()
+                    .filter(|e| member_set.contains(&e.from) && member_set.contains(&e.to))
+                    .filter(|e| e.class != DepClass::Pdepend)
+                    .cloned()
+                    .collect();
+                DependencyCycle { members, edges }
+            })
+            .collect();
+        if !cycles.is_empty() {
+            return Err(cycles);
+        }
+
+        // Levels: longest prerequisite chain below each job.
+        let mut level: HashMap<SolvableId, usize> = HashMap::new();
+        for &sid in solution {
+            compute_level(sid, &prerequisites, &mut level);
+        }
+
+        // Priority: cost plus the heaviest chain of jobs waiting on this one.
+        let mut dependents: HashMap<SolvableId, Vec<SolvableId>> = HashMap::new();
+        for (&sid, prereqs) in &prerequisites {
+            for &p in prereqs {
+                dependents.entry(p).or_default().push(sid);
+            }
+        }
+        let mut by_level_desc: Vec<SolvableId> = solution.to_vec();
+        by_level_desc.sort_by_key(|sid| std::cmp::Reverse(level[sid]));
+        let mut priority: HashMap<SolvableId, u64> = HashMap::new();
+        for &sid in &by_level_desc {
+            let waiting = dependents
+                .get(&sid)
+                .into_iter()
+                .flatten()
+                .map(|d| priority[d])
+                .max()
+                .unwrap_or(0);
+            priority.insert(sid, cost(sid).saturating_add(waiting));
+        }
+
+        let depth = level.values().copied().max().map_or(0, |max| max + 1);
+        let mut batches: Vec<Vec<SolvableId>> = vec![Vec::new(); depth];
+        for &sid in solution {
+            batches[level[&sid]].push(sid);
+        }
+        for batch in &mut batches {
+            self.sort_by_cpv(batch);
+            batch.sort_by_key(|sid| std::cmp::Reverse(priority[sid]));
+        }
+
+        let jobs = batches
+            .iter()
+            .flatten()
+            .map(|&sid| MergeJob {
+                solvable: sid,
+                prerequisites: prerequisites.remove(&sid).unwrap_or_default(),
+                priority: priority[&sid],
+            })
+            .collect();
+
+        Ok(MergeSchedule { jobs, batches })
+    }
+}
+
+/// Compute the level of `sid` (0 for jobs without prerequisites) in an
+/// acyclic prerequisite graph.
+fn compute_level(
+    sid: SolvableId,
+    prerequisites: &HashMap<SolvableId, Vec<SolvableId>>,
+    level: &mut HashMap<SolvableId, usize>,
+) -> usize {
+    if let Some(&l) = level.get(&sid) {
+        return l;
+    }
+    // Iterative post-order to avoid deep recursion on long chains.
+    let mut stack = vec![(sid, false)];
+    while let Some((node, expanded)) = stack.pop() {
+        if level.contains_key(&node) {
+            continue;
+        }
+        let prereqs = prerequisites.get(&node).map(Vec::as_slice).unwrap_or(&[]);
+        if expanded {
+            let l = prereqs
+                .iter()
+                .filter_map(|p| level.get(p))
+                .map(|l| l + 1)
+                .max()
+                .unwrap_or(0);
+            level.insert(node, l);
+        } else {
+            stack.push((node, true));
+            stack.extend(
+                prereqs
+                    .iter()
+                    .filter(|p| !level.contains_key(p))
+                    .map(|&p| (p, false)),
+            );
+        }
+    }
+    level[&sid]
+}
+
+#[cfg(test)]
+mod tests {
+    use portage_atom::Dep;
+    use resolvo::{Problem, Solver};
+
+    use super::*;
+    use crate::pool::{InstalledSet, UseConfig};
+    use crate::repository::InMemoryRepository;
+    use crate::tests::{order_names, pkg, pkg_build_run, solve_cycle};
+
+    #[test]
+    fn merge_schedule_class_semantics() {
+        // app DEPEND lib, RDEPEND other; lib RDEPEND rt.
+        // app must wait for lib and rt (lib must be usable), but not for
+        // other, which is only needed at runtime.
+        let mut repo = InMemoryRepository::new();
+        repo.add(pkg_build_run(
+            "app-misc/app-1.0",
+            "dev-lib/lib",
+            "dev-lib/other",
+        ));
+        repo.add(pkg_build_run("dev-lib/lib-1.0", "", "dev-lib/rt"));
+        repo.add(pkg("dev-lib/rt-1.0", "0", vec![]));
+        repo.add(pkg("dev-lib/other-1.0", "0", vec![]));
+
+        let mut provider = PortageDependencyProvider::new(&repo, &UseConfig::default());
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let mut solver = Solver::new(provider);
+        let solution = solver
+            .solve(Problem::new().requirements(vec![req]))
+            .unwrap();
+        let provider = solver.provider();
+        let find = |name: &str| {
+            *solution
+                .iter()
+                .find(|&&sid| provider.package_metadata(sid).cpv.cpn.package == name)
+                .unwrap()
+        };
+
+        let schedule = provider.merge_schedule(&solution).unwrap();
+        assert_eq!(schedule.batches.len(), 2);
+        assert_eq!(
+            order_names(provider, &schedule.batches[1]),
+            ["app"],
+            "batches: {:?}",
+            schedule.batches
+        );
+        let mut first = order_names(provider, &schedule.batches[0]);
+        first.sort();
+        assert_eq!(first, ["lib", "other", "rt"]);
+
+        let app = schedule.job(find("app")).unwrap();
+        assert_eq!(order_names(provider, &app.prerequisites), ["lib", "rt"]);
+        assert!(
+            schedule
+                .job(find("other"))
+                .unwrap()
+                .prerequisites
+                .is_empty()
+        );
+        assert_eq!(schedule.jobs.last().unwrap().solvable, find("app"));
+
+        // With unit costs, lib and rt carry the critical path to app.
+        assert_eq!(schedule.job(find("lib")).unwrap().priority, 2);
+        assert_eq!(schedule.job(find("other")).unwrap().priority, 1);
+
+        // A heavy independent job is started first within its batch.
+        let weighted = provider
+            .merge_schedule_weighted(&solution, |sid| if sid == find("other") { 10 } else { 1 })
+            .unwrap();
+        assert_eq!(weighted.batches[0][0], find("other"));
+    }
+
+    #[test]
+    fn merge_schedule_reports_cycles() {
+        let (solver, solution) =
+            solve_cycle(DepClass::Depend, DepClass::Depend, &InstalledSet::new());
+        let cycles = solver.provider().merge_schedule(&solution).unwrap_err();
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(cycles[0].members.len(), 2);
+    }
+}
@@ -0,0 +1,94 @@
+//! Serde helpers for portage-atom types.
+//!
+//! portage-atom has no serde support of its own, so its atoms, names and
+//! versions round-trip through their string forms. Used with
+//! `#[serde(with = "...")]` on the fields of the crate's data types.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serializer};
+
+/// Any type with matching `Display` and `FromStr` impls, e.g. [`Cpv`],
+/// [`Cpn`] or [`Version`].
+///
+/// [`Cpv`]: portage_atom::Cpv
+/// [`Cpn`]: portage_atom::Cpn
+/// [`Version`]: portage_atom::Version
+pub(crate) mod display_fromstr {
+    use super::*;
+
+    pub(crate) fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub(crate) fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// [`Operator`](portage_atom::Operator) as its atom prefix (`>=`, `~`, ...).
+pub(crate) mod operator {
+    use portage_atom::Operator;
+
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        operator: &Operator,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(operator)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Operator, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Ok(match text.as_str() {
+            "<" => Operator::Less,
+            "<=" => Operator::LessOrEqual,
+            "=" => Operator::Equal,
+            "~" => Operator::Approximate,
+            ">=" => Operator::GreaterOrEqual,
+            ">" => Operator::Greater,
+            _ => return Err(D::Error::custom(format!("invalid operator `{text}`"))),
+        })
+    }
+}
+
+/// A dependency tree as a single dependency string, e.g.
+/// `dev-libs/a ssl? ( dev-libs/openssl )`.
+pub(crate) mod dep_entries {
+    use portage_atom::DepEntry;
+
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        entries: &[DepEntry],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let text: Vec<String> = entries.iter().map(ToString::to_string).collect();
+        serializer.serialize_str(&text.join(" "))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<DepEntry>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if text.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        DepEntry::parse(&text).map_err(D::Error::custom)
+    }
+}
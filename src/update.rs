@@ -0,0 +1,777 @@
+//! Update modes: which installed packages a solve may replace or rebuild.
+//!
+//! An [`UpdateMode`] mirrors emerge's `--update`, `--deep`, `--newuse`,
+//! `--changed-use` and `--changed-deps`.
+//! [`update_problem`](PortageDependencyProvider::update_problem) turns it
+//! into the installed versions the solver prefers for the root
+//! requirements interned so far, and
+//! [`reinstalls`](PortageDependencyProvider::reinstalls) picks the
+//! installed packages of a solution that must be rebuilt for USE or
+//! dependency changes.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+use portage_atom::gentoo_interner::{DefaultInterner, Interned};
+use portage_atom::{Dep, DepEntry, SlotDep, SlotOperator, UseDep, UseDepKind};
+use resolvo::{ConditionalRequirement, NameId, Problem, Requirement, SolvableId};
+
+use crate::pool::{DepClass, DepClassMode, DepClassModes, PackageMetadata, UseConfig};
+use crate::provider::PortageDependencyProvider;
+
+/// How a solve treats installed packages.
+///
+/// The default is emerge without options: every installed package the
+/// roots need stays at its installed version if it still fits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UpdateMode {
+    /// `--update`: the packages named by root atoms may move to another
+    /// version.
+    pub update: bool,
+    /// `--deep`: with [`update`](Self::update), so may every package in
+    /// the dependency closure of the root atoms.
+    pub deep: bool,
+    /// `--newuse` / `--changed-use`: which USE changes make an installed
+    /// package a [reinstall](PortageDependencyProvider::reinstalls).
+    pub use_changes: UseChanges,
+    /// `--changed-deps`: installed packages whose
+    /// [dependencies changed](PortageDependencyProvider::dep_changes) are
+    /// reinstalls too.
+    pub changed_deps: bool,
+}
+
+impl UpdateMode {
+    /// `--update`.
+    pub fn update() -> Self {
+        Self {
+            update: true,
+            ..Self::default()
+        }
+    }
+
+    /// `--update --deep`.
+    pub fn deep() -> Self {
+        Self {
+            update: true,
+            deep: true,
+            ..Self::default()
+        }
+    }
+
+    /// The same mode with `use_changes` set.
+    pub fn with_use_changes(mut self, use_changes: UseChanges) -> Self {
+        self.use_changes = use_changes;
+        self
+    }
+
+    /// The same mode with `changed_deps` set.
+    pub fn with_changed_deps(mut self, changed_deps: bool) -> Self {
+        self.changed_deps = changed_deps;
+        self
+    }
+}
+
+/// Which USE changes trigger a reinstall.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UseChanges {
+    /// USE changes alone never reinstall a package.
+    #[default]
+    Ignore,
+    /// `--changed-use`: the enabled flags differ.
+    ChangedUse,
+    /// `--newuse`: the enabled flags differ or IUSE gained or lost a flag.
+    NewUse,
+}
+
+impl UseChanges {
+    /// Whether `change` triggers a reinstall in this mode.
+    pub fn triggers(self, change: UseChange) -> bool {
+        match self {
+            UseChanges::Ignore => false,
+            UseChanges::ChangedUse => change == UseChange::Enabled,
+            UseChanges::NewUse => true,
+        }
+    }
+}
+
+/// How the USE an installed package was built with differs from what its
+/// ebuild would be built with now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UseChange {
+    /// The enabled flags differ.
+    Enabled,
+    /// The enabled flags agree, but IUSE gained or lost a disabled flag.
+    Iuse,
+}
+
+/// Flag name of an IUSE entry and whether it defaults on (`+flag`).
+fn iuse_flag(entry: Interned<DefaultInterner>) -> (Interned<DefaultInterner>, bool) {
+    let entry = entry.as_str();
+    match entry.strip_prefix('+') {
+        Some(flag) => (Interned::intern(flag), true),
+        None => (Interned::intern(entry.trim_start_matches('-')), false),
+    }
+}
+
+/// USE change between an installed package and the repository package
+/// with the same CPV, `None` if it would be built the same way.
+///
+/// The installed package was built with its
+/// [`use_flags`](PackageMetadata::use_flags). The repository package would
+/// enable an IUSE flag that `use_config` enables, that its own `use_flags`
+/// list, or that defaults on (`+flag`), unless `use_config` disables it.
+/// Solver-decided flags are only known after solving and are ignored.
+pub(crate) fn use_change(
+    installed: &PackageMetadata,
+    candidate: &PackageMetadata,
+    use_config: &UseConfig,
+) -> Option<UseChange> {
+    let decided = |flag: &Interned<DefaultInterner>| !use_config.solver_decided.contains(flag);
+
+    let installed_iuse: HashSet<_> = installed
+        .iuse
+        .iter()
+        .map(|&entry| iuse_flag(entry).0)
+        .filter(decided)
+        .collect();
+    let installed_enabled: HashSet<_> = installed
+        .use_flags
+        .iter()
+        .copied()
+        .filter(|flag| installed_iuse.contains(flag))
+        .collect();
+
+    let mut candidate_iuse = HashSet::new();
+    let mut candidate_enabled = HashSet::new();
+    for &entry in &candidate.iuse {
+        let (flag, default_on) = iuse_flag(entry);
+        if !decided(&flag) {
+            continue;
+        }
+        candidate_iuse.insert(flag);
+        let enabled = !use_config.disabled.contains(&flag)
+            && (use_config.enabled.contains(&flag)
+                || candidate.use_flags.contains(&flag)
+                || default_on);
+        if enabled {
+            candidate_enabled.insert(flag);
+        }
+    }
+
+    if installed_enabled != candidate_enabled {
+        Some(UseChange::Enabled)
+    } else if installed_iuse != candidate_iuse {
+        Some(UseChange::Iuse)
+    } else {
+        None
+    }
+}
+
+/// How one dependency class of an installed package differs from its
+/// repository ebuild, entry by entry.
+///
+/// Both trees are normalized under the installed package's
+/// [`use_flags`](PackageMetadata::use_flags) first: USE conditionals and
+/// conditional USE deps are evaluated, all-of groups are flattened, and
+/// slot-operator bindings (`:0/1=`) are reduced to `:=`, so only changes
+/// that affect the installed build remain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepChange {
+    pub class: DepClass,
+    /// Entries only the repository ebuild has.
+    pub added: Vec<DepEntry>,
+    /// Entries only the installed package has.
+    pub removed: Vec<DepEntry>,
+}
+
+impl fmt::Display for DepChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.class)?;
+        for entry in &self.added {
+            write!(f, " +{entry}")?;
+        }
+        for entry in &self.removed {
+            write!(f, " -{entry}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Dependency changes between an installed package and the repository
+/// package with the same CPV, one [`DepChange`] per differing runtime
+/// class (`RDEPEND`, `PDEPEND`, `IDEPEND`) that `modes` does not exclude.
+/// Build-time classes cannot make an installed package inconsistent.
+pub(crate) fn dep_changes(
+    installed: &PackageMetadata,
+    candidate: &PackageMetadata,
+    modes: &DepClassModes,
+) -> Vec<DepChange> {
+    let use_flags = &installed.use_flags;
+    [
+        (
+            DepClass::Rdepend,
+            &installed.dependencies.rdepend,
+            &candidate.dependencies.rdepend,
+        ),
+        (
+            DepClass::Pdepend,
+            &installed.dependencies.pdepend,
+            &candidate.dependencies.pdepend,
+        ),
+        (
+            DepClass::Idepend,
+            &installed.dependencies.idepend,
+            &candidate.dependencies.idepend,
+        ),
+    ]
+    .into_iter()
+    .filter(|&(class, _, _)| modes.mode(class) != DepClassMode::Exclude)
+    .filter_map(|(class, old, new)| {
+        let old = normalize_deps(old, use_flags);
+        let new = normalize_deps(new, use_flags);
+        let added: Vec<_> = new.iter().filter(|e| !old.contains(e)).cloned().collect();
+        let removed: Vec<_> = old.iter().filter(|e| !new.contains(e)).cloned().collect();
+        (!added.is_empty() || !removed.is_empty()).then_some(DepChange {
+            class,
+            added,
+            removed,
+        })
+    })
+    .collect()
+}
+
+/// Top-level entries of `entries` under `use_flags`, without duplicates.
+fn normalize_deps(
+    entries: &[DepEntry],
+    use_flags: &HashSet<Interned<DefaultInterner>>,
+) -> Vec<DepEntry> {
+    let mut normalized = Vec::new();
+    normalize_into(entries, use_flags, &mut normalized);
+    let mut seen = HashSet::new();
+    normalized.retain(|entry| seen.insert(entry.clone()));
+    normalized
+}
+
+fn normalize_into(
+    entries: &[DepEntry],
+    use_flags: &HashSet<Interned<DefaultInterner>>,
+    out: &mut Vec<DepEntry>,
+) {
+    for entry in entries {
+        match entry {
+            DepEntry::Atom(dep) => out.push(DepEntry::Atom(normalize_dep(dep, use_flags))),
+            DepEntry::UseConditional {
+                flag,
+                negate,
+                children,
+            } => {
+                if use_flags.contains(flag) != *negate {
+                    normalize_into(children, use_flags, out);
+                }
+            }
+            DepEntry::AllOf(children) => normalize_into(children, use_flags, out),
+            DepEntry::AnyOf(alternatives) => {
+                normalize_group(DepEntry::AnyOf, alternatives, use_flags, out)
+            }
+            DepEntry::ExactlyOneOf(alternatives) => {
+                normalize_group(DepEntry::ExactlyOneOf, alternatives, use_flags, out)
+            }
+            DepEntry::AtMostOneOf(alternatives) => {
+                normalize_group(DepEntry::AtMostOneOf, alternatives, use_flags, out)
+            }
+        }
+    }
+}
+
+/// Normalize each alternative of a `||` / `^^` / `??` group on its own;
+/// an alternative that normalizes to nothing is dropped, and so is a group
+/// left without alternatives.
+fn normalize_group(
+    group: fn(Vec<DepEntry>) -> DepEntry,
+    alternatives: &[DepEntry],
+    use_flags: &HashSet<Interned<DefaultInterner>>,
+    out: &mut Vec<DepEntry>,
+) {
+    let mut normalized = Vec::new();
+    for alternative in alternatives {
+        let mut entries = Vec::new();
+        normalize_into(std::slice::from_ref(alternative), use_flags, &mut entries);
+        match <[DepEntry; 1]>::try_from(entries) {
+            Ok([entry]) => normalized.push(entry),
+            Err(entries) if entries.is_empty() => {}
+            Err(entries) => normalized.push(DepEntry::AllOf(entries)),
+        }
+    }
+    if !normalized.is_empty() {
+        out.push(group(normalized));
+    }
+}
+
+/// `dep` with its conditional USE deps evaluated under `use_flags` and a
+/// bound slot operator reduced to `:=`.
+fn normalize_dep(dep: &Dep, use_flags: &HashSet<Interned<DefaultInterner>>) -> Dep {
+    let mut dep = dep.clone();
+    if let Some(SlotDep::Slot {
+        op: Some(SlotOperator::Equal),
+        ..
+    }) = dep.slot_dep
+    {
+        dep.slot_dep = Some(SlotDep::Operator(SlotOperator::Equal));
+    }
+    if let Some(use_deps) = dep.use_deps.take() {
+        let use_deps: Vec<UseDep> = use_deps
+            .into_iter()
+            .filter_map(|use_dep| {
+                let enabled = use_flags.contains(&use_dep.flag);
+                let kind = match use_dep.kind {
+                    UseDepKind::Enabled | UseDepKind::Disabled => use_dep.kind,
+                    UseDepKind::Conditional => enabled.then_some(UseDepKind::Enabled)?,
+                    UseDepKind::ConditionalInverse => (!enabled).then_some(UseDepKind::Disabled)?,
+                    UseDepKind::Equal if enabled => UseDepKind::Enabled,
+                    UseDepKind::EqualInverse if !enabled => UseDepKind::Enabled,
+                    UseDepKind::Equal | UseDepKind::EqualInverse => UseDepKind::Disabled,
+                };
+                Some(UseDep { kind, ..use_dep })
+            })
+            .collect();
+        dep.use_deps = (!use_deps.is_empty()).then_some(use_deps);
+    }
+    dep
+}
+
+impl PortageDependencyProvider {
+    /// Build the [`Problem`] for `requirements` under `mode`.
+    ///
+    /// Without [`UpdateMode::update`] every installed package keeps its
+    /// [`InstalledPolicy`](crate::InstalledPolicy). With it, the favored
+    /// installed versions of the packages named by the root atoms
+    /// interned so far (see [`intern_requirement`](Self::intern_requirement))
+    /// are released so the [`CandidateOrdering`](crate::CandidateOrdering)
+    /// picks their version; [`UpdateMode::deep`] releases the whole
+    /// dependency closure of the roots, over every candidate version.
+    /// Locked installed versions are never released.
+    ///
+    /// Installed versions are kept through the solver's favored and locked
+    /// candidates, not through requirements, so installed packages the
+    /// roots do not reach stay out of the solution (and out of a
+    /// [`lazy`](Self::lazy) provider's loaded set); add `@world` atoms to
+    /// the roots to keep them. The released versions stay released for
+    /// every solve on this provider until the next call, which replaces the
+    /// previous mode.
+    pub fn update_problem(
+        &mut self,
+        mode: UpdateMode,
+        requirements: Vec<ConditionalRequirement>,
+    ) -> Problem<Vec<SolvableId>> {
+        let released = if mode.update {
+            let roots = self.root_names();
+            if mode.deep {
+                self.dependency_closure(roots)
+            } else {
+                roots.into_iter().collect()
+            }
+        } else {
+            HashSet::new()
+        };
+        self.released = released;
+        Problem::new()
+            .requirements(requirements)
+            .soft_requirements(Vec::new())
+    }
+
+    /// Installed packages in `solution` whose USE or dependencies changed
+    /// in a way `mode` reinstalls, in solution order.
+    pub fn reinstalls(&self, solution: &[SolvableId], mode: UpdateMode) -> Vec<SolvableId> {
+        solution
+            .iter()
+            .copied()
+            .filter(|&sid| {
+                self.use_change(sid)
+                    .is_some_and(|change| mode.use_changes.triggers(change))
+                    || (mode.changed_deps && self.dep_changes.borrow().contains_key(&sid))
+            })
+            .collect()
+    }
+
+    /// How the installed package `solvable` was built differently from
+    /// its repository ebuild; `None` for unchanged, not installed, or
+    /// installed packages missing from the repository.
+    pub fn use_change(&self, solvable: SolvableId) -> Option<UseChange> {
+        self.use_changes.borrow().get(&solvable).copied()
+    }
+
+    /// How the runtime dependencies of the installed package `solvable`
+    /// differ from its repository ebuild's, see [`DepChange`]. Empty for
+    /// unchanged, not installed, or installed packages missing from the
+    /// repository.
+    pub fn dep_changes(&self, solvable: SolvableId) -> Vec<DepChange> {
+        self.dep_changes
+            .borrow()
+            .get(&solvable)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Names of every slot of every root atom's package.
+    fn root_names(&self) -> Vec<NameId> {
+        let mut names = Vec::new();
+        for (dep, domain) in &self.roots {
+            self.load_cpn(dep.cpn);
+            if let Some(slots) = self.cpn_slots.borrow().get(&(*domain, dep.cpn)) {
+                names.extend(slots.iter().copied());
+            }
+        }
+        names
+    }
+
+    /// Names reachable from `roots` through the requirements of any of
+    /// their candidates, roots included.
+    fn dependency_closure(&self, roots: Vec<NameId>) -> HashSet<NameId> {
+        let mut reached: HashSet<NameId> = roots.iter().copied().collect();
+        let mut queue: VecDeque<NameId> = roots.into();
+        while let Some(name) = queue.pop_front() {
+            self.load_cpn(self.pool.resolve_name(name).cpn);
+            let candidates = self
+                .candidates
+                .borrow()
+                .get(&name)
+                .cloned()
+                .unwrap_or_default();
+            for sid in candidates {
+                if self.lazy.is_some() && self.known_dependencies(sid).is_none() {
+                    self.convert_solvable(sid);
+                }
+                let Some(deps) = self.known_dependencies(sid) else {
+                    continue;
+                };
+                for req in &deps.requirements {
+                    let version_sets = match req.requirement {
+                        Requirement::Single(vs_id) => vec![vs_id],
+                        Requirement::Union(union_id) => {
+                            self.pool.resolve_version_set_union(union_id).to_vec()
+                        }
+                    };
+                    for vs_id in version_sets {
+                        let dep_name = self.pool.version_set_name(vs_id);
+                        if reached.insert(dep_name) {
+                            queue.push_back(dep_name);
+                        }
+                    }
+                }
+            }
+        }
+        reached
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use resolvo::{ArenaId, Solver};
+
+    use super::*;
+    use crate::pool::{DepClassPolicy, InstalledSet};
+    use crate::repository::InMemoryRepository;
+    use crate::tests::{Fixture, pkg, pkg_build_run};
+
+    fn with_iuse(mut meta: PackageMetadata, iuse: &[&str], enabled: &[&str]) -> PackageMetadata {
+        meta.iuse = iuse.iter().map(|&flag| Interned::intern(flag)).collect();
+        meta.use_flags = enabled.iter().map(|&flag| Interned::intern(flag)).collect();
+        meta
+    }
+
+    /// app-1.0 was built with USE=-ssl and ssl is now enabled; lib-1.0
+    /// gained a disabled flag since it was installed.
+    fn update_fixture() -> Fixture {
+        let app = || {
+            with_iuse(
+                pkg_build_run("app-misc/app-1.0", "", "dev-lib/lib"),
+                &["ssl"],
+                &[],
+            )
+        };
+        Fixture::new([
+            app(),
+            pkg_build_run("app-misc/app-2.0", "", "dev-lib/lib"),
+            with_iuse(pkg("dev-lib/lib-1.0", "0", vec![]), &["-debug"], &[]),
+            pkg("dev-lib/lib-2.0", "0", vec![]),
+            pkg("app-misc/other-1.0", "0", vec![]),
+            pkg("app-misc/other-2.0", "0", vec![]),
+        ])
+        .favored([
+            app(),
+            pkg("dev-lib/lib-1.0", "0", vec![]),
+            pkg("app-misc/other-1.0", "0", vec![]),
+        ])
+        .use_flags(&["ssl"])
+    }
+
+    /// Solves `app-misc/app` in `mode`; returns the sorted solution and
+    /// reinstalls.
+    fn resolve_update(
+        mut provider: PortageDependencyProvider,
+        mode: UpdateMode,
+    ) -> (Vec<String>, Vec<String>) {
+        let req = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let problem = provider.update_problem(mode, vec![req]);
+        let mut solver = Solver::new(provider);
+        let solution = solver.solve(problem).unwrap();
+        let provider = solver.provider();
+        let mut cpvs: Vec<String> = solution
+            .iter()
+            .map(|&sid| provider.package_metadata(sid).cpv.to_string())
+            .collect();
+        cpvs.sort();
+        let mut reinstalls: Vec<String> = provider
+            .reinstalls(&solution, mode)
+            .into_iter()
+            .map(|sid| provider.package_metadata(sid).cpv.to_string())
+            .collect();
+        reinstalls.sort();
+        (cpvs, reinstalls)
+    }
+
+    fn use_changes(provider: &PortageDependencyProvider) -> Vec<(String, UseChange)> {
+        let mut changes: Vec<(String, UseChange)> = (0..provider.pool().solvable_count())
+            .map(SolvableId::from_usize)
+            .filter_map(|sid| {
+                let change = provider.use_change(sid)?;
+                Some((provider.package_metadata(sid).cpv.to_string(), change))
+            })
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        changes
+    }
+
+    #[test]
+    fn update_mode_default_keeps_installed() {
+        // Without --update the installed versions the root needs stay;
+        // the unrelated installed other-1.0 is not pulled in.
+        let (cpvs, reinstalls) = resolve_update(update_fixture().provider(), UpdateMode::default());
+        assert_eq!(cpvs, ["app-misc/app-1.0", "dev-lib/lib-1.0"]);
+        assert!(reinstalls.is_empty());
+    }
+
+    #[test]
+    fn update_mode_update_moves_roots_only() {
+        let (cpvs, _) = resolve_update(update_fixture().provider(), UpdateMode::update());
+        assert_eq!(cpvs, ["app-misc/app-2.0", "dev-lib/lib-1.0"]);
+    }
+
+    #[test]
+    fn update_mode_deep_moves_dependencies() {
+        let (cpvs, _) = resolve_update(update_fixture().provider(), UpdateMode::deep());
+        assert_eq!(cpvs, ["app-misc/app-2.0", "dev-lib/lib-2.0"]);
+    }
+
+    #[test]
+    fn lazy_update_mode_deep_moves_dependencies() {
+        let lazy = update_fixture().lazy_builder().build().unwrap();
+        let (cpvs, _) = resolve_update(lazy, UpdateMode::deep());
+        assert_eq!(cpvs, ["app-misc/app-2.0", "dev-lib/lib-2.0"]);
+    }
+
+    #[test]
+    fn changed_use_reinstalls_for_changed_flags() {
+        // The enabled ssl flag reinstalls app; lib's new IUSE flag does not.
+        let changed_use = UpdateMode::default().with_use_changes(UseChanges::ChangedUse);
+        let (_, reinstalls) = resolve_update(update_fixture().provider(), changed_use);
+        assert_eq!(reinstalls, ["app-misc/app-1.0"]);
+    }
+
+    #[test]
+    fn newuse_reinstalls_for_new_iuse() {
+        let newuse = UpdateMode::default().with_use_changes(UseChanges::NewUse);
+        let (_, reinstalls) = resolve_update(update_fixture().provider(), newuse);
+        assert_eq!(reinstalls, ["app-misc/app-1.0", "dev-lib/lib-1.0"]);
+    }
+
+    #[test]
+    fn use_changes_of_installed_packages() {
+        assert_eq!(
+            use_changes(&update_fixture().provider()),
+            [
+                ("app-misc/app-1.0".to_string(), UseChange::Enabled),
+                ("dev-lib/lib-1.0".to_string(), UseChange::Iuse),
+            ]
+        );
+    }
+
+    #[test]
+    fn use_changes_survive_provider_cache() {
+        let fixture = update_fixture();
+        let (repository, key) = fixture.cache_key();
+        let provider = fixture.provider();
+        let mut bytes = Vec::new();
+        provider.write_cache(&mut bytes, repository).unwrap();
+        let loaded = PortageDependencyProvider::read_cache(bytes.as_slice(), &key).unwrap();
+        assert_eq!(use_changes(&loaded), use_changes(&provider));
+    }
+
+    fn with_flags(mut meta: PackageMetadata, enabled: &[&str]) -> PackageMetadata {
+        meta.iuse = vec![Interned::intern("ssl"), Interned::intern("debug")];
+        meta.use_flags = enabled.iter().map(|&flag| Interned::intern(flag)).collect();
+        meta
+    }
+
+    /// app-1.0 swapped dev-libs/old for dev-libs/new in RDEPEND without a
+    /// revbump; the DEPEND change does not matter once installed. Only a
+    /// branch the installed USE of tool-1.0 does not take changed.
+    fn changed_deps_fixture() -> Fixture {
+        Fixture::new([
+            with_flags(
+                pkg_build_run(
+                    "app-misc/app-1.0",
+                    "dev-libs/build",
+                    "ssl? ( dev-libs/openssl[static-libs?] ) dev-libs/zlib:= dev-libs/new",
+                ),
+                &[],
+            ),
+            with_flags(
+                pkg_build_run("app-misc/tool-1.0", "", "debug? ( dev-libs/new )"),
+                &[],
+            ),
+            pkg("dev-libs/openssl-1.0", "0", vec![]),
+            pkg("dev-libs/zlib-1.0", "0", vec![]),
+            pkg("dev-libs/new-1.0", "0", vec![]),
+            pkg("dev-libs/old-1.0", "0", vec![]),
+            pkg("dev-libs/build-1.0", "0", vec![]),
+        ])
+        .favored([
+            with_flags(
+                pkg_build_run(
+                    "app-misc/app-1.0",
+                    "",
+                    "( ssl? ( dev-libs/openssl ) ) dev-libs/zlib:0/1= dev-libs/old",
+                ),
+                &["ssl"],
+            ),
+            with_flags(
+                pkg_build_run("app-misc/tool-1.0", "", "debug? ( dev-libs/old )"),
+                &[],
+            ),
+        ])
+    }
+
+    /// Packages reinstalled when solving `app-misc/app` and `app-misc/tool`
+    /// in `mode`.
+    fn changed_deps_reinstalls(
+        mut provider: PortageDependencyProvider,
+        mode: UpdateMode,
+    ) -> Vec<String> {
+        let app = provider.intern_requirement(&Dep::parse("app-misc/app").unwrap());
+        let tool = provider.intern_requirement(&Dep::parse("app-misc/tool").unwrap());
+        let problem = provider.update_problem(mode, vec![app, tool]);
+        let mut solver = Solver::new(provider);
+        let solution = solver.solve(problem).unwrap();
+        let provider = solver.provider();
+        provider
+            .reinstalls(&solution, mode)
+            .into_iter()
+            .map(|sid| provider.package_metadata(sid).cpv.to_string())
+            .collect()
+    }
+
+    fn dep_changes(provider: &PortageDependencyProvider) -> Vec<String> {
+        (0..provider.pool().solvable_count())
+            .map(SolvableId::from_usize)
+            .flat_map(|sid| {
+                let cpv = provider.package_metadata(sid).cpv.to_string();
+                provider
+                    .dep_changes(sid)
+                    .into_iter()
+                    .map(move |change| format!("{cpv} {change}"))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dep_changes_of_installed_packages() {
+        // The ssl branch, its conditional USE dep and the slot-operator
+        // binding normalize to the same entries on both sides.
+        assert_eq!(
+            dep_changes(&changed_deps_fixture().provider()),
+            ["app-misc/app-1.0 RDEPEND: +dev-libs/new -dev-libs/old"]
+        );
+    }
+
+    #[test]
+    fn dep_changes_evaluate_inverse_conditional_use_deps() {
+        // `[!ssl?]` with ssl off means `[-ssl]`.
+        let mut repo = InMemoryRepository::new();
+        repo.add(with_flags(
+            pkg_build_run("app-misc/app-1.0", "", "dev-libs/openssl[!ssl?]"),
+            &[],
+        ));
+        repo.add(pkg("dev-libs/openssl-1.0", "0", vec![]));
+        let mut installed = InstalledSet::new();
+        installed.add_favored(with_flags(
+            pkg_build_run("app-misc/app-1.0", "", "dev-libs/openssl"),
+            &[],
+        ));
+        let provider =
+            PortageDependencyProvider::with_installed(&repo, &UseConfig::default(), &installed);
+        assert_eq!(
+            dep_changes(&provider),
+            ["app-misc/app-1.0 RDEPEND: +dev-libs/openssl[-ssl] -dev-libs/openssl"]
+        );
+    }
+
+    #[test]
+    fn dep_changes_need_changed_deps() {
+        assert!(
+            changed_deps_reinstalls(changed_deps_fixture().provider(), UpdateMode::default())
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn changed_deps_reinstalls_changed_packages() {
+        let changed_deps = UpdateMode::default().with_changed_deps(true);
+        assert_eq!(
+            changed_deps_reinstalls(changed_deps_fixture().provider(), changed_deps),
+            ["app-misc/app-1.0"]
+        );
+    }
+
+    #[test]
+    fn lazy_changed_deps_reinstalls_changed_packages() {
+        let lazy = changed_deps_fixture().lazy_builder().build().unwrap();
+        let changed_deps = UpdateMode::default().with_changed_deps(true);
+        assert_eq!(
+            changed_deps_reinstalls(lazy, changed_deps),
+            ["app-misc/app-1.0"]
+        );
+    }
+
+    #[test]
+    fn dep_changes_skip_excluded_classes() {
+        use crate::pool::{DepClassMode, DepClassModes};
+
+        // Classes excluded for installed packages are not compared.
+        let policy = DepClassPolicy {
+            installed: DepClassModes::default().with(DepClass::Rdepend, DepClassMode::Exclude),
+            ..DepClassPolicy::default()
+        };
+        assert!(
+            dep_changes(
+                &changed_deps_fixture()
+                    .builder()
+                    .dep_class_policy(policy)
+                    .build()
+                    .unwrap()
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn dep_changes_survive_provider_cache() {
+        let fixture = changed_deps_fixture();
+        let (repository, key) = fixture.cache_key();
+        let provider = fixture.provider();
+        let mut bytes = Vec::new();
+        provider.write_cache(&mut bytes, repository).unwrap();
+        let loaded = PortageDependencyProvider::read_cache(bytes.as_slice(), &key).unwrap();
+        assert_eq!(dep_changes(&loaded), dep_changes(&provider));
+    }
+}